@@ -1,8 +1,9 @@
 fn main() {
-    // Compile the USP message payload protobuf schema.
-    // Proto files define USP message payloads for device communication.
-    prost_build::compile_protos(&["proto/acp.proto"], &["proto/"])
-        .expect("prost-build: failed to compile acp.proto");
+    // Legacy ACP payload schema — only with the `legacy-acp` feature.
+    if std::env::var_os("CARGO_FEATURE_LEGACY_ACP").is_some() {
+        prost_build::compile_protos(&["proto/acp.proto"], &["proto/"])
+            .expect("prost-build: failed to compile acp.proto");
+    }
 
     // TR-369 / USP wire protocol.
     prost_build::compile_protos(
@@ -1,14 +1,17 @@
-//! Apply firmware upgrades to the device.
+//! Apply firmware upgrades, provisioned certificates and UCI config reloads
+//! to the device.
 //!
 //! Firmware is applied via `/sbin/sysupgrade`.
 
 #![allow(dead_code)]
 
 use std::path::Path;
+use std::time::Duration;
 
-use log::info;
+use log::{error, info, warn};
 use tokio::process::Command;
 
+use crate::config::ClientConfig;
 use crate::error::{AcError, Result};
 
 // ── Firmware upgrade ──────────────────────────────────────────────────────────
@@ -33,3 +36,375 @@ pub async fn apply_firmware(fw_path: &Path) -> Result<()> {
     }
     Ok(())
 }
+
+/// Check a firmware image with `sysupgrade -T` (image and platform checks
+/// only, nothing is flashed).
+pub async fn test_firmware(fw_path: &Path) -> Result<()> {
+    let out = Command::new("/sbin/sysupgrade")
+        .arg("-T")
+        .arg(fw_path)
+        .output()
+        .await?;
+    if !out.status.success() {
+        return Err(AcError::Protocol(format!(
+            "sysupgrade -T rejected the image: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+// ── Certificates ──────────────────────────────────────────────────────────────
+
+/// Persist the CA, client certificate and key issued by the controller.
+///
+/// Each file is written to a temporary sibling and renamed into place so an
+/// interrupted write never leaves a half-written identity behind; the key is
+/// created with mode 0600.  Callers should then call
+/// [`crate::tls::identity_changed`] so the MTPs reconnect with the new cert.
+pub async fn save_certs(
+    cfg: &ClientConfig,
+    ca_pem: &str,
+    cert_pem: &str,
+    key_pem: &str,
+) -> Result<()> {
+    write_atomic(&cfg.ca_file, ca_pem.as_bytes(), 0o644).await?;
+    write_atomic(&cfg.cert_file, cert_pem.as_bytes(), 0o644).await?;
+    write_atomic(&cfg.key_file, key_pem.as_bytes(), 0o600).await?;
+    info!(
+        "provisioned certificate saved to {} (key {})",
+        cfg.cert_file.display(),
+        cfg.key_file.display()
+    );
+    Ok(())
+}
+
+/// Install a certificate signed for a CSR from `IssueCert()`, together with
+/// the private key staged at `staged_key` when the CSR was generated.
+///
+/// The certificate must carry the staged key's public key; anything else is
+/// rejected before a file is touched.  The staged key is moved onto
+/// `key_file` only after the CA (kept as is when `ca_pem` is `None`) and
+/// certificate are in place, and removed once installed.
+pub async fn save_signed_cert(
+    cfg: &ClientConfig,
+    ca_pem: Option<&str>,
+    cert_pem: &str,
+    staged_key: &Path,
+) -> Result<()> {
+    let key_pem = tokio::fs::read(staged_key)
+        .await
+        .map_err(|e| AcError::Config(format!("no staged key at {}: {e}", staged_key.display())))?;
+    if !cert_matches_key(cert_pem, staged_key).await? {
+        return Err(AcError::Config(format!(
+            "certificate was not issued for the key staged at {}",
+            staged_key.display()
+        )));
+    }
+    if let Some(ca_pem) = ca_pem {
+        write_atomic(&cfg.ca_file, ca_pem.as_bytes(), 0o644).await?;
+    }
+    write_atomic(&cfg.cert_file, cert_pem.as_bytes(), 0o644).await?;
+    write_atomic(&cfg.key_file, &key_pem, 0o600).await?;
+    if let Err(e) = tokio::fs::remove_file(staged_key).await {
+        warn!("staged key {} not removed: {e}", staged_key.display());
+    }
+    info!(
+        "signed certificate saved to {} (key {} from CSR)",
+        cfg.cert_file.display(),
+        cfg.key_file.display()
+    );
+    Ok(())
+}
+
+/// Whether `cert_pem` carries the public key of the private key at
+/// `key_path`, comparing `openssl x509 -pubkey` with `openssl pkey -pubout`.
+async fn cert_matches_key(cert_pem: &str, key_path: &Path) -> Result<bool> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut x509 = Command::new("openssl")
+        .args(["x509", "-noout", "-pubkey"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = x509.stdin.take() {
+        stdin.write_all(cert_pem.as_bytes()).await?;
+    }
+    let cert_pub = x509.wait_with_output().await?;
+    if !cert_pub.status.success() {
+        return Err(AcError::Config(format!(
+            "unreadable certificate: {}",
+            String::from_utf8_lossy(&cert_pub.stderr).trim()
+        )));
+    }
+
+    let key_pub = Command::new("openssl")
+        .args(["pkey", "-pubout", "-in"])
+        .arg(key_path)
+        .output()
+        .await?;
+    if !key_pub.status.success() {
+        return Err(AcError::Config(format!(
+            "unreadable key {}: {}",
+            key_path.display(),
+            String::from_utf8_lossy(&key_pub.stderr).trim()
+        )));
+    }
+    Ok(cert_pub.stdout == key_pub.stdout)
+}
+
+// ── Verified config reload ────────────────────────────────────────────────────
+
+/// Health checks after a reload before the change is declared failed.
+const RELOAD_VERIFY_ATTEMPTS: u32 = 5;
+
+/// Commit a UCI package, reload its service and confirm it came back.
+///
+/// `config_path` (e.g. `/etc/config/network`) is snapshotted before `commit`
+/// runs.  After `reload`, `healthy` is polled up to
+/// [`RELOAD_VERIFY_ATTEMPTS`] times, `retry` apart.  If the service never
+/// recovers the snapshot is restored and reloaded, and an error is returned
+/// so the controller learns the change was rolled back.
+pub async fn reload_verified(
+    config_path: &Path,
+    commit: impl FnOnce() -> std::result::Result<(), String>,
+    reload: impl Fn(),
+    healthy: impl Fn() -> bool,
+    retry: Duration,
+) -> Result<()> {
+    let backup = tokio::fs::read(config_path).await.ok();
+    commit().map_err(AcError::Config)?;
+    reload();
+
+    if wait_healthy(healthy, retry).await {
+        return Ok(());
+    }
+
+    let backup = match backup {
+        Some(b) => b,
+        None => {
+            error!(
+                "{} reload left the service down and no backup exists",
+                config_path.display()
+            );
+            return Err(AcError::Config(format!(
+                "service did not recover after reloading {}",
+                config_path.display()
+            )));
+        }
+    };
+    warn!(
+        "{} reload left the service down; rolling back",
+        config_path.display()
+    );
+    write_atomic(config_path, &backup, 0o644).await?;
+    reload();
+    Err(AcError::Config(format!(
+        "service did not recover after reloading {}; previous config restored",
+        config_path.display()
+    )))
+}
+
+/// Poll `healthy` up to [`RELOAD_VERIFY_ATTEMPTS`] times, `retry` apart.
+pub async fn wait_healthy(healthy: impl Fn() -> bool, retry: Duration) -> bool {
+    for attempt in 1..=RELOAD_VERIFY_ATTEMPTS {
+        if healthy() {
+            return true;
+        }
+        if attempt < RELOAD_VERIFY_ATTEMPTS {
+            tokio::time::sleep(retry).await;
+        }
+    }
+    false
+}
+
+/// Put a config file back as it was: rewrite it with `contents`, or remove
+/// it if it did not exist (`None`).
+pub async fn restore_file(path: &Path, contents: Option<&[u8]>) -> Result<()> {
+    match contents {
+        Some(data) => write_atomic(path, data, 0o644).await,
+        None => match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        },
+    }
+}
+
+// ── Config-applied timestamp ──────────────────────────────────────────────────
+
+/// File under `cert_dir` holding the time the last pushed config was applied.
+const CONFIG_APPLIED_FILE: &str = "config_applied_time";
+
+/// Record that a controller-pushed configuration was applied successfully.
+/// Stored on flash so the controller can still see it after a reboot.
+pub async fn record_config_applied(state_dir: &Path) -> Result<()> {
+    let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    write_atomic(&state_dir.join(CONFIG_APPLIED_FILE), now.as_bytes(), 0o644).await
+}
+
+/// RFC 3339 time of the last successful config apply, or empty if none has
+/// been applied since the device was provisioned.
+pub fn config_applied_time(state_dir: &Path) -> String {
+    std::fs::read_to_string(state_dir.join(CONFIG_APPLIED_FILE))
+        .map(|s| s.trim().to_string())
+        .unwrap_or_default()
+}
+
+async fn write_atomic(path: &Path, data: &[u8], mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::AsyncWriteExt;
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    // Created with `mode` so a private key is never readable under the
+    // umask; a leftover tmp file is removed first, as `mode` only applies
+    // to a new file
+    let tmp = path.with_extension("tmp");
+    let _ = tokio::fs::remove_file(&tmp).await;
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&tmp)
+        .await?;
+    file.write_all(data).await?;
+    file.sync_all().await?;
+    tokio::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(mode)).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn scratch_config(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("ac-client-apply-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, "option ipaddr '192.168.1.1'\n").unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn key_written_atomically_with_its_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = scratch_config("client.key");
+        // A stale tmp file from an interrupted write is replaced
+        std::fs::write(path.with_extension("tmp"), "stale").unwrap();
+
+        write_atomic(&path, b"KEY", 0o600).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"KEY");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(!path.with_extension("tmp").exists());
+    }
+
+    #[tokio::test]
+    async fn reload_leaving_interface_down_rolls_back() {
+        let path = scratch_config("network-down");
+        let reloads = Cell::new(0);
+
+        let res = reload_verified(
+            &path,
+            || std::fs::write(&path, "option ipaddr '10.0.0.1'\n").map_err(|e| e.to_string()),
+            || reloads.set(reloads.get() + 1),
+            || false,
+            Duration::from_millis(1),
+        )
+        .await;
+
+        assert!(res.is_err());
+        assert_eq!(reloads.get(), 2, "reload after commit and after restore");
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "option ipaddr '192.168.1.1'\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn reload_recovering_keeps_new_config() {
+        let path = scratch_config("network-up");
+        let checks = Cell::new(0);
+
+        reload_verified(
+            &path,
+            || std::fs::write(&path, "option ipaddr '10.0.0.1'\n").map_err(|e| e.to_string()),
+            || {},
+            || {
+                checks.set(checks.get() + 1);
+                checks.get() >= 2
+            },
+            Duration::from_millis(1),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "option ipaddr '10.0.0.1'\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn config_applied_time_recorded_on_apply() {
+        let dir = std::env::temp_dir().join(format!("ac-client-applied-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(config_applied_time(&dir), "");
+
+        record_config_applied(&dir).await.unwrap();
+        let stamp = config_applied_time(&dir);
+        let parsed = chrono::DateTime::parse_from_rfc3339(&stamp).unwrap();
+        assert!((chrono::Utc::now() - parsed.to_utc()).num_seconds() < 5);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn signed_cert_must_match_staged_key() {
+        if std::process::Command::new("openssl")
+            .arg("version")
+            .output()
+            .is_err()
+        {
+            return; // no openssl on this host
+        }
+        let dir = std::env::temp_dir().join(format!("ac-client-keymatch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let self_signed = |name: &str| {
+            let key = dir.join(format!("{name}.key"));
+            let out = std::process::Command::new("openssl")
+                .args(["req", "-x509", "-nodes", "-newkey", "ec"])
+                .args(["-pkeyopt", "ec_paramgen_curve:prime256v1", "-subj", "/CN=t"])
+                .arg("-keyout")
+                .arg(&key)
+                .output()
+                .unwrap();
+            (key, String::from_utf8(out.stdout).unwrap())
+        };
+        let (key_a, cert_a) = self_signed("a");
+        let (_, cert_b) = self_signed("b");
+
+        assert!(cert_matches_key(&cert_a, &key_a).await.unwrap());
+        assert!(!cert_matches_key(&cert_b, &key_a).await.unwrap());
+        assert!(cert_matches_key("not a cert", &key_a).await.is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn restore_file_rewrites_or_removes() {
+        let path = scratch_config("restore");
+        std::fs::write(&path, "changed\n").unwrap();
+        restore_file(&path, Some(b"before\n")).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "before\n");
+
+        // Did not exist before the change: gone again
+        restore_file(&path, None).await.unwrap();
+        assert!(!path.exists());
+        restore_file(&path, None).await.unwrap();
+    }
+}
@@ -6,14 +6,26 @@
 
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 use crate::error::{AcError, Result};
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 
 // Default interval constants (seconds)
 const PORT: u16 = 3490;
-const STATUS_INTERVAL: u64 = 300;
+pub const STATUS_INTERVAL: u64 = 300;
 const UPDATE_INTERVAL: u64 = 60;
+const OFFLINE_BUFFER_LEN: usize = 64;
+const DM_OP_TIMEOUT: u64 = 60;
+const MAX_CONCURRENT_OPS: usize = 2;
+const SELF_TEST_INTERVAL: u64 = 120;
+const SELF_TEST_FAILURES: u32 = 3;
+const WS_PING_INTERVAL: u64 = 30;
+const REHOME_TIMEOUT: u64 = 300;
+const SYSLOG_RETRIES: u32 = 5;
+const LOG_LEVEL_REVERT: u64 = 3600;
+const MIN_FW_BYTES: u64 = 1024 * 1024;
+const MAX_FW_BYTES: u64 = 64 * 1024 * 1024;
 
 /// MTP selection for the USP Agent.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -29,8 +41,28 @@ impl Default for MtpType {
     }
 }
 
+/// MQTT protocol version spoken to the broker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MqttVersion {
+    V311,
+    V5,
+}
+
+impl Default for MqttVersion {
+    fn default() -> Self {
+        MqttVersion::V311
+    }
+}
+
+fn parse_mqtt_version(val: &str) -> MqttVersion {
+    match val.trim() {
+        "5" | "5.0" | "v5" => MqttVersion::V5,
+        _ => MqttVersion::V311,
+    }
+}
+
 /// Full client configuration.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ClientConfig {
     // ── Server connection settings ────────────────────────────────────────────
     /// USP Controller server hostname or IP address.
@@ -41,6 +73,9 @@ pub struct ClientConfig {
     pub server_cn: String,
     /// Path to the CA certificate.
     pub ca_file: PathBuf,
+    /// Further CA certificates trusted alongside `ca_file` (e.g. a backup
+    /// controller's CA).  Provisioning only ever replaces `ca_file`.
+    pub extra_ca_files: Vec<PathBuf>,
     /// Path to the device's provisioned client certificate.
     pub cert_file: PathBuf,
     /// Path to the device's provisioned client private key.
@@ -61,28 +96,116 @@ pub struct ClientConfig {
     // ── GNSS ──────────────────────────────────────────────────────────────────
     pub gnss_dev: String,
     pub gnss_baud: u32,
+    /// Seconds after which a fix is too old to report in the heartbeat
+    /// (0 = no limit).
+    pub gnss_max_age: u64,
+    // ── Power supply ──────────────────────────────────────────────────────────
+    /// sysfs power-supply directory (e.g. `/sys/class/power_supply/battery`)
+    /// for PoE/battery hardware; empty = power source unknown.
+    pub power_sysfs_path: String,
     // ── Intervals ─────────────────────────────────────────────────────────────
     pub update_interval: u64,
     pub status_interval: u64,
+    /// High-temperature alarm threshold in °C (0 = disabled).
+    pub temp_alarm_c: u32,
     // ── Directories ───────────────────────────────────────────────────────────
     pub fw_dir: PathBuf,
+    /// Downloaded firmware images smaller than this many bytes are rejected
+    /// as truncated before sysupgrade runs.
+    pub min_fw_bytes: u64,
+    /// Firmware downloads are aborted past this many bytes.
+    pub max_fw_bytes: u64,
+    /// dnsmasq lease file read for DHCP clients and LAN hosts.
+    pub dhcp_leases_file: PathBuf,
     // ── Process ───────────────────────────────────────────────────────────────
     pub pid_file: PathBuf,
     pub daemonize: bool,
     pub log_syslog: bool,
+    /// Extra attempts (1 s apart) to reach syslog before falling back to
+    /// stderr; logd may not be up yet early in boot.
+    pub syslog_retries: u32,
+    /// Seconds before a log level SET over USP reverts to the start-up level
+    /// (0 = keep until restart).
+    pub log_level_revert: u64,
     // ── USP / TR-369 ──────────────────────────────────────────────────────────
     /// Agent endpoint ID (auto-built from MAC if empty).
     pub usp_endpoint_id: String,
     /// Controller endpoint ID.
     pub controller_id: String,
+    /// Further controllers announced to over MQTT, which may then reach the
+    /// agent topic too.  Notifications still go to `controller_id` only.
+    pub extra_controllers: Vec<String>,
     /// Claim token linking this device to a tenant account.
     pub claim_token: String,
     /// WebSocket MTP URL (e.g. `wss://ac-server:3491/usp`).
     pub ws_url: Option<String>,
-    /// MQTT broker URL (e.g. `mqtt://emqx:1883`).
+    /// MQTT broker URL (e.g. `mqtt://emqx:1883`, or `mqtts://emqx:8883` for mutual TLS).
     pub mqtt_url: Option<String>,
+    /// MQTT broker username; empty = connect anonymously.
+    pub mqtt_user: String,
+    /// MQTT broker password (only sent with `mqtt_user`).
+    pub mqtt_pass: String,
+    /// MQTT protocol version (3.1.1 default; 5 adds Response Topic and
+    /// Content Type properties on published records).
+    pub mqtt_version: MqttVersion,
     /// Which MTP(s) to use.
     pub mtp: MtpType,
+    /// Max notifications held in RAM while no MTP is connected.
+    pub offline_buffer_len: usize,
+    /// Seconds a single request may run before a USP Error is returned
+    /// (0 = no limit).
+    pub dm_op_timeout: u64,
+    /// OPERATEs allowed to run at once; further ones are refused as busy
+    /// (0 = no limit).
+    pub max_concurrent_ops: usize,
+    /// Seconds between application-level self-tests (a GetSupportedProto the
+    /// controller must answer before the next one; 0 = disabled).
+    pub self_test_interval: u64,
+    /// Consecutive unanswered self-tests that force an MTP reconnect.
+    pub self_test_failures: u32,
+    /// Seconds between WebSocket Pings; no Pong for two intervals drops the
+    /// connection (0 = disabled).
+    pub ws_ping_interval: u64,
+    /// Send Boot! with `send_resp` and retransmit it (up to 3 times, with
+    /// backoff) until the controller's NotifyResp arrives.
+    pub boot_notify_confirm: bool,
+    /// Seconds a controller re-home (SET of the WebSocket MTP URL) may take
+    /// until the new controller answers before the agent falls back to the
+    /// previous controller.
+    pub rehome_timeout: u64,
+    /// Seconds the controller has to call `Device.X_OptimACS_Config.Confirm()`
+    /// after a SET that changed network, firewall or SSH config before it is
+    /// rolled back (0 = changes apply unconfirmed).
+    pub confirm_timeout: u64,
+    // ── Feature switches ──────────────────────────────────────────────────────
+    /// Allow the WPS push-button OPERATE (off by default: WPS is often
+    /// intentionally disabled for security).
+    pub wps_enabled: bool,
+    /// Allow the FactoryReset() OPERATE (off by default so a compromised
+    /// controller cannot wipe the fleet).
+    pub allow_factory_reset: bool,
+    /// Write TLS session secrets to `$SSLKEYLOGFILE` (NSS key log format) so
+    /// captures can be decrypted in Wireshark.  Debug only — anyone holding
+    /// the key log can read all USP traffic, including credentials.
+    pub tls_debug: bool,
+    /// Sign every USP Record with the device key (`mac_signature` +
+    /// `sender_cert`) and require valid signatures on received ones, for
+    /// brokers that are not trusted end to end.  See `usp::e2e`.
+    pub e2e_security: bool,
+    /// Open a SessionContext with the controller on the MQTT MTP (TR-181
+    /// `Controller.{i}.E2ESession.Enable`); off, records are sent as
+    /// NoSessionContext unless the controller opens a session.
+    pub e2e_session: bool,
+    /// Preferred TLS key-exchange groups, in order (e.g. `X25519MLKEM768`,
+    /// `X25519`).  Empty keeps the crypto provider's default list.
+    pub tls_kex_groups: Vec<String>,
+    /// Interfaces whose ARP neighbours count as local clients (WAN-side
+    /// entries such as the upstream router are excluded).
+    pub lan_interfaces: Vec<String>,
+    /// HTTP echo endpoint returning the caller's public IP (plain text or
+    /// JSON `{"ip": ...}`).  Empty disables the lookup: no external call is
+    /// made unless this is set.
+    pub public_ip_url: String,
 }
 
 impl Default for ClientConfig {
@@ -92,6 +215,7 @@ impl Default for ClientConfig {
             server_port: PORT,
             server_cn: "ac-server".to_string(),
             ca_file: PathBuf::from("/etc/apclient/ca.crt"),
+            extra_ca_files: Vec::new(),
             cert_file: PathBuf::from("/etc/apclient/client.crt"),
             key_file: PathBuf::from("/etc/apclient/client.key"),
             init_cert: PathBuf::from("/etc/apclient/init/client.crt"),
@@ -102,24 +226,81 @@ impl Default for ClientConfig {
             sys_model: String::new(),
             gnss_dev: String::new(),
             gnss_baud: 9600,
+            gnss_max_age: 0,
+            power_sysfs_path: String::new(),
             update_interval: UPDATE_INTERVAL,
             status_interval: STATUS_INTERVAL,
+            temp_alarm_c: 0,
             fw_dir: PathBuf::from("/tmp/firmware"),
+            min_fw_bytes: MIN_FW_BYTES,
+            max_fw_bytes: MAX_FW_BYTES,
+            dhcp_leases_file: PathBuf::from("/tmp/dhcp.leases"),
             pid_file: PathBuf::from("/var/run/apclient.pid"),
             daemonize: false,
             log_syslog: true,
+            syslog_retries: SYSLOG_RETRIES,
+            log_level_revert: LOG_LEVEL_REVERT,
             usp_endpoint_id: String::new(),
             controller_id: String::new(),
+            extra_controllers: Vec::new(),
             claim_token: String::new(),
             ws_url: None,
             mqtt_url: None,
+            mqtt_user: String::new(),
+            mqtt_pass: String::new(),
+            mqtt_version: MqttVersion::V311,
             mtp: MtpType::WebSocket,
+            offline_buffer_len: OFFLINE_BUFFER_LEN,
+            dm_op_timeout: DM_OP_TIMEOUT,
+            max_concurrent_ops: MAX_CONCURRENT_OPS,
+            self_test_interval: SELF_TEST_INTERVAL,
+            ws_ping_interval: WS_PING_INTERVAL,
+            self_test_failures: SELF_TEST_FAILURES,
+            boot_notify_confirm: true,
+            rehome_timeout: REHOME_TIMEOUT,
+            confirm_timeout: 0,
+            wps_enabled: false,
+            allow_factory_reset: false,
+            tls_debug: false,
+            e2e_security: false,
+            e2e_session: false,
+            tls_kex_groups: Vec::new(),
+            lan_interfaces: vec!["br-lan".to_string()],
+            public_ip_url: String::new(),
         }
     }
 }
 
-/// Parse `path` as an `ac_client.conf` key=value configuration file.
-pub fn load_config(path: &Path) -> Result<ClientConfig> {
+/// On-disk format of the `--config` file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConfigFormat {
+    /// `key = value` lines (`ac_client.conf`).
+    Flat,
+    /// TOML; the same keys, optionally grouped in `[section]` tables.
+    Toml,
+}
+
+impl ConfigFormat {
+    /// `explicit` if given, otherwise TOML for a `.toml` file and flat for
+    /// anything else.
+    pub fn detect(path: &Path, explicit: Option<ConfigFormat>) -> ConfigFormat {
+        match explicit {
+            Some(f) => f,
+            None if path.extension().is_some_and(|e| e == "toml") => ConfigFormat::Toml,
+            None => ConfigFormat::Flat,
+        }
+    }
+}
+
+/// Load `path` in the given (or detected) format.
+pub fn load_config_file(path: &Path, format: Option<ConfigFormat>) -> Result<ClientConfig> {
+    match ConfigFormat::detect(path, format) {
+        ConfigFormat::Flat => load_config(path),
+        ConfigFormat::Toml => load_config_toml(path),
+    }
+}
+
+fn read_config_file(path: &Path) -> Result<String> {
     info!("Loading configuration from: {}", path.display());
 
     let content = fs::read_to_string(path).map_err(|e| {
@@ -128,7 +309,16 @@ pub fn load_config(path: &Path) -> Result<ClientConfig> {
     })?;
 
     debug!("Configuration file loaded ({} bytes)", content.len());
+    Ok(content)
+}
+
+/// Parse `path` as an `ac_client.conf` key=value configuration file.
+pub fn load_config(path: &Path) -> Result<ClientConfig> {
+    let content = read_config_file(path)?;
+    Ok(parse_flat(&content))
+}
 
+fn parse_flat(content: &str) -> ClientConfig {
     let mut cfg = ClientConfig::default();
     let mut key_count = 0;
 
@@ -151,137 +341,551 @@ pub fn load_config(path: &Path) -> Result<ClientConfig> {
         }
 
         key_count += 1;
-        trace!("Config: {} = {}", key, val);
+        match apply_key(&mut cfg, &key, val) {
+            Ok(true) => {}
+            Ok(false) => trace!("Config: ignoring unknown key '{}'", key),
+            Err(e) => warn!("Config: {e}, keeping the default"),
+        }
+    }
 
-        match key.as_str() {
-            "server_host" => {
-                cfg.server_host = val.clone();
-                debug!("Config: server_host = {}", cfg.server_host);
-            }
-            "server_port" => {
-                cfg.server_port = val.parse().unwrap_or(PORT);
-                debug!("Config: server_port = {}", cfg.server_port);
-            }
-            "server_cn" => {
-                cfg.server_cn = val.clone();
-                debug!("Config: server_cn = {}", cfg.server_cn);
-            }
-            "ca_file" => {
-                cfg.ca_file = PathBuf::from(&val);
-                debug!("Config: ca_file = {}", cfg.ca_file.display());
-            }
-            "cert_file" => {
-                cfg.cert_file = PathBuf::from(&val);
-                debug!("Config: cert_file = {}", cfg.cert_file.display());
-            }
-            "key_file" => {
-                cfg.key_file = PathBuf::from(&val);
-                debug!("Config: key_file = {}", cfg.key_file.display());
-            }
-            "init_cert" => {
-                cfg.init_cert = PathBuf::from(&val);
-                debug!("Config: init_cert = {}", cfg.init_cert.display());
-            }
-            "init_key" => {
-                cfg.init_key = PathBuf::from(&val);
-                debug!("Config: init_key = {}", cfg.init_key.display());
-            }
-            "cert_dir" => {
-                cfg.cert_dir = PathBuf::from(&val);
-                debug!("Config: cert_dir = {}", cfg.cert_dir.display());
-            }
-            "mac_addr" => {
-                cfg.mac_addr = val.clone();
-                debug!("Config: mac_addr = {}", cfg.mac_addr);
-            }
-            "arch" => {
-                cfg.arch = val.clone();
-                debug!("Config: arch = {}", cfg.arch);
-            }
-            "sys_model" => {
-                cfg.sys_model = val.clone();
-                debug!("Config: sys_model = {}", cfg.sys_model);
-            }
-            "gnss_dev" => {
-                cfg.gnss_dev = val.clone();
-                debug!("Config: gnss_dev = {}", cfg.gnss_dev);
-            }
-            "gnss_baud" => {
-                cfg.gnss_baud = val.parse().unwrap_or(9600);
-                debug!("Config: gnss_baud = {}", cfg.gnss_baud);
-            }
-            "update_interval" => {
-                cfg.update_interval = val.parse().unwrap_or(UPDATE_INTERVAL);
-                debug!("Config: update_interval = {}", cfg.update_interval);
-            }
-            "status_interval" => {
-                cfg.status_interval = val.parse().unwrap_or(STATUS_INTERVAL);
-                debug!("Config: status_interval = {}", cfg.status_interval);
-            }
-            "fw_dir" => {
-                cfg.fw_dir = PathBuf::from(&val);
-                debug!("Config: fw_dir = {}", cfg.fw_dir.display());
-            }
-            "pid_file" => {
-                cfg.pid_file = PathBuf::from(&val);
-                debug!("Config: pid_file = {}", cfg.pid_file.display());
-            }
-            "daemonize" => {
-                cfg.daemonize = val == "true" || val == "1" || val == "yes";
-                debug!("Config: daemonize = {}", cfg.daemonize);
-            }
-            "log_syslog" => {
-                cfg.log_syslog = val == "true" || val == "1" || val == "yes";
-                debug!("Config: log_syslog = {}", cfg.log_syslog);
-            }
-            // USP / TR-369
-            "usp_endpoint_id" => {
-                cfg.usp_endpoint_id = val.clone();
-                debug!("Config: usp_endpoint_id = {}", cfg.usp_endpoint_id);
-            }
-            "controller_id" => {
-                cfg.controller_id = val.clone();
-                debug!("Config: controller_id = {}", cfg.controller_id);
-            }
-            "claim_token" => {
-                cfg.claim_token = val.clone();
-                debug!("Config: claim_token = {}", cfg.claim_token);
-            }
-            "ws_url" => {
-                cfg.ws_url = Some(val.clone());
-                debug!("Config: ws_url = {}", val);
-            }
-            "mqtt_url" => {
-                cfg.mqtt_url = Some(val.clone());
-                debug!("Config: mqtt_url = {}", val);
+    info!(
+        "Configuration loaded successfully ({} keys processed)",
+        key_count
+    );
+    cfg
+}
+
+/// Parse `path` as a TOML configuration file.
+///
+/// Keys are the flat-file keys.  They may sit at the top level or inside
+/// `[section]` tables of any name, which are for grouping only.  The nested
+/// MTP and TLS tables in [`TOML_NAMES`] take shorter names instead (e.g.
+/// `url` in `[mtp.mqtt]` for `mqtt_url`).  Arrays become comma-separated
+/// lists; an array of `ca_file`s trusts every one of them, and each
+/// `[[controller]]` table names one controller by `endpoint_id`, the first
+/// being `controller_id`.  Unlike the flat format, unknown keys and invalid
+/// values are rejected.
+pub fn load_config_toml(path: &Path) -> Result<ClientConfig> {
+    let content = read_config_file(path)?;
+    parse_toml(&content).map_err(|e| AcError::Config(format!("{}: {e}", path.display())))
+}
+
+/// Names in nested TOML tables: `(table, name, flat key)`.
+const TOML_NAMES: &[(&str, &str, &str)] = &[
+    ("mtp", "type", "mtp"),
+    ("mtp.websocket", "url", "ws_url"),
+    ("mtp.websocket", "ping_interval", "ws_ping_interval"),
+    ("mtp.mqtt", "url", "mqtt_url"),
+    ("mtp.mqtt", "user", "mqtt_user"),
+    ("mtp.mqtt", "pass", "mqtt_pass"),
+    ("mtp.mqtt", "version", "mqtt_version"),
+    ("tls", "kex_groups", "tls_kex_groups"),
+    ("tls", "debug", "tls_debug"),
+];
+
+/// Flat key for `name` in TOML table `table` (`""` for the top level).
+fn toml_key(table: &str, name: &str) -> String {
+    let name = name.to_ascii_lowercase();
+    TOML_NAMES
+        .iter()
+        .find(|(t, n, _)| *t == table && *n == name)
+        .map_or(name, |(_, _, key)| key.to_string())
+}
+
+fn parse_toml(content: &str) -> std::result::Result<ClientConfig, String> {
+    let table: toml::Table = content
+        .parse()
+        .map_err(|e: toml::de::Error| e.to_string())?;
+
+    let mut cfg = ClientConfig::default();
+    let mut key_count = 0;
+    apply_toml_table(&mut cfg, "", &table, &mut key_count)?;
+
+    info!(
+        "Configuration loaded successfully ({} keys processed)",
+        key_count
+    );
+    Ok(cfg)
+}
+
+fn apply_toml_table(
+    cfg: &mut ClientConfig,
+    path: &str,
+    table: &toml::Table,
+    key_count: &mut usize,
+) -> std::result::Result<(), String> {
+    for (name, value) in table {
+        let sub = match path {
+            "" => name.clone(),
+            _ => format!("{path}.{name}"),
+        };
+        let nested = path.is_empty() || TOML_NAMES.iter().any(|(t, _, _)| *t == sub);
+        match value {
+            toml::Value::Table(section) if nested => {
+                apply_toml_table(cfg, &sub, section, key_count)?;
             }
-            "mtp" => {
-                cfg.mtp = match val.to_ascii_lowercase().as_str() {
-                    "mqtt" => {
-                        debug!("Config: mtp = mqtt");
-                        MtpType::Mqtt
-                    }
-                    "both" => {
-                        debug!("Config: mtp = both");
-                        MtpType::Both
-                    }
-                    _ => {
-                        debug!("Config: mtp = websocket (default)");
-                        MtpType::WebSocket
-                    }
-                };
+            toml::Value::Array(items) if path.is_empty() && name == "controller" => {
+                apply_toml_controllers(cfg, items)?;
+                *key_count += 1;
             }
             _ => {
-                trace!("Config: ignoring unknown key '{}'", key);
+                let key = toml_key(path, name);
+                let applied = apply_toml_value(cfg, &key, value).map_err(|e| match path {
+                    "" => e,
+                    _ => format!("[{path}] {e}"),
+                })?;
+                if applied {
+                    *key_count += 1;
+                }
             }
         }
     }
+    Ok(())
+}
 
-    info!(
-        "Configuration loaded successfully ({} keys processed)",
-        key_count
+/// Apply one TOML value; `false` if it was empty and so skipped.
+fn apply_toml_value(
+    cfg: &mut ClientConfig,
+    key: &str,
+    value: &toml::Value,
+) -> std::result::Result<bool, String> {
+    let val = toml_scalar(value).ok_or_else(|| format!("{key}: unsupported value type"))?;
+    if val.is_empty() {
+        return Ok(false);
+    }
+    if let ("ca_file", toml::Value::Array(_)) = (key, value) {
+        // The first CA is the one provisioning replaces
+        let (first, rest) = val.split_once(',').unwrap_or((&val, ""));
+        cfg.ca_file = PathBuf::from(first);
+        cfg.extra_ca_files = parse_name_list(rest)
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        debug!(
+            "Config: ca_file = {}, extra_ca_files = {:?}",
+            cfg.ca_file.display(),
+            cfg.extra_ca_files
+        );
+        return Ok(true);
+    }
+    match apply_key(cfg, key, val)? {
+        true => Ok(true),
+        false => Err(format!("unknown key '{key}'")),
+    }
+}
+
+/// `[[controller]]` tables: the first is `controller_id`, the rest
+/// `extra_controllers`.
+fn apply_toml_controllers(
+    cfg: &mut ClientConfig,
+    items: &[toml::Value],
+) -> std::result::Result<(), String> {
+    let mut ids = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let table = match item {
+            toml::Value::Table(t) => t,
+            _ => return Err(format!("[[controller]] {i}: not a table")),
+        };
+        if let Some(name) = table.keys().find(|k| *k != "endpoint_id") {
+            return Err(format!("[[controller]] {i}: unknown key '{name}'"));
+        }
+        match table.get("endpoint_id").and_then(|v| v.as_str()) {
+            Some(id) if !id.trim().is_empty() => ids.push(id.trim().to_string()),
+            _ => return Err(format!("[[controller]] {i}: endpoint_id missing")),
+        }
+    }
+    let mut ids = ids.into_iter();
+    if let Some(primary) = ids.next() {
+        cfg.controller_id = primary;
+    }
+    cfg.extra_controllers = ids.collect();
+    debug!(
+        "Config: controller_id = {}, extra_controllers = {:?}",
+        cfg.controller_id, cfg.extra_controllers
     );
-    Ok(cfg)
+    Ok(())
+}
+
+/// Flat-file form of a TOML value; arrays are joined with commas.
+fn toml_scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(s) => Some(s.trim().to_string()),
+        toml::Value::Integer(i) => Some(i.to_string()),
+        toml::Value::Float(f) => Some(f.to_string()),
+        toml::Value::Boolean(b) => Some(b.to_string()),
+        toml::Value::Array(items) => {
+            let items: Option<Vec<String>> = items
+                .iter()
+                .map(|v| match v {
+                    toml::Value::Array(_) | toml::Value::Table(_) => None,
+                    v => toml_scalar(v),
+                })
+                .collect();
+            Some(items?.join(","))
+        }
+        toml::Value::Datetime(_) | toml::Value::Table(_) => None,
+    }
+}
+
+/// Set one configuration key; returns `false` if `key` is not recognised.
+fn apply_key(cfg: &mut ClientConfig, key: &str, val: String) -> std::result::Result<bool, String> {
+    // Each arm logs its own value, secrets as `<set>`
+    match key {
+        "server_host" => {
+            cfg.server_host = val.clone();
+            debug!("Config: server_host = {}", cfg.server_host);
+        }
+        "server_port" => {
+            cfg.server_port = parse_num(key, &val)?;
+            debug!("Config: server_port = {}", cfg.server_port);
+        }
+        "server_cn" => {
+            cfg.server_cn = val.clone();
+            debug!("Config: server_cn = {}", cfg.server_cn);
+        }
+        "ca_file" => {
+            cfg.ca_file = PathBuf::from(&val);
+            debug!("Config: ca_file = {}", cfg.ca_file.display());
+        }
+        "extra_ca_files" => {
+            cfg.extra_ca_files = parse_name_list(&val)
+                .into_iter()
+                .map(PathBuf::from)
+                .collect();
+            debug!("Config: extra_ca_files = {:?}", cfg.extra_ca_files);
+        }
+        "cert_file" => {
+            cfg.cert_file = PathBuf::from(&val);
+            debug!("Config: cert_file = {}", cfg.cert_file.display());
+        }
+        "key_file" => {
+            cfg.key_file = PathBuf::from(&val);
+            debug!("Config: key_file = {}", cfg.key_file.display());
+        }
+        "init_cert" => {
+            cfg.init_cert = PathBuf::from(&val);
+            debug!("Config: init_cert = {}", cfg.init_cert.display());
+        }
+        "init_key" => {
+            cfg.init_key = PathBuf::from(&val);
+            debug!("Config: init_key = {}", cfg.init_key.display());
+        }
+        "cert_dir" => {
+            cfg.cert_dir = PathBuf::from(&val);
+            debug!("Config: cert_dir = {}", cfg.cert_dir.display());
+        }
+        "mac_addr" => {
+            cfg.mac_addr = val.clone();
+            debug!("Config: mac_addr = {}", cfg.mac_addr);
+        }
+        "arch" => {
+            cfg.arch = val.clone();
+            debug!("Config: arch = {}", cfg.arch);
+        }
+        "sys_model" => {
+            cfg.sys_model = val.clone();
+            debug!("Config: sys_model = {}", cfg.sys_model);
+        }
+        "gnss_dev" => {
+            cfg.gnss_dev = val.clone();
+            debug!("Config: gnss_dev = {}", cfg.gnss_dev);
+        }
+        "gnss_baud" => {
+            cfg.gnss_baud = parse_num(key, &val)?;
+            debug!("Config: gnss_baud = {}", cfg.gnss_baud);
+        }
+        "gnss_max_age" => {
+            cfg.gnss_max_age = parse_num(key, &val)?;
+            debug!("Config: gnss_max_age = {}", cfg.gnss_max_age);
+        }
+        "power_sysfs_path" => {
+            cfg.power_sysfs_path = val.clone();
+            debug!("Config: power_sysfs_path = {}", cfg.power_sysfs_path);
+        }
+        "update_interval" => {
+            cfg.update_interval = parse_num(key, &val)?;
+            debug!("Config: update_interval = {}", cfg.update_interval);
+        }
+        "status_interval" => {
+            cfg.status_interval = parse_num(key, &val)?;
+            debug!("Config: status_interval = {}", cfg.status_interval);
+        }
+        "temp_alarm_c" => {
+            cfg.temp_alarm_c = parse_num(key, &val)?;
+            debug!("Config: temp_alarm_c = {}", cfg.temp_alarm_c);
+        }
+        "fw_dir" => {
+            cfg.fw_dir = PathBuf::from(&val);
+            debug!("Config: fw_dir = {}", cfg.fw_dir.display());
+        }
+        "min_fw_bytes" => {
+            cfg.min_fw_bytes = parse_num(key, &val)?;
+            debug!("Config: min_fw_bytes = {}", cfg.min_fw_bytes);
+        }
+        "max_fw_bytes" => {
+            cfg.max_fw_bytes = parse_num(key, &val)?;
+            debug!("Config: max_fw_bytes = {}", cfg.max_fw_bytes);
+        }
+        "dhcp_leases_file" => {
+            cfg.dhcp_leases_file = PathBuf::from(&val);
+            debug!(
+                "Config: dhcp_leases_file = {}",
+                cfg.dhcp_leases_file.display()
+            );
+        }
+        "pid_file" => {
+            cfg.pid_file = PathBuf::from(&val);
+            debug!("Config: pid_file = {}", cfg.pid_file.display());
+        }
+        "daemonize" => {
+            cfg.daemonize = parse_bool(key, &val)?;
+            debug!("Config: daemonize = {}", cfg.daemonize);
+        }
+        "log_syslog" => {
+            cfg.log_syslog = parse_bool(key, &val)?;
+            debug!("Config: log_syslog = {}", cfg.log_syslog);
+        }
+        "syslog_retries" => {
+            cfg.syslog_retries = parse_num(key, &val)?;
+            debug!("Config: syslog_retries = {}", cfg.syslog_retries);
+        }
+        "log_level_revert" => {
+            cfg.log_level_revert = parse_num(key, &val)?;
+            debug!("Config: log_level_revert = {}", cfg.log_level_revert);
+        }
+        // USP / TR-369
+        "usp_endpoint_id" => {
+            cfg.usp_endpoint_id = val.clone();
+            debug!("Config: usp_endpoint_id = {}", cfg.usp_endpoint_id);
+        }
+        "controller_id" => {
+            cfg.controller_id = val.clone();
+            debug!("Config: controller_id = {}", cfg.controller_id);
+        }
+        "extra_controllers" => {
+            cfg.extra_controllers = parse_name_list(&val);
+            debug!("Config: extra_controllers = {:?}", cfg.extra_controllers);
+        }
+        "claim_token" => {
+            cfg.claim_token = val.clone();
+            debug!("Config: claim_token = {}", cfg.claim_token);
+        }
+        "ws_url" => {
+            cfg.ws_url = Some(val.clone());
+            debug!("Config: ws_url = {}", val);
+        }
+        "mqtt_url" => {
+            cfg.mqtt_url = Some(val.clone());
+            debug!("Config: mqtt_url = {}", val);
+        }
+        "mqtt_user" => {
+            cfg.mqtt_user = val.clone();
+            debug!("Config: mqtt_user = {}", val);
+        }
+        "mqtt_pass" => {
+            cfg.mqtt_pass = val.clone();
+            debug!("Config: mqtt_pass = <set>");
+        }
+        "mqtt_version" => {
+            cfg.mqtt_version = parse_mqtt_version(&val);
+            debug!("Config: mqtt_version = {:?}", cfg.mqtt_version);
+        }
+        "mtp" => {
+            cfg.mtp = match val.to_ascii_lowercase().as_str() {
+                "mqtt" => {
+                    debug!("Config: mtp = mqtt");
+                    MtpType::Mqtt
+                }
+                "both" => {
+                    debug!("Config: mtp = both");
+                    MtpType::Both
+                }
+                "websocket" => {
+                    debug!("Config: mtp = websocket");
+                    MtpType::WebSocket
+                }
+                _ => return Err(format!("invalid value '{val}' for {key}")),
+            };
+        }
+        "offline_buffer_len" => {
+            cfg.offline_buffer_len = parse_num(key, &val)?;
+            debug!("Config: offline_buffer_len = {}", cfg.offline_buffer_len);
+        }
+        "dm_op_timeout" => {
+            cfg.dm_op_timeout = parse_num(key, &val)?;
+            debug!("Config: dm_op_timeout = {}", cfg.dm_op_timeout);
+        }
+        "max_concurrent_ops" => {
+            cfg.max_concurrent_ops = parse_num(key, &val)?;
+            debug!("Config: max_concurrent_ops = {}", cfg.max_concurrent_ops);
+        }
+        "self_test_interval" => {
+            cfg.self_test_interval = parse_num(key, &val)?;
+            debug!("Config: self_test_interval = {}", cfg.self_test_interval);
+        }
+        "ws_ping_interval" => {
+            cfg.ws_ping_interval = parse_num(key, &val)?;
+            debug!("Config: ws_ping_interval = {}", cfg.ws_ping_interval);
+        }
+        "self_test_failures" => {
+            cfg.self_test_failures = parse_num(key, &val)?;
+            debug!("Config: self_test_failures = {}", cfg.self_test_failures);
+        }
+        "boot_notify_confirm" => {
+            cfg.boot_notify_confirm = parse_bool(key, &val)?;
+            debug!("Config: boot_notify_confirm = {}", cfg.boot_notify_confirm);
+        }
+        "rehome_timeout" => {
+            cfg.rehome_timeout = parse_num(key, &val)?;
+            debug!("Config: rehome_timeout = {}", cfg.rehome_timeout);
+        }
+        "confirm_timeout" => {
+            cfg.confirm_timeout = parse_num(key, &val)?;
+            debug!("Config: confirm_timeout = {}", cfg.confirm_timeout);
+        }
+        "wps_enabled" => {
+            cfg.wps_enabled = parse_bool(key, &val)?;
+            debug!("Config: wps_enabled = {}", cfg.wps_enabled);
+        }
+        "allow_factory_reset" => {
+            cfg.allow_factory_reset = parse_bool(key, &val)?;
+            debug!("Config: allow_factory_reset = {}", cfg.allow_factory_reset);
+        }
+        "tls_debug" => {
+            cfg.tls_debug = parse_bool(key, &val)?;
+            debug!("Config: tls_debug = {}", cfg.tls_debug);
+        }
+        "e2e_security" => {
+            cfg.e2e_security = parse_bool(key, &val)?;
+            debug!("Config: e2e_security = {}", cfg.e2e_security);
+        }
+        "e2e_session" => {
+            cfg.e2e_session = parse_bool(key, &val)?;
+            debug!("Config: e2e_session = {}", cfg.e2e_session);
+        }
+        "tls_kex_groups" => {
+            cfg.tls_kex_groups = parse_name_list(&val);
+            debug!("Config: tls_kex_groups = {:?}", cfg.tls_kex_groups);
+        }
+        "lan_interfaces" => {
+            cfg.lan_interfaces = parse_name_list(&val);
+            debug!("Config: lan_interfaces = {:?}", cfg.lan_interfaces);
+        }
+        "public_ip_url" => {
+            cfg.public_ip_url = val.clone();
+            debug!("Config: public_ip_url = {}", cfg.public_ip_url);
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn parse_num<T: std::str::FromStr>(key: &str, val: &str) -> std::result::Result<T, String> {
+    val.parse()
+        .map_err(|_| format!("invalid value '{val}' for {key}"))
+}
+
+fn parse_bool(key: &str, val: &str) -> std::result::Result<bool, String> {
+    match val {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        _ => Err(format!("invalid value '{val}' for {key}")),
+    }
+}
+
+/// Split a comma- or space-separated list (`uci get` prints list options
+/// space-separated).
+fn parse_name_list(val: &str) -> Vec<String> {
+    val.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// ── Runtime persistence ───────────────────────────────────────────────────────
+
+/// Where the running configuration was loaded from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigSource {
+    /// `optimacs.agent` in UCI (`--uci`).
+    Uci,
+    /// A `--config` file in the given format.
+    File(PathBuf, ConfigFormat),
+}
+
+static SOURCE: OnceLock<ConfigSource> = OnceLock::new();
+
+/// Record where the configuration came from, for [`persist`].
+pub fn set_source(source: ConfigSource) {
+    let _ = SOURCE.set(source);
+}
+
+/// Save one option changed at runtime where the configuration came from, so
+/// it survives a restart: `optimacs.agent.<key>` in UCI, or the key's line
+/// in the config file (appended when absent).
+pub fn persist(key: &str, value: &str) -> std::result::Result<(), String> {
+    match SOURCE.get() {
+        Some(ConfigSource::Uci) => {
+            use crate::usp::tp469::uci_backend::{uci_commit, uci_set};
+            uci_set(&format!("optimacs.agent.{key}"), value)?;
+            uci_commit("optimacs")
+        }
+        Some(ConfigSource::File(path, format)) => {
+            let content = fs::read_to_string(path)
+                .map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+            let updated = with_key(&content, key, value, *format);
+            let tmp = path.with_extension("tmp");
+            fs::write(&tmp, updated)
+                .and_then(|_| fs::rename(&tmp, path))
+                .map_err(|e| format!("cannot write {}: {e}", path.display()))
+        }
+        None => Err("configuration source unknown".into()),
+    }
+}
+
+/// `content` with `key` set to `value`: its first uncommented line is
+/// replaced (in TOML, under whatever name its table gives the key), or a
+/// new line added — at the end of a flat file, before the first table of a
+/// TOML one.
+fn with_key(content: &str, key: &str, value: &str, format: ConfigFormat) -> String {
+    let toml_value = toml::Value::String(value.to_string());
+    let mut table = String::new();
+    let mut first_table = None;
+    let mut out = Vec::new();
+    let mut replaced = false;
+    for l in content.lines() {
+        let t = l.trim();
+        if format == ConfigFormat::Toml && t.starts_with('[') {
+            table = t.trim_matches(|c| c == '[' || c == ']').trim().to_string();
+            first_table.get_or_insert(out.len());
+        }
+        let name = match t.split_once('=') {
+            Some((name, _)) if !replaced && !t.starts_with('#') => name.trim(),
+            _ => {
+                out.push(l.to_string());
+                continue;
+            }
+        };
+        match format {
+            ConfigFormat::Flat if name.eq_ignore_ascii_case(key) => {
+                out.push(format!("{key} = {value}"));
+                replaced = true;
+            }
+            ConfigFormat::Toml if toml_key(&table, name) == key && table != "controller" => {
+                out.push(format!("{name} = {toml_value}"));
+                replaced = true;
+            }
+            _ => out.push(l.to_string()),
+        }
+    }
+    if !replaced {
+        match (format, first_table) {
+            (ConfigFormat::Toml, Some(at)) => out.insert(at, format!("{key} = {toml_value}")),
+            (ConfigFormat::Toml, None) => out.push(format!("{key} = {toml_value}")),
+            (ConfigFormat::Flat, _) => out.push(format!("{key} = {value}")),
+        }
+    }
+    out.join("\n") + "\n"
 }
 
 // ── UCI loader ────────────────────────────────────────────────────────────────
@@ -346,6 +950,9 @@ pub fn load_config_uci() -> Result<ClientConfig> {
     if let Some(v) = uci_get_str("ca_file") {
         cfg.ca_file = PathBuf::from(v);
     }
+    if let Some(v) = uci_get_str("extra_ca_files") {
+        cfg.extra_ca_files = parse_name_list(&v).into_iter().map(PathBuf::from).collect();
+    }
     if let Some(v) = uci_get_str("init_cert") {
         cfg.init_cert = PathBuf::from(v);
     }
@@ -376,27 +983,54 @@ pub fn load_config_uci() -> Result<ClientConfig> {
     if let Some(v) = uci_get_str("gnss_baud") {
         cfg.gnss_baud = v.parse().unwrap_or(9600);
     }
+    if let Some(v) = uci_get_str("gnss_max_age") {
+        cfg.gnss_max_age = v.parse().unwrap_or(0);
+    }
+    if let Some(v) = uci_get_str("power_sysfs_path") {
+        cfg.power_sysfs_path = v;
+    }
     if let Some(v) = uci_get_str("update_interval") {
         cfg.update_interval = v.parse().unwrap_or(UPDATE_INTERVAL);
     }
     if let Some(v) = uci_get_str("status_interval") {
         cfg.status_interval = v.parse().unwrap_or(STATUS_INTERVAL);
     }
+    if let Some(v) = uci_get_str("temp_alarm_c") {
+        cfg.temp_alarm_c = v.parse().unwrap_or(0);
+    }
     if let Some(v) = uci_get_str("fw_dir") {
         cfg.fw_dir = PathBuf::from(v);
     }
+    if let Some(v) = uci_get_str("min_fw_bytes") {
+        cfg.min_fw_bytes = v.parse().unwrap_or(MIN_FW_BYTES);
+    }
+    if let Some(v) = uci_get_str("max_fw_bytes") {
+        cfg.max_fw_bytes = v.parse().unwrap_or(MAX_FW_BYTES);
+    }
+    if let Some(v) = uci_get_str("dhcp_leases_file") {
+        cfg.dhcp_leases_file = PathBuf::from(v);
+    }
     if let Some(v) = uci_get_str("pid_file") {
         cfg.pid_file = PathBuf::from(v);
     }
     if let Some(v) = uci_get_str("log_syslog") {
         cfg.log_syslog = v == "1" || v == "true" || v == "yes";
     }
+    if let Some(v) = uci_get_str("syslog_retries") {
+        cfg.syslog_retries = v.parse().unwrap_or(SYSLOG_RETRIES);
+    }
+    if let Some(v) = uci_get_str("log_level_revert") {
+        cfg.log_level_revert = v.parse().unwrap_or(LOG_LEVEL_REVERT);
+    }
     if let Some(v) = uci_get_str("usp_endpoint_id") {
         cfg.usp_endpoint_id = v;
     }
     if let Some(v) = uci_get_str("controller_id") {
         cfg.controller_id = v;
     }
+    if let Some(v) = uci_get_str("extra_controllers") {
+        cfg.extra_controllers = parse_name_list(&v);
+    }
     if let Some(v) = uci_get_str("claim_token") {
         cfg.claim_token = v;
     }
@@ -406,6 +1040,15 @@ pub fn load_config_uci() -> Result<ClientConfig> {
     if let Some(v) = uci_get_str("mqtt_url") {
         cfg.mqtt_url = Some(v);
     }
+    if let Some(v) = uci_get_str("mqtt_user") {
+        cfg.mqtt_user = v;
+    }
+    if let Some(v) = uci_get_str("mqtt_pass") {
+        cfg.mqtt_pass = v;
+    }
+    if let Some(v) = uci_get_str("mqtt_version") {
+        cfg.mqtt_version = parse_mqtt_version(&v);
+    }
     if let Some(v) = uci_get_str("mtp") {
         cfg.mtp = match v.to_ascii_lowercase().as_str() {
             "mqtt" => MtpType::Mqtt,
@@ -413,6 +1056,57 @@ pub fn load_config_uci() -> Result<ClientConfig> {
             _ => MtpType::WebSocket,
         };
     }
+    if let Some(v) = uci_get_str("offline_buffer_len") {
+        cfg.offline_buffer_len = v.parse().unwrap_or(OFFLINE_BUFFER_LEN);
+    }
+    if let Some(v) = uci_get_str("dm_op_timeout") {
+        cfg.dm_op_timeout = v.parse().unwrap_or(DM_OP_TIMEOUT);
+    }
+    if let Some(v) = uci_get_str("max_concurrent_ops") {
+        cfg.max_concurrent_ops = v.parse().unwrap_or(MAX_CONCURRENT_OPS);
+    }
+    if let Some(v) = uci_get_str("self_test_interval") {
+        cfg.self_test_interval = v.parse().unwrap_or(SELF_TEST_INTERVAL);
+    }
+    if let Some(v) = uci_get_str("ws_ping_interval") {
+        cfg.ws_ping_interval = v.parse().unwrap_or(WS_PING_INTERVAL);
+    }
+    if let Some(v) = uci_get_str("self_test_failures") {
+        cfg.self_test_failures = v.parse().unwrap_or(SELF_TEST_FAILURES);
+    }
+    if let Some(v) = uci_get_str("boot_notify_confirm") {
+        cfg.boot_notify_confirm = v == "1" || v == "true" || v == "yes";
+    }
+    if let Some(v) = uci_get_str("rehome_timeout") {
+        cfg.rehome_timeout = v.parse().unwrap_or(REHOME_TIMEOUT);
+    }
+    if let Some(v) = uci_get_str("confirm_timeout") {
+        cfg.confirm_timeout = v.parse().unwrap_or(0);
+    }
+    if let Some(v) = uci_get_str("wps_enabled") {
+        cfg.wps_enabled = v == "1" || v == "true" || v == "yes";
+    }
+    if let Some(v) = uci_get_str("allow_factory_reset") {
+        cfg.allow_factory_reset = v == "1" || v == "true" || v == "yes";
+    }
+    if let Some(v) = uci_get_str("tls_debug") {
+        cfg.tls_debug = v == "1" || v == "true" || v == "yes";
+    }
+    if let Some(v) = uci_get_str("e2e_security") {
+        cfg.e2e_security = v == "1" || v == "true" || v == "yes";
+    }
+    if let Some(v) = uci_get_str("e2e_session") {
+        cfg.e2e_session = v == "1" || v == "true" || v == "yes";
+    }
+    if let Some(v) = uci_get_str("tls_kex_groups") {
+        cfg.tls_kex_groups = parse_name_list(&v);
+    }
+    if let Some(v) = uci_get_str("lan_interfaces") {
+        cfg.lan_interfaces = parse_name_list(&v);
+    }
+    if let Some(v) = uci_get_str("public_ip_url") {
+        cfg.public_ip_url = v;
+    }
 
     Ok(cfg)
 }
@@ -439,12 +1133,305 @@ pub fn validate_config(cfg: &ClientConfig) -> Result<()> {
                     "ws_url (or server_host) is required for WebSocket MTP".into(),
                 ));
             }
+            check_tls_files(cfg)?;
         }
-        MtpType::Mqtt => {
-            if cfg.mqtt_url.is_none() {
+        MtpType::Mqtt => match cfg.mqtt_url.as_deref() {
+            None => {
                 return Err(AcError::Config("mqtt_url is required for MQTT MTP".into()));
             }
-        }
+            Some(url) if url.starts_with("mqtts://") => check_tls_files(cfg)?,
+            Some(_) => {}
+        },
+    }
+    Ok(())
+}
+
+/// Make sure the CA and the client identity the WebSocket MTP (or the MQTT
+/// MTP over `mqtts://`) will present are readable and parse, so a bad path
+/// fails at startup instead of as an opaque handshake error.
+fn check_tls_files(cfg: &ClientConfig) -> Result<()> {
+    let read = |what: &str, path: &Path| {
+        fs::read(path).map_err(|e| AcError::Config(format!("{what} {}: {e}", path.display())))
+    };
+
+    let ca_pem = read("ca_file", &cfg.ca_file)?;
+    let ca_count = rustls_pemfile::certs(&mut ca_pem.as_slice())
+        .filter(|c| c.is_ok())
+        .count();
+    if ca_count == 0 {
+        return Err(AcError::Config(format!(
+            "ca_file {}: no PEM certificate found",
+            cfg.ca_file.display()
+        )));
+    }
+
+    let (cert_file, key_file) = crate::tls::client_identity(cfg);
+    let cert_pem = read("client certificate", cert_file)?;
+    if rustls_pemfile::certs(&mut cert_pem.as_slice())
+        .next()
+        .map_or(true, |c| c.is_err())
+    {
+        return Err(AcError::Config(format!(
+            "client certificate {}: no PEM certificate found",
+            cert_file.display()
+        )));
+    }
+    let key_pem = read("client key", key_file)?;
+    if !matches!(
+        rustls_pemfile::private_key(&mut key_pem.as_slice()),
+        Ok(Some(_))
+    ) {
+        return Err(AcError::Config(format!(
+            "client key {}: no PEM private key found",
+            key_file.display()
+        )));
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtime_change_rewrites_config_line() {
+        let flat = "# ws_url = wss://example/usp\nws_url = wss://old:3491/usp\nmtp = websocket\n";
+        assert_eq!(
+            with_key(flat, "ws_url", "wss://new:3491/usp", ConfigFormat::Flat),
+            "# ws_url = wss://example/usp\nws_url = wss://new:3491/usp\nmtp = websocket\n"
+        );
+        assert_eq!(
+            with_key(
+                "mtp = websocket",
+                "ws_url",
+                "wss://new/usp",
+                ConfigFormat::Flat
+            ),
+            "mtp = websocket\nws_url = wss://new/usp\n"
+        );
+
+        let toml = "[mtp]\nws_url = \"wss://old/usp\"\n";
+        let updated = with_key(toml, "ws_url", "wss://new/usp", ConfigFormat::Toml);
+        assert_eq!(updated, "[mtp]\nws_url = \"wss://new/usp\"\n");
+    }
+
+    fn init_file(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("package/ac-client/files/init")
+            .join(name)
+    }
+
+    fn ws_config(ca_file: PathBuf) -> ClientConfig {
+        ClientConfig {
+            controller_id: "ac-server".into(),
+            server_host: "controller.example.com".into(),
+            ca_file,
+            cert_file: PathBuf::from("/nonexistent/client.crt"),
+            init_cert: init_file("client.crt"),
+            init_key: init_file("client.key"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn toml_config_matches_flat_file() {
+        let flat = "\
+server_host = controller.example.com
+server_port = 3491
+ws_url = wss://controller.example.com:3491/usp
+mtp = both
+mqtt_url = mqtt://broker.example.com:1883
+controller_id = ac-server
+ca_file = /etc/apclient/ca.crt
+tls_kex_groups = X25519MLKEM768,X25519
+lan_interfaces = br-lan br-guest
+gnss_dev = /dev/ttyUSB0
+gnss_baud = 4800
+wps_enabled = yes
+status_interval = 120
+";
+        let toml = r#"
+controller_id = "ac-server"
+server_host = "controller.example.com"
+server_port = 3491
+
+[mtp]
+mtp = "both"
+ws_url = "wss://controller.example.com:3491/usp"
+mqtt_url = "mqtt://broker.example.com:1883"
+
+[tls]
+ca_file = "/etc/apclient/ca.crt"
+tls_kex_groups = ["X25519MLKEM768", "X25519"]
+
+[agent]
+lan_interfaces = ["br-lan", "br-guest"]
+gnss_dev = "/dev/ttyUSB0"
+gnss_baud = 4800
+wps_enabled = true
+status_interval = 120
+"#;
+        let from_flat = parse_flat(flat);
+        assert_eq!(parse_toml(toml).unwrap(), from_flat);
+        assert_eq!(from_flat.mtp, MtpType::Both);
+        assert_eq!(from_flat.lan_interfaces, vec!["br-lan", "br-guest"]);
+
+        // Typos and nested structures are rejected rather than ignored
+        let err = parse_toml("[tls]\nca_flie = \"/x\"").unwrap_err();
+        assert!(err.contains("ca_flie"), "{err}");
+        assert!(parse_toml("[a.b]\nmtp = \"mqtt\"").is_err());
+
+        // Values are checked, not replaced by the default
+        let err = parse_toml("server_port = 99999").unwrap_err();
+        assert!(err.contains("server_port"), "{err}");
+        let err = parse_toml("[mtp]\ntype = \"carrier-pigeon\"").unwrap_err();
+        assert!(err.contains("[mtp]") && err.contains("mtp"), "{err}");
+        assert!(parse_toml("[agent]\nwps_enabled = \"maybe\"").is_err());
+
+        assert_eq!(
+            ConfigFormat::detect(Path::new("/etc/apclient/ac_client.toml"), None),
+            ConfigFormat::Toml
+        );
+        assert_eq!(
+            ConfigFormat::detect(Path::new("/etc/apclient/ac_client.conf"), None),
+            ConfigFormat::Flat
+        );
+        assert_eq!(
+            ConfigFormat::detect(
+                Path::new("/etc/apclient/ac_client.conf"),
+                Some(ConfigFormat::Toml)
+            ),
+            ConfigFormat::Toml
+        );
+    }
+
+    #[test]
+    fn toml_nested_sections_and_lists() {
+        let toml = r#"
+[[controller]]
+endpoint_id = "ac-server"
+
+[[controller]]
+endpoint_id = "ac-backup"
+
+[mtp]
+type = "both"
+
+[mtp.websocket]
+url = "wss://controller.example.com:3491/usp"
+ping_interval = 15
+
+[mtp.mqtt]
+url = "mqtts://broker.example.com:8883"
+user = "ap"
+version = "5"
+
+[tls]
+ca_file = ["/etc/apclient/ca.crt", "/etc/apclient/backup-ca.crt"]
+kex_groups = ["X25519"]
+"#;
+        let cfg = parse_toml(toml).unwrap();
+        assert_eq!(cfg.controller_id, "ac-server");
+        assert_eq!(cfg.extra_controllers, vec!["ac-backup"]);
+        assert_eq!(cfg.mtp, MtpType::Both);
+        assert_eq!(
+            cfg.ws_url.as_deref(),
+            Some("wss://controller.example.com:3491/usp")
+        );
+        assert_eq!(cfg.ws_ping_interval, 15);
+        assert_eq!(
+            cfg.mqtt_url.as_deref(),
+            Some("mqtts://broker.example.com:8883")
+        );
+        assert_eq!(cfg.mqtt_user, "ap");
+        assert_eq!(cfg.mqtt_version, MqttVersion::V5);
+        assert_eq!(cfg.ca_file, PathBuf::from("/etc/apclient/ca.crt"));
+        assert_eq!(
+            cfg.extra_ca_files,
+            vec![PathBuf::from("/etc/apclient/backup-ca.crt")]
+        );
+        assert_eq!(cfg.tls_kex_groups, vec!["X25519"]);
+
+        // The flat file spells the same configuration with plain keys
+        let flat = "\
+controller_id = ac-server
+extra_controllers = ac-backup
+mtp = both
+ws_url = wss://controller.example.com:3491/usp
+ws_ping_interval = 15
+mqtt_url = mqtts://broker.example.com:8883
+mqtt_user = ap
+mqtt_version = 5
+ca_file = /etc/apclient/ca.crt
+extra_ca_files = /etc/apclient/backup-ca.crt
+tls_kex_groups = X25519
+";
+        assert_eq!(parse_flat(flat), cfg);
+
+        assert!(parse_toml("[[controller]]\nendpoint_id = \"a\"\nurl = \"x\"").is_err());
+        assert!(parse_toml("[[controller]]\nname = \"a\"").is_err());
+
+        // A runtime change lands on the key's nested name
+        let updated = with_key(toml, "ws_url", "wss://new/usp", ConfigFormat::Toml);
+        assert!(updated.contains("[mtp.websocket]\nurl = \"wss://new/usp\""));
+        let updated = with_key(
+            "[[controller]]\nendpoint_id = \"a\"\n",
+            "ws_url",
+            "wss://n/usp",
+            ConfigFormat::Toml,
+        );
+        assert_eq!(
+            updated,
+            "ws_url = \"wss://n/usp\"\n[[controller]]\nendpoint_id = \"a\"\n"
+        );
+        parse_toml(&updated).unwrap();
+    }
+
+    #[test]
+    fn mqtt_version_defaults_to_311() {
+        assert_eq!(parse_mqtt_version("5"), MqttVersion::V5);
+        assert_eq!(parse_mqtt_version("5.0"), MqttVersion::V5);
+        assert_eq!(parse_mqtt_version("3.1.1"), MqttVersion::V311);
+        assert_eq!(parse_mqtt_version("bogus"), MqttVersion::V311);
+    }
+
+    #[test]
+    fn valid_tls_files_pass() {
+        validate_config(&ws_config(init_file("ca.crt"))).unwrap();
+    }
+
+    #[test]
+    fn missing_ca_file_rejected() {
+        let err = validate_config(&ws_config(PathBuf::from("/nonexistent/ca.crt")))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("ca_file /nonexistent/ca.crt"), "{err}");
+    }
+
+    #[test]
+    fn mqtts_checks_tls_files() {
+        let mqtt = |url: &str| ClientConfig {
+            mtp: MtpType::Mqtt,
+            mqtt_url: Some(url.into()),
+            ..ws_config(PathBuf::from("/nonexistent/ca.crt"))
+        };
+        let err = validate_config(&mqtt("mqtts://broker:8883"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("ca_file /nonexistent/ca.crt"), "{err}");
+        // Plaintext MQTT presents no certificates
+        validate_config(&mqtt("mqtt://broker:1883")).unwrap();
+    }
+
+    #[test]
+    fn malformed_ca_file_rejected() {
+        let path = std::env::temp_dir().join(format!("ac-client-bad-ca-{}", std::process::id()));
+        fs::write(&path, "this is not a certificate\n").unwrap();
+        let err = validate_config(&ws_config(path.clone()))
+            .unwrap_err()
+            .to_string();
+        fs::remove_file(&path).unwrap();
+        assert!(err.contains("no PEM certificate"), "{err}");
+        assert!(err.contains(&path.display().to_string()), "{err}");
+    }
+}
@@ -1,18 +1,26 @@
-//! GNSS/GPS receiver via serial port.
+//! GNSS/GPS receiver via serial port or gpsd.
 //!
 //! Reads NMEA 0183 sentences from a serial device (e.g. `/dev/ttyUSB0`),
 //! parses GPRMC and GPGGA sentences, and stores the latest position in a
-//! shared `Arc<Mutex<Option<GnssPosition>>>`.
+//! shared `Arc<Mutex<Option<GnssPosition>>>`.  When `gnss_dev` names a gpsd
+//! instance instead (`tcp://host:port`), the reader connects to it, asks for
+//! JSON reports and takes fixes from TPV objects, or from NMEA sentences if
+//! gpsd passes them through.
 //!
 //! The reader runs in a `spawn_blocking` task so it never blocks the async
-//! runtime.  If the device is absent the reader exits silently and position
-//! stays `None`.
+//! runtime.  If the device is absent, or goes away (a USB receiver being
+//! unplugged or re-enumerated, gpsd restarting), the reader keeps trying to
+//! reopen it, backing off from 2 s to 30 s; position keeps the last fix
+//! meanwhile.  It stops at the next line or retry once shutdown is
+//! requested.
 
 #![allow(clippy::all)]
 
 use std::fs;
-use std::io::{self, BufRead, BufReader};
-use std::sync::{Arc, Mutex};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 use log::{debug, warn};
 use nix::sys::termios::{
@@ -20,22 +28,224 @@ use nix::sys::termios::{
 };
 
 /// Latest GNSS position fix.
+///
+/// Altitude, HDOP, satellite count and fix quality only come from GGA; a
+/// fix parsed from RMC leaves them `None`/0.  Speed and heading only come
+/// from RMC.  The stored position merges both (see [`GnssPosition::merge`]).
 #[derive(Debug, Clone)]
 pub struct GnssPosition {
     pub latitude: String,
     pub longitude: String,
+    /// Metres above mean sea level.
+    pub altitude: Option<f64>,
+    /// Horizontal dilution of precision.
+    pub hdop: Option<f64>,
+    /// Satellites used in the fix.
+    pub satellites: Option<u8>,
+    /// GGA fix quality (1 = GPS, 2 = DGPS, 4 = RTK fixed, ...); 0 if unknown.
+    pub fix_quality: u8,
+    /// Speed over ground.
+    pub speed_kmh: Option<f64>,
+    /// Course over ground, degrees true.
+    pub heading_deg: Option<f64>,
+    /// When the reader took this fix.
+    pub fixed_at: Instant,
 }
 
-/// Spawns a background serial reader.  Position is updated in-place.
-/// Returns a handle to the shared position state.
+impl Default for GnssPosition {
+    fn default() -> Self {
+        Self {
+            latitude: String::new(),
+            longitude: String::new(),
+            altitude: None,
+            hdop: None,
+            satellites: None,
+            fix_quality: 0,
+            speed_kmh: None,
+            heading_deg: None,
+            fixed_at: Instant::now(),
+        }
+    }
+}
+
+/// km/h per knot.
+const KMH_PER_KNOT: f64 = 1.852;
+
+impl GnssPosition {
+    /// Combined `"<lat>,<lon>"` form.
+    pub fn combined(&self) -> String {
+        format!("{},{}", self.latitude, self.longitude)
+    }
+
+    /// Time since the fix was taken.
+    pub fn age(&self) -> Duration {
+        self.fixed_at.elapsed()
+    }
+
+    /// Older than `max_age` seconds; never with `max_age` 0.
+    pub fn is_stale(&self, max_age: u64) -> bool {
+        max_age > 0 && self.age() > Duration::from_secs(max_age)
+    }
+
+    /// Fold a newer report into this position: coordinates and fix time
+    /// come from `newer`, every other field only if `newer` carries it, so
+    /// the latest GGA and the latest RMC both contribute.
+    fn merge(&mut self, newer: GnssPosition) {
+        self.altitude = newer.altitude.or(self.altitude);
+        self.hdop = newer.hdop.or(self.hdop);
+        self.satellites = newer.satellites.or(self.satellites);
+        if newer.fix_quality > 0 {
+            self.fix_quality = newer.fix_quality;
+        }
+        self.speed_kmh = newer.speed_kmh.or(self.speed_kmh);
+        self.heading_deg = newer.heading_deg.or(self.heading_deg);
+        self.latitude = newer.latitude;
+        self.longitude = newer.longitude;
+        self.fixed_at = newer.fixed_at;
+    }
+}
+
+/// Device.DeviceInfo position parameters (name suffix, value) taken from one
+/// snapshot, so `X_OptimACS_Position` always matches the individual values.
+/// All three are empty when there is no fix.
+pub fn position_params(pos: Option<&GnssPosition>) -> [(&'static str, String); 3] {
+    [
+        (
+            "X_OptimACS_Latitude",
+            pos.map(|p| p.latitude.clone()).unwrap_or_default(),
+        ),
+        (
+            "X_OptimACS_Longitude",
+            pos.map(|p| p.longitude.clone()).unwrap_or_default(),
+        ),
+        (
+            "X_OptimACS_Position",
+            pos.map(GnssPosition::combined).unwrap_or_default(),
+        ),
+    ]
+}
+
+/// Position state registered by `spawn_gnss_reader`, for the data model.
+static SHARED_POSITION: OnceLock<Arc<Mutex<Option<GnssPosition>>>> = OnceLock::new();
+
+/// Latest fix, or `None` when no receiver is configured or there is no fix.
+pub fn current_position() -> Option<GnssPosition> {
+    SHARED_POSITION.get()?.lock().ok()?.clone()
+}
+
+/// First wait before reopening a receiver that is absent or went away.
+const REOPEN_DELAY_MIN: Duration = Duration::from_secs(2);
+/// Longest wait between attempts while it stays absent.
+const REOPEN_DELAY_MAX: Duration = Duration::from_secs(30);
+
+/// Wait after `delay` when the receiver still cannot be opened.
+fn next_reopen_delay(delay: Duration) -> Duration {
+    (delay * 2).min(REOPEN_DELAY_MAX)
+}
+
+/// gpsd's default port.
+const GPSD_PORT: u16 = 2947;
+
+/// `host:port` of the gpsd instance `gnss_dev` names (`tcp://host:port`,
+/// `gpsd://host:port` or bare `host:port`; the port defaults to 2947), or
+/// `None` for a serial device path.
+fn gpsd_addr(dev: &str) -> Option<String> {
+    let dev = dev.trim();
+    let addr = match dev
+        .strip_prefix("tcp://")
+        .or_else(|| dev.strip_prefix("gpsd://"))
+    {
+        Some(rest) => rest.trim_end_matches('/'),
+        None if !dev.starts_with('/') && dev.contains(':') => dev,
+        None => return None,
+    };
+    if addr.is_empty() {
+        None
+    } else if addr.contains(':') {
+        Some(addr.to_string())
+    } else {
+        Some(format!("{addr}:{GPSD_PORT}"))
+    }
+}
+
+/// Kind of receiver `gnss_dev` points at: `none` when unset, `serial` for a
+/// device path, `gpsd` for a `tcp://` or `gpsd://` URL or `host:port`.
+pub fn source_type(dev: &str) -> &'static str {
+    let dev = dev.trim();
+    if dev.is_empty() {
+        "none"
+    } else if dev.starts_with('/') {
+        "serial"
+    } else if gpsd_addr(dev).is_some() {
+        "gpsd"
+    } else {
+        "unknown"
+    }
+}
+
+/// Whole seconds between `last` fix and `now`; `None` if there never was one.
+pub fn fix_age(last: Option<Instant>, now: Instant) -> Option<u64> {
+    last.map(|t| now.saturating_duration_since(t).as_secs())
+}
+
+/// Seconds since the reader last got a fix.
+pub fn last_fix_age() -> Option<u64> {
+    fix_age(current_position().map(|p| p.fixed_at), Instant::now())
+}
+
+/// Device.DeviceInfo receiver parameters (name suffix, value) for `gnss_dev`.
+/// `X_OptimACS_GnssLastFixAge` is empty until the first fix.
+pub fn source_params(dev: &str) -> [(&'static str, String); 3] {
+    [
+        ("X_OptimACS_GnssSource", source_type(dev).to_string()),
+        ("X_OptimACS_GnssDevice", dev.trim().to_string()),
+        (
+            "X_OptimACS_GnssLastFixAge",
+            last_fix_age().map(|s| s.to_string()).unwrap_or_default(),
+        ),
+    ]
+}
+
+/// Spawns a background reader for a serial device or, for a `tcp://` URL,
+/// gpsd.  Position is updated in-place.  Returns a handle to the shared
+/// position state.
 pub fn spawn_gnss_reader(device: &str, baud: u32) -> Arc<Mutex<Option<GnssPosition>>> {
     let position: Arc<Mutex<Option<GnssPosition>>> = Arc::new(Mutex::new(None));
     let pos_clone = Arc::clone(&position);
+    let _ = SHARED_POSITION.set(Arc::clone(&position));
     let device = device.to_string();
 
     tokio::task::spawn_blocking(move || {
-        if let Err(e) = gnss_reader_loop(&device, baud, pos_clone) {
-            warn!("GNSS reader on {device} exited: {e}");
+        let mut delay = REOPEN_DELAY_MIN;
+        while !crate::usp::shutdown::requested() {
+            let result = match gpsd_addr(&device) {
+                Some(addr) => gpsd_reader_loop(&addr, &pos_clone),
+                None => gnss_reader_loop(&device, baud, &pos_clone),
+            };
+            if crate::usp::shutdown::requested() {
+                break;
+            }
+            let wait = match result {
+                Ok(()) => {
+                    // It was there: try again soon, it is likely coming back
+                    delay = REOPEN_DELAY_MIN;
+                    warn!(
+                        "GNSS: {device} went away, reopening in {}s",
+                        delay.as_secs()
+                    );
+                    delay
+                }
+                Err(e) => {
+                    warn!(
+                        "GNSS: cannot open {device}: {e}; retrying in {}s",
+                        delay.as_secs()
+                    );
+                    let wait = delay;
+                    delay = next_reopen_delay(delay);
+                    wait
+                }
+            };
+            std::thread::sleep(wait);
         }
     });
 
@@ -45,13 +255,38 @@ pub fn spawn_gnss_reader(device: &str, baud: u32) -> Arc<Mutex<Option<GnssPositi
 fn gnss_reader_loop(
     device: &str,
     baud: u32,
-    position: Arc<Mutex<Option<GnssPosition>>>,
+    position: &Mutex<Option<GnssPosition>>,
 ) -> io::Result<()> {
     let file = fs::OpenOptions::new().read(true).open(device)?;
     configure_serial(&file, baud)?;
 
-    let reader = BufReader::new(file);
+    store_fixes(BufReader::new(file), position, parse_nmea);
+    Ok(())
+}
+
+/// Read gpsd's reports from `addr` (`host:port`).
+fn gpsd_reader_loop(addr: &str, position: &Mutex<Option<GnssPosition>>) -> io::Result<()> {
+    let mut stream = TcpStream::connect(addr)?;
+    // JSON reports; a gpsd that only relays raw NMEA still works, as lines
+    // starting with '$' go through the NMEA parser
+    stream.write_all(b"?WATCH={\"enable\":true,\"json\":true};\n")?;
+    debug!("GNSS: connected to gpsd at {addr}");
+
+    store_fixes(BufReader::new(stream), position, parse_gpsd_line);
+    Ok(())
+}
+
+/// Merge every fix `parse` finds in the lines of `reader` into `position`
+/// until EOF or a read error.
+fn store_fixes<R: BufRead>(
+    reader: R,
+    position: &Mutex<Option<GnssPosition>>,
+    parse: fn(&str) -> Option<GnssPosition>,
+) {
     for line in reader.lines() {
+        if crate::usp::shutdown::requested() {
+            break;
+        }
         let line = match line {
             Ok(l) => l,
             Err(e) => {
@@ -59,14 +294,27 @@ fn gnss_reader_loop(
                 break;
             }
         };
-        if let Some(pos) = parse_nmea(&line) {
-            debug!("GNSS fix: lat={} lon={}", pos.latitude, pos.longitude);
+        if let Some(mut pos) = parse(&line) {
+            pos.fixed_at = Instant::now();
+            debug!(
+                "GNSS fix: lat={} lon={} alt={:?} hdop={:?} sats={:?} quality={} speed={:?} heading={:?}",
+                pos.latitude,
+                pos.longitude,
+                pos.altitude,
+                pos.hdop,
+                pos.satellites,
+                pos.fix_quality,
+                pos.speed_kmh,
+                pos.heading_deg
+            );
             if let Ok(mut guard) = position.lock() {
-                *guard = Some(pos);
+                match guard.as_mut() {
+                    Some(current) => current.merge(pos),
+                    None => *guard = Some(pos),
+                }
             }
         }
     }
-    Ok(())
 }
 
 /// Configure the serial port for raw NMEA reading (8N1, no echo, no signals).
@@ -145,7 +393,7 @@ fn parse_nmea(line: &str) -> Option<GnssPosition> {
     }
 }
 
-/// Parse a GPRMC sentence: $GPRMC,HHMMSS.ss,A,LLLL.ll,a,YYYYY.yy,a,...
+/// Parse a GPRMC sentence: $GPRMC,HHMMSS.ss,A,LLLL.ll,a,YYYYY.yy,a,kkk.k,ttt.t,...
 fn parse_rmc(f: &[&str]) -> Option<GnssPosition> {
     if f.len() < 7 {
         return None;
@@ -156,26 +404,41 @@ fn parse_rmc(f: &[&str]) -> Option<GnssPosition> {
     }
     let lat = nmea_to_decimal(f[3], f[4])?;
     let lon = nmea_to_decimal(f[5], f[6])?;
+    // 7 = speed (knots), 8 = track angle; often empty when stationary
+    let field = |i: usize| f.get(i).filter(|v| !v.is_empty());
     Some(GnssPosition {
         latitude: format!("{lat:.6}"),
         longitude: format!("{lon:.6}"),
+        speed_kmh: field(7)
+            .and_then(|v| v.parse::<f64>().ok())
+            .map(|knots| knots * KMH_PER_KNOT),
+        heading_deg: field(8).and_then(|v| v.parse().ok()),
+        ..Default::default()
     })
 }
 
-/// Parse a GPGGA sentence: $GPGGA,HHMMSS.ss,LLLL.ll,a,YYYYY.yy,a,q,...
+/// Parse a GPGGA sentence: $GPGGA,HHMMSS.ss,LLLL.ll,a,YYYYY.yy,a,q,nn,h.h,aaa.a,M,...
 fn parse_gga(f: &[&str]) -> Option<GnssPosition> {
     if f.len() < 7 {
         return None;
     }
     // field[6] is fix quality: 0 = invalid
-    if f[6] == "0" || f[6].is_empty() {
-        return None;
-    }
+    let fix_quality: u8 = match f[6].parse() {
+        Ok(0) | Err(_) => return None,
+        Ok(q) => q,
+    };
     let lat = nmea_to_decimal(f[2], f[3])?;
     let lon = nmea_to_decimal(f[4], f[5])?;
+    // Optional fields: 7 = satellites in use, 8 = HDOP, 9 = altitude (m)
+    let field = |i: usize| f.get(i).filter(|v| !v.is_empty());
     Some(GnssPosition {
         latitude: format!("{lat:.6}"),
         longitude: format!("{lon:.6}"),
+        altitude: field(9).and_then(|v| v.parse().ok()),
+        hdop: field(8).and_then(|v| v.parse().ok()),
+        satellites: field(7).and_then(|v| v.parse().ok()),
+        fix_quality,
+        ..Default::default()
     })
 }
 
@@ -219,6 +482,49 @@ fn nmea_checksum_ok(sentence: &str) -> bool {
     actual == expected
 }
 
+// ── gpsd reports ──────────────────────────────────────────────────────────────
+
+/// km/h per m/s (gpsd reports speed in m/s).
+const KMH_PER_MPS: f64 = 3.6;
+
+/// One line from gpsd: a JSON report, or an NMEA sentence it relays.
+fn parse_gpsd_line(line: &str) -> Option<GnssPosition> {
+    let line = line.trim();
+    if line.starts_with('$') {
+        parse_nmea(line)
+    } else if line.starts_with('{') {
+        parse_tpv(line)
+    } else {
+        None
+    }
+}
+
+/// Position from a gpsd TPV report with at least a 2D fix (`mode` 2 or 3).
+/// Other report classes (VERSION, DEVICES, WATCH, SKY, ...) are ignored.
+fn parse_tpv(json: &str) -> Option<GnssPosition> {
+    let v: serde_json::Value = serde_json::from_str(json).ok()?;
+    if v["class"] != "TPV" || v["mode"].as_u64().unwrap_or(0) < 2 {
+        return None;
+    }
+    let lat = v["lat"].as_f64()?;
+    let lon = v["lon"].as_f64()?;
+    Some(GnssPosition {
+        latitude: format!("{lat:.6}"),
+        longitude: format!("{lon:.6}"),
+        // gpsd 3.20+ reports altMSL; older versions only alt (also MSL)
+        altitude: v["altMSL"].as_f64().or_else(|| v["alt"].as_f64()),
+        speed_kmh: v["speed"].as_f64().map(|mps| mps * KMH_PER_MPS),
+        heading_deg: v["track"].as_f64(),
+        // gpsd status 2 = DGPS; anything else with a fix counts as plain GPS
+        fix_quality: if v["status"].as_u64() == Some(2) {
+            2
+        } else {
+            1
+        },
+        ..Default::default()
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -229,6 +535,42 @@ mod tests {
         let pos = parse_nmea(line).unwrap();
         assert!(pos.latitude.starts_with("48."), "lat={}", pos.latitude);
         assert!(pos.longitude.starts_with("11."), "lon={}", pos.longitude);
+        // RMC carries no altitude/quality
+        assert_eq!(pos.altitude, None);
+        assert_eq!(pos.satellites, None);
+        assert_eq!(pos.fix_quality, 0);
+    }
+
+    #[test]
+    fn gga_and_rmc_merge_into_one_position() {
+        let position = Mutex::new(None);
+        let sentences = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\n\
+                         $GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A\n";
+        store_fixes(sentences.as_bytes(), &position, parse_nmea);
+        let pos = position.lock().unwrap().clone().unwrap();
+        // From GGA
+        assert_eq!(pos.altitude, Some(545.4));
+        assert_eq!(pos.satellites, Some(8));
+        assert_eq!(pos.fix_quality, 1);
+        // From RMC
+        assert_eq!(pos.heading_deg, Some(84.4));
+        assert!(pos.speed_kmh.is_some());
+    }
+
+    #[test]
+    fn rmc_speed_and_heading() {
+        let line = "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A";
+        let pos = parse_nmea(line).unwrap();
+        let speed = pos.speed_kmh.unwrap();
+        assert!((speed - 41.4848).abs() < 1e-9, "speed={speed}");
+        assert_eq!(pos.heading_deg, Some(84.4));
+
+        // Stationary fix with empty speed/course
+        let f = "GPRMC,123519,A,4807.038,N,01131.000,E,,,230394,,"
+            .split(',')
+            .collect::<Vec<_>>();
+        let pos = parse_rmc(&f).unwrap();
+        assert_eq!((pos.speed_kmh, pos.heading_deg), (None, None));
     }
 
     #[test]
@@ -236,6 +578,116 @@ mod tests {
         let line = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
         let pos = parse_nmea(line).unwrap();
         assert!(pos.latitude.starts_with("48."), "lat={}", pos.latitude);
+        assert_eq!(pos.fix_quality, 1);
+        assert_eq!(pos.satellites, Some(8));
+        assert_eq!(pos.hdop, Some(0.9));
+    }
+
+    #[test]
+    fn gga_altitude() {
+        let line = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+        assert_eq!(parse_nmea(line).unwrap().altitude, Some(545.4));
+
+        // Empty optional fields are None, not a parse failure
+        let f = "GPGGA,123519,4807.038,N,01131.000,E,2,,,,M,,M,,"
+            .split(',')
+            .collect::<Vec<_>>();
+        let pos = parse_gga(&f).unwrap();
+        assert_eq!((pos.fix_quality, pos.altitude, pos.hdop), (2, None, None));
+    }
+
+    #[test]
+    fn position_combined_with_and_without_fix() {
+        let pos = GnssPosition {
+            latitude: "48.117300".into(),
+            longitude: "-11.516667".into(),
+            ..Default::default()
+        };
+        let params = position_params(Some(&pos));
+        assert_eq!(params[0], ("X_OptimACS_Latitude", "48.117300".to_string()));
+        assert_eq!(
+            params[1],
+            ("X_OptimACS_Longitude", "-11.516667".to_string())
+        );
+        assert_eq!(
+            params[2],
+            ("X_OptimACS_Position", "48.117300,-11.516667".to_string())
+        );
+
+        for (_, val) in position_params(None) {
+            assert_eq!(val, "");
+        }
+    }
+
+    #[test]
+    fn stale_fix() {
+        let pos = GnssPosition {
+            fixed_at: Instant::now() - Duration::from_secs(90),
+            ..Default::default()
+        };
+        assert!(pos.age() >= Duration::from_secs(90));
+        assert!(pos.is_stale(60));
+        assert!(!pos.is_stale(120));
+        // 0 = no limit
+        assert!(!pos.is_stale(0));
+    }
+
+    #[test]
+    fn fix_age_and_source_type() {
+        let t0 = Instant::now();
+        let later = t0 + Duration::from_millis(42_900);
+        assert_eq!(fix_age(Some(t0), later), Some(42));
+        assert_eq!(fix_age(Some(t0), t0), Some(0));
+        // A fix stamped after `now` was read is not negative
+        assert_eq!(fix_age(Some(later), t0), Some(0));
+        assert_eq!(fix_age(None, later), None);
+
+        assert_eq!(source_type(""), "none");
+        assert_eq!(source_type("/dev/ttyUSB0"), "serial");
+        assert_eq!(source_type("gpsd://127.0.0.1:2947"), "gpsd");
+        assert_eq!(source_type("localhost:2947"), "gpsd");
+        assert_eq!(source_type("tcp://localhost:2947"), "gpsd");
+    }
+
+    #[test]
+    fn reopen_delay_backs_off_to_cap() {
+        let mut delay = REOPEN_DELAY_MIN;
+        let mut waits = Vec::new();
+        for _ in 0..6 {
+            waits.push(delay.as_secs());
+            delay = next_reopen_delay(delay);
+        }
+        assert_eq!(waits, vec![2, 4, 8, 16, 30, 30]);
+    }
+
+    #[test]
+    fn gpsd_source() {
+        assert_eq!(
+            gpsd_addr("tcp://localhost:2947").as_deref(),
+            Some("localhost:2947")
+        );
+        assert_eq!(
+            gpsd_addr("gpsd://10.0.0.1").as_deref(),
+            Some("10.0.0.1:2947")
+        );
+        assert_eq!(gpsd_addr("/dev/ttyUSB0"), None);
+
+        let tpv = r#"{"class":"TPV","device":"/dev/ttyACM0","mode":3,"status":2,"time":"2024-05-01T12:00:00.000Z","lat":48.1173,"lon":11.516667,"altMSL":545.4,"track":84.4,"speed":11.52}"#;
+        let pos = parse_gpsd_line(tpv).unwrap();
+        assert_eq!(pos.latitude, "48.117300");
+        assert_eq!(pos.longitude, "11.516667");
+        assert_eq!(pos.altitude, Some(545.4));
+        assert_eq!(pos.heading_deg, Some(84.4));
+        assert!((pos.speed_kmh.unwrap() - 41.472).abs() < 1e-9);
+        assert_eq!(pos.fix_quality, 2);
+
+        // No fix yet, and non-TPV reports, are skipped
+        assert!(parse_gpsd_line(r#"{"class":"TPV","mode":1}"#).is_none());
+        assert!(parse_gpsd_line(r#"{"class":"VERSION","release":"3.25"}"#).is_none());
+
+        // gpsd relaying raw NMEA
+        let gga = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+        assert_eq!(parse_gpsd_line(gga).unwrap().altitude, Some(545.4));
     }
 
     #[test]
@@ -8,6 +8,7 @@ mod apply;
 mod config;
 mod error;
 mod gnss;
+#[cfg(feature = "legacy-acp")]
 mod proto;
 mod tls;
 mod usp;
@@ -18,7 +19,7 @@ use std::process;
 use std::sync::Arc;
 
 use clap::Parser;
-use log::{debug, error, info, LevelFilter};
+use log::{debug, error, info, warn, LevelFilter};
 
 // ── CLI ───────────────────────────────────────────────────────────────────────
 
@@ -28,8 +29,8 @@ use log::{debug, error, info, LevelFilter};
     about = "USP Agent (TR-369) — OptimACS access-point client"
 )]
 struct Cli {
-    /// Path to the flat key=value configuration file.
-    /// Ignored when --uci is set.
+    /// Path to the configuration file (flat key=value, or TOML when it ends
+    /// in `.toml`).  Ignored when --uci is set.
     #[arg(
         short = 'c',
         long = "config",
@@ -37,6 +38,10 @@ struct Cli {
     )]
     config: PathBuf,
 
+    /// Format of the --config file; overrides detection by extension.
+    #[arg(long, value_enum)]
+    format: Option<config::ConfigFormat>,
+
     /// Read configuration from UCI (/etc/config/optimacs) instead of the
     /// flat config file.  All options are read from the 'agent' section:
     ///   uci show optimacs.agent
@@ -67,7 +72,7 @@ async fn main() {
             }
         }
     } else {
-        match config::load_config(&cli.config) {
+        match config::load_config_file(&cli.config, cli.format) {
             Ok(c) => c,
             Err(e) => {
                 eprintln!("ac-client: config error: {e}");
@@ -75,16 +80,25 @@ async fn main() {
             }
         }
     };
+    config::set_source(if cli.uci {
+        config::ConfigSource::Uci
+    } else {
+        config::ConfigSource::File(
+            cli.config.clone(),
+            config::ConfigFormat::detect(&cli.config, cli.format),
+        )
+    });
     if let Err(e) = config::validate_config(&cfg) {
         eprintln!("ac-client: config validation: {e}");
         process::exit(1);
     }
 
-    // Set up logging — prefer syslog, fall back to stderr if the socket is not
-    // yet available (can happen early in the boot sequence before logd is ready).
+    // Set up logging — prefer syslog, fall back to stderr if the socket is
+    // still not available after a few retries (logd may start after us early
+    // in the boot sequence).  --stderr always wins.
     let use_syslog = cfg.log_syslog && !cli.stderr;
     if use_syslog {
-        if let Err(e) = setup_logging(true, cli.verbose) {
+        if let Err(e) = setup_syslog_with_retry(cfg.syslog_retries, cli.verbose).await {
             eprintln!("ac-client: syslog unavailable ({e}), falling back to stderr");
             setup_logging(false, cli.verbose).ok();
         }
@@ -158,16 +172,132 @@ async fn main() {
         gnss::spawn_gnss_reader(&cfg.gnss_dev, cfg.gnss_baud)
     };
 
-    // Run the USP agent; restart on error
+    if let Err(e) = usp::agent::set_status_interval(cfg.status_interval) {
+        warn!("{e}; using default status interval");
+    }
+    usp::session::set_enabled(cfg.e2e_session);
+    spawn_reload_handler(cli.uci, cli.config.clone(), cli.format);
+    spawn_shutdown_handler(cfg.pid_file.clone());
+
+    // Run the USP agent; restart on error until asked to stop
     loop {
         usp::agent::run(Arc::clone(&cfg), Arc::clone(&gnss_pos)).await;
+        if usp::shutdown::requested() {
+            break;
+        }
         error!("USP agent exited; restarting in 30s");
-        tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+        tokio::select! {
+            _ = tokio::time::sleep(std::time::Duration::from_secs(30)) => {}
+            _ = usp::shutdown::token().cancelled() => break,
+        }
+    }
+
+    if let Err(e) = util::remove_pid_file(&cfg.pid_file) {
+        warn!("cannot remove PID file {}: {e}", cfg.pid_file.display());
     }
+    info!("ac-client stopped");
+    // Returning would drop the runtime, which waits for blocking tasks such
+    // as a GNSS reader stuck in read()
+    log::logger().flush();
+    process::exit(0);
+}
+
+// ── SIGHUP reload ─────────────────────────────────────────────────────────────
+
+/// Re-read the config on SIGHUP and apply the settings that can change
+/// without a restart (currently `status_interval`).  A config that fails
+/// [`config::validate_config`] is not applied.
+fn spawn_reload_handler(uci: bool, path: PathBuf, format: Option<config::ConfigFormat>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("cannot install SIGHUP handler: {e}");
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hup.recv().await.is_some() {
+            info!("SIGHUP received, reloading config");
+            let reloaded = if uci {
+                config::load_config_uci()
+            } else {
+                config::load_config_file(&path, format)
+            }
+            .and_then(|cfg| config::validate_config(&cfg).map(|()| cfg));
+            match reloaded {
+                Ok(new_cfg) => {
+                    if let Err(e) = usp::agent::set_status_interval(new_cfg.status_interval) {
+                        warn!("reload: {e}");
+                    }
+                    usp::session::set_enabled(new_cfg.e2e_session);
+                }
+                Err(e) => error!("reload failed, keeping current config: {e}"),
+            }
+        }
+    });
+}
+
+// ── SIGTERM/SIGINT shutdown ───────────────────────────────────────────────────
+
+/// Time the MTPs get to send their DisconnectRecord and close before the
+/// process exits anyway (e.g. stuck in a connect attempt).
+const SHUTDOWN_GRACE: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// On SIGTERM or SIGINT, request a shutdown: the MTPs send a DisconnectRecord
+/// and stop reconnecting, and `main` removes the PID file and returns.
+fn spawn_shutdown_handler(pid_file: PathBuf) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (mut term, mut int) = match (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+    ) {
+        (Ok(t), Ok(i)) => (t, i),
+        (Err(e), _) | (_, Err(e)) => {
+            warn!("cannot install SIGTERM/SIGINT handler: {e}");
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        let name = tokio::select! {
+            _ = term.recv() => "SIGTERM",
+            _ = int.recv() => "SIGINT",
+        };
+        info!("{name} received, disconnecting");
+        usp::shutdown::request();
+        tokio::time::sleep(SHUTDOWN_GRACE).await;
+        warn!(
+            "MTPs did not stop within {}s, exiting",
+            SHUTDOWN_GRACE.as_secs()
+        );
+        util::remove_pid_file(&pid_file).ok();
+        process::exit(0);
+    });
 }
 
 // ── Logging setup ─────────────────────────────────────────────────────────────
 
+/// Delay between syslog connection attempts.
+const SYSLOG_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Set up syslog logging, retrying up to `retries` more times.
+async fn setup_syslog_with_retry(retries: u32, verbose: u8) -> anyhow::Result<()> {
+    let mut attempt = 0;
+    loop {
+        match setup_logging(true, verbose) {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                eprintln!("ac-client: syslog not ready ({e}), retry {attempt}/{retries}");
+                tokio::time::sleep(SYSLOG_RETRY_DELAY).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 fn setup_logging(use_syslog: bool, verbose: u8) -> anyhow::Result<()> {
     // Determine log level from verbose flag
     let level = match verbose {
@@ -189,9 +319,12 @@ fn setup_logging(use_syslog: bool, verbose: u8) -> anyhow::Result<()> {
             .map(|()| log::set_max_level(level))
             .map_err(|e| anyhow::anyhow!("set_logger: {e}"))?;
     } else {
+        // Let env_logger pass everything and gate on the global max level, so
+        // Device.DeviceInfo.X_OptimACS_LogLevel can raise it at runtime.
         env_logger::Builder::from_default_env()
-            .filter_level(level)
+            .filter_level(LevelFilter::Trace)
             .init();
+        log::set_max_level(level);
     }
 
     info!("Logging initialized at level: {:?}", level);
@@ -5,21 +5,87 @@
 //!   - Mutual TLS: client presents its certificate
 //!   - Server certificate validated against the configured CA
 //!
+//! ## Key logging (debug only)
+//!
+//! When `tls_debug` is enabled in the config *and* the `SSLKEYLOGFILE`
+//! environment variable is set, the session secrets of every handshake are
+//! appended to that file in NSS key log format, so a packet capture of the
+//! USP/WebSocket traffic can be decrypted in Wireshark.
+//!
+//! **Security:** the key log defeats TLS entirely — anyone who obtains it can
+//! decrypt the captured session, including certificates issued via
+//! IssueCert() and any passwords set over USP.  Never enable this on a
+//! production device, keep the file on tmpfs, and delete it afterwards.
+//!
 
 use std::fs;
-use std::io::Cursor;
-use std::sync::Arc;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
-use rustls::crypto::{verify_tls13_signature, CryptoProvider};
+use rustls::crypto::{verify_tls13_signature, CryptoProvider, SupportedKxGroup};
 use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
 use rustls::{
-    ClientConfig, DigitallySignedStruct, Error as TlsError, RootCertStore, SignatureScheme,
+    ClientConfig, DigitallySignedStruct, Error as TlsError, KeyLog, RootCertStore, SignatureScheme,
 };
 use rustls_pemfile::{certs, private_key};
 
 use crate::error::{AcError, Result};
 use log::{debug, trace, warn};
+use tokio::sync::watch;
+
+// ── Device identity ──────────────────────────────────────────────────────────
+
+/// Bumped each time a provisioned certificate is installed (IssueCert).
+/// Every running MTP watches it and reconnects so the new identity is
+/// presented — with `mtp = both`, both of them.
+static IDENTITY_GENERATION: OnceLock<watch::Sender<u64>> = OnceLock::new();
+
+fn identity_tx() -> &'static watch::Sender<u64> {
+    IDENTITY_GENERATION.get_or_init(|| watch::channel(0).0)
+}
+
+/// Tell every MTP that the device identity on disk changed.
+pub fn identity_changed() {
+    identity_tx().send_modify(|generation| *generation += 1);
+}
+
+/// Receiver whose `changed()` fires on the next [`identity_changed`].  Take
+/// it before building the TLS config so no change is missed.
+pub fn watch_identity() -> watch::Receiver<u64> {
+    identity_tx().subscribe()
+}
+
+/// True when both the provisioned client certificate and key are present.
+pub fn device_certs_exist(cfg: &crate::config::ClientConfig) -> bool {
+    cfg.cert_file.exists() && cfg.key_file.exists()
+}
+
+/// Identity presented by the most recently built TLS config:
+/// 0 = none yet, 1 = init (bootstrap), 2 = provisioned.
+static ACTIVE_IDENTITY: AtomicU8 = AtomicU8::new(0);
+
+/// Whether the current MTP connection presented the provisioned identity
+/// (`None` until a TLS config has been built, e.g. plain MQTT).
+pub fn active_identity_provisioned() -> Option<bool> {
+    match ACTIVE_IDENTITY.load(Ordering::Relaxed) {
+        1 => Some(false),
+        2 => Some(true),
+        _ => None,
+    }
+}
+
+/// Certificate/key pair to present: the provisioned identity when it exists,
+/// otherwise the shared init (bootstrap) identity used to request one.
+pub fn client_identity(cfg: &crate::config::ClientConfig) -> (&Path, &Path) {
+    if device_certs_exist(cfg) {
+        (&cfg.cert_file, &cfg.key_file)
+    } else {
+        (&cfg.init_cert, &cfg.init_key)
+    }
+}
 
 // ── USP server certificate verifier ──────────────────────────────────────────
 
@@ -123,44 +189,104 @@ impl ServerCertVerifier for UspServerVerifier {
     }
 }
 
+// ── Key-exchange group selection ─────────────────────────────────────────────
+
+/// Pick the configured key-exchange groups out of `available`, in the order
+/// given.  Names are matched case-insensitively (`X25519MLKEM768`, `X25519`,
+/// `secp256r1`, ...); unknown or unsupported names are skipped with a warning.
+fn select_kx_groups(
+    available: &[&'static dyn SupportedKxGroup],
+    wanted: &[String],
+) -> Vec<&'static dyn SupportedKxGroup> {
+    let mut selected: Vec<&'static dyn SupportedKxGroup> = Vec::new();
+    for name in wanted {
+        match available
+            .iter()
+            .find(|g| format!("{:?}", g.name()).eq_ignore_ascii_case(name))
+        {
+            Some(g) if !selected.iter().any(|s| s.name() == g.name()) => selected.push(*g),
+            Some(_) => {}
+            None => warn!("tls_kex_groups: unknown or unsupported group '{name}' ignored"),
+        }
+    }
+    selected
+}
+
 // ── TLS configuration builder ────────────────────────────────────────────────
 
+/// Every CA in `ca_file` and `extra_ca_files`.
+pub fn root_store(cfg: &crate::config::ClientConfig) -> Result<RootCertStore> {
+    let mut root_store = RootCertStore::empty();
+    let mut ca_count = 0;
+    for path in std::iter::once(&cfg.ca_file).chain(&cfg.extra_ca_files) {
+        debug!("Loading CA certificate from: {}", path.display());
+        for cert in certs(&mut Cursor::new(fs::read(path)?)) {
+            root_store.add(cert?)?;
+            ca_count += 1;
+        }
+    }
+    debug!("Loaded {} CA certificate(s)", ca_count);
+    Ok(root_store)
+}
+
 /// Build and return a `rustls::ClientConfig` suitable for use with
-/// tokio-tungstenite's `Connector::Rustls` (USP WebSocket MTP).
+/// tokio-tungstenite's `Connector::Rustls` (USP WebSocket MTP) and rumqttc's
+/// `TlsConfiguration::Rustls` (USP MQTT MTP over `mqtts://`).
 ///
 /// If the provisioned certificate/key don't exist, falls back to the init cert/key
 /// for unprovisioned devices.
 pub fn build_tls_config(cfg: &crate::config::ClientConfig) -> Result<Arc<ClientConfig>> {
-    debug!("Building TLS config for WebSocket connection");
+    let key_log = match cfg.tls_debug {
+        true => std::env::var_os("SSLKEYLOGFILE").map(PathBuf::from),
+        false => None,
+    };
+    if cfg.tls_debug && key_log.is_none() {
+        warn!("tls_debug is set but SSLKEYLOGFILE is not; key logging disabled");
+    }
+    tls_config_with_key_log(cfg, key_log.as_deref())
+}
+
+/// [`build_tls_config`] with session secrets appended to `key_log`, if any.
+fn tls_config_with_key_log(
+    cfg: &crate::config::ClientConfig,
+    key_log: Option<&Path>,
+) -> Result<Arc<ClientConfig>> {
+    debug!("Building TLS config for USP MTP connection");
 
-    let provider = CryptoProvider::get_default()
+    let mut provider = CryptoProvider::get_default()
         .expect("call rustls_post_quantum::provider().install_default() first")
         .clone();
     trace!("Using post-quantum crypto provider");
 
-    // ── CA trust store ────────────────────────────────────────────────────────
-    debug!("Loading CA certificate from: {}", cfg.ca_file.display());
-    let mut root_store = RootCertStore::empty();
-    let ca_pem = fs::read(&cfg.ca_file)?;
-    let mut ca_count = 0;
-    for cert in certs(&mut Cursor::new(ca_pem)) {
-        root_store.add(cert?)?;
-        ca_count += 1;
+    // ── Key-exchange group preference ─────────────────────────────────────────
+    if !cfg.tls_kex_groups.is_empty() {
+        let groups = select_kx_groups(&provider.kx_groups, &cfg.tls_kex_groups);
+        if groups.is_empty() {
+            warn!("tls_kex_groups: no usable group configured, keeping provider defaults");
+        } else {
+            debug!(
+                "Key-exchange groups: {:?}",
+                groups.iter().map(|g| g.name()).collect::<Vec<_>>()
+            );
+            let mut restricted = (*provider).clone();
+            restricted.kx_groups = groups;
+            provider = Arc::new(restricted);
+        }
     }
-    debug!("Loaded {} CA certificate(s)", ca_count);
 
-    // Use provisioned certs if they exist, otherwise fall back to init certs
-    let (cert_file, key_file) = if cfg.cert_file.exists() && cfg.key_file.exists() {
+    // ── CA trust store ────────────────────────────────────────────────────────
+    let root_store = root_store(cfg)?;
+
+    // Use provisioned certs if they exist, otherwise bootstrap with init certs
+    let (cert_file, key_file) = client_identity(cfg);
+    let provisioned = device_certs_exist(cfg);
+    if provisioned {
         debug!("Using provisioned certificates");
-        debug!("  Cert: {}", cfg.cert_file.display());
-        debug!("  Key: {}", cfg.key_file.display());
-        (&cfg.cert_file, &cfg.key_file)
     } else {
-        warn!("Provisioned certs not found, using init certs");
-        debug!("  Init Cert: {}", cfg.init_cert.display());
-        debug!("  Init Key: {}", cfg.init_key.display());
-        (&cfg.init_cert, &cfg.init_key)
-    };
+        warn!("Provisioned certs not found, using init certs (unprovisioned bootstrap)");
+    }
+    debug!("  Cert: {}", cert_file.display());
+    debug!("  Key: {}", key_file.display());
 
     // ── Client certificate chain ──────────────────────────────────────────────
     debug!("Loading client certificate from: {}", cert_file.display());
@@ -181,7 +307,7 @@ pub fn build_tls_config(cfg: &crate::config::ClientConfig) -> Result<Arc<ClientC
     debug!("Building TLS 1.3 configuration with custom certificate verifier");
     let verifier = UspServerVerifier::new(root_store, Arc::clone(&provider))?;
 
-    let tls_config = ClientConfig::builder_with_provider(Arc::clone(&provider))
+    let mut tls_config = ClientConfig::builder_with_provider(Arc::clone(&provider))
         .with_protocol_versions(&[&rustls::version::TLS13])
         .map_err(AcError::Tls)?
         .dangerous()
@@ -189,6 +315,200 @@ pub fn build_tls_config(cfg: &crate::config::ClientConfig) -> Result<Arc<ClientC
         .with_client_auth_cert(cert_chain, private_key)
         .map_err(AcError::Tls)?;
 
+    // ── Optional NSS key log (see module docs for the security implications) ──
+    if let Some(path) = key_log {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        warn!(
+            "TLS key logging ENABLED: session secrets are written to {} — \
+             do not use in production",
+            path.display()
+        );
+        tls_config.key_log = Arc::new(NssKeyLog(Mutex::new(file)));
+    }
+
+    ACTIVE_IDENTITY.store(if provisioned { 2 } else { 1 }, Ordering::Relaxed);
     debug!("TLS configuration built successfully (TLS 1.3 only, mutual TLS enabled, post-quantum)");
     Ok(Arc::new(tls_config))
 }
+
+/// NSS key log writer (`<label> <client_random> <secret>`, hex-encoded).
+#[derive(Debug)]
+struct NssKeyLog(Mutex<fs::File>);
+
+impl KeyLog for NssKeyLog {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let hex = |b: &[u8]| b.iter().map(|x| format!("{x:02x}")).collect::<String>();
+        let line = format!("{label} {} {}\n", hex(client_random), hex(secret));
+        if let Err(e) = self.0.lock().unwrap().write_all(line.as_bytes()) {
+            warn!("TLS key log write failed: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::{ClientConnection, ServerConfig, ServerConnection};
+    use std::path::PathBuf;
+
+    fn init_file(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("package/ac-client/files/init")
+            .join(name)
+    }
+
+    /// Drive an in-memory TLS 1.3 handshake between `client` and `server`.
+    fn handshake(client: &mut ClientConnection, server: &mut ServerConnection) {
+        let mut buf = Vec::new();
+        for _ in 0..16 {
+            if !client.is_handshaking() && !server.is_handshaking() {
+                return;
+            }
+            buf.clear();
+            while client.wants_write() {
+                client.write_tls(&mut buf).unwrap();
+            }
+            if !buf.is_empty() {
+                server.read_tls(&mut buf.as_slice()).unwrap();
+                server.process_new_packets().unwrap();
+            }
+            buf.clear();
+            while server.wants_write() {
+                server.write_tls(&mut buf).unwrap();
+            }
+            if !buf.is_empty() {
+                client.read_tls(&mut buf.as_slice()).unwrap();
+                client.process_new_packets().unwrap();
+            }
+        }
+        panic!("handshake did not complete");
+    }
+
+    #[test]
+    fn identity_selects_init_until_provisioned() {
+        let dir = std::env::temp_dir().join(format!("ac-client-certs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cfg = crate::config::ClientConfig {
+            cert_file: dir.join("client.crt"),
+            key_file: dir.join("client.key"),
+            init_cert: init_file("client.crt"),
+            init_key: init_file("client.key"),
+            ..Default::default()
+        };
+
+        assert!(!device_certs_exist(&cfg));
+        assert_eq!(
+            client_identity(&cfg),
+            (cfg.init_cert.as_path(), cfg.init_key.as_path())
+        );
+
+        // Cert alone is not enough
+        fs::write(&cfg.cert_file, "cert").unwrap();
+        assert_eq!(client_identity(&cfg).0, cfg.init_cert.as_path());
+
+        fs::write(&cfg.key_file, "key").unwrap();
+        assert!(device_certs_exist(&cfg));
+        assert_eq!(
+            client_identity(&cfg),
+            (cfg.cert_file.as_path(), cfg.key_file.as_path())
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn kex_groups_restricted_and_reordered() {
+        let _ = CryptoProvider::install_default(rustls::crypto::aws_lc_rs::default_provider());
+
+        let cfg = crate::config::ClientConfig {
+            ca_file: init_file("ca.crt"),
+            cert_file: PathBuf::from("/nonexistent/client.crt"),
+            init_cert: init_file("client.crt"),
+            init_key: init_file("client.key"),
+            tls_kex_groups: vec!["SECP256R1".into(), "bogus".into(), "x25519".into()],
+            ..Default::default()
+        };
+        let client_cfg = build_tls_config(&cfg).unwrap();
+        let names: Vec<_> = client_cfg
+            .crypto_provider()
+            .kx_groups
+            .iter()
+            .map(|g| g.name())
+            .collect();
+        assert_eq!(
+            names,
+            vec![rustls::NamedGroup::secp256r1, rustls::NamedGroup::X25519]
+        );
+
+        // Nothing usable: fall back to the provider's full list
+        let cfg = crate::config::ClientConfig {
+            tls_kex_groups: vec!["bogus".into()],
+            ..cfg
+        };
+        let client_cfg = build_tls_config(&cfg).unwrap();
+        assert_eq!(
+            client_cfg.crypto_provider().kx_groups.len(),
+            CryptoProvider::get_default().unwrap().kx_groups.len()
+        );
+    }
+
+    #[test]
+    fn key_log_written_when_tls_debug_enabled() {
+        let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+        let _ = CryptoProvider::install_default((*provider).clone());
+
+        let keylog = std::env::temp_dir().join(format!("ac-client-keylog-{}", std::process::id()));
+        let _ = fs::remove_file(&keylog);
+
+        let cfg = crate::config::ClientConfig {
+            ca_file: init_file("ca.crt"),
+            cert_file: PathBuf::from("/nonexistent/client.crt"),
+            init_cert: init_file("client.crt"),
+            init_key: init_file("client.key"),
+            tls_debug: true,
+            ..Default::default()
+        };
+        let client_cfg = tls_config_with_key_log(&cfg, Some(&keylog)).unwrap();
+
+        // Server side reuses the init identity; only the handshake matters here
+        let chain: Vec<CertificateDer<'static>> =
+            certs(&mut Cursor::new(fs::read(&cfg.init_cert).unwrap()))
+                .collect::<std::io::Result<_>>()
+                .unwrap();
+        let key = private_key(&mut Cursor::new(fs::read(&cfg.init_key).unwrap()))
+            .unwrap()
+            .unwrap();
+        let server_cfg = ServerConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .unwrap();
+
+        let mut client =
+            ClientConnection::new(client_cfg, ServerName::try_from("ac-server").unwrap()).unwrap();
+        let mut server = ServerConnection::new(Arc::new(server_cfg)).unwrap();
+        handshake(&mut client, &mut server);
+
+        let log = fs::read_to_string(&keylog).unwrap();
+        let _ = fs::remove_file(&keylog);
+        assert!(log.contains("CLIENT_HANDSHAKE_TRAFFIC_SECRET "));
+        assert!(log.contains("CLIENT_TRAFFIC_SECRET_0 "));
+    }
+
+    #[test]
+    fn identity_change_wakes_every_mtp() {
+        let mut ws = watch_identity();
+        let mut mqtt = watch_identity();
+        assert!(!ws.has_changed().unwrap());
+        identity_changed();
+        assert!(ws.has_changed().unwrap());
+        assert!(mqtt.has_changed().unwrap());
+        ws.mark_unchanged();
+        mqtt.mark_unchanged();
+        assert!(!mqtt.has_changed().unwrap());
+    }
+}
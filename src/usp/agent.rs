@@ -8,50 +8,50 @@
 
 #![allow(clippy::all)]
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 use log::{debug, error, info, trace, warn};
+use tokio::sync::{watch, Notify, OwnedSemaphorePermit, Semaphore};
 
 use crate::config::{ClientConfig, MtpType};
 use crate::gnss::GnssPosition;
 use crate::util;
 
 use super::{
-    dm,
+    dm::{
+        self,
+        subscriptions::{self, NotifType, Subscription},
+    },
     endpoint::EndpointId,
     message::{
-        build_boot_notify, build_error, build_operate_resp, build_set_resp,
+        build_boot_notify, build_error, build_event_notify, build_notify_resp, build_operate_resp,
+        build_operation_complete_notify, build_param_error, build_param_errors, build_set_resp,
         build_value_change_notify, decode_msg, encode_msg,
     },
-    mtp, tp469,
+    mtp,
+    offline_buffer::{NotifyClass, Outbox},
+    tp469,
     usp_msg::{body::MsgBody, header::MessageType},
 };
 
-use tokio::sync::mpsc;
-
-const _STATUS_SUBSCRIPTION_ID: &str = "status";
+/// Subscription ID carried by the periodic status ValueChange notifications.
+const STATUS_SUBSCRIPTION_ID: &str = "status";
 
 /// Run the USP agent.  Called from main after config is loaded.
 pub async fn run(cfg: Arc<ClientConfig>, gnss: Arc<std::sync::Mutex<Option<GnssPosition>>>) {
     debug!("Initializing USP Agent...");
 
-    let agent_id = if cfg.usp_endpoint_id.is_empty() {
-        // Build from vendor OUI (00005A = placeholder) + MAC
-        debug!("Building endpoint ID from MAC: {}", cfg.mac_addr);
-        EndpointId::from_mac("00005A", &cfg.mac_addr)
-    } else {
-        debug!("Using configured endpoint ID: {}", cfg.usp_endpoint_id);
-        EndpointId::new(cfg.usp_endpoint_id.clone())
-    };
-
+    let agent_id = endpoint_id(&cfg);
     info!("USP Agent endpoint ID: {agent_id}");
     debug!("MTP type: {:?}", cfg.mtp);
 
-    // Create channel for status heartbeat messages (sends encoded USP records)
-    let (status_tx, status_rx) = mpsc::channel::<Vec<u8>>(10);
-    let status_rx = Arc::new(Mutex::new(status_rx));
+    // Outbox for notify records; buffers them while no MTP is connected
+    let outbox = Arc::new(Outbox::new(cfg.offline_buffer_len));
+    // USP version agreed with the controller (TR-369 §6.2.1), shared by the
+    // MTPs that negotiate it and the tasks that queue notifications
+    let negotiated_ver = Arc::new(Mutex::new("1.3".to_string()));
 
     // Spawn status heartbeat task
     {
@@ -59,48 +59,108 @@ pub async fn run(cfg: Arc<ClientConfig>, gnss: Arc<std::sync::Mutex<Option<GnssP
         let cfg2 = Arc::clone(&cfg);
         let agent2 = agent_id.clone();
         let gnss2 = Arc::clone(&gnss);
-        let status_tx2 = status_tx.clone();
+        let outbox2 = Arc::clone(&outbox);
+        let ver2 = Arc::clone(&negotiated_ver);
         tokio::spawn(async move {
             debug!("Status heartbeat task started");
-            status_loop(cfg2, agent2, gnss2, status_tx2).await;
+            status_loop(cfg2, agent2, gnss2, outbox2, ver2).await;
+        });
+    }
+
+    {
+        let outbox2 = Arc::clone(&outbox);
+        tokio::spawn(async move {
+            notify_retry_loop(outbox2).await;
+        });
+    }
+
+    if cfg.temp_alarm_c > 0 {
+        let cfg2 = Arc::clone(&cfg);
+        let agent2 = agent_id.clone();
+        let outbox2 = Arc::clone(&outbox);
+        let ver2 = Arc::clone(&negotiated_ver);
+        tokio::spawn(async move {
+            temp_monitor_loop(cfg2, agent2, outbox2, ver2).await;
+        });
+    }
+
+    {
+        let cfg2 = Arc::clone(&cfg);
+        let agent2 = agent_id.clone();
+        let outbox2 = Arc::clone(&outbox);
+        let ver2 = Arc::clone(&negotiated_ver);
+        tokio::spawn(async move {
+            reregister_loop(cfg2, agent2, outbox2, ver2).await;
         });
     }
 
+    {
+        let cfg2 = Arc::clone(&cfg);
+        let agent2 = agent_id.clone();
+        let outbox2 = Arc::clone(&outbox);
+        let ver2 = Arc::clone(&negotiated_ver);
+        tokio::spawn(async move {
+            operation_complete_loop(cfg2, agent2, outbox2, ver2).await;
+        });
+    }
+
+    tokio::spawn(station_sample_loop());
+
     // Connect MTP
     info!("Starting MTP connection...");
     match cfg.mtp {
         MtpType::WebSocket => {
             debug!("Starting WebSocket MTP");
-            mtp::websocket::run(cfg, agent_id, status_rx).await
+            mtp::websocket::run(cfg, agent_id, outbox, negotiated_ver).await
         }
         MtpType::Mqtt => {
             debug!("Starting MQTT MTP");
-            mtp::mqtt::run(cfg, agent_id, status_rx).await
+            mtp::mqtt::run(cfg, agent_id, outbox, negotiated_ver).await
         }
         MtpType::Both => {
             debug!("Starting both WebSocket and MQTT MTP");
             let cfg2 = Arc::clone(&cfg);
             let agent2 = agent_id.clone();
-            let status_rx2 = Arc::clone(&status_rx);
-            tokio::spawn(async move {
+            let outbox2 = Arc::clone(&outbox);
+            let ver2 = Arc::clone(&negotiated_ver);
+            let mqtt = tokio::spawn(async move {
                 debug!("Starting MQTT MTP in background task");
-                mtp::mqtt::run(cfg2, agent2, status_rx2).await;
+                mtp::mqtt::run(cfg2, agent2, outbox2, ver2).await;
             });
-            mtp::websocket::run(cfg, agent_id, status_rx).await;
+            mtp::websocket::run(cfg, agent_id, outbox, negotiated_ver).await;
+            // On shutdown, give MQTT the chance to send its DisconnectRecord too
+            if super::shutdown::requested() {
+                let _ = mqtt.await;
+            }
         }
     }
 }
 
-/// Handle an incoming encoded USP Msg bytes.
+/// The agent's endpoint ID: `usp_endpoint_id` if set, else built from the MAC.
+fn endpoint_id(cfg: &ClientConfig) -> EndpointId {
+    if cfg.usp_endpoint_id.is_empty() {
+        // Build from vendor OUI (00005A = placeholder) + MAC
+        debug!("Building endpoint ID from MAC: {}", cfg.mac_addr);
+        EndpointId::from_mac("00005A", &cfg.mac_addr)
+    } else {
+        debug!("Using configured endpoint ID: {}", cfg.usp_endpoint_id);
+        EndpointId::new(cfg.usp_endpoint_id.clone())
+    }
+}
+
+/// Handle an incoming encoded USP Msg bytes from Controller `from_id`.
 /// Returns encoded response bytes if a response is required.
 ///
 /// `negotiated_ver` is updated when a `GetSupportedProtoResp` is received
-/// (TR-369 §6.2.1 version negotiation).
+/// (TR-369 §6.2.1 version negotiation), unless it answers one of the MTP's
+/// self-test probes tracked in `mtp_state`.
 pub async fn handle_incoming(
     cfg: Arc<ClientConfig>,
-    _agent_id: EndpointId,
+    agent_id: EndpointId,
+    from_id: &str,
     msg_bytes: &[u8],
     negotiated_ver: Arc<Mutex<String>>,
+    mtp_state: Arc<Mutex<mtp::MtpState>>,
 ) -> Option<Vec<u8>> {
     trace!("handle_incoming called with {} bytes", msg_bytes.len());
     trace!(
@@ -133,135 +193,594 @@ pub async fn handle_incoming(
 
     let body = msg.body.as_ref()?;
 
-    let response = match msg_type {
-        MessageType::Get => {
-            debug!("Handling GET request (msg_id={})", msg_id);
-            let (paths, max_depth) = match &body.msg_body {
-                Some(MsgBody::Request(req)) => {
-                    if let Some(super::usp_msg::request::ReqType::Get(g)) = &req.req_type {
-                        debug!("GET paths: {:?}, max_depth={}", g.param_paths, g.max_depth);
-                        (g.param_paths.clone(), g.max_depth)
-                    } else {
-                        (vec![], 0)
+    // MQTT QoS 1 may redeliver a Publish: answer a repeated non-idempotent
+    // request from the cache instead of executing it a second time.
+    let dedup = is_non_idempotent(msg_type);
+    if dedup {
+        let hit = RECENT_RESPONSES
+            .lock()
+            .unwrap()
+            .check_or_reserve(from_id, &msg_id);
+        if let Some(cached) = hit {
+            warn!(
+                "Duplicate {} (msg_id={}) — replaying cached response",
+                msg_type.as_str_name(),
+                msg_id
+            );
+            return cached;
+        }
+    }
+
+    let dispatch = async {
+        match msg_type {
+            MessageType::Get => {
+                debug!("Handling GET request (msg_id={})", msg_id);
+                let (paths, max_depth) = match &body.msg_body {
+                    Some(MsgBody::Request(req)) => {
+                        if let Some(super::usp_msg::request::ReqType::Get(g)) = &req.req_type {
+                            debug!("GET paths: {:?}, max_depth={}", g.param_paths, g.max_depth);
+                            (g.param_paths.clone(), g.max_depth)
+                        } else {
+                            (vec![], 0)
+                        }
+                    }
+                    _ => (vec![], 0),
+                };
+                let params = dm::get_params(&cfg, &paths, max_depth).await;
+                debug!("GET completed: {} parameter sets retrieved", params.len());
+                build_get_resp(&msg_id, params)
+            }
+
+            MessageType::Set => {
+                debug!("Handling SET request (msg_id={})", msg_id);
+                let (updates, allow_partial) = extract_set_updates(&body);
+                let obj_paths = extract_set_obj_paths(&body);
+                debug!(
+                    "SET: {} parameter(s) to update in {} object path(s)",
+                    updates.len(),
+                    obj_paths.len()
+                );
+                trace!(
+                    "SET updates: {:?} (allow_partial={})",
+                    updates,
+                    allow_partial
+                );
+                match dm::set_params(&cfg, &updates, allow_partial).await {
+                    Ok(()) => {
+                        debug!("SET completed successfully (msg_id={})", msg_id);
+                        Some(build_set_resp(&msg_id, &obj_paths))
+                    }
+                    Err(failures) => {
+                        error!(
+                            "SET failed (msg_id={}): {} of {} parameter(s)",
+                            msg_id,
+                            failures.len(),
+                            updates.len()
+                        );
+                        Some(build_set_error(&msg_id, &failures))
                     }
                 }
-                _ => (vec![], 0),
-            };
-            let params = dm::get_params(&cfg, &paths, max_depth).await;
-            debug!("GET completed: {} parameter sets retrieved", params.len());
-            build_get_resp(&msg_id, params)
-        }
-
-        MessageType::Set => {
-            debug!("Handling SET request (msg_id={})", msg_id);
-            let updates = extract_set_updates(&body);
-            let obj_paths = extract_set_obj_paths(&body);
-            debug!(
-                "SET: {} parameter(s) to update in {} object path(s)",
-                updates.len(),
-                obj_paths.len()
-            );
-            trace!("SET updates: {:?}", updates);
-            match dm::set_params(&cfg, &updates).await {
-                Ok(()) => {
-                    debug!("SET completed successfully (msg_id={})", msg_id);
-                    Some(build_set_resp(&msg_id, &obj_paths))
+            }
+
+            MessageType::Operate => {
+                debug!("Handling OPERATE request (msg_id={})", msg_id);
+                let (command, command_key, input_args) = extract_operate(&body);
+                info!("OPERATE: command='{}', key='{}'", command, command_key);
+                trace!("OPERATE input args: {:?}", input_args);
+                let permit = match acquire_op_slot(op_slots(cfg.max_concurrent_ops)) {
+                    Ok(permit) => permit,
+                    Err(e) => {
+                        warn!("OPERATE rejected (msg_id={}): {}", msg_id, e);
+                        return Some(build_dm_error(&msg_id, &dm::DmError::ResourcesExceeded(e)));
+                    }
+                };
+                // Asynchronous command: acknowledge now, report the outcome
+                // in an OperationComplete notification
+                if dm::is_async_command(&command) {
+                    {
+                        let cfg = Arc::clone(&cfg);
+                        let command = command.clone();
+                        let command_key = command_key.clone();
+                        tokio::spawn(async move {
+                            let _permit = permit;
+                            let result = dm::operate(&cfg, &command, &input_args).await;
+                            complete_operation(command, command_key, result);
+                        });
+                    }
+                    info!("OPERATE {command} running asynchronously (msg_id={msg_id})");
+                    let mut output = HashMap::new();
+                    output.insert("status".to_string(), "in-progress".to_string());
+                    return Some(build_operate_resp(&msg_id, &command, &command_key, output));
+                }
+                // Run detached so a dm_op_timeout only stops the wait, not the
+                // operation.  The slot is held until the operation itself
+                // finishes, and the dedup entry stays in flight until then:
+                // the task records the response, so a redelivery after the
+                // timeout is not executed a second time.
+                let op = {
+                    let cfg = Arc::clone(&cfg);
+                    let command = command.clone();
+                    tokio::spawn(async move {
+                        let _permit = permit;
+                        dm::operate(&cfg, &command, &input_args).await
+                    })
+                };
+                let finish = {
+                    let from_id = from_id.to_string();
+                    let msg_id = msg_id.clone();
+                    tokio::spawn(async move {
+                        let resp = match op.await {
+                            Ok(Ok(output)) => {
+                                debug!("OPERATE completed successfully (msg_id={})", msg_id);
+                                trace!("OPERATE output: {:?}", output);
+                                build_operate_resp(&msg_id, &command, &command_key, output)
+                            }
+                            Ok(Err(e)) => {
+                                error!("OPERATE failed (msg_id={}): {}", msg_id, e);
+                                build_dm_error(&msg_id, &e)
+                            }
+                            Err(e) => {
+                                error!("OPERATE task failed (msg_id={}): {}", msg_id, e);
+                                RECENT_RESPONSES.lock().unwrap().release(&from_id, &msg_id);
+                                return build_error(&msg_id, 7002, "operation aborted");
+                            }
+                        };
+                        RECENT_RESPONSES.lock().unwrap().complete(
+                            &from_id,
+                            &msg_id,
+                            encode_msg(&resp).ok(),
+                        );
+                        resp
+                    })
+                };
+                match finish.await {
+                    Ok(resp) => Some(resp),
+                    Err(e) => {
+                        error!("OPERATE task failed (msg_id={}): {}", msg_id, e);
+                        Some(build_error(&msg_id, 7002, "operation aborted"))
+                    }
                 }
-                Err(e) => {
-                    error!("SET failed (msg_id={}): {}", msg_id, e);
-                    Some(build_error(&msg_id, 7200, &e))
+            }
+
+            // A Notify addressed to us: acknowledge it if asked to
+            MessageType::Notify => {
+                let (subscription_id, send_resp) = extract_notify_ack_args(&body);
+                debug!(
+                    "Received Notify (msg_id={}, subscription={}, send_resp={})",
+                    msg_id, subscription_id, send_resp
+                );
+                send_resp.then(|| build_notify_resp(&msg_id, &subscription_id))
+            }
+
+            MessageType::NotifyResp => {
+                debug!(
+                    "Received NotifyResp (msg_id={}) - controller acknowledged notify",
+                    msg_id
+                );
+                let subscription_id = extract_notify_resp_subscription(&body);
+                if PENDING_NOTIFIES
+                    .lock()
+                    .unwrap()
+                    .ack(&msg_id, &subscription_id)
+                {
+                    info!("Notify {} acknowledged, retransmission stopped", msg_id);
                 }
+                None
             }
-        }
 
-        MessageType::Operate => {
-            debug!("Handling OPERATE request (msg_id={})", msg_id);
-            let (command, command_key, input_args) = extract_operate(&body);
-            info!("OPERATE: command='{}', key='{}'", command, command_key);
-            trace!("OPERATE input args: {:?}", input_args);
-            match dm::operate(&cfg, &command, &input_args).await {
-                Ok(output) => {
-                    debug!("OPERATE completed successfully (msg_id={})", msg_id);
-                    trace!("OPERATE output: {:?}", output);
-                    Some(build_operate_resp(&msg_id, &command, &command_key, output))
+            MessageType::GetSupportedProtoResp => {
+                debug!("Handling GetSupportedProtoResp (msg_id={})", msg_id);
+                if mtp_state
+                    .lock()
+                    .unwrap()
+                    .probe_answered(&msg_id, Instant::now())
+                {
+                    debug!("Self-test passed (msg_id={})", msg_id);
+                    return None;
                 }
-                Err(e) => {
-                    error!("OPERATE failed (msg_id={}): {}", msg_id, e);
-                    Some(build_error(&msg_id, 7800, &e))
+                let versions = extract_supported_versions(&body);
+                info!("Controller supports USP versions: {:?}", versions);
+                // Store the first agreed version (W2: TR-369 §6.2.1)
+                if let Some(ver) = versions.first() {
+                    *negotiated_ver.lock().unwrap() = ver.clone();
+                    info!("USP version negotiated: {ver}");
                 }
+                // Send Boot! Notify now that version is negotiated
+                debug!("Building Boot! Notify after version negotiation");
+                let boot_msg = boot_notify(&cfg, collect_boot_params(&cfg));
+                if cfg.boot_notify_confirm {
+                    let ver = negotiated_ver.lock().unwrap().clone();
+                    track_notify(
+                        &boot_msg,
+                        NotifyClass::Boot,
+                        &agent_id,
+                        &cfg.controller_id,
+                        &ver,
+                    );
+                }
+                Some(boot_msg)
+            }
+
+            // TR-369 §6.1.5: GetSupportedDM - return supported data model
+            MessageType::GetSupportedDm => {
+                let req = extract_get_supported_dm_args(&body);
+                tp469::handle_get_supported_dm(&msg_id, &req)
+            }
+
+            // TR-369 §6.1.6: GetInstances - enumerate object instances
+            MessageType::GetInstances => {
+                let (obj_paths, first_level_only) = extract_get_instances_args(&body);
+
+                tp469::handle_get_instances(&cfg, &msg_id, &obj_paths, first_level_only).await
+            }
+
+            // TR-369 §6.1.3: Add - create new object instances
+            MessageType::Add => {
+                let (create_objs, allow_partial) = extract_add_args(&body);
+                let results = dm::with_confirm(
+                    &cfg,
+                    "ADD",
+                    tp469::handle_add(&cfg, &create_objs, allow_partial),
+                )
+                .await;
+
+                // Build ADD_RESP
+                Some(build_add_resp(&msg_id, results))
+            }
+
+            // TR-369 §6.1.4: Delete - remove object instances
+            MessageType::Delete => {
+                let (obj_paths, allow_partial) = extract_delete_args(&body);
+                let results = dm::with_confirm(
+                    &cfg,
+                    "DELETE",
+                    tp469::handle_delete(&cfg, &obj_paths, allow_partial),
+                )
+                .await;
+
+                // Build DELETE_RESP
+                Some(build_delete_resp(&msg_id, results))
+            }
+
+            _ => {
+                warn!("USP Agent: unknown message type {:?}", msg_type);
+                Some(build_error(&msg_id, 7000, "MESSAGE_NOT_UNDERSTOOD"))
             }
         }
+    };
+    let op_timeout = Duration::from_secs(cfg.dm_op_timeout);
+    match with_op_timeout(op_timeout, &msg_id, msg_type, dispatch).await {
+        Ok(response) => {
+            let encoded = response.and_then(|msg| encode_msg(&msg).ok());
+            if dedup {
+                RECENT_RESPONSES
+                    .lock()
+                    .unwrap()
+                    .complete(from_id, &msg_id, encoded.clone());
+            }
+            encoded
+        }
+        // Only OPERATE times out among the deduplicated requests; its
+        // detached task keeps the entry in flight and records the real
+        // response (or releases it) once the operation is over, so the
+        // timeout itself is not cached
+        Err(timed_out) => encode_msg(&timed_out).ok(),
+    }
+}
 
-        MessageType::NotifyResp => {
-            debug!(
-                "Received NotifyResp (msg_id={}) - controller acknowledged notify",
-                msg_id
+/// Bound how long one request may hold up the MTP message loop.  On expiry
+/// `Err` carries a USP Error (7002); a zero timeout waits indefinitely.
+/// SET/ADD/DELETE always run to completion, since dropping them halfway
+/// would leave staged UCI changes behind.
+async fn with_op_timeout<F>(
+    timeout: Duration,
+    msg_id: &str,
+    msg_type: MessageType,
+    dispatch: F,
+) -> Result<Option<super::usp_msg::Msg>, super::usp_msg::Msg>
+where
+    F: std::future::Future<Output = Option<super::usp_msg::Msg>>,
+{
+    if timeout.is_zero() || changes_config(msg_type) {
+        return Ok(dispatch.await);
+    }
+    match tokio::time::timeout(timeout, dispatch).await {
+        Ok(resp) => Ok(resp),
+        Err(_) => {
+            let detail = if msg_type == MessageType::Operate {
+                "still running in background"
+            } else {
+                "abandoned"
+            };
+            error!(
+                "{} (msg_id={}) timed out after {}s; {}",
+                msg_type.as_str_name(),
+                msg_id,
+                timeout.as_secs(),
+                detail
             );
-            None
+            Err(build_error(
+                msg_id,
+                7002,
+                &format!("request timed out after {}s ({detail})", timeout.as_secs()),
+            ))
+        }
+    }
+}
+
+/// Requests that stage and commit UCI changes.
+fn changes_config(msg_type: MessageType) -> bool {
+    matches!(
+        msg_type,
+        MessageType::Set | MessageType::Add | MessageType::Delete
+    )
+}
+
+// ── Notify acknowledgement ────────────────────────────────────────────────────
+
+/// How long a `send_resp` Notify waits for its NotifyResp before the first
+/// retransmission; each further retry waits twice as long as the previous.
+const NOTIFY_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// Retransmissions of an unacknowledged Notify before it is given up.
+const NOTIFY_MAX_RETRIES: u32 = 3;
+
+/// A Notify sent with `send_resp=true` that has not been acknowledged yet.
+struct PendingNotify {
+    class: NotifyClass,
+    subscription_id: String,
+    record: Vec<u8>,
+    last_sent: Instant,
+    retries: u32,
+}
+
+/// Notifies awaiting a NotifyResp, keyed by msg_id.
+struct PendingNotifies {
+    entries: BTreeMap<String, PendingNotify>,
+}
+
+impl PendingNotifies {
+    const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
         }
+    }
+
+    /// Track a freshly sent Notify.  A new Boot! supersedes any older one
+    /// still pending (it was sent on a previous connection).
+    fn track(
+        &mut self,
+        msg_id: String,
+        class: NotifyClass,
+        subscription_id: String,
+        record: Vec<u8>,
+        now: Instant,
+    ) {
+        if class == NotifyClass::Boot {
+            self.entries.retain(|_, p| p.class != NotifyClass::Boot);
+        }
+        self.entries.insert(
+            msg_id,
+            PendingNotify {
+                class,
+                subscription_id,
+                record,
+                last_sent: now,
+                retries: 0,
+            },
+        );
+    }
 
-        MessageType::GetSupportedProtoResp => {
-            debug!("Handling GetSupportedProtoResp (msg_id={})", msg_id);
-            let versions = extract_supported_versions(&body);
-            info!("Controller supports USP versions: {:?}", versions);
-            // Store the first agreed version (W2: TR-369 §6.2.1)
-            if let Some(ver) = versions.first() {
-                *negotiated_ver.lock().unwrap() = ver.clone();
-                info!("USP version negotiated: {ver}");
+    /// Stop retransmitting `msg_id`; false if it was not pending or the
+    /// NotifyResp names a different subscription.
+    fn ack(&mut self, msg_id: &str, subscription_id: &str) -> bool {
+        match self.entries.get(msg_id) {
+            Some(p) if p.subscription_id == subscription_id => {
+                self.entries.remove(msg_id);
+                true
+            }
+            Some(p) => {
+                warn!(
+                    "NotifyResp {msg_id} for subscription '{subscription_id}', expected '{}'",
+                    p.subscription_id
+                );
+                false
             }
-            // Send Boot! Notify now that version is negotiated
-            debug!("Building Boot! Notify after version negotiation");
-            let boot_params = collect_boot_params(&cfg);
-            let boot_msg = build_boot_notify("", false, boot_params);
-            Some(boot_msg)
+            None => false,
         }
+    }
+
+    /// Records whose wait (`interval`, doubled after every retry) has run
+    /// out, marked as resent.  Records already retransmitted
+    /// [`NOTIFY_MAX_RETRIES`] times are dropped instead.
+    fn due(&mut self, now: Instant, interval: Duration) -> Vec<(NotifyClass, Vec<u8>)> {
+        let expired =
+            |p: &PendingNotify| now.duration_since(p.last_sent) >= interval * (1 << p.retries);
+        self.entries.retain(|msg_id, p| {
+            let give_up = p.retries >= NOTIFY_MAX_RETRIES && expired(p);
+            if give_up {
+                warn!(
+                    "No NotifyResp for {:?} ({msg_id}) after {} retries, giving up",
+                    p.class, p.retries
+                );
+            }
+            !give_up
+        });
+        self.entries
+            .values_mut()
+            .filter(|p| expired(p))
+            .map(|p| {
+                p.last_sent = now;
+                p.retries += 1;
+                (p.class.clone(), p.record.clone())
+            })
+            .collect()
+    }
+}
 
-        // TR-369 §6.1.5: GetSupportedDM - return supported data model
-        MessageType::GetSupportedDm => {
-            let (obj_paths, first_level_only, include_commands, include_events) =
-                extract_get_supported_dm_args(&body);
+static PENDING_NOTIFIES: Mutex<PendingNotifies> = Mutex::new(PendingNotifies::new());
+
+/// Remember a `send_resp` Notify so [`notify_retry_loop`] can resend it.
+fn track_notify(
+    msg: &super::usp_msg::Msg,
+    class: NotifyClass,
+    agent_id: &EndpointId,
+    controller_id: &str,
+    ver: &str,
+) {
+    let msg_id = match msg.header.as_ref() {
+        Some(h) => h.msg_id.clone(),
+        None => return,
+    };
+    let subscription_id = match msg.body.as_ref().and_then(|b| b.msg_body.as_ref()) {
+        Some(MsgBody::Request(super::usp_msg::Request {
+            req_type: Some(super::usp_msg::request::ReqType::Notify(n)),
+        })) => n.subscription_id.clone(),
+        _ => String::new(),
+    };
+    let record = encode_msg(msg).ok().and_then(|bytes| {
+        let rec = super::record::no_session_record(agent_id.as_str(), controller_id, bytes, ver);
+        super::record::encode_record(&rec).ok()
+    });
+    match record {
+        Some(record) => {
+            PENDING_NOTIFIES.lock().unwrap().track(
+                msg_id,
+                class,
+                subscription_id,
+                record,
+                Instant::now(),
+            );
+        }
+        None => warn!("Failed to encode notify {msg_id} for retransmission"),
+    }
+}
 
-            tp469::handle_get_supported_dm(
-                &msg_id,
-                &obj_paths,
-                first_level_only,
-                include_commands,
-                include_events,
-            )
+/// Re-queue unacknowledged Notifies until their NotifyResp arrives or the
+/// retries run out.
+async fn notify_retry_loop(outbox: Arc<Outbox>) {
+    loop {
+        tokio::time::sleep(NOTIFY_RETRY_INTERVAL / 6).await;
+        let due = PENDING_NOTIFIES
+            .lock()
+            .unwrap()
+            .due(Instant::now(), NOTIFY_RETRY_INTERVAL);
+        for (class, record) in due {
+            warn!("No NotifyResp for {:?} yet, retransmitting", class);
+            outbox.push(class, record);
         }
+    }
+}
+
+// ── OPERATE concurrency limit ─────────────────────────────────────────────────
+
+/// Shared pool of OPERATE slots, sized from `max_concurrent_ops` on first use
+/// (`None` = unlimited).
+fn op_slots(limit: usize) -> Option<&'static Arc<Semaphore>> {
+    static OP_SLOTS: OnceLock<Option<Arc<Semaphore>>> = OnceLock::new();
+    OP_SLOTS
+        .get_or_init(|| (limit > 0).then(|| Arc::new(Semaphore::new(limit))))
+        .as_ref()
+}
+
+/// Take a slot for one OPERATE without waiting; excess requests are refused
+/// rather than queued so the controller learns the device is busy.
+fn acquire_op_slot(slots: Option<&Arc<Semaphore>>) -> Result<Option<OwnedSemaphorePermit>, String> {
+    match slots {
+        None => Ok(None),
+        Some(sem) => Arc::clone(sem)
+            .try_acquire_owned()
+            .map(Some)
+            .map_err(|_| "device busy: concurrent operation limit reached".to_string()),
+    }
+}
+
+// ── Request de-duplication ────────────────────────────────────────────────────
+
+/// Number of recent msg_ids remembered for duplicate detection.
+const DEDUP_CACHE_SIZE: usize = 128;
 
-        // TR-369 §6.1.6: GetInstances - enumerate object instances
-        MessageType::GetInstances => {
-            let (obj_paths, first_level_only) = extract_get_instances_args(&body);
+/// Recently processed requests, keyed by `(from_id, msg_id)` since msg_ids
+/// are only unique per Controller.
+static RECENT_RESPONSES: Mutex<DedupCache> = Mutex::new(DedupCache::new());
 
-            tp469::handle_get_instances(&cfg, &msg_id, &obj_paths, first_level_only).await
+enum DedupEntry {
+    /// Still executing; a redelivery is dropped and the first copy answers.
+    InFlight,
+    Done(Option<Vec<u8>>),
+}
+
+/// LRU of request keys, oldest first.
+struct DedupCache {
+    entries: VecDeque<((String, String), DedupEntry)>,
+}
+
+impl DedupCache {
+    const fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
         }
+    }
 
-        // TR-369 §6.1.3: Add - create new object instances
-        MessageType::Add => {
-            let (create_objs, allow_partial) = extract_add_args(&body);
-            let results = tp469::handle_add(&cfg, &create_objs, allow_partial).await;
+    fn position(&self, from_id: &str, msg_id: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|((from, id), _)| from == from_id && id == msg_id)
+    }
 
-            // Build ADD_RESP
-            Some(build_add_resp(&msg_id, results))
+    fn insert(&mut self, from_id: &str, msg_id: &str, entry: DedupEntry) {
+        if let Some(pos) = self.position(from_id, msg_id) {
+            self.entries.remove(pos);
         }
+        if self.entries.len() >= DEDUP_CACHE_SIZE {
+            self.entries.pop_front();
+        }
+        self.entries
+            .push_back(((from_id.to_string(), msg_id.to_string()), entry));
+    }
 
-        // TR-369 §6.1.4: Delete - remove object instances
-        MessageType::Delete => {
-            let (obj_paths, allow_partial) = extract_delete_args(&body);
-            let results = tp469::handle_delete(&cfg, &obj_paths, allow_partial).await;
+    /// Look up `(from_id, msg_id)` and, on a miss, reserve it as in flight.
+    /// `Some` is a hit: the cached response, or `None` while the first
+    /// delivery is still executing.
+    fn check_or_reserve(&mut self, from_id: &str, msg_id: &str) -> Option<Option<Vec<u8>>> {
+        if msg_id.is_empty() {
+            return None;
+        }
+        let pos = match self.position(from_id, msg_id) {
+            Some(pos) => pos,
+            None => {
+                self.insert(from_id, msg_id, DedupEntry::InFlight);
+                return None;
+            }
+        };
+        // Refresh LRU position
+        let entry = self.entries.remove(pos)?;
+        let resp = match &entry.1 {
+            DedupEntry::InFlight => None,
+            DedupEntry::Done(resp) => resp.clone(),
+        };
+        self.entries.push_back(entry);
+        Some(resp)
+    }
 
-            // Build DELETE_RESP
-            Some(build_delete_resp(&msg_id, results))
+    fn complete(&mut self, from_id: &str, msg_id: &str, resp: Option<Vec<u8>>) {
+        if !msg_id.is_empty() {
+            self.insert(from_id, msg_id, DedupEntry::Done(resp));
         }
+    }
 
-        _ => {
-            warn!("USP Agent: unknown message type {:?}", msg_type);
-            Some(build_error(&msg_id, 7000, "MESSAGE_NOT_UNDERSTOOD"))
+    fn release(&mut self, from_id: &str, msg_id: &str) {
+        if let Some(pos) = self.position(from_id, msg_id) {
+            self.entries.remove(pos);
         }
-    };
+    }
+}
 
-    response.and_then(|msg| encode_msg(&msg).ok())
+/// Requests whose re-execution has side effects (reboot twice, duplicate lease).
+fn is_non_idempotent(msg_type: MessageType) -> bool {
+    matches!(
+        msg_type,
+        MessageType::Set | MessageType::Operate | MessageType::Add | MessageType::Delete
+    )
 }
 
 // ── Boot params ───────────────────────────────────────────────────────────────
@@ -326,10 +845,26 @@ fn collect_boot_params(cfg: &ClientConfig) -> HashMap<String, String> {
         m.insert("Device.IP.Interface.1.IPAddress".into(), local_ip);
     }
 
+    // Empty until the first controller-pushed config has been applied
+    m.insert(
+        "Device.DeviceInfo.X_OptimACS_ConfigAppliedTime".into(),
+        crate::apply::config_applied_time(&cfg.cert_dir),
+    );
+
     // TR-181 §9.3.6 required Boot! event parameters
     m.insert("Cause".into(), "LocalReboot".into());
     m.insert("FirmwareUpdated".into(), "false".into());
 
+    // Tells the controller whether to drive IssueCert() (bootstrap identity)
+    m.insert(
+        "Device.X_OptimACS_Security.Provisioned".into(),
+        crate::tls::device_certs_exist(cfg).to_string(),
+    );
+    m.insert(
+        "Device.X_OptimACS_Security.X_OptimACS_EnrollStatus".into(),
+        dm::security::current_enroll_status(cfg).into(),
+    );
+
     // OptimACS claim token — links device to a tenant account
     if !cfg.claim_token.is_empty() {
         m.insert(
@@ -341,93 +876,635 @@ fn collect_boot_params(cfg: &ClientConfig) -> HashMap<String, String> {
     m
 }
 
+/// Boot! Notify carrying `params`.  Boot! is always sent, under the ID of an
+/// Event subscription to it if the controller created one.  With
+/// `boot_notify_confirm` it asks for a NotifyResp, and a lost Boot! is
+/// retransmitted until one arrives.
+fn boot_notify(cfg: &ClientConfig, params: HashMap<String, String>) -> super::usp_msg::Msg {
+    let boot_sub = subscriptions::event_recipients("Device.Boot!")
+        .into_iter()
+        .next()
+        .map(|(id, _)| id)
+        .unwrap_or_default();
+    build_boot_notify(&boot_sub, cfg.boot_notify_confirm, params)
+}
+
+// ── Re-register ──────────────────────────────────────────────────────────────
+
+/// Boot! `Cause` of a re-registration requested by the controller.
+pub const REREGISTER_CAUSE: &str = "X_OptimACS_ReRegister";
+
+/// Boot! parameters waiting to be sent by [`reregister_loop`]; a newer
+/// request replaces an unsent one.
+static REREGISTER: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+static REREGISTER_READY: Notify = Notify::const_new();
+
+/// `Device.LocalAgent.X_OptimACS_ReRegister()`: queue a fresh Boot! Notify
+/// so the controller re-learns the device without a reboot.  Returns the
+/// endpoint ID and the Boot! parameters.
+pub fn request_reregister(cfg: &ClientConfig) -> (EndpointId, HashMap<String, String>) {
+    let mut params = collect_boot_params(cfg);
+    params.insert("Cause".into(), REREGISTER_CAUSE.into());
+    *REREGISTER.lock().unwrap() = Some(params.clone());
+    REREGISTER_READY.notify_one();
+    (endpoint_id(cfg), params)
+}
+
+/// Send the Boot! of each re-register request through the outbox, which
+/// hands it to the connected MTP (or holds it until one connects).
+async fn reregister_loop(
+    cfg: Arc<ClientConfig>,
+    agent_id: EndpointId,
+    outbox: Arc<Outbox>,
+    negotiated_ver: Arc<Mutex<String>>,
+) {
+    loop {
+        REREGISTER_READY.notified().await;
+        let ver = negotiated_ver.lock().unwrap().clone();
+        queue_reregister(&cfg, &agent_id, &outbox, &ver);
+    }
+}
+
+fn queue_reregister(cfg: &ClientConfig, agent_id: &EndpointId, outbox: &Outbox, ver: &str) {
+    let params = match REREGISTER.lock().unwrap().take() {
+        Some(p) => p,
+        None => return,
+    };
+    let msg = boot_notify(cfg, params);
+    if cfg.boot_notify_confirm {
+        track_notify(&msg, NotifyClass::Boot, agent_id, &cfg.controller_id, ver);
+    }
+    let record = encode_msg(&msg).ok().and_then(|bytes| {
+        let rec =
+            super::record::no_session_record(agent_id.as_str(), &cfg.controller_id, bytes, ver);
+        super::record::encode_record(&rec).ok()
+    });
+    match record {
+        Some(bytes) => {
+            info!("Queueing Boot! for re-registration ({} bytes)", bytes.len());
+            outbox.push(NotifyClass::Boot, bytes);
+        }
+        None => warn!("Failed to encode re-registration Boot!"),
+    }
+}
+
+// ── Asynchronous operations ──────────────────────────────────────────────────
+
+/// An asynchronous OPERATE that has finished: command path, command_key and
+/// outcome.
+type CompletedOperation = (String, String, Result<HashMap<String, String>, dm::DmError>);
+
+/// Finished operations waiting for [`operation_complete_loop`].
+static COMPLETED_OPERATIONS: Mutex<VecDeque<CompletedOperation>> = Mutex::new(VecDeque::new());
+static OPERATION_COMPLETED: Notify = Notify::const_new();
+
+/// Hand the outcome of an asynchronous command to the OperationComplete
+/// sender.
+fn complete_operation(
+    command: String,
+    command_key: String,
+    result: Result<HashMap<String, String>, dm::DmError>,
+) {
+    match &result {
+        Ok(_) => info!("{command} (key '{command_key}') completed"),
+        Err(e) => error!("{command} (key '{command_key}') failed: {e}"),
+    }
+    COMPLETED_OPERATIONS
+        .lock()
+        .unwrap()
+        .push_back((command, command_key, result));
+    OPERATION_COMPLETED.notify_one();
+}
+
+/// Send an OperationComplete Notify through the outbox for every finished
+/// asynchronous command.
+async fn operation_complete_loop(
+    cfg: Arc<ClientConfig>,
+    agent_id: EndpointId,
+    outbox: Arc<Outbox>,
+    negotiated_ver: Arc<Mutex<String>>,
+) {
+    loop {
+        OPERATION_COMPLETED.notified().await;
+        let ver = negotiated_ver.lock().unwrap().clone();
+        queue_operation_complete(&cfg, &agent_id, &outbox, &ver);
+    }
+}
+
+/// Queue an OperationComplete per matching subscription for every finished
+/// command; none is sent without one.  A verified firmware image is flashed
+/// once its notifications are out.
+fn queue_operation_complete(cfg: &ClientConfig, agent_id: &EndpointId, outbox: &Outbox, ver: &str) {
+    let completed: Vec<CompletedOperation> =
+        COMPLETED_OPERATIONS.lock().unwrap().drain(..).collect();
+    for (command, command_key, result) in completed {
+        let result = result.map_err(|e| (e.code(), e.to_string()));
+        let recipients = subscriptions::operation_complete_recipients(&command);
+        if recipients.is_empty() {
+            debug!("No OperationComplete subscription for {command}; not notifying");
+        }
+        let mut sent = Vec::new();
+        for (sub_id, send_resp) in recipients {
+            let msg = build_operation_complete_notify(
+                &sub_id,
+                send_resp,
+                &command,
+                &command_key,
+                result.clone(),
+            );
+            if send_resp {
+                track_notify(
+                    &msg,
+                    NotifyClass::OperationComplete,
+                    agent_id,
+                    &cfg.controller_id,
+                    ver,
+                );
+            }
+            let record = encode_msg(&msg).ok().and_then(|bytes| {
+                let rec = super::record::no_session_record(
+                    agent_id.as_str(),
+                    &cfg.controller_id,
+                    bytes,
+                    ver,
+                );
+                super::record::encode_record(&rec).ok()
+            });
+            match record {
+                Some(bytes) => {
+                    info!(
+                        "Queueing OperationComplete for {command} ({} bytes)",
+                        bytes.len()
+                    );
+                    sent.push(outbox.push_tracked(NotifyClass::OperationComplete, bytes));
+                }
+                None => warn!("Failed to encode OperationComplete for {command}"),
+            }
+        }
+        if dm::firmware::is_download(&command) {
+            tokio::spawn(dm::firmware::flash_staged(async move {
+                for s in sent {
+                    s.notified().await;
+                }
+            }));
+        }
+    }
+}
+
 // ── Status heartbeat ─────────────────────────────────────────────────────────
 
-/// Channel sender type for status updates
-pub type StatusSender = mpsc::Sender<Vec<u8>>;
+/// Current heartbeat period.  Changed at runtime by SIGHUP or a USP SET of
+/// `Device.LocalAgent.X_OptimACS_StatusInterval`.
+static STATUS_INTERVAL: OnceLock<watch::Sender<Duration>> = OnceLock::new();
+
+fn status_interval_tx() -> &'static watch::Sender<Duration> {
+    STATUS_INTERVAL
+        .get_or_init(|| watch::channel(Duration::from_secs(crate::config::STATUS_INTERVAL)).0)
+}
+
+/// Heartbeat period currently in effect, in seconds.
+pub fn status_interval() -> u64 {
+    status_interval_tx().borrow().as_secs()
+}
+
+/// Change the heartbeat period; the running status loop picks it up
+/// immediately instead of waiting out its current sleep.
+pub fn set_status_interval(secs: u64) -> Result<(), String> {
+    if secs == 0 {
+        return Err("status_interval must be at least 1 second".into());
+    }
+    let new = Duration::from_secs(secs);
+    let old = status_interval_tx().send_replace(new);
+    if old != new {
+        info!("Status interval changed: {}s -> {}s", old.as_secs(), secs);
+    }
+    Ok(())
+}
+
+/// Sleep for one heartbeat period.  An interval change while sleeping
+/// restarts the wait with the new period, so shortening a long interval takes
+/// effect straight away.
+async fn wait_status_tick(rx: &mut watch::Receiver<Duration>) {
+    loop {
+        let period = *rx.borrow_and_update();
+        tokio::select! {
+            _ = tokio::time::sleep(period) => return,
+            changed = rx.changed() => {
+                if changed.is_err() {
+                    tokio::time::sleep(period).await;
+                    return;
+                }
+            }
+        }
+    }
+}
 
 async fn status_loop(
     cfg: Arc<ClientConfig>,
     agent_id: EndpointId,
-    _gnss: Arc<std::sync::Mutex<Option<GnssPosition>>>,
-    tx: StatusSender,
+    gnss: Arc<std::sync::Mutex<Option<GnssPosition>>>,
+    outbox: Arc<Outbox>,
+    negotiated_ver: Arc<Mutex<String>>,
 ) {
-    let interval = Duration::from_secs(cfg.status_interval);
+    let mut interval_rx = status_interval_tx().subscribe();
     let controller_id = cfg.controller_id.clone();
 
     // Store previous values for delta tracking
     let mut prev_uptime = String::new();
     let mut prev_load = String::new();
     let mut prev_mem = String::new();
+    let mut prev_flash = String::new();
+    let mut prev_conntrack = String::new();
+    let mut prev_neighbors = String::new();
+    let mut prev_power: HashMap<&'static str, String> = HashMap::new();
+    let mut prev_links: HashMap<String, String> = HashMap::new();
+    let mut prev_traffic: HashMap<String, String> = HashMap::new();
+    let mut prev_position: HashMap<&'static str, String> = HashMap::new();
+    let mut prev_subscribed: HashMap<String, String> = HashMap::new();
 
     loop {
-        tokio::time::sleep(interval).await;
+        wait_status_tick(&mut interval_rx).await;
+        let ver = negotiated_ver.lock().unwrap().clone();
+
+        // Once the controller has subscribed to ValueChanges, notify exactly
+        // what it asked for instead of the built-in heartbeat set.
+        let subs = subscriptions::enabled(NotifType::ValueChange);
+        if !subs.is_empty() {
+            notify_subscribed_changes(&cfg, &subs, &mut prev_subscribed, &agent_id, &outbox, &ver)
+                .await;
+            continue;
+        }
+        prev_subscribed.clear();
 
         // Read current values
         let uptime = util::read_uptime();
         let load = util::read_load_avg();
         let mem = util::read_free_mem();
+        let flash = util::read_flash_usage()
+            .map(|u| u.free_kb.to_string())
+            .unwrap_or_default();
+        let conntrack = util::read_conntrack()
+            .map(|ct| ct.count.to_string())
+            .unwrap_or_default();
 
         // Only send changed values (delta updates)
-        let mut params_to_send: Vec<(&str, String)> = Vec::new();
+        let mut params_to_send: Vec<(String, String)> = Vec::new();
 
         if uptime != prev_uptime {
-            params_to_send.push(("Device.DeviceInfo.UpTime", uptime.clone()));
+            params_to_send.push(("Device.DeviceInfo.UpTime".into(), uptime.clone()));
             prev_uptime = uptime;
         }
 
         if load != prev_load {
-            params_to_send.push(("Device.DeviceInfo.X_OptimACS_LoadAvg", load.clone()));
+            params_to_send.push(("Device.DeviceInfo.X_OptimACS_LoadAvg".into(), load.clone()));
             prev_load = load;
         }
 
         if mem != prev_mem {
-            params_to_send.push(("Device.DeviceInfo.X_OptimACS_FreeMem", mem.clone()));
+            params_to_send.push(("Device.DeviceInfo.X_OptimACS_FreeMem".into(), mem.clone()));
             prev_mem = mem;
         }
 
+        if !flash.is_empty() && flash != prev_flash {
+            params_to_send.push((
+                "Device.DeviceInfo.X_OptimACS_FlashFree".into(),
+                flash.clone(),
+            ));
+            prev_flash = flash;
+        }
+
+        if !conntrack.is_empty() && conntrack != prev_conntrack {
+            params_to_send.push((
+                "Device.DeviceInfo.X_OptimACS_ConntrackCount".into(),
+                conntrack.clone(),
+            ));
+            prev_conntrack = conntrack;
+        }
+
+        // Nearby BSSes from the cached WiFi scans (empty until one has run)
+        let neighbors = dm::device_info::neighbor_count();
+        if !neighbors.is_empty() && neighbors != prev_neighbors {
+            params_to_send.push((
+                "Device.DeviceInfo.X_OptimACS_NeighborCount".into(),
+                neighbors.clone(),
+            ));
+            prev_neighbors = neighbors;
+        }
+
+        // Power source and battery (PoE/battery-powered hardware)
+        let power = util::read_power_supply(&cfg.power_sysfs_path);
+        let power_params = [
+            ("X_OptimACS_PowerSource", Some(power.source)),
+            (
+                "X_OptimACS_BatteryLevel",
+                power.capacity.map(|p| p.to_string()),
+            ),
+            (
+                "X_OptimACS_PowerVoltage",
+                power.voltage_mv.map(|v| v.to_string()),
+            ),
+        ];
+        for (name, val) in power_params {
+            let val = match val {
+                Some(v) => v,
+                None => continue,
+            };
+            if prev_power.get(name) != Some(&val) {
+                params_to_send.push((format!("Device.DeviceInfo.{name}"), val.clone()));
+                prev_power.insert(name, val);
+            }
+        }
+
+        // Ethernet link speed per port (cabling diagnostics)
+        for (idx, iface) in util::list_ethernet_interfaces().iter().enumerate() {
+            let link = util::read_link_speed(iface).describe();
+            if prev_links.get(iface) != Some(&link) {
+                params_to_send.push((
+                    format!("Device.Ethernet.Interface.{}.X_OptimACS_LinkSpeed", idx + 1),
+                    link.clone(),
+                ));
+                prev_links.insert(iface.clone(), link);
+            }
+        }
+
+        // Uplink byte counters (throughput monitoring)
+        for (path, val) in dm::ip::uplink_stats() {
+            if prev_traffic.get(&path) != Some(&val) {
+                params_to_send.push((path.clone(), val.clone()));
+                prev_traffic.insert(path, val);
+            }
+        }
+
+        // GNSS position (individual and combined values from one snapshot);
+        // a fix older than gnss_max_age is reported as no fix
+        let fix = gnss
+            .lock()
+            .ok()
+            .and_then(|g| g.clone())
+            .filter(|p| !p.is_stale(cfg.gnss_max_age));
+        for (suffix, val) in crate::gnss::position_params(fix.as_ref()) {
+            if prev_position
+                .get(suffix)
+                .map_or(!val.is_empty(), |p| *p != val)
+            {
+                params_to_send.push((format!("Device.DeviceInfo.{suffix}"), val.clone()));
+                prev_position.insert(suffix, val);
+            }
+        }
+
         // Send only changed parameters
         if !params_to_send.is_empty() {
             for (path, val) in &params_to_send {
                 info!("USP status (delta): {path} = {val}");
+                queue_value_change(
+                    &agent_id,
+                    &controller_id,
+                    &outbox,
+                    &ver,
+                    STATUS_SUBSCRIPTION_ID,
+                    false,
+                    path,
+                    val,
+                );
+            }
+        } else {
+            debug!("No status changes, skipping delta update");
+        }
+    }
+}
 
-                // Build ValueChange Notify message
-                let msg = build_value_change_notify("status", path, val);
-
-                // Encode to USP record
-                match encode_msg(&msg) {
-                    Ok(msg_bytes) => {
-                        let record = super::record::no_session_record(
-                            agent_id.as_str(),
-                            &controller_id,
-                            msg_bytes,
-                            "1.3",
-                        );
+/// ValueChange notifications for controller-created subscriptions: read every
+/// referenced parameter and notify each matching subscription of values that
+/// differ from the previous tick.  A parameter seen for the first time only
+/// sets the baseline.
+async fn notify_subscribed_changes(
+    cfg: &ClientConfig,
+    subs: &[Subscription],
+    prev: &mut HashMap<String, String>,
+    agent_id: &EndpointId,
+    outbox: &Outbox,
+    ver: &str,
+) {
+    let mut read_paths: Vec<&str> = subs
+        .iter()
+        .flat_map(|s| s.reference_list.iter())
+        .map(|r| subscriptions::read_path(r))
+        .collect();
+    read_paths.sort_unstable();
+    read_paths.dedup();
 
-                        match super::record::encode_record(&record) {
-                            Ok(record_bytes) => {
-                                info!(
-                                    "Sending delta update ({} bytes): {path} = {val}",
-                                    record_bytes.len()
-                                );
-                                if let Err(e) = tx.send(record_bytes).await {
-                                    warn!("Failed to send status update: {e}");
-                                }
-                            }
-                            Err(e) => warn!("Failed to encode record: {e}"),
-                        }
-                    }
-                    Err(e) => warn!("Failed to encode message: {e}"),
+    let mut current = HashMap::new();
+    for path in read_paths {
+        current.extend(dm::get_all(cfg, path).await);
+    }
+    current.retain(|path, _| subs.iter().any(|s| s.references(path)));
+
+    let mut changed: Vec<(&String, &String)> = current
+        .iter()
+        .filter(|(path, val)| prev.get(*path).map_or(false, |p| p != *val))
+        .collect();
+    changed.sort();
+    for (path, val) in changed {
+        for sub in subs.iter().filter(|s| s.references(path)) {
+            info!("USP ValueChange ({}): {path} = {val}", sub.id);
+            queue_value_change(
+                agent_id,
+                &cfg.controller_id,
+                outbox,
+                ver,
+                &sub.id,
+                sub.notif_retry,
+                path,
+                val,
+            );
+        }
+    }
+    *prev = current;
+}
+
+/// Encode a ValueChange Notify and queue it.  With `send_resp` the record is
+/// also retransmitted until the controller's NotifyResp arrives.
+fn queue_value_change(
+    agent_id: &EndpointId,
+    controller_id: &str,
+    outbox: &Outbox,
+    ver: &str,
+    subscription_id: &str,
+    send_resp: bool,
+    path: &str,
+    val: &str,
+) {
+    let msg = build_value_change_notify(subscription_id, send_resp, path, val);
+    let class = NotifyClass::ValueChange(path.to_string());
+    if send_resp {
+        track_notify(&msg, class.clone(), agent_id, controller_id, ver);
+    }
+
+    // Encode to USP record
+    match encode_msg(&msg) {
+        Ok(msg_bytes) => {
+            let record =
+                super::record::no_session_record(agent_id.as_str(), controller_id, msg_bytes, ver);
+
+            match super::record::encode_record(&record) {
+                Ok(record_bytes) => {
+                    info!(
+                        "Queueing delta update ({} bytes): {path} = {val}",
+                        record_bytes.len()
+                    );
+                    outbox.push(class, record_bytes);
                 }
+                Err(e) => warn!("Failed to encode record: {e}"),
             }
+        }
+        Err(e) => warn!("Failed to encode message: {e}"),
+    }
+}
+
+// ── Temperature alarm ─────────────────────────────────────────────────────────
+
+/// How often the thermal zones are sampled for the alarm.
+const TEMP_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Degrees below the threshold the temperature must fall before the alarm
+/// clears, so a reading hovering at the threshold doesn't flap.
+const TEMP_ALARM_HYSTERESIS_C: f64 = 5.0;
+
+/// High-temperature alarm state with hysteresis.
+struct TempAlarm {
+    threshold: f64,
+    raised: bool,
+}
+
+impl TempAlarm {
+    fn new(threshold_c: u32) -> Self {
+        Self {
+            threshold: threshold_c as f64,
+            raised: false,
+        }
+    }
+
+    /// Feed a reading; returns the new state when the alarm raises or clears.
+    fn update(&mut self, temp_c: f64) -> Option<bool> {
+        if !self.raised && temp_c >= self.threshold {
+            self.raised = true;
+            Some(true)
+        } else if self.raised && temp_c <= self.threshold - TEMP_ALARM_HYSTERESIS_C {
+            self.raised = false;
+            Some(false)
         } else {
-            debug!("No status changes, skipping delta update");
+            None
+        }
+    }
+}
+
+/// Seconds between station snapshots for the WiFi client history.
+const STATION_SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Snapshot associated stations periodically so connects and disconnects
+/// land in `X_OptimACS_ClientHistory` without the controller polling.
+async fn station_sample_loop() {
+    let mut tick = tokio::time::interval(STATION_SAMPLE_INTERVAL);
+    loop {
+        tick.tick().await;
+        if let Err(e) = tokio::task::spawn_blocking(dm::wifi::sample_stations).await {
+            warn!("Station sampling failed: {e}");
+        }
+    }
+}
+
+/// Emit `Device.DeviceInfo.X_OptimACS_TempAlarm!` when the hottest thermal
+/// zone crosses `temp_alarm_c` (and again when it has cooled down).
+async fn temp_monitor_loop(
+    cfg: Arc<ClientConfig>,
+    agent_id: EndpointId,
+    outbox: Arc<Outbox>,
+    negotiated_ver: Arc<Mutex<String>>,
+) {
+    let mut alarm = TempAlarm::new(cfg.temp_alarm_c);
+    info!("Temperature alarm armed at {}°C", cfg.temp_alarm_c);
+
+    loop {
+        tokio::time::sleep(TEMP_CHECK_INTERVAL).await;
+        let temp = match util::read_max_temperature_c() {
+            Some(t) => t,
+            None => continue,
+        };
+        let raised = match alarm.update(temp) {
+            Some(r) => r,
+            None => continue,
+        };
+        let state = if raised { "Raised" } else { "Cleared" };
+        warn!(
+            "Temperature alarm {state}: {temp:.1}°C (threshold {}°C)",
+            cfg.temp_alarm_c
+        );
+
+        let mut params = HashMap::new();
+        params.insert("State".to_string(), state.to_string());
+        params.insert("Temperature".to_string(), format!("{temp:.1}"));
+        params.insert("Threshold".to_string(), cfg.temp_alarm_c.to_string());
+        let ver = negotiated_ver.lock().unwrap().clone();
+        for (sub_id, send_resp) in
+            subscriptions::event_recipients("Device.DeviceInfo.X_OptimACS_TempAlarm!")
+        {
+            let msg = build_event_notify(
+                &sub_id,
+                send_resp,
+                "Device.DeviceInfo.",
+                "X_OptimACS_TempAlarm!",
+                params.clone(),
+            );
+            if send_resp {
+                track_notify(
+                    &msg,
+                    NotifyClass::Event,
+                    &agent_id,
+                    &cfg.controller_id,
+                    &ver,
+                );
+            }
+            let record = encode_msg(&msg).ok().and_then(|bytes| {
+                let rec = super::record::no_session_record(
+                    agent_id.as_str(),
+                    &cfg.controller_id,
+                    bytes,
+                    &ver,
+                );
+                super::record::encode_record(&rec).ok()
+            });
+            match record {
+                Some(bytes) => outbox.push(NotifyClass::Event, bytes),
+                None => warn!("Failed to encode temperature alarm"),
+            }
         }
     }
 }
 
 // ── Helpers ───────────────────────────────────────────────────────────────────
 
+/// ERROR response for a SET in which parameters failed, one `param_errs`
+/// entry each carrying its own code.  The top-level code is always 7200
+/// (Unable to apply Set, TR-369 §10.6.3).
+fn build_set_error(msg_id: &str, failures: &[dm::ParamFailure]) -> super::usp_msg::Msg {
+    let param_errs: Vec<(String, u32, String)> = failures
+        .iter()
+        .map(|f| (f.path.clone(), f.error.code(), f.error.to_string()))
+        .collect();
+    let err_msg = match param_errs.as_slice() {
+        [(_, _, msg)] => msg.clone(),
+        _ => format!("{} parameters failed", param_errs.len()),
+    };
+    build_param_errors(msg_id, 7200, &err_msg, &param_errs)
+}
+
+/// ERROR response carrying the USP code (and parameter) of a data-model failure.
+fn build_dm_error(msg_id: &str, e: &dm::DmError) -> super::usp_msg::Msg {
+    match e.param_path() {
+        Some(path) => build_param_error(msg_id, e.code(), &e.to_string(), path),
+        None => build_error(msg_id, e.code(), &e.to_string()),
+    }
+}
+
 fn build_get_resp(msg_id: &str, params: HashMap<String, String>) -> Option<super::usp_msg::Msg> {
     use super::usp_msg::{get_resp::*, *};
     Some(super::usp_msg::Msg {
@@ -460,11 +1537,14 @@ fn build_get_resp(msg_id: &str, params: HashMap<String, String>) -> Option<super
     })
 }
 
-fn extract_set_updates(body: &super::usp_msg::Body) -> Vec<(String, String)> {
+/// (path, value) pairs of an incoming Set, and its `allow_partial` flag.
+fn extract_set_updates(body: &super::usp_msg::Body) -> (Vec<(String, String)>, bool) {
     use super::usp_msg::body::MsgBody;
     let mut updates = vec![];
+    let mut allow_partial = false;
     if let Some(MsgBody::Request(req)) = &body.msg_body {
         if let Some(super::usp_msg::request::ReqType::Set(s)) = &req.req_type {
+            allow_partial = s.allow_partial;
             for obj in &s.update_objs {
                 for param in &obj.param_settings {
                     updates.push((
@@ -475,7 +1555,7 @@ fn extract_set_updates(body: &super::usp_msg::Body) -> Vec<(String, String)> {
             }
         }
     }
-    updates
+    (updates, allow_partial)
 }
 
 fn extract_set_obj_paths(body: &super::usp_msg::Body) -> Vec<String> {
@@ -504,6 +1584,26 @@ fn extract_operate(body: &super::usp_msg::Body) -> (String, String, HashMap<Stri
     (String::new(), String::new(), HashMap::new())
 }
 
+/// Subscription ID and `send_resp` flag of an incoming Notify.
+fn extract_notify_ack_args(body: &super::usp_msg::Body) -> (String, bool) {
+    if let Some(MsgBody::Request(req)) = &body.msg_body {
+        if let Some(super::usp_msg::request::ReqType::Notify(n)) = &req.req_type {
+            return (n.subscription_id.clone(), n.send_resp);
+        }
+    }
+    (String::new(), false)
+}
+
+/// Subscription ID echoed in a NotifyResp.
+fn extract_notify_resp_subscription(body: &super::usp_msg::Body) -> String {
+    if let Some(MsgBody::Response(resp)) = &body.msg_body {
+        if let Some(super::usp_msg::response::RespType::NotifyResp(r)) = &resp.resp_type {
+            return r.subscription_id.clone();
+        }
+    }
+    String::new()
+}
+
 fn extract_supported_versions(body: &super::usp_msg::Body) -> Vec<String> {
     if let Some(MsgBody::Response(resp)) = &body.msg_body {
         if let Some(super::usp_msg::response::RespType::GetSupportedProtoResp(r)) = &resp.resp_type
@@ -521,19 +1621,17 @@ fn extract_supported_versions(body: &super::usp_msg::Body) -> Vec<String> {
 
 // ── TP-469 Helper Functions ───────────────────────────────────────────────────
 
-fn extract_get_supported_dm_args(body: &super::usp_msg::Body) -> (Vec<String>, bool, bool, bool) {
+fn extract_get_supported_dm_args(body: &super::usp_msg::Body) -> super::usp_msg::GetSupportedDm {
     use super::usp_msg::body::MsgBody;
     if let Some(MsgBody::Request(req)) = &body.msg_body {
         if let Some(super::usp_msg::request::ReqType::GetSupportedDm(r)) = &req.req_type {
-            return (
-                r.obj_paths.clone(),
-                r.first_level_only,
-                r.return_commands,
-                r.return_events,
-            );
+            return r.clone();
         }
     }
-    (vec![], true, false, false)
+    super::usp_msg::GetSupportedDm {
+        first_level_only: true,
+        ..Default::default()
+    }
 }
 
 fn extract_get_instances_args(body: &super::usp_msg::Body) -> (Vec<String>, bool) {
@@ -658,3 +1756,483 @@ fn build_delete_resp(msg_id: &str, results: Vec<tp469::DeleteResult>) -> super::
         }),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usp::usp_msg::{self, request::ReqType};
+
+    fn operate_msg(msg_id: &str, command: &str) -> Vec<u8> {
+        let msg = usp_msg::Msg {
+            header: Some(usp_msg::Header {
+                msg_id: msg_id.into(),
+                msg_type: MessageType::Operate as i32,
+            }),
+            body: Some(usp_msg::Body {
+                msg_body: Some(MsgBody::Request(usp_msg::Request {
+                    req_type: Some(ReqType::Operate(usp_msg::Operate {
+                        command: command.into(),
+                        command_key: "k".into(),
+                        send_resp: true,
+                        input_args: HashMap::new(),
+                    })),
+                })),
+            }),
+        };
+        encode_msg(&msg).unwrap()
+    }
+
+    #[test]
+    fn temp_alarm_hysteresis() {
+        let mut alarm = TempAlarm::new(80);
+        assert_eq!(alarm.update(70.0), None);
+        assert_eq!(alarm.update(80.0), Some(true));
+        assert_eq!(alarm.update(85.0), None);
+        // Dipping just under the threshold must not clear it
+        assert_eq!(alarm.update(78.0), None);
+        assert_eq!(alarm.update(80.5), None);
+        assert_eq!(alarm.update(75.0), Some(false));
+        assert_eq!(alarm.update(79.0), None);
+        assert_eq!(alarm.update(81.0), Some(true));
+    }
+
+    #[tokio::test]
+    async fn hung_dm_handler_times_out() {
+        let resp = tokio::time::timeout(
+            Duration::from_secs(2),
+            with_op_timeout(
+                Duration::from_millis(50),
+                "hung-1",
+                MessageType::Get,
+                std::future::pending(),
+            ),
+        )
+        .await
+        .expect("handler timeout should fire before the test deadline")
+        .expect_err("timeout yields an error response");
+
+        match resp.body.and_then(|b| b.msg_body) {
+            Some(MsgBody::Error(e)) => assert_eq!(e.err_code, 7002),
+            other => panic!("expected Error body, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn config_change_is_not_cut_short() {
+        let resp = with_op_timeout(
+            Duration::from_millis(10),
+            "set-slow",
+            MessageType::Set,
+            async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Some(build_set_resp("set-slow", &[]))
+            },
+        )
+        .await
+        .expect("SET must not time out");
+        assert!(matches!(
+            resp.and_then(|m| m.body).and_then(|b| b.msg_body),
+            Some(MsgBody::Response(_))
+        ));
+    }
+
+    #[test]
+    fn dm_error_carries_code_and_param() {
+        let path = "Device.DeviceInfo.UpTime";
+        let resp = build_dm_error("set-1", &dm::DmError::NotWritable(path.into()));
+        match resp.body.and_then(|b| b.msg_body) {
+            Some(MsgBody::Error(e)) => {
+                assert_eq!(e.err_code, 7013);
+                assert_eq!(e.param_errs.len(), 1);
+                assert_eq!(e.param_errs[0].param_path, path);
+                assert_eq!(e.param_errs[0].err_code, 7013);
+            }
+            other => panic!("expected Error body, got {other:?}"),
+        }
+
+        let resp = build_dm_error("op-1", &dm::DmError::CommandFailure("exit 1".into()));
+        match resp.body.and_then(|b| b.msg_body) {
+            Some(MsgBody::Error(e)) => {
+                assert_eq!(e.err_code, 7022);
+                assert!(e.param_errs.is_empty());
+            }
+            other => panic!("expected Error body, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn set_error_lists_every_failed_param() {
+        let failures = vec![
+            dm::ParamFailure {
+                path: "Device.DeviceInfo.UpTime".into(),
+                error: dm::DmError::NotWritable("Device.DeviceInfo.UpTime".into()),
+            },
+            dm::ParamFailure {
+                path: "Device.WiFi.Radio.1.Channel".into(),
+                error: dm::DmError::invalid_value("Device.WiFi.Radio.1.Channel", "bad"),
+            },
+        ];
+        let errs = |msg: super::super::usp_msg::Msg| match msg.body.and_then(|b| b.msg_body) {
+            Some(MsgBody::Error(e)) => e,
+            other => panic!("expected Error body, got {other:?}"),
+        };
+
+        let e = errs(build_set_error("set-2", &failures));
+        assert_eq!(e.err_code, 7200);
+        let paths: Vec<_> = e.param_errs.iter().map(|p| p.param_path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec!["Device.DeviceInfo.UpTime", "Device.WiFi.Radio.1.Channel"]
+        );
+        assert_eq!(e.param_errs[0].err_code, 7013);
+        assert_eq!(e.param_errs[1].err_code, 7012);
+
+        // A single failure still reports 7200, its own code per parameter
+        let e = errs(build_set_error("set-3", &failures[1..]));
+        assert_eq!(e.err_code, 7200);
+        assert_eq!(e.param_errs.len(), 1);
+        assert_eq!(e.param_errs[0].err_code, 7012);
+    }
+
+    #[tokio::test]
+    async fn set_attempts_every_update() {
+        let cfg = ClientConfig::default();
+        let updates = vec![
+            ("Device.Nope.A".to_string(), "1".to_string()),
+            ("Device.Nope.B".to_string(), "2".to_string()),
+        ];
+        let failures = dm::set_params(&cfg, &updates, true).await.unwrap_err();
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[1].path, "Device.Nope.B");
+        assert_eq!(failures[1].error.code(), 7026);
+    }
+
+    #[test]
+    fn boot_retried_until_notify_resp() {
+        let mut pending = PendingNotifies::new();
+        let t0 = Instant::now();
+        let secs = Duration::from_secs;
+        pending.track(
+            "boot-1".into(),
+            NotifyClass::Boot,
+            String::new(),
+            b"boot".to_vec(),
+            t0,
+        );
+
+        assert!(pending.due(t0 + secs(10), NOTIFY_RETRY_INTERVAL).is_empty());
+        let resent = pending.due(t0 + secs(30), NOTIFY_RETRY_INTERVAL);
+        assert_eq!(resent, vec![(NotifyClass::Boot, b"boot".to_vec())]);
+        // Backoff: the next retry waits twice as long
+        assert!(pending.due(t0 + secs(60), NOTIFY_RETRY_INTERVAL).is_empty());
+        assert_eq!(pending.due(t0 + secs(90), NOTIFY_RETRY_INTERVAL).len(), 1);
+
+        assert!(!pending.ack("other", ""));
+        // A NotifyResp naming another subscription does not count
+        assert!(!pending.ack("boot-1", "sub-9"));
+        assert!(pending.ack("boot-1", ""));
+        assert!(pending
+            .due(t0 + secs(600), NOTIFY_RETRY_INTERVAL)
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn incoming_notify_acknowledged_when_send_resp() {
+        for send_resp in [true, false] {
+            let msg = build_value_change_notify("sub-1", send_resp, "Device.X", "1");
+            let resp = handle("proto::controller", &encode_msg(&msg).unwrap()).await;
+            if !send_resp {
+                assert!(resp.is_none());
+                continue;
+            }
+            let resp = decode_msg(&resp.unwrap()).unwrap();
+            assert_eq!(
+                resp.header.as_ref().unwrap().msg_id,
+                msg.header.unwrap().msg_id
+            );
+            match resp.body.and_then(|b| b.msg_body) {
+                Some(MsgBody::Response(usp_msg::Response {
+                    resp_type: Some(usp_msg::response::RespType::NotifyResp(r)),
+                })) => assert_eq!(r.subscription_id, "sub-1"),
+                other => panic!("expected NotifyResp, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn unacknowledged_notify_given_up_after_max_retries() {
+        let mut pending = PendingNotifies::new();
+        let t0 = Instant::now();
+        let secs = Duration::from_secs;
+        pending.track(
+            "boot-1".into(),
+            NotifyClass::Boot,
+            String::new(),
+            b"boot".to_vec(),
+            t0,
+        );
+
+        // Retries at 30, 30+60 and 90+120 seconds
+        assert_eq!(pending.due(t0 + secs(30), NOTIFY_RETRY_INTERVAL).len(), 1);
+        assert_eq!(pending.due(t0 + secs(90), NOTIFY_RETRY_INTERVAL).len(), 1);
+        assert_eq!(pending.due(t0 + secs(210), NOTIFY_RETRY_INTERVAL).len(), 1);
+        // The third retry gets its full wait too, then the record is dropped
+        assert!(pending
+            .due(t0 + secs(400), NOTIFY_RETRY_INTERVAL)
+            .is_empty());
+        assert!(pending.entries.contains_key("boot-1"));
+        assert!(pending
+            .due(t0 + secs(450), NOTIFY_RETRY_INTERVAL)
+            .is_empty());
+        assert!(pending.entries.is_empty());
+    }
+
+    #[test]
+    fn new_boot_supersedes_pending_one() {
+        let mut pending = PendingNotifies::new();
+        let t0 = Instant::now();
+        pending.track(
+            "boot-1".into(),
+            NotifyClass::Boot,
+            String::new(),
+            b"old".to_vec(),
+            t0,
+        );
+        pending.track(
+            "boot-2".into(),
+            NotifyClass::Boot,
+            String::new(),
+            b"new".to_vec(),
+            t0,
+        );
+
+        let resent = pending.due(t0 + NOTIFY_RETRY_INTERVAL, NOTIFY_RETRY_INTERVAL);
+        assert_eq!(resent, vec![(NotifyClass::Boot, b"new".to_vec())]);
+        assert!(!pending.ack("boot-1", ""));
+    }
+
+    #[test]
+    fn operates_beyond_limit_are_refused_as_busy() {
+        let sem = Arc::new(Semaphore::new(2));
+        let first = acquire_op_slot(Some(&sem)).unwrap();
+        let _second = acquire_op_slot(Some(&sem)).unwrap();
+        assert!(acquire_op_slot(Some(&sem)).unwrap_err().contains("busy"));
+
+        // A finished operation frees its slot
+        drop(first);
+        assert!(acquire_op_slot(Some(&sem)).unwrap().is_some());
+
+        // No limit configured
+        assert!(acquire_op_slot(None).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn status_tick_observes_mid_flight_interval_change() {
+        let (tx, mut rx) = watch::channel(Duration::from_secs(3600));
+        let tick = tokio::spawn(async move { wait_status_tick(&mut rx).await });
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        tx.send_replace(Duration::from_millis(10));
+
+        tokio::time::timeout(Duration::from_secs(2), tick)
+            .await
+            .expect("shortened interval should end the long sleep")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn async_operate_reports_operation_complete() {
+        let cmd = "Device.X_OptimACS_Firmware.Download()";
+        let resp = handle("proto::controller", &operate_msg("fw-async", cmd))
+            .await
+            .unwrap();
+        let resp = match decode_msg(&resp).unwrap().body.unwrap().msg_body {
+            Some(MsgBody::Response(usp_msg::Response {
+                resp_type: Some(usp_msg::response::RespType::OperateResp(r)),
+            })) => r,
+            other => panic!("not an OperateResp: {other:?}"),
+        };
+        assert_eq!(resp.command_key, "k");
+        match &resp.operation_results[0].operate_resp_type {
+            Some(usp_msg::operate_resp::operation_result::OperateRespType::ReqOutputArgs(o)) => {
+                assert_eq!(o.output_args["status"], "in-progress")
+            }
+            other => panic!("no output args: {other:?}"),
+        }
+
+        // No url: the download fails and the error comes back asynchronously
+        tokio::time::timeout(Duration::from_secs(2), OPERATION_COMPLETED.notified())
+            .await
+            .unwrap();
+        let outbox = Outbox::new(4);
+        let cfg = ClientConfig::default();
+        let sub = [
+            ("ID", "oc-fw"),
+            ("Enable", "true"),
+            ("NotifType", "OperationComplete"),
+            ("ReferenceList", "Device.X_OptimACS_Firmware.Download()"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        let instance = subscriptions::add(&sub).unwrap();
+        queue_operation_complete(&cfg, &EndpointId::new("proto::agent"), &outbox, "1.3");
+        let item = outbox.next().await;
+        assert_eq!(item.class, NotifyClass::OperationComplete);
+
+        // A command no subscription covers is not notified
+        complete_operation(
+            "Device.IP.Diagnostics.IPPing()".into(),
+            "p".into(),
+            Ok(HashMap::new()),
+        );
+        queue_operation_complete(&cfg, &EndpointId::new("proto::agent"), &outbox, "1.3");
+        assert_eq!(outbox.len(), 0);
+        subscriptions::delete(instance).unwrap();
+        let rec = super::super::record::decode_record(&item.record).unwrap();
+        let payload = super::super::record::extract_msg_payload(&rec).unwrap();
+        let (sub_id, oc) = match decode_msg(payload).unwrap().body.unwrap().msg_body {
+            Some(MsgBody::Request(usp_msg::Request {
+                req_type:
+                    Some(ReqType::Notify(usp_msg::Notify {
+                        subscription_id,
+                        notification: Some(usp_msg::notify::Notification::OperComplete(oc)),
+                        ..
+                    })),
+            })) => (subscription_id, oc),
+            other => panic!("not an OperationComplete: {other:?}"),
+        };
+        assert_eq!(sub_id, "oc-fw");
+        assert_eq!(oc.obj_path, "Device.X_OptimACS_Firmware.");
+        assert_eq!(oc.command_name, "Download()");
+        assert_eq!(oc.command_key, "k");
+        match oc.operate_resp_type {
+            Some(usp_msg::notify::oper_complete::OperateRespType::CmdFailure(f)) => {
+                assert_eq!(f.err_code, 7027)
+            }
+            other => panic!("expected a failure: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn zero_status_interval_rejected() {
+        assert!(set_status_interval(0).is_err());
+    }
+
+    async fn handle(from_id: &str, bytes: &[u8]) -> Option<Vec<u8>> {
+        handle_incoming(
+            Arc::new(ClientConfig::default()),
+            EndpointId::new("proto::agent"),
+            from_id,
+            bytes,
+            Arc::new(Mutex::new("1.3".into())),
+            Arc::new(Mutex::new(mtp::MtpState::default())),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn duplicate_msg_id_replays_cached_response() {
+        // A cached entry short-circuits execution entirely
+        RECENT_RESPONSES.lock().unwrap().complete(
+            "proto::dup-ctrl",
+            "dup-cached",
+            Some(b"cached".to_vec()),
+        );
+        let resp = handle(
+            "proto::dup-ctrl",
+            &operate_msg("dup-cached", "Device.Nope()"),
+        )
+        .await;
+        assert_eq!(resp, Some(b"cached".to_vec()));
+
+        // First delivery executes, redelivery returns the identical response
+        let bytes = operate_msg("dup-fresh", "Device.Nope()");
+        let first = handle("proto::dup-ctrl", &bytes).await;
+        assert!(first.is_some());
+        assert_eq!(handle("proto::dup-ctrl", &bytes).await, first);
+
+        // The same msg_id from another Controller is a different request
+        let other = handle(
+            "proto::other-ctrl",
+            &operate_msg("dup-cached", "Device.Nope()"),
+        )
+        .await;
+        assert_ne!(other, Some(b"cached".to_vec()));
+    }
+
+    #[test]
+    fn redelivery_while_in_flight_is_dropped() {
+        let mut cache = DedupCache::new();
+        assert_eq!(cache.check_or_reserve("ctrl", "m1"), None);
+        assert_eq!(cache.check_or_reserve("ctrl", "m1"), Some(None));
+        cache.complete("ctrl", "m1", Some(b"resp".to_vec()));
+        assert_eq!(
+            cache.check_or_reserve("ctrl", "m1"),
+            Some(Some(b"resp".to_vec()))
+        );
+
+        // A released (timed-out) reservation executes again on redelivery
+        assert_eq!(cache.check_or_reserve("ctrl", "m2"), None);
+        cache.release("ctrl", "m2");
+        assert_eq!(cache.check_or_reserve("ctrl", "m2"), None);
+    }
+
+    #[tokio::test]
+    async fn reregister_queues_boot_notify() {
+        let cfg = ClientConfig {
+            usp_endpoint_id: "proto::agent".into(),
+            controller_id: "proto::controller".into(),
+            boot_notify_confirm: false,
+            ..ClientConfig::default()
+        };
+        let out = dm::operate(
+            &cfg,
+            "Device.LocalAgent.X_OptimACS_ReRegister()",
+            &HashMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(out["EndpointID"], "proto::agent");
+        assert_eq!(out["Cause"], REREGISTER_CAUSE);
+
+        let outbox = Outbox::new(4);
+        queue_reregister(&cfg, &EndpointId::new("proto::agent"), &outbox, "1.3");
+        let item = outbox.next().await;
+        assert_eq!(item.class, NotifyClass::Boot);
+
+        let rec = super::super::record::decode_record(&item.record).unwrap();
+        assert_eq!(rec.to_id, "proto::controller");
+        let payload = super::super::record::extract_msg_payload(&rec).unwrap();
+        let notify = match decode_msg(payload).unwrap().body.unwrap().msg_body {
+            Some(MsgBody::Request(usp_msg::Request {
+                req_type: Some(ReqType::Notify(n)),
+            })) => n,
+            other => panic!("not a Notify: {other:?}"),
+        };
+        match notify.notification {
+            Some(usp_msg::notify::Notification::Event(ev)) => {
+                assert_eq!(ev.event_name, "Boot!");
+                assert_eq!(ev.params["Cause"], REREGISTER_CAUSE);
+                let sent: HashMap<String, String> =
+                    serde_json::from_str(&out["ParameterMap"]).unwrap();
+                assert_eq!(sent, ev.params);
+            }
+            other => panic!("not an Event: {other:?}"),
+        }
+
+        // Nothing pending: no second Boot!
+        queue_reregister(&cfg, &EndpointId::new("proto::agent"), &outbox, "1.3");
+        assert_eq!(outbox.len(), 0);
+    }
+
+    #[test]
+    fn dedup_cache_is_bounded() {
+        let mut cache = DedupCache::new();
+        for i in 0..DEDUP_CACHE_SIZE + 10 {
+            cache.complete("ctrl", &format!("bound-{i}"), None);
+        }
+        assert_eq!(cache.entries.len(), DEDUP_CACHE_SIZE);
+        assert!(cache.position("ctrl", "bound-0").is_none());
+        let newest = format!("bound-{}", DEDUP_CACHE_SIZE + 9);
+        assert_eq!(cache.check_or_reserve("ctrl", &newest), Some(None));
+    }
+}
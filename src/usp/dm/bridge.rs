@@ -3,6 +3,7 @@
 
 #![allow(clippy::all)]
 
+use super::{DmError, UciTxn};
 use crate::config::ClientConfig;
 use crate::usp::tp469::uci_backend::{uci_commit, uci_get, uci_set};
 use log::{info, warn};
@@ -97,7 +98,12 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> Params {
 }
 
 /// Set bridge configuration parameter
-pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), String> {
+pub async fn set(
+    _cfg: &ClientConfig,
+    txn: &mut UciTxn,
+    path: &str,
+    value: &str,
+) -> Result<(), DmError> {
     let bridge_idx = parse_bridge_index(path).unwrap_or(1);
     let bridge_name = get_bridge_name_by_index(bridge_idx);
     let param = path.split('.').last().unwrap_or("");
@@ -121,7 +127,10 @@ pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), Str
         "Type" => {
             // Type is always 'bridge' for bridge devices
             if value != "bridge" {
-                return Err(format!("Bridge type must be 'bridge', got: {}", value));
+                return Err(DmError::invalid_value(
+                    path,
+                    format!("Bridge type must be 'bridge', got: {}", value),
+                ));
             }
         }
         "Ports" => {
@@ -164,15 +173,17 @@ pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), Str
         }
         "Status" => {
             // Status is read-only
-            return Err(format!("Status is read-only: {}", path));
+            return Err(DmError::NotWritable(path.to_string()));
         }
         _ => {
-            return Err(format!("Unknown bridge parameter: {}", param));
+            return Err(DmError::InvalidPath(path.to_string()));
         }
     }
 
     // Commit the changes
-    uci_commit("network")?;
+    if !txn.defer("network") {
+        uci_commit("network")?;
+    }
 
     info!(
         "Bridge config updated successfully: {}.{} = {}",
@@ -525,7 +536,7 @@ pub async fn operate(
     _cfg: &ClientConfig,
     command: &str,
     _input_args: &HashMap<String, String>,
-) -> Result<HashMap<String, String>, String> {
+) -> Result<HashMap<String, String>, DmError> {
     let mut output = HashMap::new();
 
     if command.ends_with(".Restart()") {
@@ -552,14 +563,20 @@ pub async fn operate(
                     );
                 }
                 Err(e) => {
-                    return Err(format!("Failed to restart bridge {}: {}", bridge_name, e));
+                    return Err(DmError::CommandFailure(format!(
+                        "Failed to restart bridge {}: {}",
+                        bridge_name, e
+                    )));
                 }
             }
         } else {
-            return Err(format!("No interface found using bridge {}", bridge_name));
+            return Err(DmError::CommandFailure(format!(
+                "No interface found using bridge {}",
+                bridge_name
+            )));
         }
     } else {
-        return Err(format!("Unknown bridge command: {}", command));
+        return Err(DmError::InvalidPath(command.to_string()));
     }
 
     Ok(output)
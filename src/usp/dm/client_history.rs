@@ -0,0 +1,178 @@
+//! Device.WiFi.AccessPoint.{i}.X_OptimACS_ClientHistory.{n}. — recent
+//! station departures per access point, for roaming and churn analytics.
+//!
+//! Successive `iw station dump` snapshots are compared: a station that
+//! appears is remembered with its connect time, and when it is gone from a
+//! later snapshot an entry with both timestamps and its last signal is
+//! recorded.  Kept in memory only.  Randomised MACs can produce many
+//! short-lived stations, so each AP keeps at most [`HISTORY_LEN`] entries and
+//! drops any older than [`HISTORY_MAX_AGE`].
+
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Departures remembered per access point.
+pub const HISTORY_LEN: usize = 32;
+/// Departures older than this are dropped.
+pub const HISTORY_MAX_AGE: chrono::Duration = chrono::Duration::hours(24);
+
+/// A station that has left the access point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Departure {
+    pub mac: String,
+    pub connected: DateTime<Utc>,
+    pub disconnected: DateTime<Utc>,
+    /// Signal in dBm from the last snapshot the station was seen in.
+    pub last_signal: String,
+}
+
+#[derive(Debug, Clone)]
+struct Present {
+    connected: DateTime<Utc>,
+    last_signal: String,
+}
+
+/// Stations currently associated to one AP and its recent departures.
+#[derive(Debug, Default)]
+pub struct ApClients {
+    present: HashMap<String, Present>,
+    history: VecDeque<Departure>,
+}
+
+impl ApClients {
+    /// Fold in a station snapshot of (MAC, signal) pairs taken at `now`.
+    pub fn observe(&mut self, stations: &[(String, String)], now: DateTime<Utc>) {
+        let gone: Vec<String> = self
+            .present
+            .keys()
+            .filter(|mac| !stations.iter().any(|(m, _)| m == *mac))
+            .cloned()
+            .collect();
+        for mac in gone {
+            if let Some(p) = self.present.remove(&mac) {
+                self.history.push_back(Departure {
+                    mac,
+                    connected: p.connected,
+                    disconnected: now,
+                    last_signal: p.last_signal,
+                });
+            }
+        }
+
+        for (mac, signal) in stations {
+            let entry = self.present.entry(mac.clone()).or_insert(Present {
+                connected: now,
+                last_signal: String::new(),
+            });
+            if !signal.is_empty() {
+                entry.last_signal = signal.clone();
+            }
+        }
+
+        while self.history.len() > HISTORY_LEN {
+            self.history.pop_front();
+        }
+        while self
+            .history
+            .front()
+            .is_some_and(|d| now - d.disconnected > HISTORY_MAX_AGE)
+        {
+            self.history.pop_front();
+        }
+    }
+
+    /// Departures, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &Departure> {
+        self.history.iter()
+    }
+}
+
+/// Per-AP state, keyed by AccessPoint instance number.
+static CLIENTS: Mutex<BTreeMap<usize, ApClients>> = Mutex::new(BTreeMap::new());
+
+/// Record a station snapshot for AccessPoint `ap_idx`.
+pub fn observe(ap_idx: usize, stations: &[(String, String)], now: DateTime<Utc>) {
+    CLIENTS
+        .lock()
+        .unwrap()
+        .entry(ap_idx)
+        .or_default()
+        .observe(stations, now);
+}
+
+/// Parameters under `Device.WiFi.AccessPoint.{i}.X_OptimACS_ClientHistory`
+/// (plus the entry count) that fall under `path`.
+pub fn get(path: &str) -> HashMap<String, String> {
+    let mut m = HashMap::new();
+    let clients = CLIENTS.lock().unwrap();
+    for (ap_idx, ap) in clients.iter() {
+        let ap_base = format!("Device.WiFi.AccessPoint.{ap_idx}.");
+        m.insert(
+            format!("{ap_base}X_OptimACS_ClientHistoryNumberOfEntries"),
+            ap.history.len().to_string(),
+        );
+        for (n, d) in ap.history().enumerate() {
+            let base = format!("{ap_base}X_OptimACS_ClientHistory.{}.", n + 1);
+            let ts = |t: &DateTime<Utc>| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+            m.insert(format!("{base}MACAddress"), d.mac.clone());
+            m.insert(format!("{base}ConnectTime"), ts(&d.connected));
+            m.insert(format!("{base}DisconnectTime"), ts(&d.disconnected));
+            m.insert(format!("{base}LastSignalStrength"), d.last_signal.clone());
+        }
+    }
+    m.retain(|k, _| k.starts_with(path));
+    m
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sta(mac: &str, signal: &str) -> (String, String) {
+        (mac.to_string(), signal.to_string())
+    }
+
+    #[test]
+    fn station_leaving_recorded_in_history() {
+        let t0 = Utc::now();
+        let secs = chrono::Duration::seconds;
+        let mut ap = ApClients::default();
+
+        ap.observe(&[sta("AA:BB:CC:00:00:01", "-48")], t0);
+        ap.observe(&[sta("AA:BB:CC:00:00:01", "-61")], t0 + secs(30));
+        assert_eq!(ap.history().count(), 0);
+
+        ap.observe(&[], t0 + secs(60));
+        let hist: Vec<_> = ap.history().collect();
+        assert_eq!(
+            hist,
+            vec![&Departure {
+                mac: "AA:BB:CC:00:00:01".into(),
+                connected: t0,
+                disconnected: t0 + secs(60),
+                last_signal: "-61".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn randomised_macs_capped_and_aged_out() {
+        let t0 = Utc::now();
+        let secs = chrono::Duration::seconds;
+        let mut ap = ApClients::default();
+
+        // A new random MAC every snapshot
+        for i in 0..100 {
+            let mac = format!("DA:00:00:00:00:{i:02X}");
+            ap.observe(&[sta(&mac, "-70")], t0 + secs(i * 30));
+        }
+        assert_eq!(ap.history().count(), HISTORY_LEN);
+        assert_eq!(ap.history().last().unwrap().mac, "DA:00:00:00:00:62");
+
+        // A day later they are all gone
+        ap.observe(&[], t0 + HISTORY_MAX_AGE + secs(100 * 30 + 1));
+        assert_eq!(ap.history().count(), 1);
+        assert_eq!(ap.history().next().unwrap().mac, "DA:00:00:00:00:63");
+    }
+}
@@ -0,0 +1,284 @@
+//! Device.X_OptimACS_Config.* — confirmed commits for changes that can cut
+//! the device off from its controller.
+//!
+//! With `confirm_timeout` set, a SET, ADD or DELETE that changes a
+//! package in [`RISKY_PACKAGES`] is applied as usual, but the files as they
+//! were before — committed under [`UCI_CONFIG_DIR`] and staged under
+//! [`UCI_DELTA_DIR`] — are kept in memory.  Unless the controller calls
+//! `Device.X_OptimACS_Config.Confirm()` within the window — which it can only
+//! do if it still reaches the device — they are written back (files that did
+//! not exist are removed) and the services reloaded, like a router's "commit
+//! confirmed".  Further risky changes inside the window extend it and keep
+//! the oldest backup, so a rollback always lands on the last confirmed state.
+
+use super::DmError;
+use crate::config::ClientConfig;
+use log::{error, info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// UCI packages whose changes need confirmation: addressing and routing,
+/// filtering, and the SSH server.
+pub const RISKY_PACKAGES: [&str; 3] = ["network", "firewall", "dropbear"];
+
+/// Where UCI keeps its packages.
+pub const UCI_CONFIG_DIR: &str = "/etc/config";
+
+/// Where UCI stages uncommitted changes (`uci set` without `commit`).
+pub const UCI_DELTA_DIR: &str = "/tmp/.uci";
+
+/// One risky package file as it was: `None` if it did not exist.
+type Backup = (&'static str, Option<Vec<u8>>);
+
+/// Risky package files, committed and staged, as they were before a change.
+#[derive(Debug)]
+pub struct Snapshot {
+    files: BTreeMap<PathBuf, Backup>,
+}
+
+impl Snapshot {
+    /// The live UCI configuration.
+    pub fn current() -> Self {
+        Self::take(Path::new(UCI_CONFIG_DIR), Path::new(UCI_DELTA_DIR))
+    }
+
+    pub fn take(config_dir: &Path, delta_dir: &Path) -> Self {
+        let files = RISKY_PACKAGES
+            .iter()
+            .flat_map(|pkg| [config_dir.join(pkg), delta_dir.join(pkg)].map(|path| (*pkg, path)))
+            .map(|(pkg, path)| {
+                let before = std::fs::read(&path).ok();
+                (path, (pkg, before))
+            })
+            .collect();
+        Self { files }
+    }
+
+    /// Files that differ now, with their previous contents.  A file that
+    /// was created counts too: rolling it back removes it.
+    fn changed(self) -> Vec<(PathBuf, Backup)> {
+        self.files
+            .into_iter()
+            .filter(|(path, (_, before))| std::fs::read(path).ok() != *before)
+            .collect()
+    }
+}
+
+/// A risky change waiting for Confirm().
+#[derive(Debug)]
+struct Pending {
+    /// Set from [`GENERATION`] by every change, so only the newest timer
+    /// rolls back.
+    generation: u64,
+    deadline: Instant,
+    backups: BTreeMap<PathBuf, Backup>,
+}
+
+impl Pending {
+    fn packages(&self) -> BTreeSet<&'static str> {
+        self.backups.values().map(|(pkg, _)| *pkg).collect()
+    }
+}
+
+static PENDING: Mutex<Option<Pending>> = Mutex::new(None);
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Start (or extend) the confirmation window if the change since `snapshot`
+/// touched a risky package.  `reload` re-applies a package after rollback.
+/// Returns the window length when one is running.
+pub fn arm<F, Fut>(snapshot: Snapshot, timeout: Duration, reload: F) -> Option<Duration>
+where
+    F: Fn(&'static str) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), String>> + Send,
+{
+    let changed = snapshot.changed();
+    if changed.is_empty() {
+        return None;
+    }
+
+    let generation = {
+        let mut pending = PENDING.lock().unwrap();
+        let p = pending.get_or_insert_with(|| Pending {
+            generation: 0,
+            deadline: Instant::now(),
+            backups: BTreeMap::new(),
+        });
+        for (path, before) in changed {
+            p.backups.entry(path).or_insert(before);
+        }
+        p.generation = GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+        p.deadline = Instant::now() + timeout;
+        p.generation
+    };
+
+    tokio::spawn(async move {
+        tokio::time::sleep(timeout).await;
+        let expired = {
+            let mut pending = PENDING.lock().unwrap();
+            match pending.as_ref() {
+                Some(p) if p.generation == generation => pending.take(),
+                _ => None,
+            }
+        };
+        if let Some(p) = expired {
+            rollback(p, reload).await;
+        }
+    });
+    Some(timeout)
+}
+
+/// Restore every backed-up file and reload the packages involved.
+async fn rollback<F, Fut>(p: Pending, reload: F)
+where
+    F: Fn(&'static str) -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    let packages = p.packages();
+    error!(
+        "Config change not confirmed in time; restoring {}",
+        packages.iter().copied().collect::<Vec<_>>().join(", ")
+    );
+    let mut failed = BTreeSet::new();
+    for (path, (pkg, before)) in &p.backups {
+        if let Err(e) = crate::apply::restore_file(path, before.as_deref()).await {
+            error!("Rollback of {} failed: {e}", path.display());
+            failed.insert(*pkg);
+        }
+    }
+    for pkg in packages.difference(&failed) {
+        if let Err(e) = reload(pkg).await {
+            warn!("Rolled back {pkg} but reload failed: {e}");
+        }
+    }
+}
+
+/// Seconds left to confirm, or `None` when nothing is pending.
+fn remaining(now: Instant) -> Option<u64> {
+    PENDING
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|p| p.deadline.saturating_duration_since(now).as_secs())
+}
+
+pub fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
+    let base = "Device.X_OptimACS_Config.";
+    let remaining = remaining(Instant::now());
+    let mut m = HashMap::new();
+    m.insert(
+        format!("{base}ConfirmPending"),
+        remaining.is_some().to_string(),
+    );
+    m.insert(
+        format!("{base}ConfirmTimeRemaining"),
+        remaining.unwrap_or(0).to_string(),
+    );
+    m.retain(|k, _| k.starts_with(path));
+    m
+}
+
+/// `Device.X_OptimACS_Config.Confirm()` — keep the pending risky change.
+/// `Status` is `Confirmed`, or `NothingPending` when no change awaits
+/// confirmation (e.g. it was already rolled back).
+pub async fn operate_confirm(
+    _cfg: &ClientConfig,
+    _command: &str,
+    _input_args: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, DmError> {
+    let status = match PENDING.lock().unwrap().take() {
+        Some(p) => {
+            info!(
+                "Config change confirmed: {}",
+                p.packages().into_iter().collect::<Vec<_>>().join(", ")
+            );
+            "Confirmed"
+        }
+        None => "NothingPending",
+    };
+    let mut out = HashMap::new();
+    out.insert("Status".to_string(), status.to_string());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test(start_paused = true)]
+    async fn unconfirmed_change_rolled_back_after_timeout() {
+        let dir = std::env::temp_dir().join(format!("ac-client-confirm-{}", std::process::id()));
+        let (config_dir, delta_dir) = (dir.join("config"), dir.join("uci"));
+        std::fs::create_dir_all(&config_dir).unwrap();
+        std::fs::create_dir_all(&delta_dir).unwrap();
+        let take = || Snapshot::take(&config_dir, &delta_dir);
+        let network = config_dir.join("network");
+        let firewall = config_dir.join("firewall");
+        let staged = delta_dir.join("network");
+        let cfg = ClientConfig::default();
+        let reloaded = Arc::new(Mutex::new(Vec::new()));
+        let reloader = || {
+            let reloaded = Arc::clone(&reloaded);
+            move |pkg: &'static str| {
+                reloaded.lock().unwrap().push(pkg);
+                async { Ok(()) }
+            }
+        };
+
+        // A change to a package that isn't risky needs no confirmation
+        std::fs::write(&network, "option ipaddr '192.168.1.1'\n").unwrap();
+        let snap = take();
+        std::fs::write(config_dir.join("wireless"), "option ssid 'x'\n").unwrap();
+        assert_eq!(arm(snap, Duration::from_secs(20), reloader()), None);
+
+        // Confirmed in time: the new config stays
+        let snap = take();
+        std::fs::write(&network, "option ipaddr '10.0.0.1'\n").unwrap();
+        assert!(arm(snap, Duration::from_secs(50), reloader()).is_some());
+        assert_eq!(
+            get(&cfg, "Device.X_OptimACS_Config.ConfirmPending")
+                .values()
+                .next()
+                .unwrap(),
+            "true"
+        );
+        let out = operate_confirm(&cfg, "", &HashMap::new()).await.unwrap();
+        assert_eq!(out["Status"], "Confirmed");
+        tokio::time::sleep(Duration::from_secs(100)).await;
+        assert_eq!(
+            std::fs::read_to_string(&network).unwrap(),
+            "option ipaddr '10.0.0.1'\n"
+        );
+        assert!(reloaded.lock().unwrap().is_empty());
+
+        // Two unconfirmed changes: back to the state before the first.  The
+        // firewall file created by the second is removed again, and the
+        // change still staged for network is dropped.
+        let snap = take();
+        std::fs::write(&network, "option ipaddr '10.0.0.2'\n").unwrap();
+        arm(snap, Duration::from_secs(30), reloader()).unwrap();
+        let snap = take();
+        std::fs::write(&firewall, "config zone\n").unwrap();
+        std::fs::write(&staged, "network.lan.ipaddr='10.0.0.3'\n").unwrap();
+        arm(snap, Duration::from_secs(60), reloader()).unwrap();
+        tokio::time::sleep(Duration::from_secs(59)).await;
+        assert!(reloaded.lock().unwrap().is_empty());
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(
+            std::fs::read_to_string(&network).unwrap(),
+            "option ipaddr '10.0.0.1'\n"
+        );
+        assert!(!firewall.exists());
+        assert!(!staged.exists());
+        assert_eq!(*reloaded.lock().unwrap(), vec!["firewall", "network"]);
+        let out = operate_confirm(&cfg, "", &HashMap::new()).await.unwrap();
+        assert_eq!(out["Status"], "NothingPending");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,9 +1,18 @@
 //! TR-181 Device.DeviceInfo.* — reads from /proc and UCI.
 
+use super::{DmError, UciTxn};
 use crate::config::ClientConfig;
+use crate::gnss;
 use crate::usp::tp469::uci_backend;
 use crate::util;
+use log::LevelFilter;
+use log::{debug, error, info, warn};
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 pub fn get(cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
     let mut m = HashMap::new();
@@ -53,6 +62,54 @@ pub fn get(cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
             );
             insert(&mut m, "ProductClass", "Gateway".to_string());
             insert(&mut m, "DeviceStatus", util::read_device_status());
+            insert_storage_usage(&mut m, base);
+            insert_conntrack(&mut m, base);
+            insert_power_supply(&mut m, base, cfg);
+            insert_release_info(&mut m, base);
+            insert_position(&mut m, base);
+            insert_gnss_source(&mut m, base, cfg);
+            insert_watchdog(&mut m, base);
+            insert(&mut m, "X_OptimACS_NeighborCount", neighbor_count());
+            insert(&mut m, "X_OptimACS_LogLevel", log_level());
+            insert(
+                &mut m,
+                "X_OptimACS_ConfigAppliedTime",
+                crate::apply::config_applied_time(&cfg.cert_dir),
+            );
+        }
+        "X_OptimACS_WatchdogEnabled" | "X_OptimACS_WatchdogInterval" => {
+            let mut all = HashMap::new();
+            insert_watchdog(&mut all, base);
+            if let Some(v) = all.remove(path) {
+                m.insert(path.to_string(), v);
+            }
+        }
+        "X_OptimACS_LogLevel" => {
+            insert(&mut m, "X_OptimACS_LogLevel", log_level());
+        }
+        "X_OptimACS_NeighborCount" => {
+            insert(&mut m, "X_OptimACS_NeighborCount", neighbor_count());
+        }
+        "X_OptimACS_ConfigAppliedTime" => {
+            insert(
+                &mut m,
+                "X_OptimACS_ConfigAppliedTime",
+                crate::apply::config_applied_time(&cfg.cert_dir),
+            );
+        }
+        "X_OptimACS_Latitude" | "X_OptimACS_Longitude" | "X_OptimACS_Position" => {
+            let mut all = HashMap::new();
+            insert_position(&mut all, base);
+            if let Some(v) = all.remove(path) {
+                m.insert(path.to_string(), v);
+            }
+        }
+        "X_OptimACS_GnssSource" | "X_OptimACS_GnssDevice" | "X_OptimACS_GnssLastFixAge" => {
+            let mut all = HashMap::new();
+            insert_gnss_source(&mut all, base, cfg);
+            if let Some(v) = all.remove(path) {
+                m.insert(path.to_string(), v);
+            }
         }
         "HostName" => {
             let hostname = uci_backend::get_system_hostname();
@@ -129,11 +186,33 @@ pub fn get(cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
         "DeviceStatus" => {
             insert(&mut m, "DeviceStatus", util::read_device_status());
         }
-        "VendorConfigFileNumberOfEntries" => {
-            m.insert(
-                format!("{base}VendorConfigFileNumberOfEntries"),
-                "0".to_string(),
-            );
+        sub if sub.starts_with("X_OptimACS_Distribution") || sub == "X_OptimACS_Target" => {
+            let mut all = HashMap::new();
+            insert_release_info(&mut all, base);
+            if let Some(v) = all.remove(path) {
+                m.insert(path.to_string(), v);
+            }
+        }
+        "X_OptimACS_ConntrackCount" | "X_OptimACS_ConntrackMax" => {
+            let mut all = HashMap::new();
+            insert_conntrack(&mut all, base);
+            if let Some(v) = all.remove(path) {
+                m.insert(path.to_string(), v);
+            }
+        }
+        "X_OptimACS_PowerSource" | "X_OptimACS_BatteryLevel" | "X_OptimACS_PowerVoltage" => {
+            let mut all = HashMap::new();
+            insert_power_supply(&mut all, base, cfg);
+            if let Some(v) = all.remove(path) {
+                m.insert(path.to_string(), v);
+            }
+        }
+        sub if sub.starts_with("X_OptimACS_Flash") || sub.starts_with("X_OptimACS_Tmp") => {
+            let mut all = HashMap::new();
+            insert_storage_usage(&mut all, base);
+            if let Some(v) = all.remove(path) {
+                m.insert(path.to_string(), v);
+            }
         }
         // ── ProcessStatus ────────────────────────────────────
         sub if sub.starts_with("ProcessStatus.") => {
@@ -243,6 +322,15 @@ pub fn get(cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
                 }
             }
         }
+        // ── VendorConfigFile ─────────────────────────────────
+        sub if sub.starts_with("VendorConfigFile") => {
+            insert_vendor_config_files(
+                &mut m,
+                base,
+                Path::new("/etc/config"),
+                uci_backend::uci_export,
+            );
+        }
         // ── X_TP_LEDs ────────────────────────────────────────
         sub if sub.starts_with("X_TP_LEDs.") => {
             let leaf = sub.trim_start_matches("X_TP_LEDs.");
@@ -280,6 +368,269 @@ pub fn get(cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
     m
 }
 
+/// Flash (overlay/rootfs) and /tmp usage in kB.  Parameters are omitted when
+/// `statvfs` fails so the controller never sees bogus zeros.
+fn insert_storage_usage(m: &mut HashMap<String, String>, base: &str) {
+    if let Some(flash) = util::read_flash_usage() {
+        m.insert(
+            format!("{base}X_OptimACS_FlashTotal"),
+            flash.total_kb.to_string(),
+        );
+        m.insert(
+            format!("{base}X_OptimACS_FlashUsed"),
+            flash.used_kb.to_string(),
+        );
+        m.insert(
+            format!("{base}X_OptimACS_FlashFree"),
+            flash.free_kb.to_string(),
+        );
+    }
+    if let Some(tmp) = util::read_filesystem_usage("/tmp") {
+        m.insert(
+            format!("{base}X_OptimACS_TmpTotal"),
+            tmp.total_kb.to_string(),
+        );
+        m.insert(format!("{base}X_OptimACS_TmpFree"), tmp.free_kb.to_string());
+    }
+}
+
+/// Conntrack table count and limit; omitted when nf_conntrack is not loaded.
+fn insert_conntrack(m: &mut HashMap<String, String>, base: &str) {
+    if let Some(ct) = util::read_conntrack() {
+        m.insert(
+            format!("{base}X_OptimACS_ConntrackCount"),
+            ct.count.to_string(),
+        );
+        m.insert(format!("{base}X_OptimACS_ConntrackMax"), ct.max.to_string());
+    }
+}
+
+/// Power source, plus battery level and voltage when the supply exposes them.
+fn insert_power_supply(m: &mut HashMap<String, String>, base: &str, cfg: &ClientConfig) {
+    let ps = util::read_power_supply(&cfg.power_sysfs_path);
+    m.insert(format!("{base}X_OptimACS_PowerSource"), ps.source);
+    if let Some(pct) = ps.capacity {
+        m.insert(format!("{base}X_OptimACS_BatteryLevel"), pct.to_string());
+    }
+    if let Some(mv) = ps.voltage_mv {
+        m.insert(format!("{base}X_OptimACS_PowerVoltage"), mv.to_string());
+    }
+}
+
+/// GNSS position (latitude, longitude and combined "lat,lon"); empty without a fix.
+fn insert_position(m: &mut HashMap<String, String>, base: &str) {
+    for (suffix, val) in gnss::position_params(gnss::current_position().as_ref()) {
+        m.insert(format!("{base}{suffix}"), val);
+    }
+}
+
+/// Configured GNSS receiver, its type, and seconds since its last fix.
+fn insert_gnss_source(m: &mut HashMap<String, String>, base: &str, cfg: &ClientConfig) {
+    for (suffix, val) in gnss::source_params(&cfg.gnss_dev) {
+        m.insert(format!("{base}{suffix}"), val);
+    }
+}
+
+// ── Runtime log level ────────────────────────────────────────────────────────
+
+/// Level the daemon started with; restored when a temporary override expires.
+static BASE_LOG_LEVEL: OnceLock<LevelFilter> = OnceLock::new();
+/// Bumped on every SET so only the latest override's revert timer fires.
+static LOG_LEVEL_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Nearby BSSes from the WiFi scan cache; empty when no scan is available.
+pub fn neighbor_count() -> String {
+    super::wifi::neighbor_count()
+        .map(|n| n.to_string())
+        .unwrap_or_default()
+}
+
+fn log_level() -> String {
+    log::max_level().to_string().to_lowercase()
+}
+
+/// Change the effective log level now.  Unless `revert` is zero, the start-up
+/// level is restored after `revert` so a device is not left at trace forever.
+fn set_log_level(value: &str, revert: Duration) -> Result<(), String> {
+    let level: LevelFilter = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid log level: {value}"))?;
+    let base = *BASE_LOG_LEVEL.get_or_init(log::max_level);
+    let generation = LOG_LEVEL_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+
+    log::set_max_level(level);
+    log::info!("Log level set to {level} over USP");
+
+    if level != base && !revert.is_zero() {
+        tokio::spawn(async move {
+            tokio::time::sleep(revert).await;
+            if LOG_LEVEL_GENERATION.load(Ordering::SeqCst) == generation {
+                log::set_max_level(base);
+                log::info!("Log level override expired, back to {base}");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Undo a [`set_log_level`] whose SET batch failed, cancelling its expiry.
+fn restore_log_level(level: LevelFilter) {
+    LOG_LEVEL_GENERATION.fetch_add(1, Ordering::SeqCst);
+    log::set_max_level(level);
+    log::info!("Log level change rolled back to {level}");
+}
+
+/// Hardware watchdog state as driven by procd (`ubus call system watchdog`).
+fn insert_watchdog(m: &mut HashMap<String, String>, base: &str) {
+    let out = std::process::Command::new("ubus")
+        .args(["call", "system", "watchdog"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).into_owned())
+        .unwrap_or_default();
+    let (enabled, interval) = parse_watchdog_status(&out);
+    m.insert(
+        format!("{base}X_OptimACS_WatchdogEnabled"),
+        enabled.to_string(),
+    );
+    m.insert(
+        format!("{base}X_OptimACS_WatchdogInterval"),
+        interval.to_string(),
+    );
+}
+
+/// (armed, kick interval in seconds) from the procd watchdog status JSON.
+/// No watchdog device (or no reply) reports disabled with interval 0.
+fn parse_watchdog_status(json: &str) -> (bool, u64) {
+    let v: serde_json::Value = match serde_json::from_str(json) {
+        Ok(v) => v,
+        Err(_) => return (false, 0),
+    };
+    if v.get("status").and_then(|s| s.as_str()) != Some("running") {
+        return (false, 0);
+    }
+    let frequency = v.get("frequency").and_then(|f| f.as_u64()).unwrap_or(0);
+    (true, frequency)
+}
+
+/// OS identity from `/etc/openwrt_release`.
+fn insert_release_info(m: &mut HashMap<String, String>, base: &str) {
+    if let Some(rel) = util::read_openwrt_release() {
+        m.insert(format!("{base}X_OptimACS_Distribution"), rel.distribution());
+        m.insert(format!("{base}X_OptimACS_DistributionID"), rel.id);
+        m.insert(format!("{base}X_OptimACS_DistributionRelease"), rel.release);
+        m.insert(
+            format!("{base}X_OptimACS_DistributionRevision"),
+            rel.revision,
+        );
+        m.insert(format!("{base}X_OptimACS_Target"), rel.target);
+    }
+}
+
+/// UCI packages exposed as Device.DeviceInfo.VendorConfigFile.{i}, in index order.
+const VENDOR_CONFIG_PACKAGES: &[&str] = &["network", "wireless", "dhcp", "system", "firewall"];
+
+/// VendorConfigFile table: `Version` is a hash of the package's `uci export`
+/// so a controller can spot changes without fetching the contents, `Date` the
+/// mtime of the file under `config_dir`.  Indices are fixed by
+/// [`VENDOR_CONFIG_PACKAGES`]; a missing package keeps its index but has an
+/// empty `Version`.
+fn insert_vendor_config_files(
+    m: &mut HashMap<String, String>,
+    base: &str,
+    config_dir: &Path,
+    export: impl Fn(&str) -> Option<String>,
+) {
+    m.insert(
+        format!("{base}VendorConfigFileNumberOfEntries"),
+        VENDOR_CONFIG_PACKAGES.len().to_string(),
+    );
+    for (i, pkg) in VENDOR_CONFIG_PACKAGES.iter().enumerate() {
+        let prefix = format!("{base}VendorConfigFile.{}.", i + 1);
+        let version = export(pkg)
+            .map(|text| util::config_hash(text.as_bytes()))
+            .unwrap_or_default();
+        let date = std::fs::metadata(config_dir.join(pkg))
+            .and_then(|meta| meta.modified())
+            .map(|t| {
+                chrono::DateTime::<chrono::Utc>::from(t)
+                    .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            })
+            .unwrap_or_default();
+        m.insert(format!("{prefix}Name"), pkg.to_string());
+        m.insert(format!("{prefix}Version"), version);
+        m.insert(format!("{prefix}Date"), date);
+    }
+}
+
+// ── Public IP ─────────────────────────────────────────────────────────────────
+
+/// How long a looked-up public IP is reused before querying again.
+const PUBLIC_IP_TTL: Duration = Duration::from_secs(15 * 60);
+const PUBLIC_IP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Last successful lookup: (when, address).
+static PUBLIC_IP_CACHE: Mutex<Option<(Instant, String)>> = Mutex::new(None);
+
+/// Device.DeviceInfo.X_OptimACS_PublicIP — the WAN address as seen from
+/// `public_ip_url`.  `None` when the lookup is not configured.  A failed
+/// lookup falls back to the last known address (or empty).
+pub async fn get_public_ip(cfg: &ClientConfig) -> Option<String> {
+    if cfg.public_ip_url.is_empty() {
+        return None;
+    }
+    let cached = PUBLIC_IP_CACHE.lock().unwrap().clone();
+    if let Some((at, ip)) = &cached {
+        if at.elapsed() < PUBLIC_IP_TTL {
+            return Some(ip.clone());
+        }
+    }
+
+    match fetch_public_ip(&cfg.public_ip_url).await {
+        Ok(ip) => {
+            debug!("Public IP: {ip}");
+            *PUBLIC_IP_CACHE.lock().unwrap() = Some((Instant::now(), ip.clone()));
+            Some(ip)
+        }
+        Err(e) => {
+            warn!("Public IP lookup via {} failed: {e}", cfg.public_ip_url);
+            Some(cached.map(|(_, ip)| ip).unwrap_or_default())
+        }
+    }
+}
+
+async fn fetch_public_ip(url: &str) -> Result<String, String> {
+    let client = reqwest::Client::builder()
+        .timeout(PUBLIC_IP_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let body = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())?;
+    parse_public_ip(&body)
+        .map(|ip| ip.to_string())
+        .ok_or_else(|| format!("unrecognised response: {:.64}", body.trim()))
+}
+
+/// Extract the address from an echo-service reply: either the bare address
+/// (`203.0.113.7`) or a JSON object with an `ip` field (`{"ip":"203.0.113.7"}`).
+fn parse_public_ip(body: &str) -> Option<IpAddr> {
+    let body = body.trim();
+    if let Ok(ip) = body.parse() {
+        return Some(ip);
+    }
+    let json: serde_json::Value = serde_json::from_str(body).ok()?;
+    json.get("ip")?.as_str()?.trim().parse().ok()
+}
+
 fn read_cpu_usage() -> String {
     // Simple: read /proc/loadavg and estimate CPU% from 1-min avg
     // Or read /proc/stat for more accurate measure
@@ -346,23 +697,305 @@ fn get_led_info(idx: usize) -> Option<(String, u32)> {
     Some((name.clone(), brightness))
 }
 
-pub fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), String> {
-    use crate::usp::tp469::uci_backend;
-
+pub fn set(cfg: &ClientConfig, txn: &mut UciTxn, path: &str, value: &str) -> Result<(), DmError> {
     match path {
+        "Device.DeviceInfo.X_OptimACS_LogLevel" => {
+            let previous = log::max_level();
+            set_log_level(value, Duration::from_secs(cfg.log_level_revert))
+                .map_err(|e| DmError::invalid_value(path, e))?;
+            txn.on_revert(move || restore_log_level(previous));
+            Ok(())
+        }
         "Device.DeviceInfo.HostName" => {
+            if txn.defer("system") {
+                return Ok(uci_backend::uci_set("system.@system[0].hostname", value)?);
+            }
             let result = uci_backend::set_system_hostname(value);
             if result.success {
                 Ok(())
             } else {
-                Err(result
-                    .err_msg
-                    .unwrap_or_else(|| "Failed to set hostname".to_string()))
+                Err(DmError::Internal(
+                    result
+                        .err_msg
+                        .unwrap_or_else(|| "Failed to set hostname".to_string()),
+                ))
+            }
+        }
+        _ => Err(DmError::NotWritable(path.to_string())),
+    }
+}
+
+// ── Reboot ────────────────────────────────────────────────────────────────────
+
+/// Seconds between Reboot() and the actual reboot, unless `delay_seconds`
+/// says otherwise; long enough for the OperateResp to reach the controller.
+const REBOOT_DELAY_DEFAULT: u64 = 3;
+/// Upper bound on `delay_seconds`.
+const REBOOT_DELAY_MAX: u64 = 60;
+
+/// Device.DeviceInfo.Reboot() — schedule `/sbin/reboot` and return at once.
+pub async fn operate_reboot(
+    _cfg: &ClientConfig,
+    _command: &str,
+    input_args: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, DmError> {
+    let delay = reboot_delay(input_args.get("delay_seconds").map(String::as_str))
+        .map_err(DmError::InvalidArguments)?;
+    warn!("Reboot requested by controller, rebooting in {delay}s");
+
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(delay)).await;
+        reboot().await;
+    });
+
+    let mut out = HashMap::new();
+    out.insert("status".to_string(), "scheduled".to_string());
+    out.insert("delay_seconds".to_string(), delay.to_string());
+    Ok(out)
+}
+
+pub(super) async fn reboot() {
+    match tokio::process::Command::new("/sbin/reboot").status().await {
+        Ok(s) if s.success() => {}
+        Ok(s) => error!("/sbin/reboot exited with {s}"),
+        Err(e) => error!("/sbin/reboot: {e}"),
+    }
+}
+
+/// Parse `delay_seconds`: default when absent, capped at [`REBOOT_DELAY_MAX`].
+fn reboot_delay(arg: Option<&str>) -> Result<u64, String> {
+    match arg.map(str::trim) {
+        None | Some("") => Ok(REBOOT_DELAY_DEFAULT),
+        Some(v) => v
+            .parse::<u64>()
+            .map(|secs| secs.min(REBOOT_DELAY_MAX))
+            .map_err(|_| format!("delay_seconds must be a number of seconds, got {v:?}")),
+    }
+}
+
+// ── Factory reset ─────────────────────────────────────────────────────────────
+
+/// Archive `jffs2reset -k` leaves on the overlay; preinit (`80_mount_root`)
+/// unpacks it over the fresh root on the next boot.
+const KEEP_ARCHIVE: &str = "/sysupgrade.tgz";
+
+/// Device.DeviceInfo.FactoryReset() — wipe the overlay (`jffs2reset -y`,
+/// falling back to `firstboot -y`) and reboot.  Disabled unless
+/// `allow_factory_reset` is set.  With `keep_certs` the client certificate,
+/// key and CA are packed into [`KEEP_ARCHIVE`] and the wipe runs with `-k`,
+/// so preinit restores them before the client starts again.
+pub async fn operate_factory_reset(
+    cfg: &ClientConfig,
+    _command: &str,
+    input_args: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, DmError> {
+    if !cfg.allow_factory_reset {
+        return Err(DmError::CommandFailure(
+            "factory reset is disabled (set allow_factory_reset to enable)".into(),
+        ));
+    }
+    let keep_certs = match input_args.get("keep_certs").map(|v| v.trim()) {
+        None | Some("") | Some("false") | Some("0") => false,
+        Some("true") | Some("1") => true,
+        Some(v) => {
+            return Err(DmError::InvalidArguments(format!(
+                "keep_certs must be true or false, got {v:?}"
+            )))
+        }
+    };
+
+    // Pack before answering, so a failure is reported instead of leaving
+    // the device reset without its certificates.
+    if keep_certs {
+        let files = [&cfg.cert_file, &cfg.key_file, &cfg.ca_file];
+        write_keep_archive(&files, Path::new(KEEP_ARCHIVE))
+            .await
+            .map_err(|e| DmError::CommandFailure(format!("cannot keep certificates: {e}")))?;
+    }
+    warn!(
+        "Factory reset requested by controller (keep_certs={keep_certs}), \
+         resetting in {REBOOT_DELAY_DEFAULT}s"
+    );
+
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(REBOOT_DELAY_DEFAULT)).await;
+        if let Err(e) = wipe_overlay(keep_certs).await {
+            error!("Factory reset failed: {e}");
+            return;
+        }
+        nix::unistd::sync();
+        reboot().await;
+    });
+
+    let mut out = HashMap::new();
+    out.insert("status".to_string(), "reset-scheduled".to_string());
+    Ok(out)
+}
+
+/// Erase the overlay's files; the running system keeps working until reboot.
+/// With `keep` the wipe spares [`KEEP_ARCHIVE`].
+async fn wipe_overlay(keep: bool) -> Result<(), String> {
+    let args: &[&str] = if keep { &["-y", "-k"] } else { &["-y"] };
+    for cmd in ["jffs2reset", "firstboot"] {
+        match tokio::process::Command::new(cmd).args(args).status().await {
+            Ok(s) if s.success() => {
+                info!("Overlay wiped ({cmd} {})", args.join(" "));
+                return Ok(());
             }
+            Ok(s) => warn!("{cmd} exited with {s}"),
+            Err(e) => warn!("{cmd}: {e}"),
+        }
+    }
+    Err("neither jffs2reset nor firstboot succeeded".into())
+}
+
+/// Pack the existing `files` into the gzipped tarball `archive`, with paths
+/// relative to `/` as preinit extracts it there.
+async fn write_keep_archive(files: &[&PathBuf], archive: &Path) -> Result<(), String> {
+    let members: Vec<&Path> = files
+        .iter()
+        .filter(|f| f.exists())
+        .filter_map(|f| f.strip_prefix("/").ok())
+        .collect();
+    if members.is_empty() {
+        return Err("no certificate files found".into());
+    }
+    let status = tokio::process::Command::new("tar")
+        .arg("-czf")
+        .arg(archive)
+        .arg("-C")
+        .arg("/")
+        .args(&members)
+        .status()
+        .await
+        .map_err(|e| format!("tar: {e}"))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("tar exited with {status}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn log_level_set_and_reverted() {
+        let base = log::max_level();
+
+        set_log_level("debug", Duration::ZERO).unwrap();
+        assert_eq!(log::max_level(), LevelFilter::Debug);
+        assert_eq!(log_level(), "debug");
+
+        set_log_level("TRACE", Duration::from_millis(20)).unwrap();
+        assert_eq!(log::max_level(), LevelFilter::Trace);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(log::max_level(), base);
+
+        assert!(set_log_level("loud", Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn reboot_delay_defaults_and_clamps() {
+        assert_eq!(reboot_delay(None), Ok(3));
+        assert_eq!(reboot_delay(Some(" ")), Ok(3));
+        assert_eq!(reboot_delay(Some("0")), Ok(0));
+        assert_eq!(reboot_delay(Some("10")), Ok(10));
+        assert_eq!(reboot_delay(Some("3600")), Ok(60));
+        assert!(reboot_delay(Some("-1")).is_err());
+        assert!(reboot_delay(Some("soon")).is_err());
+    }
+
+    #[tokio::test]
+    async fn kept_certs_packed_for_preinit() {
+        let root = std::env::temp_dir().join(format!("ac-client-keep-{}", std::process::id()));
+        let certs = root.join("certs");
+        std::fs::create_dir_all(&certs).unwrap();
+        let crt = certs.join("client.crt");
+        let key = certs.join("client.key");
+        let ca = certs.join("ca.crt");
+        std::fs::write(&crt, "CERT").unwrap();
+        std::fs::write(&key, "KEY").unwrap();
+
+        // Missing files (no CA here) are skipped; members are relative to /
+        let archive = root.join("sysupgrade.tgz");
+        write_keep_archive(&[&crt, &key, &ca], &archive)
+            .await
+            .unwrap();
+        let out = std::process::Command::new("tar")
+            .arg("-tzf")
+            .arg(&archive)
+            .output()
+            .unwrap();
+        let listed = String::from_utf8(out.stdout).unwrap();
+        let rel = |p: &Path| p.strip_prefix("/").unwrap().display().to_string();
+        assert_eq!(listed.lines().collect::<Vec<_>>(), [rel(&crt), rel(&key)]);
+
+        assert!(write_keep_archive(&[&ca], &archive).await.is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watchdog_status_mapped_to_params() {
+        let running = r#"{"status":"running","timeout":30,"frequency":5,"magicclose":false}"#;
+        assert_eq!(parse_watchdog_status(running), (true, 5));
+
+        let stopped = r#"{"status":"offline","timeout":30,"frequency":5,"magicclose":false}"#;
+        assert_eq!(parse_watchdog_status(stopped), (false, 0));
+
+        // No watchdog device / ubus unavailable
+        assert_eq!(parse_watchdog_status(""), (false, 0));
+    }
+
+    #[test]
+    fn vendor_config_files_enumerated_with_stable_versions() {
+        let dir = std::env::temp_dir().join(format!("ac-client-uci-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("network"), "config interface 'lan'\n").unwrap();
+
+        let export = |pkg: &str| match pkg {
+            "firewall" => None,
+            other => Some(format!("package {other}\n")),
+        };
+        let base = "Device.DeviceInfo.";
+        let mut first = HashMap::new();
+        insert_vendor_config_files(&mut first, base, &dir, export);
+        let mut second = HashMap::new();
+        insert_vendor_config_files(&mut second, base, &dir, export);
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(first, second);
+        assert_eq!(
+            first[&format!("{base}VendorConfigFileNumberOfEntries")],
+            "5"
+        );
+        for (i, pkg) in VENDOR_CONFIG_PACKAGES.iter().enumerate() {
+            assert_eq!(
+                first[&format!("{base}VendorConfigFile.{}.Name", i + 1)],
+                *pkg
+            );
         }
-        _ => Err(format!(
-            "Device.DeviceInfo.{} is read-only",
-            path.trim_start_matches("Device.DeviceInfo.")
-        )),
+        let v1 = &first[&format!("{base}VendorConfigFile.1.Version")];
+        let v2 = &first[&format!("{base}VendorConfigFile.2.Version")];
+        assert_eq!(v1, &util::config_hash(b"package network\n"));
+        assert_ne!(v1, v2);
+        assert_eq!(first[&format!("{base}VendorConfigFile.5.Version")], "");
+        assert!(first[&format!("{base}VendorConfigFile.1.Date")].ends_with('Z'));
+        assert_eq!(first[&format!("{base}VendorConfigFile.2.Date")], "");
+    }
+
+    #[test]
+    fn parse_echo_responses() {
+        assert_eq!(
+            parse_public_ip("203.0.113.7\n"),
+            Some("203.0.113.7".parse().unwrap())
+        );
+        assert_eq!(
+            parse_public_ip(r#"{"ip": "2001:db8::1"}"#),
+            Some("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(parse_public_ip("<html>rate limited</html>"), None);
+        assert_eq!(parse_public_ip(r#"{"ip": "not-an-ip"}"#), None);
     }
 }
@@ -2,9 +2,11 @@
 
 #![allow(clippy::all)]
 
+use super::{DmError, UciTxn};
 use crate::config::ClientConfig;
 use log::{info, warn};
 use std::collections::HashMap;
+use std::path::Path;
 
 /// UCI helper — read a single value, returning None if empty/missing
 fn uci_get_raw(key: &str) -> Option<String> {
@@ -43,7 +45,7 @@ fn get_dhcp_pools() -> Vec<String> {
 }
 
 /// Get DHCP parameters — pool config + static leases
-pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
+pub async fn get(cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
     let mut m = HashMap::new();
     let pools = get_dhcp_pools();
 
@@ -97,21 +99,10 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
                 .unwrap_or_else(|| "12h".to_string());
             m.insert(path.to_string(), lt);
         } else if path.ends_with("LeaseNumberOfEntries") {
-            let count = std::fs::read_to_string("/tmp/dhcp.leases")
-                .map(|s| s.lines().filter(|l| !l.trim().is_empty()).count())
-                .unwrap_or(0);
+            let count = get_active_leases(&cfg.dhcp_leases_file).len();
             m.insert(path.to_string(), count.to_string());
         } else if path.ends_with("StaticAddressNumberOfEntries") {
-            let out = std::process::Command::new("uci")
-                .args(["show", "dhcp"])
-                .output()
-                .ok()
-                .and_then(|o| String::from_utf8(o.stdout).ok())
-                .unwrap_or_default();
-            let count = out
-                .lines()
-                .filter(|l| l.contains("@host[") && l.contains(".mac="))
-                .count();
+            let count = read_static_leases().len();
             m.insert(path.to_string(), count.to_string());
         } else if path.ends_with("Interface") {
             let iface = uci_get_raw(&format!("dhcp.{pool_name}.interface"))
@@ -127,9 +118,9 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
                 .join(",");
             m.insert(path.to_string(), servers);
         } else if path.contains("Client.") {
-            // Active DHCP leases from /tmp/dhcp.leases
+            // Active DHCP leases from the dnsmasq lease file
             // Format: <expiry_epoch> <mac> <ip> <hostname> <duid>
-            let leases = get_active_leases();
+            let leases = get_active_leases(&cfg.dhcp_leases_file);
             for (li, lease) in leases.iter().enumerate() {
                 let ci = li + 1;
                 let base = format!("Device.DHCPv4.Server.Pool.{pool_idx}.Client.{ci}");
@@ -147,44 +138,12 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
                 );
             }
         } else if path.contains("StaticAddress.") {
-            // Static lease query — original logic
-            let uci_out = std::process::Command::new("uci")
-                .args(["show", "dhcp"])
-                .output()
-                .ok()
-                .and_then(|o| String::from_utf8(o.stdout).ok())
-                .unwrap_or_default();
-            let mut idx = 1u32;
-            for line in uci_out.lines() {
-                if line.contains("host.") && line.contains(".mac=") {
-                    let section = line.split('.').nth(1).unwrap_or("").to_string();
-                    let mac = line
-                        .split('=')
-                        .nth(1)
-                        .unwrap_or("")
-                        .trim_matches('\'')
-                        .to_string();
-                    let ip = uci_out
-                        .lines()
-                        .find(|l| l.contains(&format!("dhcp.{section}.ip=")))
-                        .and_then(|l| l.split('=').nth(1))
-                        .unwrap_or("")
-                        .trim_matches('\'')
-                        .to_string();
-                    let name = uci_out
-                        .lines()
-                        .find(|l| l.contains(&format!("dhcp.{section}.name=")))
-                        .and_then(|l| l.split('=').nth(1))
-                        .unwrap_or("")
-                        .trim_matches('\'')
-                        .to_string();
-                    let base = format!("Device.DHCPv4.Server.Pool.1.StaticAddress.{idx}.");
-                    m.insert(format!("{base}Chaddr"), mac);
-                    m.insert(format!("{base}Yiaddr"), ip);
-                    if !name.is_empty() {
-                        m.insert(format!("{base}X_OptimACS_Hostname"), name);
-                    }
-                    idx += 1;
+            for (li, lease) in read_static_leases().into_iter().enumerate() {
+                let base = format!("Device.DHCPv4.Server.Pool.1.StaticAddress.{}.", li + 1);
+                m.insert(format!("{base}Chaddr"), lease.mac);
+                m.insert(format!("{base}Yiaddr"), lease.ip);
+                if !lease.name.is_empty() {
+                    m.insert(format!("{base}X_OptimACS_Hostname"), lease.name);
                 }
             }
         }
@@ -193,55 +152,139 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
     m
 }
 
-struct DhcpLease {
-    mac: String,
-    ip: String,
-    hostname: String,
-    remaining: String,
+#[derive(Debug)]
+pub(super) struct DhcpLease {
+    pub(super) mac: String,
+    pub(super) ip: String,
+    pub(super) hostname: String,
+    /// Seconds until expiry; `-1` for an infinite lease.
+    pub(super) remaining: String,
 }
 
-/// Parse active DHCP leases from /tmp/dhcp.leases
-fn get_active_leases() -> Vec<DhcpLease> {
-    let content = std::fs::read_to_string("/tmp/dhcp.leases").unwrap_or_default();
+/// Active DHCP leases from the dnsmasq lease file (`dhcp_leases_file`).
+pub(super) fn get_active_leases(leases_file: &Path) -> Vec<DhcpLease> {
+    let content = std::fs::read_to_string(leases_file).unwrap_or_default();
     let now = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .map(|d| d.as_secs())
         .unwrap_or(0);
+    parse_leases(&content, now)
+}
 
-    content
+/// Parse a dnsmasq lease file, dropping expired entries.
+///
+/// Format: `<expiry_epoch> <mac> <ip> <hostname> <duid>`, where an expiry of
+/// `0` means the lease never expires.  The result is sorted soonest-to-expire
+/// first (infinite leases last); ties keep file order so indices are stable.
+pub(super) fn parse_leases(content: &str, now: u64) -> Vec<DhcpLease> {
+    let mut leases: Vec<(Option<u64>, DhcpLease)> = content
         .lines()
         .filter(|l| !l.trim().is_empty())
         .filter_map(|line| {
             let fields: Vec<&str> = line.split_whitespace().collect();
-            if fields.len() >= 4 {
-                let expiry = fields[0].parse::<u64>().unwrap_or(0);
-                let remaining = if expiry > now { expiry - now } else { 0 };
-                Some(DhcpLease {
+            if fields.len() < 4 {
+                return None;
+            }
+            let expiry = match fields[0].parse::<u64>().ok()? {
+                0 => None,
+                e if e <= now => return None,
+                e => Some(e),
+            };
+            let remaining = match expiry {
+                Some(e) => (e - now).to_string(),
+                None => "-1".to_string(),
+            };
+            Some((
+                expiry,
+                DhcpLease {
                     mac: fields[1].to_uppercase(),
                     ip: fields[2].to_string(),
                     hostname: fields[3].to_string(),
-                    remaining: remaining.to_string(),
-                })
-            } else {
-                None
-            }
+                    remaining,
+                },
+            ))
         })
-        .collect()
+        .collect();
+
+    leases.sort_by_key(|(expiry, _)| expiry.unwrap_or(u64::MAX));
+    leases.into_iter().map(|(_, lease)| lease).collect()
+}
+
+/// A `config host` section in `/etc/config/dhcp`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaticLease {
+    pub section: String,
+    pub mac: String,
+    pub ip: String,
+    pub name: String,
+}
+
+/// Host sections from `uci show dhcp` output, in file order.  Position + 1 is
+/// the StaticAddress instance number, for anonymous and named sections alike.
+pub fn parse_static_leases(uci_show: &str) -> Vec<StaticLease> {
+    let mut leases: Vec<StaticLease> = Vec::new();
+    for line in uci_show.lines() {
+        let (key, value) = match line.split_once('=') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        let key = match key.strip_prefix("dhcp.") {
+            Some(k) => k,
+            None => continue,
+        };
+        let value = value.trim_matches('\'');
+        match key.split_once('.') {
+            None if value == "host" => leases.push(StaticLease {
+                section: key.to_string(),
+                mac: String::new(),
+                ip: String::new(),
+                name: String::new(),
+            }),
+            Some((section, option)) => {
+                if let Some(lease) = leases.iter_mut().find(|l| l.section == section) {
+                    match option {
+                        "mac" => lease.mac = value.to_string(),
+                        "ip" => lease.ip = value.to_string(),
+                        "name" => lease.name = value.to_string(),
+                        _ => {}
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+    leases
+}
+
+/// Current static leases from UCI.
+pub fn read_static_leases() -> Vec<StaticLease> {
+    let out = std::process::Command::new("uci")
+        .args(["show", "dhcp"])
+        .output()
+        .ok()
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .unwrap_or_default();
+    parse_static_leases(&out)
 }
 
 /// Set DHCP static lease parameters (Chaddr/MAC or Yiaddr/IP)
-pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), String> {
+pub async fn set(
+    _cfg: &ClientConfig,
+    txn: &mut UciTxn,
+    path: &str,
+    value: &str,
+) -> Result<(), DmError> {
     // Parse path: Device.DHCPv4.Server.Pool.1.StaticAddress.{idx}.{Param}
     let parts: Vec<&str> = path.split('.').collect();
     if parts.len() < 7 {
-        return Err(format!("Invalid DHCP path: {path}"));
+        return Err(DmError::InvalidPath(path.to_string()));
     }
 
     let idx_str = parts[5]; // {idx}
     let param = parts[6]; // Chaddr, Yiaddr, or X_OptimACS_Hostname
     let idx: usize = idx_str
         .parse()
-        .map_err(|_| format!("Invalid index: {idx_str}"))?;
+        .map_err(|_| DmError::InvalidPath(path.to_string()))?;
 
     // Find existing section or create new one
     let section = find_or_create_host_section(idx).await?;
@@ -264,60 +307,32 @@ pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), Str
         }
         _ => {
             warn!("Unknown DHCP parameter: {param}");
-            return Err(format!("Unknown DHCP parameter: {param}"));
+            return Err(DmError::InvalidPath(path.to_string()));
         }
     }
 
-    // Commit changes
-    uci_commit("dhcp").await?;
+    if !txn.defer("dhcp") {
+        // Commit changes
+        uci_commit("dhcp").await?;
 
-    // Restart dnsmasq to apply changes
-    restart_dnsmasq().await?;
+        // Restart dnsmasq to apply changes
+        restart_dnsmasq().await?;
+    }
 
     Ok(())
 }
 
 /// Find existing host section by index or create a new one
 async fn find_or_create_host_section(target_idx: usize) -> Result<String, String> {
-    let out = std::process::Command::new("uci")
-        .args(["show", "dhcp"])
-        .output()
-        .ok()
-        .and_then(|o| String::from_utf8(o.stdout).ok())
-        .unwrap_or_default();
-
-    // Count existing host sections
-    let mut host_count = 0;
-    let mut last_section = String::new();
-
-    for line in out.lines() {
-        if line.starts_with("dhcp.host") && line.contains(".mac=") {
-            host_count += 1;
-            if let Some(section) = line.split('.').nth(1) {
-                last_section = section.to_string();
-            }
-            if host_count == target_idx {
-                // Found the section at this index
-                return Ok(last_section.clone());
-            }
-        }
+    if let Some(lease) = read_static_leases().get(target_idx.wrapping_sub(1)) {
+        return Ok(lease.section.clone());
     }
 
     // Need to create a new section
     let new_section = format!("host_{}", generate_host_id());
 
-    // Add new section to dhcp config
-    let status = std::process::Command::new("uci")
-        .args(["add", "dhcp", "host"])
-        .status()
-        .map_err(|e| e.to_string())?;
-
-    if !status.success() {
-        return Err("Failed to add new dhcp host section".to_string());
-    }
-
-    // Get the name of the newly added section (usually @host[-1])
-    // We'll rename it to our preferred name
+    // Add it as a named section so the options below land on it
+    uci_set(&format!("dhcp.{new_section}"), "host").await?;
     info!("Created new DHCP host section: {new_section}");
 
     Ok(new_section)
@@ -357,7 +372,7 @@ async fn uci_commit(pkg: &str) -> Result<(), String> {
     }
 }
 
-async fn restart_dnsmasq() -> Result<(), String> {
+pub async fn restart_dnsmasq() -> Result<(), String> {
     // Try multiple methods to restart dnsmasq
     let methods: Vec<Vec<&str>> = vec![
         vec!["/etc/init.d/dnsmasq", "restart"],
@@ -381,3 +396,66 @@ async fn restart_dnsmasq() -> Result<(), String> {
     warn!("Could not restart dnsmasq, changes will apply after reboot");
     Ok(()) // Don't fail the operation if restart fails
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_leases_numbered_in_file_order() {
+        let show = "dhcp.lan=dhcp\n\
+                    dhcp.lan.start='100'\n\
+                    dhcp.@host[0]=host\n\
+                    dhcp.@host[0].mac='AA:BB:CC:00:00:01'\n\
+                    dhcp.@host[0].ip='192.168.1.10'\n\
+                    dhcp.host_aabbcc000002=host\n\
+                    dhcp.host_aabbcc000002.mac='AA:BB:CC:00:00:02'\n\
+                    dhcp.host_aabbcc000002.ip='192.168.1.11'\n\
+                    dhcp.host_aabbcc000002.name='cam'\n\
+                    dhcp.wan=dhcp\n";
+        let leases = parse_static_leases(show);
+        assert_eq!(leases.len(), 2);
+        assert_eq!(leases[0].section, "@host[0]");
+        assert_eq!(leases[0].ip, "192.168.1.10");
+        assert_eq!(leases[1].section, "host_aabbcc000002");
+        assert_eq!(leases[1].mac, "AA:BB:CC:00:00:02");
+        assert_eq!(leases[1].name, "cam");
+    }
+
+    #[test]
+    fn leases_filtered_and_sorted_by_expiry() {
+        let now = 1_700_000_000;
+        let content = "\
+1700000600 aa:bb:cc:00:00:01 192.168.1.10 live-late *
+1699999000 aa:bb:cc:00:00:02 192.168.1.11 expired *
+0 aa:bb:cc:00:00:03 192.168.1.12 forever *
+1700000060 aa:bb:cc:00:00:04 192.168.1.13 live-soon *
+";
+        let leases = parse_leases(content, now);
+        let names: Vec<&str> = leases.iter().map(|l| l.hostname.as_str()).collect();
+        assert_eq!(names, vec!["live-soon", "live-late", "forever"]);
+        assert_eq!(leases[0].remaining, "60");
+        assert_eq!(leases[1].remaining, "600");
+        assert_eq!(leases[2].remaining, "-1");
+        assert_eq!(leases[0].mac, "AA:BB:CC:00:00:04");
+    }
+
+    #[test]
+    fn leases_read_from_configured_file() {
+        let file = std::env::temp_dir().join(format!("ac-client-leases-{}", std::process::id()));
+        std::fs::write(
+            &file,
+            "0 aa:bb:cc:00:00:05 192.168.1.50 printer 01:aa:bb:cc:00:00:05\n\n",
+        )
+        .unwrap();
+
+        let leases = get_active_leases(&file);
+        assert_eq!(leases.len(), 1);
+        assert_eq!(leases[0].mac, "AA:BB:CC:00:00:05");
+        assert_eq!(leases[0].ip, "192.168.1.50");
+        assert_eq!(leases[0].remaining, "-1");
+
+        std::fs::remove_file(&file).unwrap();
+        assert!(get_active_leases(&file).is_empty());
+    }
+}
@@ -0,0 +1,99 @@
+//! Data-model error type.
+//!
+//! Every SET and OPERATE handler returns [`DmError`], so the agent can answer
+//! with the USP fault code (TR-369 Appendix A) that matches the failure and
+//! name the offending parameter, instead of one generic code per message.
+
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum DmError {
+    /// Path or command not supported by this agent (7026).
+    #[error("invalid path: {0}")]
+    InvalidPath(String),
+    /// Parameter exists but is read-only (7013).
+    #[error("parameter not writable: {0}")]
+    NotWritable(String),
+    /// Value rejected for the given parameter (7012).
+    #[error("invalid value for {path}: {msg}")]
+    InvalidValue { path: String, msg: String },
+    /// Missing or malformed OPERATE input argument (7027).
+    #[error("invalid command arguments: {0}")]
+    InvalidArguments(String),
+    /// The command ran but did not succeed (7022).
+    #[error("command failed: {0}")]
+    CommandFailure(String),
+    /// Agent busy, e.g. too many concurrent operations (7005).
+    #[error("resources exceeded: {0}")]
+    ResourcesExceeded(String),
+    /// Backend failure while applying the change, e.g. uci or ubus (7003).
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+impl DmError {
+    pub fn invalid_value(path: &str, msg: impl Into<String>) -> Self {
+        DmError::InvalidValue {
+            path: path.to_string(),
+            msg: msg.into(),
+        }
+    }
+
+    /// USP error code for this failure.
+    pub fn code(&self) -> u32 {
+        match self {
+            DmError::InvalidPath(_) => 7026,
+            DmError::NotWritable(_) => 7013,
+            DmError::InvalidValue { .. } => 7012,
+            DmError::InvalidArguments(_) => 7027,
+            DmError::CommandFailure(_) => 7022,
+            DmError::ResourcesExceeded(_) => 7005,
+            DmError::Internal(_) => 7003,
+        }
+    }
+
+    /// Parameter the error refers to, for `param_errs`.
+    pub fn param_path(&self) -> Option<&str> {
+        match self {
+            DmError::InvalidPath(p) | DmError::NotWritable(p) => Some(p),
+            DmError::InvalidValue { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+}
+
+/// Backend helpers still report plain strings; those are internal failures.
+impl From<String> for DmError {
+    fn from(msg: String) -> Self {
+        DmError::Internal(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_map_to_usp_codes() {
+        let p = "Device.WiFi.Radio.1.Channel";
+        assert_eq!(DmError::InvalidPath(p.into()).code(), 7026);
+        assert_eq!(DmError::NotWritable(p.into()).code(), 7013);
+        assert_eq!(DmError::invalid_value(p, "out of range").code(), 7012);
+        assert_eq!(DmError::InvalidArguments("url".into()).code(), 7027);
+        assert_eq!(DmError::CommandFailure("exit 1".into()).code(), 7022);
+        assert_eq!(DmError::ResourcesExceeded("busy".into()).code(), 7005);
+        assert_eq!(DmError::from("uci commit failed".to_string()).code(), 7003);
+    }
+
+    #[test]
+    fn param_path_only_for_parameter_errors() {
+        let p = "Device.WiFi.Radio.1.Channel";
+        assert_eq!(DmError::invalid_value(p, "bad").param_path(), Some(p));
+        assert_eq!(DmError::NotWritable(p.into()).param_path(), Some(p));
+        assert_eq!(DmError::CommandFailure("x".into()).param_path(), None);
+        assert_eq!(
+            DmError::invalid_value(p, "bad").to_string(),
+            format!("invalid value for {p}: bad")
+        );
+    }
+}
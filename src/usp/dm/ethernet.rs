@@ -0,0 +1,57 @@
+//! TR-181 Device.Ethernet.Interface.* — physical ports from sysfs.
+
+use crate::config::ClientConfig;
+use crate::util;
+use std::collections::HashMap;
+
+pub fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
+    let mut m = HashMap::new();
+    let ifaces = util::list_ethernet_interfaces();
+
+    m.insert(
+        "Device.Ethernet.InterfaceNumberOfEntries".to_string(),
+        ifaces.len().to_string(),
+    );
+
+    // Device.Ethernet.Interface.{i}.* — restrict to one instance if given
+    let wanted: Option<usize> = path
+        .strip_prefix("Device.Ethernet.Interface.")
+        .and_then(|rest| rest.split('.').next())
+        .and_then(|s| s.parse().ok());
+
+    for (idx, iface) in ifaces.iter().enumerate() {
+        let i = idx + 1;
+        if wanted.is_some_and(|w| w != i) {
+            continue;
+        }
+        let base = format!("Device.Ethernet.Interface.{i}");
+        let oper = std::fs::read_to_string(format!("/sys/class/net/{iface}/operstate"))
+            .unwrap_or_default();
+        let status = match oper.trim() {
+            "up" => "Up",
+            "dormant" => "Dormant",
+            "notpresent" => "NotPresent",
+            "lowerlayerdown" => "LowerLayerDown",
+            _ => "Down",
+        };
+        let link = util::read_link_speed(iface);
+
+        m.insert(format!("{base}.Name"), iface.clone());
+        m.insert(format!("{base}.Status"), status.to_string());
+        if let Ok(mac) = util::read_mac_from_sysfs(iface) {
+            m.insert(format!("{base}.MACAddress"), mac);
+        }
+        // TR-181 CurrentBitRate is in Mbps; 0 when no link is negotiated
+        m.insert(
+            format!("{base}.CurrentBitRate"),
+            link.speed_mbps.unwrap_or(0).to_string(),
+        );
+        if let Some(duplex) = &link.duplex {
+            let mode = if duplex == "full" { "Full" } else { "Half" };
+            m.insert(format!("{base}.DuplexMode"), mode.to_string());
+        }
+        m.insert(format!("{base}.X_OptimACS_LinkSpeed"), link.describe());
+    }
+
+    m
+}
@@ -1,11 +1,19 @@
-//! TR-181 Device.X_OptimACS_Firmware.* — firmware version and download operation.
+//! TR-181 Device.X_OptimACS_Firmware.* — firmware version, download and
+//! rollback operations.
 
+use super::{device_info, DmError};
 use crate::apply;
 use crate::config::ClientConfig;
 use crate::util;
+use log::{debug, error, info, warn};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
 
-pub fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
+pub async fn get(cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
     let mut m = HashMap::new();
     if path.ends_with("AvailableVersion") || path.ends_with("Device.X_OptimACS_Firmware.") {
         m.insert(
@@ -13,30 +21,555 @@ pub fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
             util::read_fw_version(),
         );
     }
+    if path.ends_with("RollbackAvailable") || path.ends_with("Device.X_OptimACS_Firmware.") {
+        // Judged against the running slot, as Rollback() would
+        let available = match RollbackState::load(&cfg.cert_dir) {
+            Some(st) => {
+                let current_part = fw_printenv(BOOT_PART_VAR).await;
+                rollback_target(&st, current_part.as_deref()).is_ok()
+            }
+            None => false,
+        };
+        m.insert(
+            "Device.X_OptimACS_Firmware.RollbackAvailable".into(),
+            available.to_string(),
+        );
+    }
     m
 }
 
+/// Digests the controller can pass with Download(), checked before
+/// sysupgrade runs.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Expected {
+    md5: Option<String>,
+    sha256: Option<String>,
+}
+
+impl Expected {
+    fn from_args(input_args: &HashMap<String, String>) -> Result<Self, DmError> {
+        let digest = |name: &str, len: usize| -> Result<Option<String>, DmError> {
+            match input_args.get(name).map(|v| v.trim()) {
+                None | Some("") => Ok(None),
+                Some(v) if v.len() == len && v.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                    Ok(Some(v.to_ascii_lowercase()))
+                }
+                Some(v) => Err(DmError::InvalidArguments(format!(
+                    "{name} must be {len} hex digits, got {v:?}"
+                ))),
+            }
+        };
+        Ok(Self {
+            md5: digest("md5", 32)?,
+            sha256: digest("sha256", 64)?,
+        })
+    }
+}
+
+/// Check a downloaded image before it is flashed: at least `min_bytes`
+/// long and matching every digest the controller supplied.
+fn verify_image(path: &Path, min_bytes: u64, expected: &Expected) -> Result<(), String> {
+    let size = std::fs::metadata(path)
+        .map_err(|e| format!("{}: {e}", path.display()))?
+        .len();
+    if size < min_bytes {
+        return Err(format!(
+            "image is {size} bytes, below the {min_bytes}-byte minimum (truncated download?)"
+        ));
+    }
+    if let Some(want) = &expected.md5 {
+        let got = util::md5_file(path).map_err(|e| format!("md5: {e}"))?;
+        if got != *want {
+            return Err(format!("md5 mismatch: expected {want}, got {got}"));
+        }
+    }
+    if let Some(want) = &expected.sha256 {
+        let got = util::sha256_file(path).map_err(|e| format!("sha256: {e}"))?;
+        if got != *want {
+            return Err(format!("sha256 mismatch: expected {want}, got {got}"));
+        }
+    }
+    Ok(())
+}
+
+/// Longest a verified image waits for its OperationComplete to be sent
+/// (e.g. while no MTP is connected) before it is flashed anyway.
+const FLASH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time the transport gets to deliver the OperationComplete before
+/// sysupgrade takes the device down.
+const FLASH_GRACE: Duration = Duration::from_secs(2);
+
+/// Image verified by Download(), flashed by [`flash_staged`].
+static STAGED_IMAGE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Log download progress every this many bytes.
+const PROGRESS_STEP: u64 = 1024 * 1024;
+
+/// Firmware image being written to disk as it arrives, so peak memory stays
+/// at one HTTP chunk however large the image is.
+struct ImageWriter {
+    file: tokio::fs::File,
+    written: u64,
+    max_bytes: u64,
+    next_progress: u64,
+}
+
+impl ImageWriter {
+    async fn create(path: &Path, max_bytes: u64) -> std::io::Result<Self> {
+        Ok(Self {
+            file: tokio::fs::File::create(path).await?,
+            written: 0,
+            max_bytes,
+            next_progress: PROGRESS_STEP,
+        })
+    }
+
+    /// Append a chunk; fails once the image would exceed `max_bytes`.
+    async fn write(&mut self, chunk: &[u8]) -> Result<(), String> {
+        let total = self.written + chunk.len() as u64;
+        if total > self.max_bytes {
+            return Err(format!(
+                "image exceeds max_fw_bytes ({} bytes)",
+                self.max_bytes
+            ));
+        }
+        self.file
+            .write_all(chunk)
+            .await
+            .map_err(|e| format!("writing image: {e}"))?;
+        self.written = total;
+        if self.written >= self.next_progress {
+            debug!("Firmware download: {} MiB", self.written / PROGRESS_STEP);
+            self.next_progress = (self.written / PROGRESS_STEP + 1) * PROGRESS_STEP;
+        }
+        Ok(())
+    }
+
+    async fn finish(mut self) -> Result<u64, String> {
+        self.file
+            .flush()
+            .await
+            .map_err(|e| format!("writing image: {e}"))?;
+        Ok(self.written)
+    }
+}
+
+/// Stream the response body into `path`, at most `max_bytes` of it.
+async fn download_to(
+    mut resp: reqwest::Response,
+    path: &Path,
+    max_bytes: u64,
+) -> Result<u64, String> {
+    let mut writer = ImageWriter::create(path, max_bytes)
+        .await
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    while let Some(chunk) = resp.chunk().await.map_err(|e| e.to_string())? {
+        writer.write(&chunk).await?;
+    }
+    writer.finish().await
+}
+
+/// Device.X_OptimACS_Firmware.Download() — fetch `url` into `fw_dir`,
+/// verify it (`min_fw_bytes`, optional `md5`/`sha256` args) and sysupgrade.
+/// The body is streamed to disk and capped at `max_fw_bytes`.  A rejected
+/// image is deleted and nothing is flashed.
+///
+/// Runs asynchronously (see [`super::is_async_command`]): the result goes to
+/// the controller in an OperationComplete, after which the image is flashed
+/// and the device reboots.
 pub async fn operate_download(
     cfg: &ClientConfig,
     _command: &str,
     input_args: &HashMap<String, String>,
-) -> Result<HashMap<String, String>, String> {
+) -> Result<HashMap<String, String>, DmError> {
     let fw_url = input_args.get("url").cloned().unwrap_or_default();
     if fw_url.is_empty() {
-        return Err("firmware download requires 'url' input arg".into());
+        return Err(DmError::InvalidArguments(
+            "firmware download requires 'url' input arg".into(),
+        ));
+    }
+    let expected = Expected::from_args(input_args)?;
+    if expected == Expected::default() {
+        warn!("Firmware download from {fw_url} without md5/sha256; only the size is checked");
     }
+
     // Download to fw_dir then apply
+    tokio::fs::create_dir_all(&cfg.fw_dir)
+        .await
+        .map_err(|e| DmError::CommandFailure(format!("{}: {e}", cfg.fw_dir.display())))?;
     let fw_path = cfg.fw_dir.join("firmware.bin");
     // Use a simple HTTP download via reqwest
-    let resp = reqwest::get(&fw_url).await.map_err(|e| e.to_string())?;
-    let bytes = resp.bytes().await.map_err(|e| e.to_string())?;
-    tokio::fs::write(&fw_path, &bytes)
+    let resp = reqwest::get(&fw_url)
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| DmError::CommandFailure(e.to_string()))?;
+    if let Some(len) = resp.content_length().filter(|&l| l > cfg.max_fw_bytes) {
+        return Err(DmError::CommandFailure(format!(
+            "image is {len} bytes, above max_fw_bytes ({})",
+            cfg.max_fw_bytes
+        )));
+    }
+    if let (Some(len), Some(fs)) = (
+        resp.content_length(),
+        util::read_filesystem_usage(&cfg.fw_dir.to_string_lossy()),
+    ) {
+        if len > fs.free_kb.saturating_mul(1024) {
+            return Err(DmError::CommandFailure(format!(
+                "image is {len} bytes but only {} kB free in {}",
+                fs.free_kb,
+                cfg.fw_dir.display()
+            )));
+        }
+    }
+    let size = match download_to(resp, &fw_path, cfg.max_fw_bytes).await {
+        Ok(size) => size,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&fw_path).await;
+            warn!("Firmware download from {fw_url} failed: {e}");
+            return Err(DmError::CommandFailure(e));
+        }
+    };
+    if let Err(e) = verify_image(&fw_path, cfg.min_fw_bytes, &expected) {
+        let _ = tokio::fs::remove_file(&fw_path).await;
+        warn!("Firmware from {fw_url} rejected: {e}");
+        return Err(DmError::CommandFailure(format!("firmware rejected: {e}")));
+    }
+    if let Err(e) = apply::test_firmware(&fw_path).await {
+        let _ = tokio::fs::remove_file(&fw_path).await;
+        warn!("Firmware from {fw_url} rejected: {e}");
+        return Err(DmError::CommandFailure(format!("firmware rejected: {e}")));
+    }
+    info!("Firmware image verified ({size} bytes)");
+    prepare_rollback(cfg).await;
+
+    // sysupgrade reboots; the image is flashed by flash_staged() once the
+    // OperationComplete carrying this result is out
+    *STAGED_IMAGE.lock().unwrap() = Some(fw_path);
+    let mut out = HashMap::new();
+    out.insert("status".into(), "applying".into());
+    out.insert("size".into(), size.to_string());
+    Ok(out)
+}
+
+/// Whether `command` is the asynchronous firmware Download().
+pub fn is_download(command: &str) -> bool {
+    command.starts_with("Device.X_OptimACS_Firmware.") && command.ends_with(".Download()")
+}
+
+/// Flash the image Download() verified, if any, once `reported` completes
+/// (its OperationComplete has been sent) or after [`FLASH_TIMEOUT`].
+pub async fn flash_staged(reported: impl std::future::Future<Output = ()>) {
+    if STAGED_IMAGE.lock().unwrap().is_none() {
+        return;
+    }
+    match tokio::time::timeout(FLASH_TIMEOUT, reported).await {
+        Ok(()) => tokio::time::sleep(FLASH_GRACE).await,
+        Err(_) => warn!(
+            "OperationComplete for Download() not sent within {}s; flashing anyway",
+            FLASH_TIMEOUT.as_secs()
+        ),
+    }
+    let fw_path = match STAGED_IMAGE.lock().unwrap().take() {
+        Some(path) => path,
+        None => return,
+    };
+    warn!("Flashing firmware {}", fw_path.display());
+    if let Err(e) = apply::apply_firmware(&fw_path).await {
+        error!("Firmware upgrade failed: {e}");
+    }
+}
+
+// ── Rollback ──────────────────────────────────────────────────────────────────
+
+/// Rollback record in `cert_dir`, written before each firmware update.
+const ROLLBACK_FILE: &str = "firmware_rollback";
+/// `sysupgrade -b` config archive taken before each firmware update.
+const CONFIG_BACKUP_FILE: &str = "pre-upgrade-config.tar.gz";
+/// U-Boot variable selecting the boot partition on dual-image devices.
+const BOOT_PART_VAR: &str = "boot_part";
+
+/// What was in place before the last firmware update.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct RollbackState {
+    previous_version: String,
+    /// Boot partition the device ran from, on dual-image platforms.
+    boot_part: Option<String>,
+    /// Config archive restorable with `sysupgrade -r`.
+    config_backup: Option<PathBuf>,
+}
+
+impl RollbackState {
+    fn load(dir: &Path) -> Option<Self> {
+        let text = std::fs::read_to_string(dir.join(ROLLBACK_FILE)).ok()?;
+        Some(Self::parse(&text))
+    }
+
+    fn parse(text: &str) -> Self {
+        let mut st = Self::default();
+        for line in text.lines() {
+            match line.split_once('=') {
+                Some(("previous_version", v)) => st.previous_version = v.trim().to_string(),
+                Some(("boot_part", v)) if !v.trim().is_empty() => {
+                    st.boot_part = Some(v.trim().to_string())
+                }
+                Some(("config_backup", v)) if !v.trim().is_empty() => {
+                    st.config_backup = Some(PathBuf::from(v.trim()))
+                }
+                _ => {}
+            }
+        }
+        st
+    }
+
+    fn render(&self) -> String {
+        let mut text = format!("previous_version={}\n", self.previous_version);
+        if let Some(part) = &self.boot_part {
+            text.push_str(&format!("boot_part={part}\n"));
+        }
+        if let Some(path) = &self.config_backup {
+            text.push_str(&format!("config_backup={}\n", path.display()));
+        }
+        text
+    }
+}
+
+/// How a rollback will be carried out.
+#[derive(Debug, PartialEq, Eq)]
+enum RollbackTarget {
+    /// Boot the other image again by switching `boot_part` back.
+    BootSlot(String),
+    /// Single-image platform: restore the pre-update configuration.
+    ConfigBackup(PathBuf),
+}
+
+/// Pick the rollback target.  The alternate boot slot is preferred when the
+/// device has since switched partitions (`current_part` differs from the
+/// recorded one; `None` = not known yet); otherwise the config backup, if
+/// its archive still exists.
+fn rollback_target(
+    st: &RollbackState,
+    current_part: Option<&str>,
+) -> Result<RollbackTarget, DmError> {
+    if let Some(prev) = &st.boot_part {
+        if current_part != Some(prev.as_str()) {
+            return Ok(RollbackTarget::BootSlot(prev.clone()));
+        }
+    }
+    match &st.config_backup {
+        Some(path) if path.exists() => Ok(RollbackTarget::ConfigBackup(path.clone())),
+        _ => Err(DmError::CommandFailure(
+            "no rollback target: no alternate boot slot or config backup recorded".into(),
+        )),
+    }
+}
+
+async fn fw_printenv(var: &str) -> Option<String> {
+    let out = Command::new("fw_printenv")
+        .args(["-n", var])
+        .output()
         .await
-        .map_err(|e| e.to_string())?;
-    apply::apply_firmware(&fw_path)
+        .ok()?;
+    let val = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    (out.status.success() && !val.is_empty()).then_some(val)
+}
+
+/// Record the running image's boot partition and back up the configuration
+/// before a firmware update.  Best effort: a failure here only means
+/// Rollback() will have nothing to return to, so the update still proceeds.
+async fn prepare_rollback(cfg: &ClientConfig) {
+    // cert_dir is itself kept across upgrades, so the archive is built in
+    // tmpfs without the previous one inside it, then moved there
+    let backup = cfg.cert_dir.join(CONFIG_BACKUP_FILE);
+    let staging = std::env::temp_dir().join(CONFIG_BACKUP_FILE);
+    let _ = tokio::fs::remove_file(&backup).await;
+    let config_backup = match Command::new("sysupgrade")
+        .arg("-b")
+        .arg(&staging)
+        .status()
         .await
-        .map_err(|e| e.to_string())?;
+    {
+        Ok(s) if s.success() => {
+            let copied = tokio::fs::copy(&staging, &backup).await;
+            let _ = tokio::fs::remove_file(&staging).await;
+            match copied {
+                Ok(_) => Some(backup),
+                Err(e) => {
+                    warn!("config backup not saved to {}: {e}", backup.display());
+                    None
+                }
+            }
+        }
+        Ok(s) => {
+            warn!("sysupgrade -b exited with {s}; no config backup for rollback");
+            None
+        }
+        Err(e) => {
+            warn!("sysupgrade -b: {e}; no config backup for rollback");
+            None
+        }
+    };
+    let st = RollbackState {
+        previous_version: util::read_fw_version(),
+        boot_part: fw_printenv(BOOT_PART_VAR).await,
+        config_backup,
+    };
+    let path = cfg.cert_dir.join(ROLLBACK_FILE);
+    match tokio::fs::write(&path, st.render()).await {
+        Ok(()) => info!("Rollback state recorded in {}", path.display()),
+        Err(e) => warn!("Rollback state not recorded in {}: {e}", path.display()),
+    }
+}
+
+/// Device.X_OptimACS_Firmware.Rollback() — undo the last firmware update
+/// and reboot: back to the previous boot slot where the platform has two,
+/// otherwise restore the configuration saved before the update.
+pub async fn operate_rollback(
+    cfg: &ClientConfig,
+    _command: &str,
+    _input_args: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, DmError> {
+    let st = RollbackState::load(&cfg.cert_dir).unwrap_or_default();
+    let current_part = fw_printenv(BOOT_PART_VAR).await;
+    let target = rollback_target(&st, current_part.as_deref())?;
+
+    let method = match &target {
+        RollbackTarget::BootSlot(part) => {
+            run_checked(Command::new("fw_setenv").args([BOOT_PART_VAR, part])).await?;
+            "boot_slot"
+        }
+        RollbackTarget::ConfigBackup(path) => {
+            run_checked(Command::new("sysupgrade").arg("-r").arg(path)).await?;
+            "config_backup"
+        }
+    };
+    // One-shot: the record describes the update just undone
+    if let Err(e) = tokio::fs::remove_file(cfg.cert_dir.join(ROLLBACK_FILE)).await {
+        warn!("Rollback state not cleared: {e}");
+    }
+    warn!(
+        "Rolling back firmware update (was {}) via {method}; rebooting",
+        st.previous_version
+    );
+    tokio::spawn(async {
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        device_info::reboot().await;
+    });
+
     let mut out = HashMap::new();
-    out.insert("status".into(), "applied".into());
+    out.insert("status".into(), "rebooting".into());
+    out.insert("method".into(), method.into());
+    out.insert("previous_version".into(), st.previous_version);
     Ok(out)
 }
+
+async fn run_checked(cmd: &mut Command) -> Result<(), DmError> {
+    let out = cmd
+        .output()
+        .await
+        .map_err(|e| DmError::CommandFailure(e.to_string()))?;
+    if out.status.success() {
+        Ok(())
+    } else {
+        Err(DmError::CommandFailure(format!(
+            "{:?} failed: {}",
+            cmd.as_std().get_program(),
+            String::from_utf8_lossy(&out.stderr).trim()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corrupt_or_truncated_image_rejected() {
+        let path = std::env::temp_dir().join(format!("ac-client-fw-{}", std::process::id()));
+        std::fs::write(&path, b"hello\n").unwrap();
+        let md5 = "b1946ac92492d2347c6235b4d2611184";
+        let sha256 = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+        let args = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        let good = Expected::from_args(&args(&[("md5", md5), ("sha256", &sha256.to_uppercase())]))
+            .unwrap();
+        assert_eq!(good.sha256.as_deref(), Some(sha256));
+        assert_eq!(verify_image(&path, 6, &good), Ok(()));
+
+        // Below the minimum size
+        assert!(verify_image(&path, 7, &good)
+            .unwrap_err()
+            .contains("minimum"));
+
+        // One wrong digest is enough to reject
+        let mut bad = Expected::from_args(&args(&[("sha256", sha256)])).unwrap();
+        bad.md5 = Some("0".repeat(32));
+        assert!(verify_image(&path, 0, &bad)
+            .unwrap_err()
+            .starts_with("md5 mismatch"));
+
+        assert!(matches!(
+            Expected::from_args(&args(&[("md5", "abc")])),
+            Err(DmError::InvalidArguments(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversized_image_aborted() {
+        let path = std::env::temp_dir().join(format!("ac-client-fw-cap-{}", std::process::id()));
+        let mut w = ImageWriter::create(&path, 2 * PROGRESS_STEP).await.unwrap();
+        let chunk = vec![0u8; 512 * 1024];
+        for _ in 0..4 {
+            w.write(&chunk).await.unwrap();
+        }
+        assert_eq!(w.written, 2 * PROGRESS_STEP);
+        assert_eq!(w.next_progress, 3 * PROGRESS_STEP);
+        assert!(w.write(&[0]).await.unwrap_err().contains("max_fw_bytes"));
+        assert_eq!(w.finish().await.unwrap(), 2 * PROGRESS_STEP);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 2 * PROGRESS_STEP);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rollback_without_target_is_an_error() {
+        // Nothing recorded
+        assert!(matches!(
+            rollback_target(&RollbackState::default(), None),
+            Err(DmError::CommandFailure(_))
+        ));
+        // Single-image device whose config archive has gone missing
+        let st = RollbackState {
+            previous_version: "23.05.3".into(),
+            boot_part: None,
+            config_backup: Some(PathBuf::from("/nonexistent/pre-upgrade-config.tar.gz")),
+        };
+        assert!(rollback_target(&st, None).is_err());
+    }
+
+    #[test]
+    fn rollback_state_round_trips_and_prefers_boot_slot() {
+        let st = RollbackState {
+            previous_version: "23.05.3".into(),
+            boot_part: Some("1".into()),
+            config_backup: Some(std::env::temp_dir()),
+        };
+        assert_eq!(RollbackState::parse(&st.render()), st);
+
+        // The update flipped to partition 2: go back to 1
+        assert_eq!(
+            rollback_target(&st, Some("2")).unwrap(),
+            RollbackTarget::BootSlot("1".into())
+        );
+        // Still on the recorded partition: only the config can be restored
+        assert_eq!(
+            rollback_target(&st, Some("1")).unwrap(),
+            RollbackTarget::ConfigBackup(std::env::temp_dir())
+        );
+    }
+}
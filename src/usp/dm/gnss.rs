@@ -0,0 +1,75 @@
+//! TR-181 Device.X_OptimACS_GNSS.* — the latest fix from the GNSS reader.
+//!
+//! Values come from the position `spawn_gnss_reader` keeps up to date.
+//! Without a receiver or a fix every parameter is empty except
+//! `FixQuality`, which is `0` (invalid).
+
+use crate::config::ClientConfig;
+use crate::gnss::{self, GnssPosition};
+use std::collections::HashMap;
+use std::time::Instant;
+
+const BASE: &str = "Device.X_OptimACS_GNSS.";
+
+pub fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
+    params(gnss::current_position().as_ref(), Instant::now())
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(path))
+        .collect()
+}
+
+/// Every parameter of the object for `pos` as seen at `now`.
+fn params(pos: Option<&GnssPosition>, now: Instant) -> HashMap<String, String> {
+    let opt = |v: Option<String>| v.unwrap_or_default();
+    [
+        ("Latitude", opt(pos.map(|p| p.latitude.clone()))),
+        ("Longitude", opt(pos.map(|p| p.longitude.clone()))),
+        (
+            "Altitude",
+            opt(pos.and_then(|p| p.altitude).map(|a| a.to_string())),
+        ),
+        ("FixQuality", pos.map_or(0, |p| p.fix_quality).to_string()),
+        (
+            "Satellites",
+            opt(pos.and_then(|p| p.satellites).map(|n| n.to_string())),
+        ),
+        (
+            "LastFixAge",
+            opt(gnss::fix_age(pos.map(|p| p.fixed_at), now).map(|s| s.to_string())),
+        ),
+    ]
+    .into_iter()
+    .map(|(name, val)| (format!("{BASE}{name}"), val))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn gnss_params_with_and_without_fix() {
+        let now = Instant::now();
+        let pos = GnssPosition {
+            latitude: "48.117300".into(),
+            longitude: "11.516667".into(),
+            altitude: Some(545.4),
+            satellites: Some(8),
+            fix_quality: 1,
+            fixed_at: now - Duration::from_secs(3),
+            ..Default::default()
+        };
+        let m = params(Some(&pos), now);
+        assert_eq!(m["Device.X_OptimACS_GNSS.Latitude"], "48.117300");
+        assert_eq!(m["Device.X_OptimACS_GNSS.Altitude"], "545.4");
+        assert_eq!(m["Device.X_OptimACS_GNSS.FixQuality"], "1");
+        assert_eq!(m["Device.X_OptimACS_GNSS.Satellites"], "8");
+        assert_eq!(m["Device.X_OptimACS_GNSS.LastFixAge"], "3");
+
+        let m = params(None, now);
+        assert_eq!(m.len(), 6);
+        assert_eq!(m["Device.X_OptimACS_GNSS.FixQuality"], "0");
+        assert_eq!(m["Device.X_OptimACS_GNSS.LastFixAge"], "");
+    }
+}
@@ -1,9 +1,16 @@
 //! TR-181 Device.Hosts.Host.* — reads/writes via UCI dnsmasq and /etc/hosts.
+//!
+//! Static entries come first and are the only writable ones; after them come
+//! the clients actually seen on the LAN, from the dnsmasq lease file and the
+//! ARP table.
 
 #![allow(clippy::all)]
 
+use super::dhcp::DhcpLease;
+use super::{DmError, UciTxn};
 use crate::config::ClientConfig;
 use crate::usp::tp469::uci_backend::uci_commit;
+use crate::util::ArpEntry;
 use log::{info, warn};
 use std::collections::HashMap;
 
@@ -31,31 +38,140 @@ fn uci_delete(path: &str) -> Result<(), String> {
     }
 }
 
-/// Get DNS entries from UCI dnsmasq config
-fn get_dns_entries() -> Vec<(String, String)> {
-    let mut entries = Vec::new();
+/// Where a static `Device.Hosts.Host.{i}` entry is stored.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostSource {
+    /// Line number in /etc/hosts.
+    HostsFile(usize),
+    /// Raw `/hostname/ip` value in the dnsmasq `address` list.
+    Dnsmasq(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaticHost {
+    pub ip: String,
+    pub hostname: String,
+    pub source: HostSource,
+}
 
+/// Static host entries in Host.{i} order: /etc/hosts first, then the
+/// dnsmasq `address` list (`uci get` prints a list space-separated).
+/// GET, SET and DELETE all number instances from this list.
+pub fn parse_static_hosts(hosts_file: &str, dns_addresses: &str) -> Vec<StaticHost> {
+    let mut hosts = Vec::new();
+
+    for (line_no, line) in hosts_file.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        let ip = parts.next().unwrap_or("");
+        let hostname = parts.next().unwrap_or("");
+        if !ip.is_empty() && !hostname.is_empty() {
+            hosts.push(StaticHost {
+                ip: ip.to_string(),
+                hostname: hostname.to_string(),
+                source: HostSource::HostsFile(line_no),
+            });
+        }
+    }
+
+    for entry in dns_addresses.split_whitespace() {
+        // Format in UCI: /hostname/ip or /domain/ip
+        let entry = entry.trim_matches('\'');
+        let mut parts = match entry.strip_prefix('/') {
+            Some(rest) => rest.split('/'),
+            None => continue,
+        };
+        if let (Some(hostname), Some(ip)) = (parts.next(), parts.next()) {
+            hosts.push(StaticHost {
+                ip: ip.to_string(),
+                hostname: hostname.to_string(),
+                source: HostSource::Dnsmasq(entry.to_string()),
+            });
+        }
+    }
+
+    hosts
+}
+
+/// Current static host entries from /etc/hosts and UCI.
+pub fn read_static_hosts() -> Vec<StaticHost> {
+    let content = std::fs::read_to_string("/etc/hosts").unwrap_or_default();
     let out = std::process::Command::new("uci")
         .args(["get", "dhcp.@dnsmasq[0].address"])
         .output()
         .ok()
         .and_then(|o| String::from_utf8(o.stdout).ok())
         .unwrap_or_default();
+    parse_static_hosts(&content, &out)
+}
 
-    for line in out.lines() {
-        // Format in UCI: /hostname/ip or /domain/ip
-        let line = line.trim_matches('\'').trim();
-        if line.starts_with('/') {
-            let parts: Vec<&str> = line[1..].split('/').collect();
-            if parts.len() >= 2 {
-                let hostname = parts[0].to_string();
-                let ip = parts[1].to_string();
-                entries.push((ip, hostname));
-            }
+/// A client seen on the LAN, from its DHCP lease and/or the ARP table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynamicHost {
+    /// Lower-case MAC address.
+    pub mac: String,
+    pub ip: String,
+    /// Hostname the client sent in its DHCP request; empty when unknown.
+    pub hostname: String,
+    /// TR-181 `AddressSource`: `DHCP` when leased, otherwise `None`.
+    pub source: &'static str,
+    /// A complete ARP entry exists for the MAC.
+    pub active: bool,
+    /// Interface the client was last seen on in ARP.
+    pub interface: String,
+    /// Seconds left on the lease (`-1` infinite, `0` without a lease).
+    pub lease_remaining: String,
+}
+
+/// Merge DHCP leases and LAN ARP entries into one host per MAC.  A lease
+/// supplies the hostname and lease time, the ARP entry whether the client is
+/// active; incomplete ARP entries without a lease are left out.  Sorted by
+/// MAC so instance numbers don't move as leases are renewed.
+fn merge_dynamic_hosts(leases: &[DhcpLease], arp: &[ArpEntry]) -> Vec<DynamicHost> {
+    let resolved = |mac: &str| arp.iter().find(|e| e.complete && e.mac == mac);
+    let mut hosts: Vec<DynamicHost> = Vec::new();
+
+    for lease in leases {
+        let mac = lease.mac.to_lowercase();
+        if hosts.iter().any(|h| h.mac == mac) {
+            continue;
+        }
+        let seen = resolved(&mac);
+        let hostname = match lease.hostname.as_str() {
+            "*" => String::new(),
+            name => name.to_string(),
+        };
+        hosts.push(DynamicHost {
+            ip: lease.ip.clone(),
+            hostname,
+            source: "DHCP",
+            active: seen.is_some(),
+            interface: seen.map(|e| e.device.clone()).unwrap_or_default(),
+            lease_remaining: lease.remaining.clone(),
+            mac,
+        });
+    }
+
+    for entry in arp.iter().filter(|e| e.complete) {
+        if hosts.iter().any(|h| h.mac == entry.mac) {
+            continue;
         }
+        hosts.push(DynamicHost {
+            mac: entry.mac.clone(),
+            ip: entry.ip.clone(),
+            hostname: String::new(),
+            source: "None",
+            active: true,
+            interface: entry.device.clone(),
+            lease_remaining: "0".to_string(),
+        });
     }
 
-    entries
+    hosts.sort_by(|a, b| a.mac.cmp(&b.mac));
+    hosts
 }
 
 /// Parse host index from path like "Device.Hosts.Host.1.HostName"
@@ -72,109 +188,92 @@ fn parse_host_index(path: &str) -> Option<usize> {
     }
 }
 
-pub async fn get(_cfg: &ClientConfig, _path: &str) -> HashMap<String, String> {
+pub async fn get(cfg: &ClientConfig, _path: &str) -> HashMap<String, String> {
     let mut m = HashMap::new();
-    let dns_entries = get_dns_entries();
-
-    // First, read from /etc/hosts
-    let content = std::fs::read_to_string("/etc/hosts").unwrap_or_default();
     let mut idx = 1u32;
+    let arp = crate::util::lan_arp_entries(crate::util::read_arp_table(), &cfg.lan_interfaces);
+    let statics = read_static_hosts();
 
-    for line in content.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
-        }
-        let mut parts = line.split_whitespace();
-        let ip = parts.next().unwrap_or("");
-        let hostname = parts.next().unwrap_or("");
-        if !ip.is_empty() && !hostname.is_empty() {
-            let base = format!("Device.Hosts.Host.{idx}.");
-            m.insert(format!("{base}IPAddress"), ip.into());
-            m.insert(format!("{base}HostName"), hostname.into());
-            m.insert(format!("{base}Active"), "true".to_string());
-            idx += 1;
-        }
-    }
-
-    // Then add DNS entries from UCI
-    for (ip, hostname) in &dns_entries {
+    // /etc/hosts entries, then DNS entries from UCI
+    for host in &statics {
         let base = format!("Device.Hosts.Host.{idx}.");
-        m.insert(format!("{base}IPAddress"), ip.clone());
-        m.insert(format!("{base}HostName"), hostname.clone());
-        m.insert(format!("{base}Active"), "true".to_string());
+        let active = arp.iter().any(|e| e.complete && e.ip == host.ip);
+        m.insert(format!("{base}IPAddress"), host.ip.clone());
+        m.insert(format!("{base}HostName"), host.hostname.clone());
+        m.insert(format!("{base}AddressSource"), "Static".to_string());
+        m.insert(format!("{base}Active"), active.to_string());
+        m.insert(format!("{base}LeaseTimeRemaining"), "0".to_string());
         idx += 1;
     }
 
-    m
-}
-
-pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), String> {
-    // Parse host index
-    let idx = parse_host_index(path)
-        .ok_or_else(|| format!("Cannot parse host index from path: {path}"))?;
-
-    // Get current entries to find the one we're modifying
-    let dns_entries = get_dns_entries();
-    let content = std::fs::read_to_string("/etc/hosts").unwrap_or_default();
-    let mut hosts_entries: Vec<(String, String)> = Vec::new();
-
-    for line in content.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
+    // Finally, LAN clients from DHCP leases and ARP not already listed by
+    // address
+    for host in merge_dynamic_hosts(&super::dhcp::get_active_leases(&cfg.dhcp_leases_file), &arp) {
+        if statics.iter().any(|s| s.ip == host.ip) {
             continue;
         }
-        let mut parts = line.split_whitespace();
-        let ip = parts.next().unwrap_or("");
-        let hostname = parts.next().unwrap_or("");
-        if !ip.is_empty() && !hostname.is_empty() {
-            hosts_entries.push((ip.to_string(), hostname.to_string()));
+        let base = format!("Device.Hosts.Host.{idx}.");
+        m.insert(format!("{base}IPAddress"), host.ip);
+        m.insert(format!("{base}PhysAddress"), host.mac);
+        m.insert(format!("{base}HostName"), host.hostname);
+        m.insert(format!("{base}AddressSource"), host.source.to_string());
+        m.insert(format!("{base}Active"), host.active.to_string());
+        m.insert(format!("{base}LeaseTimeRemaining"), host.lease_remaining);
+        if !host.interface.is_empty() {
+            m.insert(format!("{base}X_OptimACS_Interface"), host.interface);
         }
+        idx += 1;
     }
 
-    // Total entries = hosts entries + DNS entries
-    let total_entries = hosts_entries.len() + dns_entries.len();
-
-    if idx == 0 || idx > total_entries {
-        return Err(format!(
-            "Host index {idx} out of range (total: {total_entries})"
-        ));
-    }
+    m
+}
 
-    // Determine if this is a hosts entry or DNS entry
-    let is_dns_entry = idx > hosts_entries.len();
-    let effective_idx = if is_dns_entry {
-        idx - hosts_entries.len()
-    } else {
-        idx
+pub async fn set(
+    _cfg: &ClientConfig,
+    txn: &mut UciTxn,
+    path: &str,
+    value: &str,
+) -> Result<(), DmError> {
+    // Parse host index
+    let idx = parse_host_index(path).ok_or_else(|| DmError::InvalidPath(path.to_string()))?;
+
+    // Same list (and numbering) as GET
+    let host = match idx
+        .checked_sub(1)
+        .and_then(|i| read_static_hosts().into_iter().nth(i))
+    {
+        Some(h) => h,
+        None => return Err(DmError::InvalidPath(path.to_string())),
     };
+    let is_dns_entry = matches!(host.source, HostSource::Dnsmasq(_));
 
     if path.ends_with(".HostName") {
-        if is_dns_entry {
-            // Update DNS entry in UCI
-            let (old_ip, _old_hostname) = &dns_entries[effective_idx - 1];
-            let new_entry = format!("/{}/{}", value, old_ip);
-
-            // This is complex with UCI - we need to replace the specific list item
-            // For now, just add the new one and we'll rely on external tools to clean up
-            uci_add_list("dhcp.@dnsmasq[0].address", &new_entry)?;
-            info!("Added DNS entry: {value} -> {old_ip}");
-        } else {
-            // Update /etc/hosts entry - this requires rewriting the file
-            let (old_ip, _old_hostname) = &hosts_entries[effective_idx - 1];
-            update_hosts_file(effective_idx - 1, old_ip, value).await?;
-            info!("Updated hosts entry: {old_ip} -> {value}");
+        match host.source {
+            HostSource::Dnsmasq(_) => {
+                // Update DNS entry in UCI
+                let new_entry = format!("/{}/{}", value, host.ip);
+
+                // This is complex with UCI - we need to replace the specific list item
+                // For now, just add the new one and we'll rely on external tools to clean up
+                uci_add_list("dhcp.@dnsmasq[0].address", &new_entry)?;
+                info!("Added DNS entry: {value} -> {}", host.ip);
+            }
+            HostSource::HostsFile(line) => {
+                update_hosts_file(line, &host.ip, value).await?;
+                info!("Updated hosts entry: {} -> {value}", host.ip);
+            }
         }
     } else if path.ends_with(".IPAddress") {
-        if is_dns_entry {
-            let (_old_ip, old_hostname) = &dns_entries[effective_idx - 1];
-            let new_entry = format!("/{}/{}", old_hostname, value);
-            uci_add_list("dhcp.@dnsmasq[0].address", &new_entry)?;
-            info!("Added DNS entry: {old_hostname} -> {value}");
-        } else {
-            let (_old_ip, old_hostname) = &hosts_entries[effective_idx - 1];
-            update_hosts_file(effective_idx - 1, value, old_hostname).await?;
-            info!("Updated hosts entry: {value} -> {old_hostname}");
+        match host.source {
+            HostSource::Dnsmasq(_) => {
+                let new_entry = format!("/{}/{}", host.hostname, value);
+                uci_add_list("dhcp.@dnsmasq[0].address", &new_entry)?;
+                info!("Added DNS entry: {} -> {value}", host.hostname);
+            }
+            HostSource::HostsFile(line) => {
+                update_hosts_file(line, value, &host.hostname).await?;
+                info!("Updated hosts entry: {value} -> {}", host.hostname);
+            }
         }
     } else if path.ends_with(".Active") {
         // Enable/disable logic - for DNS entries we can't easily remove
@@ -182,10 +281,10 @@ pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), Str
         info!("Host {idx} Active set to {value}");
     } else {
         warn!("Unknown Host parameter in path: {path}");
-        return Err(format!("Unknown Host parameter: {path}"));
+        return Err(DmError::InvalidPath(path.to_string()));
     }
 
-    if is_dns_entry {
+    if is_dns_entry && !txn.defer("dhcp") {
         uci_commit("dhcp")?;
         restart_dnsmasq().await?;
     }
@@ -193,38 +292,15 @@ pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), Str
     Ok(())
 }
 
-/// Update a line in /etc/hosts
-async fn update_hosts_file(idx: usize, new_ip: &str, new_hostname: &str) -> Result<(), String> {
+/// Rewrite line `line_no` (0-based, as in [`HostSource::HostsFile`]) of /etc/hosts
+async fn update_hosts_file(line_no: usize, new_ip: &str, new_hostname: &str) -> Result<(), String> {
     let content = std::fs::read_to_string("/etc/hosts")
         .map_err(|e| format!("Failed to read /etc/hosts: {e}"))?;
 
     let mut lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
-    let mut hosts_idx = 0;
-    let mut found = false;
-
-    for (_i, line) in lines.iter_mut().enumerate() {
-        let trimmed = line.trim();
-        if trimmed.is_empty() || trimmed.starts_with('#') {
-            continue;
-        }
-
-        let mut parts = trimmed.split_whitespace();
-        let ip = parts.next().unwrap_or("");
-        let hostname = parts.next().unwrap_or("");
-
-        if !ip.is_empty() && !hostname.is_empty() {
-            if hosts_idx == idx {
-                *line = format!("{new_ip} {new_hostname}");
-                found = true;
-                break;
-            }
-            hosts_idx += 1;
-        }
-    }
-
-    if !found {
-        // Append new entry
-        lines.push(format!("{new_ip} {new_hostname}"));
+    match lines.get_mut(line_no) {
+        Some(line) => *line = format!("{new_ip} {new_hostname}"),
+        None => return Err(format!("/etc/hosts has no line {}", line_no + 1)),
     }
 
     std::fs::write("/etc/hosts", lines.join("\n"))
@@ -256,3 +332,73 @@ async fn restart_dnsmasq() -> Result<(), String> {
     warn!("Could not restart dnsmasq");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_hosts_numbered_hosts_file_then_dnsmasq() {
+        let hosts_file = "127.0.0.1 localhost\n\
+                          # Removed by USP DELETE: 10.0.0.5 old\n\
+                          \n\
+                          192.168.1.20 nas\n";
+        let dns = "'/printer/192.168.1.30' '/cam.lan/192.168.1.31'\n";
+
+        let hosts = parse_static_hosts(hosts_file, dns);
+        assert_eq!(hosts.len(), 4);
+        assert_eq!(hosts[1].hostname, "nas");
+        assert_eq!(hosts[1].source, HostSource::HostsFile(3));
+        assert_eq!(hosts[2].ip, "192.168.1.30");
+        assert_eq!(hosts[3].hostname, "cam.lan");
+        assert_eq!(
+            hosts[3].source,
+            HostSource::Dnsmasq("/cam.lan/192.168.1.31".into())
+        );
+    }
+
+    #[test]
+    fn leases_and_arp_merged_by_mac() {
+        let leases = super::super::dhcp::parse_leases(
+            "1700000600 AA:BB:CC:00:00:02 192.168.1.102 laptop 01:aa:bb:cc:00:00:02\n\
+             1700000900 AA:BB:CC:00:00:01 192.168.1.101 * 01:aa:bb:cc:00:00:01\n",
+            1_700_000_000,
+        );
+        let arp = crate::util::parse_arp_table(
+            "IP address       HW type     Flags       HW address            Mask     Device\n\
+             192.168.1.102    0x1         0x2         aa:bb:cc:00:00:02     *        br-lan\n\
+             192.168.1.150    0x1         0x2         aa:bb:cc:00:00:03     *        br-lan\n\
+             192.168.1.151    0x1         0x0         00:00:00:00:00:00     *        br-lan\n",
+        );
+
+        let hosts = merge_dynamic_hosts(&leases, &arp);
+        assert_eq!(hosts.len(), 3);
+
+        // Leased, but not currently in ARP
+        assert_eq!(hosts[0].mac, "aa:bb:cc:00:00:01");
+        assert_eq!(hosts[0].hostname, "");
+        assert_eq!(hosts[0].source, "DHCP");
+        assert!(!hosts[0].active);
+        assert_eq!(hosts[0].lease_remaining, "900");
+
+        // Leased and active: one entry, with the lease's hostname
+        assert_eq!(
+            hosts[1],
+            DynamicHost {
+                mac: "aa:bb:cc:00:00:02".into(),
+                ip: "192.168.1.102".into(),
+                hostname: "laptop".into(),
+                source: "DHCP",
+                active: true,
+                interface: "br-lan".into(),
+                lease_remaining: "600".into(),
+            }
+        );
+
+        // Static IP configured on the client itself: ARP only
+        assert_eq!(hosts[2].mac, "aa:bb:cc:00:00:03");
+        assert_eq!(hosts[2].source, "None");
+        assert_eq!(hosts[2].lease_remaining, "0");
+        assert!(hosts[2].active);
+    }
+}
@@ -1,78 +1,60 @@
 //! TR-181 Device.IP.Interface.* — reads/writes via UCI with multi-interface support.
 
+use super::{DmError, UciTxn};
 use crate::config::ClientConfig;
 use crate::usp::tp469::uci_backend::{uci_commit, uci_get, uci_set};
 use log::{info, warn};
 use std::collections::HashMap;
+use std::path::Path;
 
-/// Get all network interface sections from UCI
+/// Logical interfaces from UCI as (section_name, network_name), in config
+/// order; `Device.IP.Interface.{i}` is the i-th entry.
 fn get_network_interfaces() -> Vec<(String, String)> {
-    // Returns vec of (section_name, network_name) tuples
-    let mut interfaces = Vec::new();
-
     let out = std::process::Command::new("uci")
         .args(["show", "network"])
         .output()
         .ok()
         .and_then(|o| String::from_utf8(o.stdout).ok())
         .unwrap_or_default();
+    parse_network_interfaces(&out)
+}
 
-    // Look for sections that have proto= (indicating they're interfaces)
-    let mut current_section = String::new();
-    let mut has_proto = false;
-
-    for line in out.lines() {
-        if line.starts_with("network.") {
-            let parts: Vec<&str> = line.split('.').collect();
-            if parts.len() >= 2 {
-                let section = parts[1].to_string();
-
-                // Skip non-interface sections
-                if section == "globals" || section == "switch" || section == "loopback" {
-                    continue;
-                }
-
-                if line.contains(".proto=") {
-                    // Check if this is a new section
-                    if section != current_section {
-                        if !current_section.is_empty() && has_proto {
-                            interfaces.push((current_section.clone(), current_section.clone()));
-                        }
-                        current_section = section.clone();
-                    }
-                    has_proto = true;
-                }
-            }
-        }
-    }
-
-    // Don't forget the last section
-    if !current_section.is_empty() && has_proto {
-        interfaces.push((current_section.clone(), current_section.clone()));
-    }
-
-    // Filter out @named sections (aliases) and ensure common interfaces are present
-    let common_interfaces = vec!["lan", "wan", "wan6"];
-    let mut filtered = Vec::new();
-
-    for (section, name) in interfaces {
-        if !section.starts_with('@') {
-            filtered.push((section, name));
-        }
-    }
-
-    // Ensure common interfaces exist even if not found
-    for iface in &common_interfaces {
-        if !filtered.iter().any(|(s, _)| s == *iface) {
-            // Check if it actually exists in UCI
-            let test = uci_get(&format!("network.{iface}.proto"));
-            if !test.is_empty() {
-                filtered.push((iface.to_string(), iface.to_string()));
+/// Every `config interface` section of `uci show network` (named or
+/// anonymous) except the loopback.  The loopback is left out: it cannot be
+/// managed remotely, and controllers already address `lan` as instance 1.
+fn parse_network_interfaces(show: &str) -> Vec<(String, String)> {
+    let mut sections: Vec<String> = Vec::new();
+    let mut loopback: Vec<String> = Vec::new();
+    for line in show.lines() {
+        let (key, value) = match line
+            .strip_prefix("network.")
+            .and_then(|l| l.split_once('='))
+        {
+            Some(kv) => kv,
+            None => continue,
+        };
+        let value = value.trim_matches('\'');
+        match key.split_once('.') {
+            // "network.lan=interface"
+            None if value == "interface" => sections.push(key.to_string()),
+            // "network.loopback.device='lo'" (ifname before OpenWrt 21.02)
+            Some((section, "device" | "ifname")) if value == "lo" => {
+                loopback.push(section.to_string())
             }
+            _ => {}
         }
     }
+    sections
+        .into_iter()
+        .filter(|s| s != "loopback" && !loopback.contains(s))
+        .map(|s| (s.clone(), s))
+        .collect()
+}
 
-    filtered
+/// Linux device carrying the interface: netifd's `l3_device` while it is
+/// up, else the `br-<name>` bridge OpenWrt creates for bridged networks.
+fn interface_device(section: &str) -> String {
+    l3_device(section).unwrap_or_else(|| format!("br-{section}"))
 }
 
 /// Parse interface index from path like "Device.IP.Interface.1.IPv4Address.1.IPAddress"
@@ -89,377 +71,855 @@ fn parse_interface_index(path: &str) -> Option<usize> {
     }
 }
 
+/// `Device.IP.Interface.*` GET.  Every interface is reported for the table
+/// and `IPv4Address.` paths; a path naming one instance reports that one.
 pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
     let mut m = HashMap::new();
-    let interfaces = get_network_interfaces();
-
-    // Check if this is a specific interface request or a general request
-    let specific_idx = parse_interface_index(path);
+    let interfaces = network_interfaces().await;
 
     if path == "Device.IP.Interface."
         || path.ends_with("IPv4Address.")
         || path.ends_with("IPv4Address.1.")
     {
-        // Return all interfaces
         for (idx, (section, _name)) in interfaces.iter().enumerate() {
-            let iface_idx = idx + 1;
-            let base = format!("Device.IP.Interface.{iface_idx}.IPv4Address.1.");
-
-            let mut ip = uci_get(&format!("network.{section}.ipaddr"));
-            let mut mask = uci_get(&format!("network.{section}.netmask"));
-            let proto = uci_get(&format!("network.{section}.proto"));
-            let mut gateway = uci_get(&format!("network.{section}.gateway"));
-            let mut dns = uci_get(&format!("network.{section}.dns"));
-
-            // For DHCP/dynamic protocols, get runtime state from ubus
-            if ip.is_empty() || proto == "dhcp" || proto == "dhcpv6" || proto == "pppoe" {
-                let rt = get_ubus_interface_status(section);
-                if let Some(v) = rt.get("ipaddr") {
-                    if !v.is_empty() {
-                        ip = v.clone();
-                    }
-                }
-                if let Some(v) = rt.get("netmask") {
-                    if !v.is_empty() {
-                        mask = v.clone();
-                    }
-                }
-                if let Some(v) = rt.get("gateway") {
-                    if !v.is_empty() {
-                        gateway = v.clone();
-                    }
-                }
-                if let Some(v) = rt.get("dns") {
-                    if !v.is_empty() {
-                        dns = v.clone();
-                    }
-                }
-            }
-
-            // Get interface name and stats
-            let bridge_name = format!("br-{section}");
-            let stats = get_interface_stats(&bridge_name).await;
-            let mac = get_interface_mac(&bridge_name).await;
-            let status = get_interface_status(&bridge_name).await;
+            insert_interface(&mut m, idx + 1, section, path).await;
+        }
+    } else if let Some(idx) = parse_interface_index(path) {
+        if let Some((section, _name)) = idx.checked_sub(1).and_then(|i| interfaces.get(i)) {
+            insert_interface(&mut m, idx, section, path).await;
+        }
+    }
 
-            // Insert name
-            m.insert(
-                format!("Device.IP.Interface.{iface_idx}.X_OptimACS_Name"),
-                section.clone(),
-            );
+    m
+}
 
-            if !ip.is_empty() {
-                m.insert(format!("{base}IPAddress"), ip);
-            }
-            if !mask.is_empty() {
-                m.insert(format!("{base}SubnetMask"), mask);
-            }
-            if !proto.is_empty() {
-                m.insert(format!("{base}AddressingType"), proto.clone());
-            }
-            if !gateway.is_empty() {
-                m.insert(
-                    format!("Device.IP.Interface.{iface_idx}.X_OptimACS_Gateway"),
-                    gateway.clone(),
-                );
-            }
-            if !dns.is_empty() {
-                m.insert(
-                    format!("Device.IP.Interface.{iface_idx}.X_OptimACS_DNS"),
-                    dns,
-                );
-            }
-            if !mac.is_empty() {
-                m.insert(format!("Device.IP.Interface.{iface_idx}.MACAddress"), mac);
-            }
-            m.insert(format!("Device.IP.Interface.{iface_idx}.Status"), status);
-
-            // Upstream flag: true for wan/wan6 interfaces
-            let is_upstream = section.starts_with("wan");
-            m.insert(
-                format!("Device.IP.Interface.{iface_idx}.X_OptimACS_Upstream"),
-                is_upstream.to_string(),
-            );
-
-            // Friendly protocol name
-            let proto_friendly = match proto.as_str() {
-                "dhcp" => "DHCPv4",
-                "dhcpv6" => "DHCPv6",
-                "static" => "Static",
-                "pppoe" => "PPPoE",
-                "pptp" => "PPTP",
-                "l2tp" => "L2TP",
-                "none" => "Unmanaged",
-                other => other,
-            };
-            m.insert(
-                format!("Device.IP.Interface.{iface_idx}.X_OptimACS_Protocol"),
-                proto_friendly.to_string(),
-            );
-
-            // GatewayIPv4 alias for Gateway
-            if !gateway.is_empty() {
-                m.insert(
-                    format!("Device.IP.Interface.{iface_idx}.X_OptimACS_GatewayIPv4"),
-                    gateway.clone(),
-                );
-            }
+/// [`get_network_interfaces`] off the async runtime.
+async fn network_interfaces() -> Vec<(String, String)> {
+    tokio::task::spawn_blocking(get_network_interfaces)
+        .await
+        .unwrap_or_default()
+}
 
-            // Add stats if available
-            if let Some(rx_bytes) = stats.get("rx_bytes") {
-                m.insert(
-                    format!("Device.IP.Interface.{iface_idx}.X_OptimACS_RXBytes"),
-                    rx_bytes.clone(),
-                );
-            }
-            if let Some(rx_packets) = stats.get("rx_packets") {
-                m.insert(
-                    format!("Device.IP.Interface.{iface_idx}.X_OptimACS_RXPackets"),
-                    rx_packets.clone(),
-                );
-            }
-            if let Some(tx_bytes) = stats.get("tx_bytes") {
-                m.insert(
-                    format!("Device.IP.Interface.{iface_idx}.X_OptimACS_TXBytes"),
-                    tx_bytes.clone(),
-                );
-            }
-            if let Some(tx_packets) = stats.get("tx_packets") {
-                m.insert(
-                    format!("Device.IP.Interface.{iface_idx}.X_OptimACS_TXPackets"),
-                    tx_packets.clone(),
-                );
-            }
-            if let Some(uptime) = stats.get("uptime") {
-                m.insert(
-                    format!("Device.IP.Interface.{iface_idx}.X_OptimACS_Uptime"),
-                    uptime.clone(),
-                );
-            }
+/// IPv4 settings and device of one logical interface.  Configured values
+/// come from UCI; for dynamic protocols netifd's runtime state wins.
+#[derive(Default)]
+struct IfaceConfig {
+    ip: String,
+    mask: String,
+    proto: String,
+    gateway: String,
+    dns: String,
+    /// Linux device, see [`interface_device`].
+    device: String,
+    mtu: String,
+}
 
-            // IPv6 — always from runtime state
-            let rt6 = get_ubus_interface_status(section);
-            if let Some(v6) = rt6.get("ipv6addr") {
-                if !v6.is_empty() {
-                    m.insert(
-                        format!("Device.IP.Interface.{iface_idx}.IPv6Address.1.IPAddress"),
-                        v6.clone(),
-                    );
-                    if let Some(prefix) = rt6.get("ipv6prefix") {
-                        m.insert(
-                            format!("Device.IP.Interface.{iface_idx}.IPv6Address.1.PrefixLength"),
-                            prefix.clone(),
-                        );
-                    }
-                }
+/// Read an [`IfaceConfig`]; blocking (`uci` and `ubus` subprocesses).
+fn read_iface_config(section: &str) -> IfaceConfig {
+    let mut c = IfaceConfig {
+        ip: uci_get(&format!("network.{section}.ipaddr")),
+        mask: uci_get(&format!("network.{section}.netmask")),
+        proto: uci_get(&format!("network.{section}.proto")),
+        gateway: uci_get(&format!("network.{section}.gateway")),
+        dns: uci_get(&format!("network.{section}.dns")),
+        device: interface_device(section),
+        mtu: String::new(),
+    };
+    c.mtu = interface_mtu(section, &c.device);
+
+    // For DHCP/dynamic protocols, get runtime state from ubus
+    if c.ip.is_empty() || c.proto == "dhcp" || c.proto == "dhcpv6" || c.proto == "pppoe" {
+        let rt = get_ubus_interface_status(section);
+        for (key, field) in [
+            ("ipaddr", &mut c.ip),
+            ("netmask", &mut c.mask),
+            ("gateway", &mut c.gateway),
+            ("dns", &mut c.dns),
+        ] {
+            if let Some(v) = rt.get(key).filter(|v| !v.is_empty()) {
+                *field = v.clone();
             }
         }
-    } else if let Some(idx) = specific_idx {
-        // Specific interface requested
-        if idx > 0 && idx <= interfaces.len() {
-            let (section, _name) = &interfaces[idx - 1];
-            let base = format!("Device.IP.Interface.{idx}.IPv4Address.1.");
-            let bridge_name = format!("br-{section}");
-
-            let mut ip = uci_get(&format!("network.{section}.ipaddr"));
-            let mut mask = uci_get(&format!("network.{section}.netmask"));
-            let proto = uci_get(&format!("network.{section}.proto"));
-            let mut gateway = uci_get(&format!("network.{section}.gateway"));
-            let mut dns = uci_get(&format!("network.{section}.dns"));
-
-            // For DHCP/dynamic protocols, get runtime state from ubus
-            if ip.is_empty() || proto == "dhcp" || proto == "dhcpv6" || proto == "pppoe" {
-                let rt = get_ubus_interface_status(section);
-                if let Some(v) = rt.get("ipaddr") {
-                    if !v.is_empty() {
-                        ip = v.clone();
-                    }
-                }
-                if let Some(v) = rt.get("netmask") {
-                    if !v.is_empty() {
-                        mask = v.clone();
-                    }
-                }
-                if let Some(v) = rt.get("gateway") {
-                    if !v.is_empty() {
-                        gateway = v.clone();
-                    }
-                }
-                if let Some(v) = rt.get("dns") {
-                    if !v.is_empty() {
-                        dns = v.clone();
-                    }
-                }
-            }
-            let stats = get_interface_stats(&bridge_name).await;
-            let mac = get_interface_mac(&bridge_name).await;
-            let status = get_interface_status(&bridge_name).await;
-
-            // Insert name
-            m.insert(
-                format!("Device.IP.Interface.{idx}.X_OptimACS_Name"),
-                section.clone(),
-            );
-
-            if path.ends_with(".IPAddress") {
-                m.insert(format!("{base}IPAddress"), ip);
-            } else if path.ends_with(".SubnetMask") {
-                m.insert(format!("{base}SubnetMask"), mask);
-            } else if path.ends_with(".AddressingType") {
-                m.insert(format!("{base}AddressingType"), proto);
-            } else {
-                // Return all parameters for this interface
-                if !ip.is_empty() {
-                    m.insert(format!("{base}IPAddress"), ip);
-                }
-                if !mask.is_empty() {
-                    m.insert(format!("{base}SubnetMask"), mask);
-                }
-                if !proto.is_empty() {
-                    m.insert(format!("{base}AddressingType"), proto.clone());
-                }
-                if !gateway.is_empty() {
-                    m.insert(
-                        format!("Device.IP.Interface.{idx}.X_OptimACS_Gateway"),
-                        gateway.clone(),
-                    );
-                }
-                if !dns.is_empty() {
-                    m.insert(format!("Device.IP.Interface.{idx}.X_OptimACS_DNS"), dns);
-                }
-                if !mac.is_empty() {
-                    m.insert(format!("Device.IP.Interface.{idx}.MACAddress"), mac);
-                }
-                m.insert(format!("Device.IP.Interface.{idx}.Status"), status);
-
-                // Upstream flag
-                let is_upstream = section.starts_with("wan");
-                m.insert(
-                    format!("Device.IP.Interface.{idx}.X_OptimACS_Upstream"),
-                    is_upstream.to_string(),
-                );
-
-                // Friendly protocol name
-                let proto_friendly = match proto.as_str() {
-                    "dhcp" => "DHCPv4",
-                    "dhcpv6" => "DHCPv6",
-                    "static" => "Static",
-                    "pppoe" => "PPPoE",
-                    "pptp" => "PPTP",
-                    "l2tp" => "L2TP",
-                    "none" => "Unmanaged",
-                    other => other,
-                };
-                m.insert(
-                    format!("Device.IP.Interface.{idx}.X_OptimACS_Protocol"),
-                    proto_friendly.to_string(),
-                );
-
-                // GatewayIPv4 alias
-                if !gateway.is_empty() {
-                    m.insert(
-                        format!("Device.IP.Interface.{idx}.X_OptimACS_GatewayIPv4"),
-                        gateway.clone(),
-                    );
-                }
+    }
+    c
+}
 
-                // Add stats if available
-                if let Some(rx_bytes) = stats.get("rx_bytes") {
-                    m.insert(
-                        format!("Device.IP.Interface.{idx}.X_OptimACS_RXBytes"),
-                        rx_bytes.clone(),
-                    );
-                }
-                if let Some(rx_packets) = stats.get("rx_packets") {
-                    m.insert(
-                        format!("Device.IP.Interface.{idx}.X_OptimACS_RXPackets"),
-                        rx_packets.clone(),
-                    );
-                }
-                if let Some(tx_bytes) = stats.get("tx_bytes") {
-                    m.insert(
-                        format!("Device.IP.Interface.{idx}.X_OptimACS_TXBytes"),
-                        tx_bytes.clone(),
-                    );
-                }
-                if let Some(tx_packets) = stats.get("tx_packets") {
-                    m.insert(
-                        format!("Device.IP.Interface.{idx}.X_OptimACS_TXPackets"),
-                        tx_packets.clone(),
-                    );
-                }
-                if let Some(uptime) = stats.get("uptime") {
-                    m.insert(
-                        format!("Device.IP.Interface.{idx}.X_OptimACS_Uptime"),
-                        uptime.clone(),
-                    );
-                }
+/// `Device.IP.Interface.{idx}.*` for `section`: the single parameter `path`
+/// names, or every parameter of the interface.
+async fn insert_interface(m: &mut HashMap<String, String>, idx: usize, section: &str, path: &str) {
+    let owned = section.to_string();
+    let c = tokio::task::spawn_blocking(move || read_iface_config(&owned))
+        .await
+        .unwrap_or_default();
+    let prefix = format!("Device.IP.Interface.{idx}.");
+    let base = format!("{prefix}IPv4Address.1.");
+
+    // Insert name
+    m.insert(format!("{prefix}Name"), section.to_string());
+    m.insert(format!("{prefix}X_OptimACS_Name"), section.to_string());
+
+    if path.contains(".IPv6Address.") {
+        insert_ipv6_addresses(m, idx, &c.device, path).await;
+        return;
+    } else if path.ends_with(".IPAddress") {
+        m.insert(format!("{base}IPAddress"), c.ip);
+        return;
+    } else if path.ends_with(".SubnetMask") {
+        m.insert(format!("{base}SubnetMask"), c.mask);
+        return;
+    } else if path.ends_with(".AddressingType") {
+        m.insert(format!("{base}AddressingType"), c.proto);
+        return;
+    } else if path.ends_with(".MaxMTUSize") {
+        m.insert(format!("{prefix}MaxMTUSize"), c.mtu);
+        return;
+    }
 
-                // IPv6 — always from runtime state
-                let rt6 = get_ubus_interface_status(section);
-                if let Some(v6) = rt6.get("ipv6addr") {
-                    if !v6.is_empty() {
-                        m.insert(
-                            format!("Device.IP.Interface.{idx}.IPv6Address.1.IPAddress"),
-                            v6.clone(),
-                        );
-                        if let Some(prefix) = rt6.get("ipv6prefix") {
-                            m.insert(
-                                format!("Device.IP.Interface.{idx}.IPv6Address.1.PrefixLength"),
-                                prefix.clone(),
-                            );
-                        }
-                    }
-                }
-            }
+    // Return all parameters for this interface
+    let stats = get_interface_stats(&c.device).await;
+    let mac = get_interface_mac(&c.device).await;
+    let status = get_interface_status(&c.device).await;
+
+    if !c.ip.is_empty() {
+        m.insert(format!("{base}IPAddress"), c.ip);
+    }
+    if !c.mask.is_empty() {
+        m.insert(format!("{base}SubnetMask"), c.mask);
+    }
+    if !c.proto.is_empty() {
+        m.insert(format!("{base}AddressingType"), c.proto.clone());
+    }
+    if !c.gateway.is_empty() {
+        m.insert(format!("{prefix}X_OptimACS_Gateway"), c.gateway.clone());
+        // GatewayIPv4 alias
+        m.insert(format!("{prefix}X_OptimACS_GatewayIPv4"), c.gateway);
+    }
+    if !c.dns.is_empty() {
+        m.insert(format!("{prefix}X_OptimACS_DNS"), c.dns);
+    }
+    if !mac.is_empty() {
+        m.insert(format!("{prefix}MACAddress"), mac);
+    }
+    m.insert(format!("{prefix}Status"), status);
+    m.insert(format!("{prefix}MaxMTUSize"), c.mtu);
+
+    // Upstream flag: true for wan/wan6 interfaces
+    let is_upstream = section.starts_with("wan");
+    m.insert(
+        format!("{prefix}X_OptimACS_Upstream"),
+        is_upstream.to_string(),
+    );
+
+    // Friendly protocol name
+    let proto_friendly = match c.proto.as_str() {
+        "dhcp" => "DHCPv4",
+        "dhcpv6" => "DHCPv6",
+        "static" => "Static",
+        "pppoe" => "PPPoE",
+        "pptp" => "PPTP",
+        "l2tp" => "L2TP",
+        "none" => "Unmanaged",
+        other => other,
+    };
+    m.insert(
+        format!("{prefix}X_OptimACS_Protocol"),
+        proto_friendly.to_string(),
+    );
+
+    // Add stats if available
+    for (key, name) in [
+        ("rx_bytes", "X_OptimACS_RXBytes"),
+        ("rx_packets", "X_OptimACS_RXPackets"),
+        ("tx_bytes", "X_OptimACS_TXBytes"),
+        ("tx_packets", "X_OptimACS_TXPackets"),
+        ("uptime", "X_OptimACS_Uptime"),
+    ] {
+        if let Some(v) = stats.get(key) {
+            m.insert(format!("{prefix}{name}"), v.clone());
         }
     }
+    insert_stats(m, idx, &stats);
 
-    m
+    insert_ipv6_addresses(m, idx, &c.device, path).await;
 }
 
-pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), String> {
-    let interfaces = get_network_interfaces();
+/// One IPv6 address of an interface, from `ip -6 -o addr show`.
+#[derive(Debug, PartialEq)]
+struct Ipv6Entry {
+    address: std::net::Ipv6Addr,
+    prefix_len: u8,
+    /// TR-181 Origin: `AutoConfigured` (SLAAC, link-local), `DHCPv6` or
+    /// `Static`.
+    origin: &'static str,
+}
 
-    // Parse the interface index from the path
-    let idx = parse_interface_index(path)
-        .ok_or_else(|| format!("Cannot parse interface index from path: {path}"))?;
+impl Ipv6Entry {
+    fn is_link_local(&self) -> bool {
+        self.address.segments()[0] & 0xffc0 == 0xfe80
+    }
 
-    if idx == 0 || idx > interfaces.len() {
-        return Err(format!(
-            "Interface index {idx} out of range (max: {})",
-            interfaces.len()
-        ));
+    /// The on-link prefix in CIDR notation, e.g. `2001:db8:1::/64`.
+    fn prefix(&self) -> String {
+        let bits = u128::from(self.address);
+        let mask = match self.prefix_len {
+            0 => 0,
+            n => u128::MAX << (128 - u32::from(n.min(128))),
+        };
+        format!(
+            "{}/{}",
+            std::net::Ipv6Addr::from(bits & mask),
+            self.prefix_len
+        )
+    }
+}
+
+/// Parse `ip -6 -o addr show`:
+/// `3: br-lan    inet6 2001:db8::1/64 scope global dynamic ...`.
+///
+/// The origin is inferred from the flags: an address without `dynamic`
+/// lifetimes was configured statically; a dynamic /128 is a DHCPv6 lease
+/// (IA_NA), any other dynamic or `kernel_ra` address comes from SLAAC.
+fn parse_ipv6_addrs(output: &str) -> Vec<Ipv6Entry> {
+    let mut entries = Vec::new();
+    for line in output.lines() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let cidr = match tokens.iter().position(|t| *t == "inet6") {
+            Some(i) => tokens.get(i + 1).copied().unwrap_or(""),
+            None => continue,
+        };
+        let (address, prefix_len) = match cidr.split_once('/') {
+            Some((a, l)) => match (a.parse(), l.parse()) {
+                (Ok(a), Ok(l)) => (a, l),
+                _ => continue,
+            },
+            None => continue,
+        };
+        let has = |flag: &str| tokens.contains(&flag);
+        let link = tokens.windows(2).any(|w| w[0] == "scope" && w[1] == "link");
+        let origin = if link || has("kernel_ra") {
+            "AutoConfigured"
+        } else if !has("dynamic") {
+            "Static"
+        } else if prefix_len == 128 {
+            "DHCPv6"
+        } else {
+            "AutoConfigured"
+        };
+        entries.push(Ipv6Entry {
+            address,
+            prefix_len,
+            origin,
+        });
+    }
+    entries
+}
+
+/// `Device.IP.Interface.{idx}.IPv6Address.{j}.*` for the interface's live
+/// addresses on `dev`.  Link-local (fe80::/10) addresses keep their instance
+/// number but are only reported when `path` names that instance.
+async fn insert_ipv6_addresses(m: &mut HashMap<String, String>, idx: usize, dev: &str, path: &str) {
+    let output = tokio::process::Command::new("ip")
+        .args(["-6", "-o", "addr", "show", "dev", dev])
+        .output()
+        .await
+        .map(|o| String::from_utf8_lossy(&o.stdout).into_owned())
+        .unwrap_or_default();
+    let requested = path
+        .split_once(".IPv6Address.")
+        .and_then(|(_, rest)| rest.split('.').next()?.parse::<usize>().ok());
+
+    for (j, entry) in parse_ipv6_addrs(&output).iter().enumerate() {
+        let j = j + 1;
+        if entry.is_link_local() && requested != Some(j) {
+            continue;
+        }
+        let base = format!("Device.IP.Interface.{idx}.IPv6Address.{j}.");
+        m.insert(format!("{base}IPAddress"), entry.address.to_string());
+        m.insert(format!("{base}PrefixLength"), entry.prefix_len.to_string());
+        m.insert(format!("{base}Prefix"), entry.prefix());
+        m.insert(format!("{base}Origin"), entry.origin.to_string());
+    }
+}
+
+/// Smallest and largest MaxMTUSize accepted by SET (IPv4 minimum to jumbo).
+const MTU_MIN: u32 = 576;
+const MTU_MAX: u32 = 9000;
+
+/// Device.IP.Interface.{i}.MaxMTUSize — the live MTU of the interface's L3
+/// device `dev`, else the configured `mtu` option, else empty.
+fn interface_mtu(section: &str, dev: &str) -> String {
+    match read_mtu_in(Path::new("/sys/class/net"), dev) {
+        Some(mtu) => mtu.to_string(),
+        None => uci_get(&format!("network.{section}.mtu")),
     }
+}
+
+/// MTU from `<root>/<dev>/mtu`.
+fn read_mtu_in(root: &Path, dev: &str) -> Option<u32> {
+    std::fs::read_to_string(root.join(dev).join("mtu"))
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+}
 
-    let (section, _name) = &interfaces[idx - 1];
-    info!("Setting IP parameter for interface {idx} (section: {section}): {path} = {value}");
+/// Linux device netifd runs the logical interface on (e.g. `br-lan`,
+/// `pppoe-wan`).
+fn l3_device(section: &str) -> Option<String> {
+    std::process::Command::new("ubus")
+        .args(["call", &format!("network.interface.{section}"), "status"])
+        .output()
+        .ok()
+        .and_then(|o| serde_json::from_slice::<serde_json::Value>(&o.stdout).ok())
+        .and_then(|v| v.get("l3_device")?.as_str().map(str::to_string))
+}
 
+fn mtu_to_uci(value: &str) -> Result<String, String> {
+    match value.trim().parse::<u32>() {
+        Ok(mtu) if (MTU_MIN..=MTU_MAX).contains(&mtu) => Ok(mtu.to_string()),
+        _ => Err(format!(
+            "MTU must be {MTU_MIN}-{MTU_MAX} bytes, got {value:?}"
+        )),
+    }
+}
+
+/// `network.<section>` option behind a writable `Device.IP.Interface`
+/// parameter.
+fn uci_option(path: &str) -> Option<&'static str> {
     if path.ends_with(".IPAddress") {
-        uci_set(&format!("network.{section}.ipaddr"), value)?;
+        Some("ipaddr")
     } else if path.ends_with(".SubnetMask") {
-        uci_set(&format!("network.{section}.netmask"), value)?;
+        Some("netmask")
     } else if path.ends_with(".AddressingType") {
-        uci_set(&format!("network.{section}.proto"), value)?;
+        Some("proto")
+    } else if path.ends_with(".MaxMTUSize") {
+        Some("mtu")
     } else if path.contains("X_OptimACS_Gateway") {
-        uci_set(&format!("network.{section}.gateway"), value)?;
+        Some("gateway")
     } else if path.contains("X_OptimACS_DNS") {
-        uci_set(&format!("network.{section}.dns"), value)?;
+        Some("dns")
     } else {
-        warn!("Unknown IP parameter in path: {path}");
-        return Err(format!("Unknown IP parameter: {path}"));
+        None
     }
+}
 
-    uci_commit("network")?;
+/// UCI network section backing the `Device.IP.Interface.{i}` in `path`.
+fn resolve_section<'a>(interfaces: &'a [(String, String)], path: &str) -> Result<&'a str, DmError> {
+    // Parse the interface index from the path
+    let idx = parse_interface_index(path).ok_or_else(|| DmError::InvalidPath(path.to_string()))?;
 
-    // Reload network
-    reload_network().await?;
+    if idx == 0 || idx > interfaces.len() {
+        return Err(DmError::InvalidPath(path.to_string()));
+    }
+    Ok(&interfaces[idx - 1].0)
+}
+
+pub async fn set(
+    _cfg: &ClientConfig,
+    txn: &mut UciTxn,
+    path: &str,
+    value: &str,
+) -> Result<(), DmError> {
+    let interfaces = network_interfaces().await;
+    let section = resolve_section(&interfaces, path)?;
+    info!("Setting IP parameter (section: {section}): {path} = {value}");
+
+    // IPv6 addresses are reported from the running system only
+    if path.contains(".IPv6Address.") {
+        return Err(DmError::NotWritable(path.to_string()));
+    }
+
+    let option = match uci_option(path) {
+        Some(o) => o,
+        None => {
+            warn!("Unknown IP parameter in path: {path}");
+            return Err(DmError::InvalidPath(path.to_string()));
+        }
+    };
+    let value = if option == "mtu" {
+        mtu_to_uci(value).map_err(|e| DmError::invalid_value(path, e))?
+    } else {
+        value.to_string()
+    };
+    uci_set(&format!("network.{section}.{option}"), &value)?;
+
+    // Commit, reload and make sure the interface comes back; a change that
+    // takes down a previously-up interface is rolled back and reported as an
+    // invalid value.  An MTU change only needs this interface re-brought up.
+    // In an all-or-nothing batch the same check runs after the batch commit.
+    let iface = section.to_string();
+    let was_up = {
+        let iface = iface.clone();
+        tokio::task::spawn_blocking(move || interface_is_up(&iface))
+            .await
+            .unwrap_or(false)
+    };
+    if txn.defer_verified("network", NETWORK_VERIFY_RETRY, move || {
+        !was_up || interface_is_up(&iface)
+    }) {
+        return Ok(());
+    }
+    crate::apply::reload_verified(
+        Path::new("/etc/config/network"),
+        || uci_commit("network"),
+        || {
+            if option == "mtu" {
+                let _ = std::process::Command::new("ifup").arg(section).status();
+            } else {
+                let _ = reload_network();
+            }
+        },
+        || !was_up || interface_is_up(section),
+        NETWORK_VERIFY_RETRY,
+    )
+    .await
+    .map_err(|e| DmError::invalid_value(path, e.to_string()))?;
 
     Ok(())
 }
 
+/// Device.IP.Interface.{i}.Reset() — bounce the logical interface via netifd.
+pub async fn operate_reset(
+    _cfg: &ClientConfig,
+    command: &str,
+    _input_args: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, DmError> {
+    let interfaces = network_interfaces().await;
+    let section = resolve_section(&interfaces, command)?;
+
+    let owned = section.to_string();
+    if tokio::task::spawn_blocking(move || carries_default_route(&owned))
+        .await
+        .unwrap_or(false)
+    {
+        warn!(
+            "Resetting interface {section}: it carries the default route, \
+             the MTP connection will likely drop and reconnect"
+        );
+    }
+    info!("Resetting interface {section}");
+
+    let object = format!("network.interface.{section}");
+    for action in ["down", "up"] {
+        let status = tokio::process::Command::new("ubus")
+            .args(["call", &object, action])
+            .status()
+            .await
+            .map_err(|e| format!("ubus call {object} {action}: {e}"))?;
+        if !status.success() {
+            return Err(DmError::CommandFailure(format!(
+                "ubus call {object} {action} failed: {status}"
+            )));
+        }
+    }
+
+    let mut out = HashMap::new();
+    out.insert("Interface".to_string(), section.to_string());
+    Ok(out)
+}
+
+// ── IP diagnostics ───────────────────────────────────────────────────────────
+
+/// Diagnostics `Host` input arg; it is passed to ping/traceroute as an
+/// argument, so option-like or multi-word values are refused.
+fn diag_host(args: &HashMap<String, String>) -> Result<String, String> {
+    let host = args.get("Host").map(|h| h.trim()).unwrap_or("");
+    if host.is_empty() || host.starts_with('-') || host.contains(char::is_whitespace) {
+        return Err(format!("invalid Host {host:?}"));
+    }
+    Ok(host.to_string())
+}
+
+/// Numeric diagnostics input arg in `1..=max`, `default` when absent.
+fn diag_number(
+    args: &HashMap<String, String>,
+    name: &str,
+    default: u32,
+    max: u32,
+) -> Result<u32, String> {
+    match args.get(name).map(|v| v.trim()) {
+        None | Some("") => Ok(default),
+        Some(v) => match v.parse::<u32>() {
+            Ok(n) if (1..=max).contains(&n) => Ok(n),
+            _ => Err(format!("{name} must be 1-{max}, got {v:?}")),
+        },
+    }
+}
+
+/// Longest a ping or traceroute may run; the process is killed after that.
+const DIAG_MAX_RUNTIME: std::time::Duration = std::time::Duration::from_secs(50);
+
+/// What a diagnostics process printed, and whether it hit [`DIAG_MAX_RUNTIME`].
+struct DiagOutput {
+    stdout: String,
+    stderr: String,
+    timed_out: bool,
+}
+
+/// Run `program` with `args`, collecting stdout line by line so whatever
+/// it printed before [`DIAG_MAX_RUNTIME`] is kept when it is killed.
+async fn run_diag(program: &str, args: &[String]) -> std::io::Result<DiagOutput> {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut lines = match child.stdout.take() {
+        Some(out) => BufReader::new(out).lines(),
+        None => return Err(std::io::Error::other("no stdout pipe")),
+    };
+    let deadline = tokio::time::Instant::now() + DIAG_MAX_RUNTIME;
+    let mut stdout = String::new();
+    let timed_out = loop {
+        match tokio::time::timeout_at(deadline, lines.next_line()).await {
+            Ok(Ok(Some(line))) => {
+                stdout.push_str(&line);
+                stdout.push('\n');
+            }
+            Ok(_) => break false,
+            Err(_) => break true,
+        }
+    };
+    let mut stderr = String::new();
+    if timed_out {
+        warn!(
+            "{program} still running after {}s, killed",
+            DIAG_MAX_RUNTIME.as_secs()
+        );
+        let _ = child.kill().await;
+    } else {
+        if let Some(mut err) = child.stderr.take() {
+            let _ = err.read_to_string(&mut stderr).await;
+        }
+        let _ = child.wait().await;
+    }
+    Ok(DiagOutput {
+        stdout,
+        stderr,
+        timed_out,
+    })
+}
+
+/// TR-181 diagnostics Status for a ping/traceroute that produced no result.
+fn diag_error_status(stderr: &str) -> &'static str {
+    let resolve_errors = ["bad address", "unknown host", "Name or service not known"];
+    if resolve_errors.iter().any(|e| stderr.contains(e)) {
+        "Error_CannotResolveHostName"
+    } else {
+        "Error_Other"
+    }
+}
+
+/// Upper bound on NumberOfRepetitions, so one OPERATE cannot tie up the box.
+const PING_MAX_REPETITIONS: u32 = 16;
+/// Upper bound on the per-reply Timeout, in milliseconds.
+const PING_MAX_TIMEOUT_MS: u32 = 10_000;
+/// Upper bound on DataBlockSize (largest ICMP payload in an IPv4 packet).
+const PING_MAX_DATA_BLOCK: u32 = 65_507;
+
+/// Validated IPPing() input args.
+#[derive(Debug, PartialEq)]
+struct PingArgs {
+    host: String,
+    repetitions: u32,
+    timeout_ms: u32,
+    data_block_size: u32,
+}
+
+impl PingArgs {
+    fn from_input(args: &HashMap<String, String>) -> Result<Self, String> {
+        Ok(Self {
+            host: diag_host(args)?,
+            repetitions: diag_number(args, "NumberOfRepetitions", 3, PING_MAX_REPETITIONS)?,
+            timeout_ms: diag_number(args, "Timeout", 1000, PING_MAX_TIMEOUT_MS)?,
+            data_block_size: diag_number(args, "DataBlockSize", 56, PING_MAX_DATA_BLOCK)?,
+        })
+    }
+
+    /// `ping` arguments; `-W` takes whole seconds, so Timeout is rounded up.
+    fn command_args(&self) -> Vec<String> {
+        vec![
+            "-c".into(),
+            self.repetitions.to_string(),
+            "-W".into(),
+            self.timeout_ms.div_ceil(1000).to_string(),
+            "-s".into(),
+            self.data_block_size.to_string(),
+            self.host.clone(),
+        ]
+    }
+}
+
+/// Counts and round-trip times (ms) from a `ping` summary.
+#[derive(Debug, Default, PartialEq)]
+struct PingSummary {
+    transmitted: u32,
+    received: u32,
+    rtt_ms: Option<(f64, f64, f64)>,
+}
+
+/// Parse the summary of BusyBox or iputils `ping`:
+/// `3 packets transmitted, 2 packets received, 33% packet loss` /
+/// `3 packets transmitted, 2 received, ...` and
+/// `round-trip min/avg/max = 9.5/10.1/10.9 ms` /
+/// `rtt min/avg/max/mdev = 9.5/10.1/10.9/0.4 ms`.
+fn parse_ping_summary(output: &str) -> Option<PingSummary> {
+    let mut summary: Option<PingSummary> = None;
+    for line in output.lines() {
+        let line = line.trim();
+        if line.contains("packets transmitted") {
+            let mut s = PingSummary::default();
+            for part in line.split(',') {
+                let part = part.trim();
+                let n = part.split_whitespace().next().and_then(|n| n.parse().ok());
+                if part.ends_with("transmitted") {
+                    s.transmitted = n?;
+                } else if part.ends_with("received") {
+                    s.received = n?;
+                }
+            }
+            summary = Some(s);
+        } else if line.contains("min/avg/max") {
+            let values = line.split('=').nth(1)?.split_whitespace().next()?;
+            let v: Vec<f64> = values.split('/').filter_map(|x| x.parse().ok()).collect();
+            if let (Some(s), [min, avg, max, ..]) = (summary.as_mut(), v.as_slice()) {
+                s.rtt_ms = Some((*min, *avg, *max));
+            }
+        }
+    }
+    summary
+}
+
+/// IPPing() output args for a parsed summary.
+fn ping_output_args(s: &PingSummary) -> HashMap<String, String> {
+    let mut out = HashMap::new();
+    out.insert("Status".to_string(), "Complete".to_string());
+    out.insert("SuccessCount".to_string(), s.received.to_string());
+    out.insert(
+        "FailureCount".to_string(),
+        s.transmitted.saturating_sub(s.received).to_string(),
+    );
+    let (min, avg, max) = s.rtt_ms.unwrap_or_default();
+    let ms = |v: f64| (v.round() as u64).to_string();
+    out.insert("AverageResponseTime".to_string(), ms(avg));
+    out.insert("MinimumResponseTime".to_string(), ms(min));
+    out.insert("MaximumResponseTime".to_string(), ms(max));
+    out
+}
+
+/// Device.IP.Diagnostics.IPPing() — ping `Host` and report the results.
+/// Input: `Host`, `NumberOfRepetitions` (default 3, max 16), `Timeout` (ms
+/// per reply, default 1000), `DataBlockSize` (bytes, default 56).
+pub async fn operate_ip_ping(
+    _cfg: &ClientConfig,
+    _command: &str,
+    input_args: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, DmError> {
+    let args = PingArgs::from_input(input_args).map_err(DmError::InvalidArguments)?;
+    info!(
+        "IPPing {} x{} (timeout {} ms, {} bytes)",
+        args.host, args.repetitions, args.timeout_ms, args.data_block_size
+    );
+
+    let output = run_diag("ping", &args.command_args())
+        .await
+        .map_err(|e| format!("ping: {e}"))?;
+
+    // ping exits non-zero on packet loss too; only a missing summary means
+    // it never got as far as sending (or was killed before finishing)
+    match parse_ping_summary(&output.stdout) {
+        Some(summary) => Ok(ping_output_args(&summary)),
+        None => {
+            let stderr = output.stderr.trim();
+            warn!("IPPing {} failed: {stderr}", args.host);
+            let mut out = HashMap::new();
+            out.insert("Status".to_string(), diag_error_status(stderr).to_string());
+            out.insert("SuccessCount".to_string(), "0".to_string());
+            out.insert("FailureCount".to_string(), args.repetitions.to_string());
+            Ok(out)
+        }
+    }
+}
+
+/// Upper bound on NumberOfTries (probes per hop).
+const TRACE_MAX_TRIES: u32 = 3;
+/// Upper bound on the per-probe Timeout, in milliseconds.
+const TRACE_MAX_TIMEOUT_MS: u32 = 10_000;
+/// Upper bound on MaxHopCount (IPv4 TTL).
+const TRACE_MAX_HOPS: u32 = 64;
+
+/// Validated TraceRoute() input args.
+#[derive(Debug, PartialEq)]
+struct TraceArgs {
+    host: String,
+    tries: u32,
+    timeout_ms: u32,
+    max_hops: u32,
+}
+
+impl TraceArgs {
+    fn from_input(args: &HashMap<String, String>) -> Result<Self, String> {
+        Ok(Self {
+            host: diag_host(args)?,
+            tries: diag_number(args, "NumberOfTries", 3, TRACE_MAX_TRIES)?,
+            timeout_ms: diag_number(args, "Timeout", 5000, TRACE_MAX_TIMEOUT_MS)?,
+            max_hops: diag_number(args, "MaxHopCount", 30, TRACE_MAX_HOPS)?,
+        })
+    }
+
+    /// `traceroute` arguments; `-w` takes whole seconds, so Timeout is
+    /// rounded up.
+    fn command_args(&self) -> Vec<String> {
+        vec![
+            "-n".into(),
+            "-m".into(),
+            self.max_hops.to_string(),
+            "-q".into(),
+            self.tries.to_string(),
+            "-w".into(),
+            self.timeout_ms.div_ceil(1000).to_string(),
+            self.host.clone(),
+        ]
+    }
+}
+
+/// One line of `traceroute -n` output.
+#[derive(Debug, Default, PartialEq)]
+struct TraceHop {
+    /// First address that answered; empty when no probe did (`* * *`).
+    host: String,
+    /// Round-trip time of each answered probe, in ms.
+    rtt_ms: Vec<f64>,
+}
+
+/// Parse `traceroute -n` output (BusyBox or the traceroute package):
+/// ` 1  192.168.1.1  0.512 ms  0.401 ms  0.388 ms`, ` 2  * * *`.  Probes
+/// answered by a different router add their RTTs to the hop; `!H`-style
+/// annotations are ignored.
+fn parse_traceroute(output: &str) -> Vec<TraceHop> {
+    let mut hops = Vec::new();
+    for line in output.lines() {
+        let mut tokens = line.split_whitespace().peekable();
+        match tokens.next().map(|t| t.parse::<u32>()) {
+            Some(Ok(_)) => {}
+            _ => continue,
+        }
+        let mut hop = TraceHop::default();
+        while let Some(tok) = tokens.next() {
+            if tok == "*" || tok.starts_with('!') {
+                continue;
+            }
+            if tokens.peek() == Some(&"ms") {
+                tokens.next();
+                if let Ok(ms) = tok.parse() {
+                    hop.rtt_ms.push(ms);
+                }
+            } else if hop.host.is_empty() {
+                hop.host = tok.to_string();
+            }
+        }
+        hops.push(hop);
+    }
+    hops
+}
+
+/// TraceRoute() output args: `RouteHops.{i}.Host` and `.HopRTTimes` (comma-
+/// separated whole ms, empty for a hop that did not answer).
+fn traceroute_output_args(hops: &[TraceHop]) -> HashMap<String, String> {
+    let mut out = HashMap::new();
+    out.insert("Status".to_string(), "Complete".to_string());
+    out.insert(
+        "RouteHopsNumberOfEntries".to_string(),
+        hops.len().to_string(),
+    );
+    for (i, hop) in hops.iter().enumerate() {
+        let rtts: Vec<String> = hop
+            .rtt_ms
+            .iter()
+            .map(|ms| (ms.round() as u64).to_string())
+            .collect();
+        out.insert(format!("RouteHops.{}.Host", i + 1), hop.host.clone());
+        out.insert(format!("RouteHops.{}.HopRTTimes", i + 1), rtts.join(","));
+    }
+    out
+}
+
+/// Device.IP.Diagnostics.TraceRoute() — trace the route to `Host`.
+/// Input: `Host`, `NumberOfTries` (probes per hop, default 3), `Timeout` (ms
+/// per probe, default 5000), `MaxHopCount` (default 30).
+pub async fn operate_traceroute(
+    _cfg: &ClientConfig,
+    _command: &str,
+    input_args: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, DmError> {
+    let args = TraceArgs::from_input(input_args).map_err(DmError::InvalidArguments)?;
+    info!(
+        "TraceRoute {} (max {} hops, {} tries, timeout {} ms)",
+        args.host, args.max_hops, args.tries, args.timeout_ms
+    );
+
+    let output = match run_diag("traceroute", &args.command_args()).await {
+        Ok(o) => o,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(DmError::CommandFailure(
+                "traceroute is not installed on this device".to_string(),
+            ));
+        }
+        Err(e) => return Err(format!("traceroute: {e}").into()),
+    };
+
+    let hops = parse_traceroute(&output.stdout);
+    if hops.is_empty() {
+        let stderr = output.stderr.trim();
+        warn!("TraceRoute {} failed: {stderr}", args.host);
+        let mut out = HashMap::new();
+        out.insert("Status".to_string(), diag_error_status(stderr).to_string());
+        out.insert("RouteHopsNumberOfEntries".to_string(), "0".to_string());
+        return Ok(out);
+    }
+    let mut out = traceroute_output_args(&hops);
+    // Killed partway: report the hops found so far
+    if output.timed_out {
+        out.insert("Status".to_string(), "Error_Other".to_string());
+    }
+    Ok(out)
+}
+
+/// Whether netifd has installed the IPv4 default route on this interface.
+pub(super) fn carries_default_route(section: &str) -> bool {
+    std::process::Command::new("ubus")
+        .args(["call", &format!("network.interface.{section}"), "status"])
+        .output()
+        .ok()
+        .and_then(|o| serde_json::from_slice::<serde_json::Value>(&o.stdout).ok())
+        .and_then(|v| {
+            v.get("route").and_then(|r| r.as_array()).map(|routes| {
+                routes.iter().any(|r| {
+                    r.get("target").and_then(|t| t.as_str()) == Some("0.0.0.0")
+                        && r.get("mask").and_then(|m| m.as_u64()) == Some(0)
+                })
+            })
+        })
+        .unwrap_or(false)
+}
+
+/// Delay between post-reload interface checks.
+const NETWORK_VERIFY_RETRY: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Whether netifd reports the logical interface as up.
+fn interface_is_up(section: &str) -> bool {
+    std::process::Command::new("ubus")
+        .args(["call", &format!("network.interface.{section}"), "status"])
+        .output()
+        .ok()
+        .and_then(|o| serde_json::from_slice::<serde_json::Value>(&o.stdout).ok())
+        .and_then(|v| v.get("up").and_then(|u| u.as_bool()))
+        .unwrap_or(false)
+}
+
 /// Reload network configuration
-async fn reload_network() -> Result<(), String> {
+pub fn reload_network() -> Result<(), String> {
     // Try multiple methods
     let methods: Vec<Vec<&str>> = vec![
         vec!["/etc/init.d/network", "reload"],
@@ -567,44 +1027,6 @@ fn get_ubus_interface_status(iface_name: &str) -> HashMap<String, String> {
         }
     }
 
-    // Parse ipv6-address[0].address and mask
-    if let Some(pos) = out.find("\"ipv6-address\"") {
-        let chunk = &out[pos..];
-        if let Some(addr_pos) = chunk.find("\"address\"") {
-            let after = &chunk[addr_pos + 9..];
-            if let Some(start) = after.find('"') {
-                let rest = &after[start + 1..];
-                if let Some(end) = rest.find('"') {
-                    result.insert("ipv6addr".to_string(), rest[..end].to_string());
-                }
-            }
-        }
-        if let Some(mask_pos) = chunk.find("\"mask\"") {
-            let after = &chunk[mask_pos + 5..];
-            let after = after.trim_start_matches(|c: char| !c.is_ascii_digit());
-            if let Some(end) = after.find(|c: char| !c.is_ascii_digit()) {
-                result.insert("ipv6prefix".to_string(), after[..end].to_string());
-            }
-        }
-    }
-
-    // Parse ipv6-prefix-assignment for delegated prefix
-    if let Some(pos) = out.find("\"ipv6-prefix-assignment\"") {
-        let chunk = &out[pos..];
-        if let Some(addr_pos) = chunk.find("\"address\"") {
-            let after = &chunk[addr_pos + 9..];
-            if let Some(start) = after.find('"') {
-                let rest = &after[start + 1..];
-                if let Some(end) = rest.find('"') {
-                    let addr = &rest[..end];
-                    if !addr.is_empty() {
-                        result.insert("ipv6prefix_addr".to_string(), addr.to_string());
-                    }
-                }
-            }
-        }
-    }
-
     result
 }
 
@@ -623,33 +1045,15 @@ fn cidr_to_netmask(cidr: u32) -> String {
     )
 }
 
-/// Get interface statistics from /proc/net/dev
+/// Get interface counters (sysfs) and carrier uptime.
 async fn get_interface_stats(iface: &str) -> HashMap<String, String> {
     let mut stats = HashMap::new();
 
-    // Read /proc/net/dev
-    if let Ok(content) = tokio::fs::read_to_string("/proc/net/dev").await {
-        for line in content.lines() {
-            if line.contains(iface) {
-                // Parse line like: "  br-lan: 123456789 1234567    0    0    0     0          0         0 987654321 9876543    0    0    0    0       0          0"
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 9 {
-                    if let Ok(rx_bytes) = parts[1].parse::<u64>() {
-                        stats.insert("rx_bytes".to_string(), format_bytes(rx_bytes));
-                    }
-                    if let Ok(rx_packets) = parts[2].parse::<u64>() {
-                        stats.insert("rx_packets".to_string(), format_number(rx_packets));
-                    }
-                    if let Ok(tx_bytes) = parts[9].parse::<u64>() {
-                        stats.insert("tx_bytes".to_string(), format_bytes(tx_bytes));
-                    }
-                    if let Ok(tx_packets) = parts[10].parse::<u64>() {
-                        stats.insert("tx_packets".to_string(), format_number(tx_packets));
-                    }
-                }
-                break;
-            }
-        }
+    if let Some(s) = crate::util::read_iface_stats(iface) {
+        stats.insert("rx_bytes".to_string(), format_bytes(s.rx_bytes));
+        stats.insert("rx_packets".to_string(), format_number(s.rx_packets));
+        stats.insert("tx_bytes".to_string(), format_bytes(s.tx_bytes));
+        stats.insert("tx_packets".to_string(), format_number(s.tx_packets));
     }
 
     // Get interface uptime from /sys/class/net/{iface}/operstate or similar
@@ -671,6 +1075,46 @@ async fn get_interface_stats(iface: &str) -> HashMap<String, String> {
     stats
 }
 
+/// TR-181 `Stats.` counters from [`get_interface_stats`].  They are the
+/// kernel's since the device was created, so they restart from zero when
+/// netifd recreates it (and wrap at 4 GiB on 32-bit kernels).
+fn insert_stats(m: &mut HashMap<String, String>, idx: usize, stats: &HashMap<String, String>) {
+    for (key, name) in [
+        ("tx_bytes", "BytesSent"),
+        ("rx_bytes", "BytesReceived"),
+        ("tx_packets", "PacketsSent"),
+        ("rx_packets", "PacketsReceived"),
+    ] {
+        if let Some(v) = stats.get(key) {
+            m.insert(format!("Device.IP.Interface.{idx}.Stats.{name}"), v.clone());
+        }
+    }
+}
+
+/// Byte counters of the uplink for the status heartbeat: the `wan`
+/// interface, or the first one (normally `lan`) on devices without a WAN.
+/// Returned as `Device.IP.Interface.{i}.Stats.Bytes*` parameters.
+pub fn uplink_stats() -> Vec<(String, String)> {
+    let interfaces = get_network_interfaces();
+    let idx = interfaces
+        .iter()
+        .position(|(section, _)| section == "wan")
+        .unwrap_or(0);
+    let section = match interfaces.get(idx) {
+        Some((section, _)) => section,
+        None => return Vec::new(),
+    };
+    let s = match crate::util::read_iface_stats(&interface_device(section)) {
+        Some(s) => s,
+        None => return Vec::new(),
+    };
+    let base = format!("Device.IP.Interface.{}.Stats.", idx + 1);
+    vec![
+        (format!("{base}BytesSent"), s.tx_bytes.to_string()),
+        (format!("{base}BytesReceived"), s.rx_bytes.to_string()),
+    ]
+}
+
 /// Get MAC address from /sys/class/net/{iface}/address
 async fn get_interface_mac(iface: &str) -> String {
     if let Ok(content) =
@@ -725,3 +1169,265 @@ fn format_duration(seconds: u64) -> String {
         format!("{}s", secs)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ping_summary_parsed_for_busybox_and_iputils() {
+        let busybox = "\
+PING 192.0.2.1 (192.0.2.1): 56 data bytes
+64 bytes from 192.0.2.1: seq=0 ttl=64 time=1.204 ms
+64 bytes from 192.0.2.1: seq=2 ttl=64 time=2.511 ms
+
+--- 192.0.2.1 ping statistics ---
+3 packets transmitted, 2 packets received, 33% packet loss
+round-trip min/avg/max = 1.204/1.857/2.511 ms
+";
+        let s = parse_ping_summary(busybox).unwrap();
+        let out = ping_output_args(&s);
+        assert_eq!(out["Status"], "Complete");
+        assert_eq!(out["SuccessCount"], "2");
+        assert_eq!(out["FailureCount"], "1");
+        assert_eq!(out["MinimumResponseTime"], "1");
+        assert_eq!(out["AverageResponseTime"], "2");
+        assert_eq!(out["MaximumResponseTime"], "3");
+
+        let iputils = "\
+--- example.com ping statistics ---
+4 packets transmitted, 4 received, 0% packet loss, time 3004ms
+rtt min/avg/max/mdev = 10.100/12.400/15.900/2.100 ms
+";
+        let s = parse_ping_summary(iputils).unwrap();
+        assert_eq!((s.transmitted, s.received), (4, 4));
+        assert_eq!(s.rtt_ms, Some((10.1, 12.4, 15.9)));
+
+        // Nothing answered: no round-trip line, times reported as 0
+        let lost = "3 packets transmitted, 0 packets received, 100% packet loss\n";
+        let out = ping_output_args(&parse_ping_summary(lost).unwrap());
+        assert_eq!(out["FailureCount"], "3");
+        assert_eq!(out["AverageResponseTime"], "0");
+
+        assert_eq!(parse_ping_summary("ping: bad address 'nosuch'"), None);
+        assert_eq!(
+            diag_error_status("ping: bad address 'nosuch'"),
+            "Error_CannotResolveHostName"
+        );
+    }
+
+    #[test]
+    fn traceroute_hops_parsed_with_silent_hops() {
+        let output = "\
+traceroute to 8.8.8.8 (8.8.8.8), 30 hops max, 46 byte packets
+ 1  192.168.1.1  0.512 ms  0.401 ms  0.388 ms
+ 2  * * *
+ 3  10.20.0.1  9.612 ms *  10.402 ms
+ 4  10.30.0.1  12.1 ms  10.30.0.9  14.8 ms  13.0 ms
+ 5  8.8.8.8  20.250 ms !H  19.7 ms  21.0 ms
+";
+        let hops = parse_traceroute(output);
+        assert_eq!(hops.len(), 5);
+        assert_eq!(hops[1], TraceHop::default());
+        assert_eq!(hops[2].rtt_ms, vec![9.612, 10.402]);
+        assert_eq!(hops[3].host, "10.30.0.1");
+        assert_eq!(hops[3].rtt_ms.len(), 3);
+
+        let out = traceroute_output_args(&hops);
+        assert_eq!(out["RouteHopsNumberOfEntries"], "5");
+        assert_eq!(out["RouteHops.1.Host"], "192.168.1.1");
+        assert_eq!(out["RouteHops.1.HopRTTimes"], "1,0,0");
+        assert_eq!(out["RouteHops.2.Host"], "");
+        assert_eq!(out["RouteHops.2.HopRTTimes"], "");
+        assert_eq!(out["RouteHops.5.Host"], "8.8.8.8");
+        assert_eq!(out["RouteHops.5.HopRTTimes"], "20,20,21");
+
+        let args = |pairs: &[(&str, &str)]| {
+            TraceArgs::from_input(
+                &pairs
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            )
+        };
+        assert_eq!(
+            args(&[("Host", "8.8.8.8")]).unwrap().command_args(),
+            ["-n", "-m", "30", "-q", "3", "-w", "5", "8.8.8.8"]
+        );
+        assert!(args(&[("Host", "8.8.8.8"), ("MaxHopCount", "65")]).is_err());
+        assert!(args(&[("Host", "8.8.8.8"), ("NumberOfTries", "0")]).is_err());
+    }
+
+    #[test]
+    fn ping_args_bounded() {
+        let args = |pairs: &[(&str, &str)]| {
+            PingArgs::from_input(
+                &pairs
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            )
+        };
+        let p = args(&[("Host", "192.0.2.1")]).unwrap();
+        assert_eq!(
+            p.command_args(),
+            ["-c", "3", "-W", "1", "-s", "56", "192.0.2.1"]
+        );
+        let p = args(&[
+            ("Host", "example.com"),
+            ("NumberOfRepetitions", "16"),
+            ("Timeout", "2500"),
+            ("DataBlockSize", "1400"),
+        ])
+        .unwrap();
+        assert_eq!(
+            p.command_args(),
+            ["-c", "16", "-W", "3", "-s", "1400", "example.com"]
+        );
+
+        assert!(args(&[("Host", "192.0.2.1"), ("NumberOfRepetitions", "17")]).is_err());
+        assert!(args(&[("Host", "192.0.2.1"), ("Timeout", "0")]).is_err());
+        assert!(args(&[("Host", "-f")]).is_err());
+        assert!(args(&[]).is_err());
+    }
+
+    #[test]
+    fn mtu_read_from_sysfs_and_mapped_to_uci() {
+        let root = std::env::temp_dir().join(format!("ac-client-mtu-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("pppoe-wan")).unwrap();
+        std::fs::write(root.join("pppoe-wan").join("mtu"), "1492\n").unwrap();
+        assert_eq!(read_mtu_in(&root, "pppoe-wan"), Some(1492));
+        assert_eq!(read_mtu_in(&root, "br-lan"), None);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(uci_option("Device.IP.Interface.2.MaxMTUSize"), Some("mtu"));
+        assert_eq!(
+            uci_option("Device.IP.Interface.1.IPv4Address.1.IPAddress"),
+            Some("ipaddr")
+        );
+        assert_eq!(uci_option("Device.IP.Interface.1.Status"), None);
+
+        assert_eq!(mtu_to_uci(" 1420 "), Ok("1420".to_string()));
+        assert_eq!(mtu_to_uci("9000"), Ok("9000".to_string()));
+        for bad in ["575", "9001", "0", "jumbo", ""] {
+            assert!(mtu_to_uci(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn ipv6_addresses_parsed_with_origin() {
+        let output = "\
+5: eth1    inet6 2001:db8:0:10::1a2b/128 scope global dynamic noprefixroute \\       valid_lft 7140sec preferred_lft 3540sec
+5: eth1    inet6 2001:db8:0:20:211:22ff:fe33:4455/64 scope global dynamic mngtmpaddr \\       valid_lft 86335sec preferred_lft 14335sec
+5: eth1    inet6 fe80::211:22ff:fe33:4455/64 scope link \\       valid_lft forever preferred_lft forever
+7: br-lan    inet6 fd12:3456:789a::1/60 scope global noprefixroute \\       valid_lft forever preferred_lft forever
+";
+        let addrs = parse_ipv6_addrs(output);
+        let summary: Vec<(String, String, &str, bool)> = addrs
+            .iter()
+            .map(|a| {
+                (
+                    a.address.to_string(),
+                    a.prefix(),
+                    a.origin,
+                    a.is_link_local(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "2001:db8:0:10::1a2b".into(),
+                    "2001:db8:0:10::1a2b/128".into(),
+                    "DHCPv6",
+                    false
+                ),
+                (
+                    "2001:db8:0:20:211:22ff:fe33:4455".into(),
+                    "2001:db8:0:20::/64".into(),
+                    "AutoConfigured",
+                    false
+                ),
+                (
+                    "fe80::211:22ff:fe33:4455".into(),
+                    "fe80::/64".into(),
+                    "AutoConfigured",
+                    true
+                ),
+                (
+                    "fd12:3456:789a::1".into(),
+                    "fd12:3456:789a::/60".into(),
+                    "Static",
+                    false
+                ),
+            ]
+        );
+        assert!(parse_ipv6_addrs("7: br-lan    inet 192.168.1.1/24 brd 192.168.1.255").is_empty());
+    }
+
+    #[test]
+    fn interfaces_enumerated_by_section_type() {
+        let show = "\
+network.loopback=interface
+network.loopback.device='lo'
+network.loopback.proto='static'
+network.globals=globals
+network.globals.ula_prefix='fd12:3456:789a::/48'
+network.cfg030f15=device
+network.cfg030f15.name='br-lan'
+network.cfg030f15.type='bridge'
+network.lan=interface
+network.lan.device='br-lan'
+network.lan.proto='static'
+network.wan=interface
+network.wan.device='eth1'
+network.wan.proto='dhcp'
+network.wan6=interface
+network.wan6.device='eth1'
+network.wan6.proto='dhcpv6'
+network.guest=interface
+network.guest.device='br-guest'
+network.guest.proto='static'
+network.vpn=interface
+network.vpn.proto='wireguard'
+network.cfg0a1b2c=interface
+network.cfg0a1b2c.proto='none'
+network.vlan20=interface
+network.vlan20.ifname='lan1.20'
+";
+        let names: Vec<String> = parse_network_interfaces(show)
+            .into_iter()
+            .map(|(s, _)| s)
+            .collect();
+        assert_eq!(
+            names,
+            ["lan", "wan", "wan6", "guest", "vpn", "cfg0a1b2c", "vlan20"]
+        );
+
+        // A loopback under another name is recognised by its device
+        let show = "network.lo0=interface\nnetwork.lo0.ifname='lo'\nnetwork.lan=interface\n";
+        assert_eq!(
+            parse_network_interfaces(show),
+            vec![("lan".to_string(), "lan".to_string())]
+        );
+    }
+
+    #[test]
+    fn reset_resolves_instance_to_network_section() {
+        let interfaces: Vec<(String, String)> = ["lan", "wan", "wan6"]
+            .iter()
+            .map(|s| (s.to_string(), s.to_string()))
+            .collect();
+        assert_eq!(
+            resolve_section(&interfaces, "Device.IP.Interface.2.Reset()").unwrap(),
+            "wan"
+        );
+        assert_eq!(
+            resolve_section(&interfaces, "Device.IP.Interface.3.Reset()").unwrap(),
+            "wan6"
+        );
+        assert!(resolve_section(&interfaces, "Device.IP.Interface.0.Reset()").is_err());
+        assert!(resolve_section(&interfaces, "Device.IP.Interface.4.Reset()").is_err());
+    }
+}
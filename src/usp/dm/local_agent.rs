@@ -0,0 +1,174 @@
+//! TR-181 Device.LocalAgent.* — runtime settings of the USP agent itself.
+
+use super::{subscriptions, DmError, UciTxn};
+use crate::config::ClientConfig;
+use crate::usp::agent;
+use crate::usp::mtp::rehome;
+use crate::usp::session;
+use crate::usp::tp469::uci_backend;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::time::Duration;
+
+const BASE: &str = "Device.LocalAgent.";
+/// The one controller this agent talks to, and its WebSocket MTP.
+const CONTROLLER_WS: &str = "Controller.1.MTP.1.WebSocket.";
+/// Whether the agent opens a SessionContext with that controller.
+const E2E_SESSION_ENABLE: &str = "Controller.1.E2ESession.Enable";
+
+pub fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
+    let mut m = HashMap::new();
+    let rel = path.trim_start_matches(BASE);
+    if rel.is_empty() || rel == "X_OptimACS_StatusInterval" {
+        m.insert(
+            format!("{BASE}X_OptimACS_StatusInterval"),
+            agent::status_interval().to_string(),
+        );
+    }
+    if rel.is_empty() || rel.starts_with("Subscription") {
+        m.extend(subscriptions::get(path));
+    }
+    if rel.is_empty() || CONTROLLER_WS.starts_with(rel) || rel.starts_with(CONTROLLER_WS) {
+        for (name, val) in controller_ws_params() {
+            let key = format!("{BASE}{CONTROLLER_WS}{name}");
+            if key.starts_with(path) {
+                m.insert(key, val);
+            }
+        }
+    }
+    if rel.is_empty() || E2E_SESSION_ENABLE.starts_with(rel) {
+        m.insert(
+            format!("{BASE}{E2E_SESSION_ENABLE}"),
+            session::enabled().to_string(),
+        );
+    }
+    m
+}
+
+/// Host, Port and Path of the WebSocket URL in use (after any re-home).
+fn controller_ws_params() -> Vec<(&'static str, String)> {
+    let url = match rehome::current_url().and_then(|u| url::Url::parse(&u).ok()) {
+        Some(u) => u,
+        None => return vec![],
+    };
+    vec![
+        ("Host", url.host_str().unwrap_or_default().to_string()),
+        (
+            "Port",
+            url.port_or_known_default()
+                .map(|p| p.to_string())
+                .unwrap_or_default(),
+        ),
+        ("Path", url.path().to_string()),
+    ]
+}
+
+/// `Device.LocalAgent.X_OptimACS_ReRegister()` — re-send Boot! now.  Outputs
+/// the `EndpointID`, the `Cause` and the Boot! parameters as a JSON
+/// `ParameterMap`.
+pub async fn operate_reregister(
+    cfg: &ClientConfig,
+    _command: &str,
+    _input_args: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, DmError> {
+    let (endpoint_id, params) = agent::request_reregister(cfg);
+    info!(
+        "Re-registration requested: Boot! with {} parameter(s) queued",
+        params.len()
+    );
+    let mut out = HashMap::new();
+    out.insert("EndpointID".to_string(), endpoint_id.to_string());
+    out.insert("Cause".to_string(), agent::REREGISTER_CAUSE.to_string());
+    out.insert(
+        "ParameterMap".to_string(),
+        serde_json::to_string(&params).map_err(|e| e.to_string())?,
+    );
+    Ok(out)
+}
+
+pub fn set(cfg: &ClientConfig, txn: &mut UciTxn, path: &str, value: &str) -> Result<(), DmError> {
+    match path.trim_start_matches(BASE) {
+        "X_OptimACS_StatusInterval" => {
+            let secs: u64 = value
+                .trim()
+                .parse()
+                .map_err(|_| DmError::invalid_value(path, format!("not a number: {value}")))?;
+            let previous = agent::status_interval();
+            agent::set_status_interval(secs).map_err(|e| DmError::invalid_value(path, e))?;
+            txn.on_revert(move || {
+                let _ = agent::set_status_interval(previous);
+            });
+            // Persist so the new interval survives a restart; the running
+            // value is already applied even if UCI is unavailable.
+            if let Err(e) =
+                uci_backend::uci_set("optimacs.agent.status_interval", &secs.to_string()).and_then(
+                    |_| {
+                        if txn.defer("optimacs") {
+                            Ok(())
+                        } else {
+                            uci_backend::uci_commit("optimacs")
+                        }
+                    },
+                )
+            {
+                warn!("status_interval applied but not persisted: {e}");
+            }
+            Ok(())
+        }
+        E2E_SESSION_ENABLE => {
+            let on = match value.trim() {
+                "true" | "1" => true,
+                "false" | "0" => false,
+                other => {
+                    return Err(DmError::invalid_value(
+                        path,
+                        format!("not a boolean: {other}"),
+                    ))
+                }
+            };
+            let previous = session::enabled();
+            session::set_enabled(on);
+            txn.on_revert(move || session::set_enabled(previous));
+            if let Err(e) =
+                uci_backend::uci_set("optimacs.agent.e2e_session", if on { "1" } else { "0" })
+                    .and_then(|_| {
+                        if txn.defer("optimacs") {
+                            Ok(())
+                        } else {
+                            uci_backend::uci_commit("optimacs")
+                        }
+                    })
+            {
+                warn!("E2ESession.Enable applied but not persisted: {e}");
+            }
+            Ok(())
+        }
+        sub if sub.starts_with("Subscription.") => {
+            let previous = subscriptions::get(path).remove(path);
+            subscriptions::set(path, value)?;
+            if let Some(previous) = previous {
+                let path = path.to_string();
+                txn.on_revert(move || {
+                    let _ = subscriptions::set(&path, &previous);
+                });
+            }
+            Ok(())
+        }
+        sub if sub.starts_with(CONTROLLER_WS) => {
+            let param = &sub[CONTROLLER_WS.len()..];
+            if !matches!(param, "Host" | "Port" | "Path") {
+                return Err(DmError::InvalidPath(path.to_string()));
+            }
+            let current = rehome::current_url()
+                .ok_or_else(|| DmError::invalid_value(path, "WebSocket MTP is not running"))?;
+            let url = rehome::with_component(&current, param, value)
+                .map_err(|e| DmError::invalid_value(path, e))?;
+            let previous = rehome::checkpoint();
+            rehome::set_url(url, Duration::from_secs(cfg.rehome_timeout))
+                .map_err(|e| DmError::invalid_value(path, e))?;
+            txn.on_revert(move || rehome::restore(previous));
+            Ok(())
+        }
+        _ => Err(DmError::NotWritable(path.to_string())),
+    }
+}
@@ -3,6 +3,7 @@
 
 #![allow(clippy::all)]
 
+use super::DmError;
 use crate::config::ClientConfig;
 use log::debug;
 use std::collections::HashMap;
@@ -90,9 +91,9 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> Params {
 }
 
 /// Set miscellaneous TR-181 parameters (most are read-only)
-pub async fn set(_cfg: &ClientConfig, path: &str, _value: &str) -> Result<(), String> {
+pub async fn set(_cfg: &ClientConfig, path: &str, _value: &str) -> Result<(), DmError> {
     // Most of these paths are read-only in stub implementation
-    Err(format!("Read-only or not implemented: {path}"))
+    Err(DmError::NotWritable(path.to_string()))
 }
 
 // ── DNS ─────────────────────────────────────────────────────────────────────
@@ -6,19 +6,31 @@
 #![allow(dead_code)]
 
 pub mod bridge;
+pub mod client_history;
+pub mod confirm;
 pub mod device_info;
 pub mod dhcp;
+pub mod error;
+pub mod ethernet;
 pub mod firmware;
+pub mod gnss;
 pub mod hosts;
 pub mod ip;
+pub mod local_agent;
 pub mod misc;
 pub mod security;
+pub mod services;
+pub mod subscriptions;
 pub mod wifi;
 
+pub use error::DmError;
+
 use crate::config::ClientConfig;
-use log::{debug, info};
+use log::{debug, info, warn};
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Mutex;
+use std::time::Duration;
 
 pub type Params = HashMap<String, String>;
 
@@ -125,22 +137,362 @@ pub async fn get_params(cfg: &ClientConfig, paths: &[String], max_depth: u32) ->
     filter_delta(result, force_full)
 }
 
+/// Read every parameter under `path`, bypassing depth and delta filtering.
+/// Used for instance enumeration, which must see unchanged values too.
+pub async fn get_all(cfg: &ClientConfig, path: &str) -> Params {
+    dispatch_get(cfg, path).await
+}
+
+/// A SET update that could not be applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamFailure {
+    pub path: String,
+    pub error: DmError,
+}
+
+/// Where SET handlers commit the UCI packages they change.
+///
+/// With `allow_partial = true` every parameter is committed and reloaded as
+/// it is set.  With `allow_partial = false` the batch is all-or-nothing:
+/// handlers leave their `uci set` pending and only record the package, and
+/// [`set_params`] commits every recorded package once the whole batch
+/// succeeded, or `uci revert`s them all if any update failed.  Changes a
+/// handler applies outside UCI register an undo with [`UciTxn::on_revert`].
+#[derive(Default)]
+pub struct UciTxn {
+    atomic: bool,
+    /// Packages with pending changes, in the order they were first touched.
+    packages: Vec<&'static str>,
+    /// Run after the staged packages are reloaded; any failing check rolls
+    /// the whole batch back.
+    checks: Vec<HealthCheck>,
+    /// Undo non-UCI side effects, run newest first on revert.
+    undo: Vec<Box<dyn FnOnce() + Send + Sync>>,
+    /// `(iface, option, value)` of staged `wifi-iface` options that may be
+    /// applied to the running BSS instead of a `wifi reload`.
+    iface_options: Vec<(String, String, String)>,
+    /// Some staged `wireless` change needs a full `wifi reload`.
+    wireless_full: bool,
+}
+
+struct HealthCheck {
+    retry: Duration,
+    healthy: Box<dyn Fn() -> bool + Send + Sync>,
+}
+
+impl std::fmt::Debug for UciTxn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UciTxn")
+            .field("atomic", &self.atomic)
+            .field("packages", &self.packages)
+            .field("checks", &self.checks.len())
+            .field("undo", &self.undo.len())
+            .field("iface_options", &self.iface_options)
+            .field("wireless_full", &self.wireless_full)
+            .finish()
+    }
+}
+
+impl UciTxn {
+    pub fn new(allow_partial: bool) -> Self {
+        Self {
+            atomic: !allow_partial,
+            ..Default::default()
+        }
+    }
+
+    /// Called where a handler would commit `package`.  `false`: commit and
+    /// reload it now as usual.  `true`: the change is staged and is
+    /// committed (or reverted) with the rest of the batch.
+    pub fn defer(&mut self, package: &'static str) -> bool {
+        if package == "wireless" && self.atomic {
+            self.wireless_full = true;
+        }
+        self.stage(package)
+    }
+
+    /// [`defer`](Self::defer) for one `wifi-iface` option, which at commit
+    /// goes straight into the running BSS when hostapd allows it.
+    pub fn defer_iface_option(&mut self, iface: &str, option: &str, value: &str) -> bool {
+        if self.atomic {
+            self.iface_options
+                .push((iface.to_string(), option.to_string(), value.to_string()));
+        }
+        self.stage("wireless")
+    }
+
+    fn stage(&mut self, package: &'static str) -> bool {
+        if self.atomic && !self.packages.contains(&package) {
+            self.packages.push(package);
+        }
+        self.atomic
+    }
+
+    /// [`defer`](Self::defer), and when deferred also check `healthy` (up to
+    /// five times, `retry` apart) once the batch has been reloaded — what
+    /// [`crate::apply::reload_verified`] does for a single change.
+    pub fn defer_verified(
+        &mut self,
+        package: &'static str,
+        retry: Duration,
+        healthy: impl Fn() -> bool + Send + Sync + 'static,
+    ) -> bool {
+        let deferred = self.defer(package);
+        if deferred {
+            self.checks.push(HealthCheck {
+                retry,
+                healthy: Box::new(healthy),
+            });
+        }
+        deferred
+    }
+
+    /// Register how to undo a change applied outside UCI, should the batch
+    /// fail.  Nothing is kept with `allow_partial`, where changes stand.
+    pub fn on_revert(&mut self, undo: impl FnOnce() + Send + Sync + 'static) {
+        if self.atomic {
+            self.undo.push(Box::new(undo));
+        }
+    }
+
+    /// Commit and reload every staged package, as a unit: if a commit, a
+    /// reload or a health check fails, every package is put back as it was
+    /// before the batch and reloaded.
+    async fn commit(&self) -> Result<(), String> {
+        use crate::usp::tp469::uci_backend::{uci_commit, uci_revert};
+        let dir = Path::new(confirm::UCI_CONFIG_DIR);
+        let before: Vec<(&'static str, Option<Vec<u8>>)> = self
+            .packages
+            .iter()
+            .map(|pkg| (*pkg, std::fs::read(dir.join(pkg)).ok()))
+            .collect();
+
+        for (i, pkg) in self.packages.iter().enumerate() {
+            if let Err(e) = uci_commit(pkg) {
+                for rest in &self.packages[i..] {
+                    let _ = uci_revert(rest);
+                }
+                restore_packages(dir, &before[..i]).await;
+                return Err(format!("commit of {pkg} failed: {e}"));
+            }
+            info!("SET: committed {pkg}");
+        }
+
+        let mut failure = None;
+        for pkg in &self.packages {
+            let reloaded = match *pkg {
+                "wireless" if !self.wireless_full => {
+                    wifi::apply_staged_options(&self.iface_options).await
+                }
+                _ => reload_package(pkg).await,
+            };
+            if let Err(e) = reloaded {
+                failure = Some(e);
+                break;
+            }
+        }
+        if failure.is_none() {
+            for check in &self.checks {
+                if !crate::apply::wait_healthy(&check.healthy, check.retry).await {
+                    failure = Some("service did not recover after the change".to_string());
+                    break;
+                }
+            }
+        }
+        match failure {
+            None => Ok(()),
+            Some(e) => {
+                warn!("SET: {e}; rolling back {:?}", self.packages);
+                restore_packages(dir, &before).await;
+                Err(format!("{e}; previous config restored"))
+            }
+        }
+    }
+
+    /// Drop every staged change and undo the non-UCI ones.
+    fn revert(&mut self) {
+        use crate::usp::tp469::uci_backend::uci_revert;
+        for pkg in &self.packages {
+            match uci_revert(pkg) {
+                Ok(()) => info!("SET: reverted pending {pkg} changes"),
+                Err(e) => warn!("SET: could not revert {pkg}: {e}"),
+            }
+        }
+        while let Some(undo) = self.undo.pop() {
+            undo();
+        }
+    }
+}
+
+/// Write committed packages back as they were and reload them.
+async fn restore_packages(dir: &Path, before: &[(&'static str, Option<Vec<u8>>)]) {
+    for (pkg, contents) in before {
+        match crate::apply::restore_file(&dir.join(pkg), contents.as_deref()).await {
+            Ok(()) => info!("SET: restored previous {pkg}"),
+            Err(e) => warn!("SET: could not restore {pkg}: {e}"),
+        }
+    }
+    for (pkg, _) in before {
+        if let Err(e) = reload_package(pkg).await {
+            warn!("SET: reload of restored {pkg} failed: {e}");
+        }
+    }
+}
+
+/// Apply a committed package to the running system.
+async fn reload_package(pkg: &str) -> Result<(), String> {
+    match pkg {
+        "wireless" => wifi::wifi_reload().await,
+        "network" => ip::reload_network(),
+        "dhcp" => dhcp::restart_dnsmasq().await,
+        "firewall" | "dropbear" => {
+            let script = format!("/etc/init.d/{pkg}");
+            let status = tokio::process::Command::new(&script)
+                .arg("reload")
+                .status()
+                .await;
+            match status {
+                Ok(s) if s.success() => Ok(()),
+                _ => Err(format!("{script} reload failed")),
+            }
+        }
+        "system" => {
+            let status = tokio::process::Command::new("/etc/init.d/system")
+                .arg("reload")
+                .status()
+                .await;
+            if !matches!(status, Ok(s) if s.success()) {
+                warn!("System reload failed, changes will apply on reboot");
+            }
+            Ok(())
+        }
+        // optimacs: the agent already applied the value at runtime
+        _ => Ok(()),
+    }
+}
+
 /// Handle a SET request for the given (path, value) pairs.
-pub async fn set_params(cfg: &ClientConfig, updates: &[(String, String)]) -> Result<(), String> {
+///
+/// Every update is attempted, so one bad parameter does not hide the state
+/// of the others; all failures are returned together.  Without
+/// `allow_partial` a single failure leaves the UCI configuration untouched
+/// (see [`UciTxn`]).
+pub async fn set_params(
+    cfg: &ClientConfig,
+    updates: &[(String, String)],
+    allow_partial: bool,
+) -> Result<(), Vec<ParamFailure>> {
+    let mut txn = UciTxn::new(allow_partial);
+    let mut failures = Vec::new();
+    let snapshot = (cfg.confirm_timeout > 0).then(confirm::Snapshot::current);
     for (path, value) in updates {
-        dispatch_set(cfg, path, value).await?;
+        if let Err(error) = dispatch_set(cfg, &mut txn, path, value).await {
+            warn!("DM SET {path} failed: {error}");
+            failures.push(ParamFailure {
+                path: path.clone(),
+                error,
+            });
+        }
     }
-    Ok(())
+
+    let applied = if !txn.atomic {
+        failures.len() < updates.len()
+    } else if failures.is_empty() {
+        match txn.commit().await {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("DM SET commit failed: {e}");
+                txn.revert();
+                failures = updates
+                    .iter()
+                    .map(|(path, _)| ParamFailure {
+                        path: path.clone(),
+                        error: DmError::Internal(e.clone()),
+                    })
+                    .collect();
+                false
+            }
+        }
+    } else {
+        warn!(
+            "DM SET: {} of {} update(s) failed, rolling back the batch",
+            failures.len(),
+            updates.len()
+        );
+        txn.revert();
+        false
+    };
+
+    if applied {
+        if let Err(e) = crate::apply::record_config_applied(&cfg.cert_dir).await {
+            warn!("Config applied but timestamp not recorded: {e}");
+        }
+        if let Some(snapshot) = snapshot {
+            arm_confirm(cfg, snapshot, "SET");
+        }
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
+
+/// Run an ADD or DELETE `change` under the confirmation window SET uses
+/// (see [`confirm`]), when `confirm_timeout` is set.
+pub async fn with_confirm<T>(
+    cfg: &ClientConfig,
+    what: &str,
+    change: impl std::future::Future<Output = T>,
+) -> T {
+    let snapshot = (cfg.confirm_timeout > 0).then(confirm::Snapshot::current);
+    let out = change.await;
+    if let Some(snapshot) = snapshot {
+        arm_confirm(cfg, snapshot, what);
+    }
+    out
+}
+
+fn arm_confirm(cfg: &ClientConfig, snapshot: confirm::Snapshot, what: &str) {
+    let timeout = Duration::from_secs(cfg.confirm_timeout);
+    if confirm::arm(snapshot, timeout, reload_package).is_some() {
+        warn!(
+            "DM {what} changed management-path config; rolling back in {}s \
+             unless Device.X_OptimACS_Config.Confirm() is called",
+            cfg.confirm_timeout
+        );
+    }
+}
+
+/// Commands run asynchronously (TR-369 §7.4.4): OPERATE is answered at once
+/// and the result follows in an OperationComplete notification.  Firmware
+/// Download() reboots the device, so it could never answer synchronously;
+/// the IP diagnostics can outlast `dm_op_timeout`.
+pub fn is_async_command(command: &str) -> bool {
+    firmware::is_download(command)
+        || command == "Device.IP.Diagnostics.IPPing()"
+        || command == "Device.IP.Diagnostics.TraceRoute()"
 }
 
 /// Handle an OPERATE command; returns output_args on success.
+///
+/// Not run under [`with_confirm`]: no command edits the risky packages,
+/// and restoring them after Reboot(), FactoryReset() or a firmware
+/// Download() would only race the reboot.
 pub async fn operate(
     cfg: &ClientConfig,
     command: &str,
     input_args: &HashMap<String, String>,
-) -> Result<HashMap<String, String>, String> {
-    if command.starts_with("Device.X_OptimACS_Firmware.") && command.ends_with(".Download()") {
+) -> Result<HashMap<String, String>, DmError> {
+    if command == "Device.DeviceInfo.Reboot()" {
+        device_info::operate_reboot(cfg, command, input_args).await
+    } else if command == "Device.DeviceInfo.FactoryReset()" {
+        device_info::operate_factory_reset(cfg, command, input_args).await
+    } else if command.starts_with("Device.X_OptimACS_Firmware.") && command.ends_with(".Download()")
+    {
         firmware::operate_download(cfg, command, input_args).await
+    } else if command == "Device.X_OptimACS_Firmware.Rollback()" {
+        firmware::operate_rollback(cfg, command, input_args).await
     } else if command.starts_with("Device.X_OptimACS_Security.")
         && command.ends_with(".IssueCert()")
     {
@@ -149,16 +501,49 @@ pub async fn operate(
         && command.ends_with(".Restart()")
     {
         bridge::operate(cfg, command, input_args).await
+    } else if command.starts_with("Device.WiFi.AccessPoint.")
+        && command.ends_with(".WPS.X_OptimACS_PushButton()")
+    {
+        wifi::operate_wps_push_button(cfg, command, input_args).await
+    } else if command.starts_with("Device.WiFi.Radio.")
+        && command.ends_with(".X_OptimACS_ChannelSurvey()")
+    {
+        wifi::operate_channel_survey(cfg, command, input_args).await
+    } else if command.starts_with("Device.WiFi.Radio.")
+        && command.ends_with(".X_OptimACS_ScanChannels()")
+    {
+        wifi::operate_scan(cfg, command, input_args).await
+    } else if command == "Device.IP.Diagnostics.IPPing()" {
+        ip::operate_ip_ping(cfg, command, input_args).await
+    } else if command == "Device.IP.Diagnostics.TraceRoute()" {
+        ip::operate_traceroute(cfg, command, input_args).await
+    } else if command.starts_with("Device.IP.Interface.") && command.ends_with(".Reset()") {
+        ip::operate_reset(cfg, command, input_args).await
+    } else if command == "Device.LocalAgent.X_OptimACS_ReRegister()" {
+        local_agent::operate_reregister(cfg, command, input_args).await
+    } else if command == "Device.X_OptimACS_Config.Confirm()" {
+        confirm::operate_confirm(cfg, command, input_args).await
+    } else if command == "Device.X_OptimACS_Services.Restart()" {
+        services::operate_restart(cfg, command, input_args).await
     } else {
-        Err(format!("unknown command: {command}"))
+        Err(DmError::InvalidPath(command.to_string()))
     }
 }
 
 async fn dispatch_get(cfg: &ClientConfig, path: &str) -> Params {
     if path.starts_with("Device.DeviceInfo.") {
-        device_info::get(cfg, path)
+        let mut m = device_info::get(cfg, path);
+        let public_ip = "Device.DeviceInfo.X_OptimACS_PublicIP";
+        if path == "Device.DeviceInfo." || path == public_ip {
+            if let Some(ip) = device_info::get_public_ip(cfg).await {
+                m.insert(public_ip.to_string(), ip);
+            }
+        }
+        m
     } else if path.starts_with("Device.WiFi.") {
         wifi::get(cfg, path).await
+    } else if path.starts_with("Device.Ethernet.") {
+        ethernet::get(cfg, path)
     } else if path.starts_with("Device.IP.Interface.") {
         ip::get(cfg, path).await
     } else if path.starts_with("Device.DHCPv4.") {
@@ -170,7 +555,15 @@ async fn dispatch_get(cfg: &ClientConfig, path: &str) -> Params {
     {
         bridge::get(cfg, path).await
     } else if path.starts_with("Device.X_OptimACS_Firmware.") {
-        firmware::get(cfg, path)
+        firmware::get(cfg, path).await
+    } else if path.starts_with("Device.LocalAgent.") {
+        local_agent::get(cfg, path)
+    } else if path.starts_with("Device.X_OptimACS_Security.") {
+        security::get(cfg, path)
+    } else if path.starts_with("Device.X_OptimACS_Config.") {
+        confirm::get(cfg, path)
+    } else if path.starts_with("Device.X_OptimACS_GNSS.") {
+        gnss::get(cfg, path)
     } else if path.starts_with("Device.IP.")
         || path.starts_with("Device.DNS.")
         || path.starts_with("Device.Routing.")
@@ -193,24 +586,112 @@ async fn dispatch_get(cfg: &ClientConfig, path: &str) -> Params {
     }
 }
 
-async fn dispatch_set(cfg: &ClientConfig, path: &str, value: &str) -> Result<(), String> {
+async fn dispatch_set(
+    cfg: &ClientConfig,
+    txn: &mut UciTxn,
+    path: &str,
+    value: &str,
+) -> Result<(), DmError> {
     if path.starts_with("Device.DeviceInfo.") {
-        device_info::set(cfg, path, value)
+        device_info::set(cfg, txn, path, value)
     } else if path.starts_with("Device.WiFi.") {
-        wifi::set(cfg, path, value).await
+        wifi::set(cfg, txn, path, value).await
     } else if path.starts_with("Device.IP.Interface.") {
-        ip::set(cfg, path, value).await
+        ip::set(cfg, txn, path, value).await
     } else if path.starts_with("Device.DHCPv4.") {
-        dhcp::set(cfg, path, value).await
+        dhcp::set(cfg, txn, path, value).await
     } else if path.starts_with("Device.Hosts.") {
-        hosts::set(cfg, path, value).await
+        hosts::set(cfg, txn, path, value).await
     } else if path.starts_with("Device.X_OptimACS_Network.Bridge.")
         || path.starts_with("Device.X_OptimACS_Network.Bridge")
     {
-        bridge::set(cfg, path, value).await
+        bridge::set(cfg, txn, path, value).await
     } else if path.starts_with("Device.X_OptimACS_Security.") {
-        security::set(cfg, path, value).await
+        security::set(cfg, txn, path, value).await
+    } else if path.starts_with("Device.LocalAgent.") {
+        local_agent::set(cfg, txn, path, value)
     } else {
-        Err(format!("read-only or unknown path: {path}"))
+        Err(DmError::InvalidPath(path.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_running_commands_are_async() {
+        assert!(is_async_command("Device.X_OptimACS_Firmware.1.Download()"));
+        assert!(is_async_command("Device.IP.Diagnostics.TraceRoute()"));
+        assert!(is_async_command("Device.IP.Diagnostics.IPPing()"));
+        assert!(!is_async_command("Device.DeviceInfo.Reboot()"));
+    }
+
+    #[test]
+    fn atomic_set_defers_commits_per_package() {
+        // allow_partial: handlers commit as they go
+        let mut txn = UciTxn::new(true);
+        assert!(!txn.defer("wireless"));
+        assert!(txn.packages.is_empty());
+
+        // all-or-nothing: each package staged once, in first-touched order
+        let mut txn = UciTxn::new(false);
+        assert!(txn.defer("wireless"));
+        assert!(txn.defer("network"));
+        assert!(txn.defer("wireless"));
+        assert_eq!(txn.packages, vec!["wireless", "network"]);
+    }
+
+    #[test]
+    fn staged_iface_options_avoid_full_wifi_reload() {
+        let mut txn = UciTxn::new(false);
+        assert!(txn.defer_iface_option("wlan0", "ssid", "Guest"));
+        assert!(txn.defer_iface_option("wlan0", "hidden", "1"));
+        assert_eq!(txn.packages, vec!["wireless"]);
+        assert_eq!(txn.iface_options.len(), 2);
+        assert!(!txn.wireless_full);
+
+        // A radio option staged through plain defer needs the full reload
+        txn.defer("wireless");
+        assert!(txn.wireless_full);
+
+        let mut txn = UciTxn::new(true);
+        assert!(!txn.defer_iface_option("wlan0", "ssid", "Guest"));
+        assert!(txn.iface_options.is_empty());
+    }
+
+    #[test]
+    fn revert_undoes_side_effects_newest_first() {
+        use std::sync::{Arc, Mutex};
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let mut txn = UciTxn::new(false);
+        for step in ["log level", "status interval"] {
+            let log = Arc::clone(&log);
+            txn.on_revert(move || log.lock().unwrap().push(step));
+        }
+        assert!(txn.defer_verified("network", Duration::ZERO, || true));
+        assert_eq!(txn.checks.len(), 1);
+        txn.revert();
+        assert_eq!(*log.lock().unwrap(), vec!["status interval", "log level"]);
+
+        // allow_partial: changes stand, nothing to undo or check later
+        let mut txn = UciTxn::new(true);
+        txn.on_revert(|| panic!("must not run"));
+        assert!(!txn.defer_verified("network", Duration::ZERO, || false));
+        assert!(txn.checks.is_empty());
+        txn.revert();
+    }
+
+    #[tokio::test]
+    async fn atomic_set_reports_every_failure() {
+        let cfg = ClientConfig::default();
+        let updates = vec![
+            ("Device.Nope.A".to_string(), "1".to_string()),
+            ("Device.DeviceInfo.UpTime".to_string(), "2".to_string()),
+        ];
+        let failures = set_params(&cfg, &updates, false).await.unwrap_err();
+        let codes: Vec<_> = failures.iter().map(|f| f.error.code()).collect();
+        assert_eq!(codes, vec![7026, 7013]);
     }
 }
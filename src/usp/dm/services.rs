@@ -0,0 +1,67 @@
+//! Device.X_OptimACS_Services.* — controlled restart of system services.
+//!
+//! Only the services in [`RESTARTABLE_SERVICES`] may be restarted; the name
+//! is never passed to a shell.
+
+use super::DmError;
+use crate::config::ClientConfig;
+use log::{info, warn};
+use std::collections::HashMap;
+
+/// Services the controller is allowed to restart via `/etc/init.d/<svc>`.
+const RESTARTABLE_SERVICES: &[&str] = &["network", "dnsmasq", "firewall", "wpad", "hostapd"];
+
+/// Device.X_OptimACS_Services.Restart() — input arg `Service`.
+pub async fn operate_restart(
+    _cfg: &ClientConfig,
+    _command: &str,
+    input_args: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, DmError> {
+    let service = input_args
+        .get("Service")
+        .map(|s| s.trim())
+        .unwrap_or_default();
+    let script = init_script(service).map_err(DmError::InvalidArguments)?;
+
+    if service == "network" {
+        warn!("Restarting network: the MTP connection will likely drop and reconnect");
+    }
+    info!("Restarting service {service}");
+
+    let status = tokio::process::Command::new(&script)
+        .arg("restart")
+        .status()
+        .await
+        .map_err(|e| format!("{script}: {e}"))?;
+
+    let code = status.code().unwrap_or(-1);
+    if !status.success() {
+        warn!("{script} restart exited with {code}");
+    }
+    let mut out = HashMap::new();
+    out.insert("Service".to_string(), service.to_string());
+    out.insert("ExitCode".to_string(), code.to_string());
+    Ok(out)
+}
+
+/// Init script for an allowlisted service name.
+fn init_script(service: &str) -> Result<String, String> {
+    if RESTARTABLE_SERVICES.contains(&service) {
+        Ok(format!("/etc/init.d/{service}"))
+    } else {
+        Err(format!("service not restartable: {service:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_allowlisted_services_restart() {
+        assert_eq!(init_script("dnsmasq").unwrap(), "/etc/init.d/dnsmasq");
+        assert!(init_script("dropbear").is_err());
+        assert!(init_script("../../bin/sh").is_err());
+        assert!(init_script("").is_err());
+    }
+}
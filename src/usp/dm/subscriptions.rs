@@ -0,0 +1,410 @@
+//! TR-181 Device.LocalAgent.Subscription.{i}. — controller-managed
+//! notification subscriptions.
+//!
+//! Rows are created by ADD and removed by DELETE.  The table lives in memory
+//! for the lifetime of the process: it survives MTP reconnects but not a
+//! restart, after which the controller re-creates its subscriptions.
+
+use super::DmError;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+const BASE: &str = "Device.LocalAgent.Subscription.";
+
+/// Notification types the agent can deliver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifType {
+    ValueChange,
+    Event,
+    OperationComplete,
+}
+
+impl NotifType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotifType::ValueChange => "ValueChange",
+            NotifType::Event => "Event",
+            NotifType::OperationComplete => "OperationComplete",
+        }
+    }
+
+    fn parse(path: &str, value: &str) -> Result<Self, DmError> {
+        match value {
+            "ValueChange" => Ok(NotifType::ValueChange),
+            "Event" => Ok(NotifType::Event),
+            "OperationComplete" => Ok(NotifType::OperationComplete),
+            other => Err(DmError::invalid_value(
+                path,
+                format!("unsupported NotifType: {other}"),
+            )),
+        }
+    }
+}
+
+/// One Subscription row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subscription {
+    pub instance: u32,
+    /// Subscription ID carried in every Notify for this row.
+    pub id: String,
+    pub enable: bool,
+    pub recipient: String,
+    pub notif_type: NotifType,
+    /// Parameter, object or event paths; `*` stands for any instance number.
+    pub reference_list: Vec<String>,
+    /// Sent as the Notify `send_resp` flag: retransmit until acknowledged.
+    pub notif_retry: bool,
+}
+
+impl Subscription {
+    /// Whether any ReferenceList entry covers `path`.
+    pub fn references(&self, path: &str) -> bool {
+        self.reference_list
+            .iter()
+            .any(|r| reference_matches(r, path))
+    }
+}
+
+/// Whether `path` is covered by `reference`: either the same path, or
+/// anything below a partial path ending in `.`.  A `*` segment matches any
+/// one instance number.
+pub fn reference_matches(reference: &str, path: &str) -> bool {
+    let partial = reference.ends_with('.');
+    let ref_segs: Vec<&str> = reference.trim_end_matches('.').split('.').collect();
+    let path_segs: Vec<&str> = path.trim_end_matches('.').split('.').collect();
+    let len_ok = if partial {
+        path_segs.len() >= ref_segs.len()
+    } else {
+        path_segs.len() == ref_segs.len()
+    };
+    len_ok
+        && ref_segs
+            .iter()
+            .zip(&path_segs)
+            .all(|(r, p)| r == p || (*r == "*" && p.bytes().all(|b| b.is_ascii_digit())))
+}
+
+/// Path to GET in order to observe `reference`.  Wildcards cannot be read
+/// directly, so the object above the first `*` is read and filtered with
+/// [`reference_matches`].
+pub fn read_path(reference: &str) -> &str {
+    match reference.find(".*") {
+        Some(i) => &reference[..=i],
+        None => reference,
+    }
+}
+
+/// Subscriptions keyed by ID.
+#[derive(Debug, Default)]
+pub struct SubscriptionTable {
+    rows: BTreeMap<String, Subscription>,
+    last_instance: u32,
+}
+
+impl SubscriptionTable {
+    pub const fn new() -> Self {
+        Self {
+            rows: BTreeMap::new(),
+            last_instance: 0,
+        }
+    }
+
+    /// Create a row from ADD `param_settings` (names relative to the row).
+    /// NotifType is required; ID defaults to one derived from the instance
+    /// number and must be unique.
+    pub fn add(&mut self, settings: &[(String, String)]) -> Result<u32, DmError> {
+        let instance = self.last_instance + 1;
+        let path = |name: &str| format!("{BASE}{instance}.{name}");
+        let mut sub = Subscription {
+            instance,
+            id: format!("sub-{instance}"),
+            enable: false,
+            recipient: String::new(),
+            notif_type: NotifType::ValueChange,
+            reference_list: Vec::new(),
+            notif_retry: false,
+        };
+        let mut have_type = false;
+        for (name, value) in settings {
+            if name == "NotifType" {
+                have_type = true;
+            }
+            if name == "ID" {
+                if value.is_empty() {
+                    return Err(DmError::invalid_value(&path("ID"), "ID must not be empty"));
+                }
+                sub.id = value.clone();
+            } else {
+                apply_param(&mut sub, &path(name), name, value)?;
+            }
+        }
+        if !have_type {
+            return Err(DmError::invalid_value(
+                &path("NotifType"),
+                "NotifType is required",
+            ));
+        }
+        if self.rows.contains_key(&sub.id) {
+            return Err(DmError::invalid_value(
+                &path("ID"),
+                format!("subscription ID already in use: {}", sub.id),
+            ));
+        }
+        self.last_instance = instance;
+        self.rows.insert(sub.id.clone(), sub);
+        Ok(instance)
+    }
+
+    /// Remove the row with the given instance number.
+    pub fn delete(&mut self, instance: u32) -> Result<(), DmError> {
+        let id = match self.find(instance) {
+            Some(s) => s.id.clone(),
+            None => return Err(DmError::InvalidPath(format!("{BASE}{instance}."))),
+        };
+        self.rows.remove(&id);
+        Ok(())
+    }
+
+    fn find(&self, instance: u32) -> Option<&Subscription> {
+        self.rows.values().find(|s| s.instance == instance)
+    }
+
+    /// Rows in instance order.
+    pub fn list(&self) -> Vec<Subscription> {
+        let mut rows: Vec<Subscription> = self.rows.values().cloned().collect();
+        rows.sort_by_key(|s| s.instance);
+        rows
+    }
+
+    /// Enabled rows of the given type.
+    pub fn enabled(&self, notif_type: NotifType) -> Vec<Subscription> {
+        self.list()
+            .into_iter()
+            .filter(|s| s.enable && s.notif_type == notif_type)
+            .collect()
+    }
+
+    /// SET one parameter of an existing row (`rest` is `<i>.<Name>`).
+    pub fn set(&mut self, path: &str, rest: &str, value: &str) -> Result<(), DmError> {
+        let (instance, name) = match rest.split_once('.') {
+            Some((i, n)) => match i.parse::<u32>() {
+                Ok(i) => (i, n),
+                Err(_) => return Err(DmError::InvalidPath(path.to_string())),
+            },
+            None => return Err(DmError::InvalidPath(path.to_string())),
+        };
+        let id = match self.find(instance) {
+            Some(s) => s.id.clone(),
+            None => return Err(DmError::InvalidPath(path.to_string())),
+        };
+        if name == "ID" {
+            return Err(DmError::NotWritable(path.to_string()));
+        }
+        match self.rows.get_mut(&id) {
+            Some(sub) => apply_param(sub, path, name, value),
+            None => Err(DmError::InvalidPath(path.to_string())),
+        }
+    }
+}
+
+/// Apply one writable parameter to `sub`; ID is handled by the callers.
+fn apply_param(sub: &mut Subscription, path: &str, name: &str, value: &str) -> Result<(), DmError> {
+    match name {
+        "Enable" => sub.enable = parse_bool(path, value)?,
+        "NotifRetry" => sub.notif_retry = parse_bool(path, value)?,
+        "NotifType" => sub.notif_type = NotifType::parse(path, value)?,
+        "Recipient" => sub.recipient = value.to_string(),
+        "ReferenceList" => {
+            sub.reference_list = value
+                .split(',')
+                .map(str::trim)
+                .filter(|r| !r.is_empty())
+                .map(String::from)
+                .collect()
+        }
+        _ => return Err(DmError::InvalidPath(path.to_string())),
+    }
+    Ok(())
+}
+
+fn parse_bool(path: &str, value: &str) -> Result<bool, DmError> {
+    match value {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(DmError::invalid_value(
+            path,
+            format!("not a boolean: {value}"),
+        )),
+    }
+}
+
+static TABLE: Mutex<SubscriptionTable> = Mutex::new(SubscriptionTable::new());
+
+/// Handle ADD of `Device.LocalAgent.Subscription.`; returns the new instance.
+pub fn add(settings: &[(String, String)]) -> Result<u32, DmError> {
+    TABLE.lock().unwrap().add(settings)
+}
+
+/// Handle DELETE of `Device.LocalAgent.Subscription.<instance>.`.
+pub fn delete(instance: u32) -> Result<(), DmError> {
+    TABLE.lock().unwrap().delete(instance)
+}
+
+/// Enabled subscriptions of the given type.
+pub fn enabled(notif_type: NotifType) -> Vec<Subscription> {
+    TABLE.lock().unwrap().enabled(notif_type)
+}
+
+/// `(subscription_id, send_resp)` pairs an event should be notified with.
+/// Until the controller creates an Event subscription, events go out
+/// unsolicited with an empty subscription ID, as they did before the table
+/// existed.
+pub fn event_recipients(event_path: &str) -> Vec<(String, bool)> {
+    let subs = enabled(NotifType::Event);
+    if subs.is_empty() {
+        return vec![(String::new(), false)];
+    }
+    subs.into_iter()
+        .filter(|s| s.references(event_path))
+        .map(|s| (s.id, s.notif_retry))
+        .collect()
+}
+
+/// `(subscription_id, send_resp)` pairs the OperationComplete of `command`
+/// should be notified with: one per OperationComplete subscription whose
+/// ReferenceList covers it, and none without one (TR-369 §7.4.4).
+pub fn operation_complete_recipients(command: &str) -> Vec<(String, bool)> {
+    enabled(NotifType::OperationComplete)
+        .into_iter()
+        .filter(|s| s.references(command))
+        .map(|s| (s.id, s.notif_retry))
+        .collect()
+}
+
+pub fn get(path: &str) -> HashMap<String, String> {
+    let mut m = HashMap::new();
+    let rows = TABLE.lock().unwrap().list();
+    m.insert(
+        "Device.LocalAgent.SubscriptionNumberOfEntries".to_string(),
+        rows.len().to_string(),
+    );
+    for sub in rows {
+        let prefix = format!("{BASE}{}.", sub.instance);
+        let params = [
+            ("Enable", sub.enable.to_string()),
+            ("ID", sub.id),
+            ("Recipient", sub.recipient),
+            ("NotifType", sub.notif_type.as_str().to_string()),
+            ("ReferenceList", sub.reference_list.join(",")),
+            ("NotifRetry", sub.notif_retry.to_string()),
+        ];
+        for (name, value) in params {
+            m.insert(format!("{prefix}{name}"), value);
+        }
+    }
+    if path.ends_with('.') {
+        m.retain(|k, _| k.starts_with(path));
+    } else {
+        m.retain(|k, _| k == path);
+    }
+    m
+}
+
+pub fn set(path: &str, value: &str) -> Result<(), DmError> {
+    match path.strip_prefix(BASE) {
+        Some(rest) => TABLE.lock().unwrap().set(path, rest, value),
+        None => Err(DmError::NotWritable(path.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn reference_list_matching() {
+        assert!(reference_matches(
+            "Device.DeviceInfo.UpTime",
+            "Device.DeviceInfo.UpTime"
+        ));
+        assert!(!reference_matches(
+            "Device.DeviceInfo.UpTime",
+            "Device.DeviceInfo.UpTimeX"
+        ));
+        assert!(reference_matches(
+            "Device.WiFi.Radio.",
+            "Device.WiFi.Radio.2.Channel"
+        ));
+        assert!(reference_matches(
+            "Device.WiFi.Radio.*.Channel",
+            "Device.WiFi.Radio.2.Channel"
+        ));
+        assert!(!reference_matches(
+            "Device.WiFi.Radio.*.Channel",
+            "Device.WiFi.Radio.2.Enable"
+        ));
+        assert!(reference_matches(
+            "Device.DeviceInfo.X_OptimACS_TempAlarm!",
+            "Device.DeviceInfo.X_OptimACS_TempAlarm!"
+        ));
+        assert_eq!(
+            read_path("Device.WiFi.Radio.*.Channel"),
+            "Device.WiFi.Radio."
+        );
+        assert_eq!(
+            read_path("Device.DeviceInfo.UpTime"),
+            "Device.DeviceInfo.UpTime"
+        );
+    }
+
+    #[test]
+    fn add_set_delete_rows() {
+        let mut t = SubscriptionTable::new();
+        let i = t
+            .add(&settings(&[
+                ("ID", "uptime"),
+                ("Enable", "true"),
+                ("NotifType", "ValueChange"),
+                (
+                    "ReferenceList",
+                    "Device.DeviceInfo.UpTime, Device.WiFi.Radio.*.Channel",
+                ),
+                ("NotifRetry", "true"),
+            ]))
+            .unwrap();
+        assert_eq!(i, 1);
+        let subs = t.enabled(NotifType::ValueChange);
+        assert_eq!(subs.len(), 1);
+        assert_eq!(subs[0].reference_list.len(), 2);
+        assert!(subs[0].notif_retry);
+        assert!(subs[0].references("Device.WiFi.Radio.1.Channel"));
+
+        // ID is the key: duplicates are refused, and NotifType is required
+        let dup = t.add(&settings(&[("ID", "uptime"), ("NotifType", "Event")]));
+        assert_eq!(dup.unwrap_err().code(), 7012);
+        assert!(t.add(&settings(&[("ID", "x")])).is_err());
+        let bad = t.add(&settings(&[("NotifType", "ObjectCreation")]));
+        assert_eq!(bad.unwrap_err().code(), 7012);
+
+        // Default ID, disabled until enabled by SET
+        assert_eq!(t.add(&settings(&[("NotifType", "Event")])).unwrap(), 2);
+        assert!(t.enabled(NotifType::Event).is_empty());
+        t.set("p", "2.Enable", "true").unwrap();
+        assert_eq!(t.enabled(NotifType::Event)[0].id, "sub-2");
+        assert_eq!(t.set("p", "2.ID", "y").unwrap_err().code(), 7013);
+        assert_eq!(t.set("p", "9.Enable", "true").unwrap_err().code(), 7026);
+
+        t.delete(1).unwrap();
+        assert!(t.enabled(NotifType::ValueChange).is_empty());
+        assert!(t.delete(1).is_err());
+        // Instance numbers are not reused
+        assert_eq!(t.add(&settings(&[("NotifType", "Event")])).unwrap(), 3);
+    }
+}
@@ -1,94 +1,110 @@
 //! TR-181 Device.WiFi.* — reads/writes via UCI with multi-SSID support.
 
+use super::{client_history, DmError, UciTxn};
 use crate::config::ClientConfig;
-use crate::usp::tp469::uci_backend::{uci_commit, uci_get, uci_set};
-use log::{info, warn};
+use crate::usp::tp469::uci_backend::{uci_commit, uci_delete, uci_get, uci_set};
+use log::{debug, info, warn};
 use std::collections::HashMap;
 
-/// Get all wireless sections from UCI
-fn uci_show_wireless() -> HashMap<String, HashMap<String, String>> {
-    let mut sections = HashMap::new();
+/// `uci show wireless`, parsed: the wifi-iface and wifi-device sections in
+/// config order, and every section's options.
+///
+/// DM instance numbers are positions in these lists (`SSID.{i}` and
+/// `AccessPoint.{i}` are the i-th wifi-iface, `Radio.{i}` the i-th
+/// wifi-device), so they stay stable across calls while the config does not
+/// change, whether sections are named or anonymous.
+#[derive(Debug, Default)]
+struct Wireless {
+    ifaces: Vec<String>,
+    devices: Vec<String>,
+    options: HashMap<String, HashMap<String, String>>,
+}
 
-    let out = std::process::Command::new("uci")
-        .args(["show", "wireless"])
-        .output()
-        .ok()
-        .and_then(|o| String::from_utf8(o.stdout).ok())
-        .unwrap_or_default();
+impl Wireless {
+    fn load() -> Self {
+        let out = std::process::Command::new("uci")
+            .args(["show", "wireless"])
+            .output()
+            .ok()
+            .and_then(|o| String::from_utf8(o.stdout).ok())
+            .unwrap_or_default();
+        Self::parse(&out)
+    }
 
-    let _current_section = String::new();
-
-    for line in out.lines() {
-        if line.starts_with("wireless.") {
-            let parts: Vec<&str> = line.split('.').collect();
-            if parts.len() >= 3 {
-                let section = parts[1].to_string();
-                let key = parts[2].split('=').next().unwrap_or("").to_string();
-                let value = line
-                    .split('=')
-                    .nth(1)
-                    .unwrap_or("")
-                    .trim_matches('\'')
-                    .to_string();
-
-                sections
-                    .entry(section)
-                    .or_insert_with(HashMap::new)
-                    .insert(key, value);
+    fn parse(out: &str) -> Self {
+        let mut w = Self::default();
+        for line in out.lines() {
+            let rest = match line.strip_prefix("wireless.") {
+                Some(r) => r,
+                None => continue,
+            };
+            let (lhs, value) = match rest.split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            let value = value.trim_matches('\'');
+            match lhs.split_once('.') {
+                // "wireless.default_radio0=wifi-iface"
+                None => match value {
+                    "wifi-iface" => w.ifaces.push(lhs.to_string()),
+                    "wifi-device" => w.devices.push(lhs.to_string()),
+                    _ => {}
+                },
+                // "wireless.default_radio0.ssid='OpenWrt'"
+                Some((section, key)) => {
+                    w.options
+                        .entry(section.to_string())
+                        .or_default()
+                        .insert(key.to_string(), value.to_string());
+                }
             }
         }
+        w
     }
 
-    sections
-}
-
-/// Get list of wifi-iface sections in order
-fn get_wifi_ifaces() -> Vec<String> {
-    let mut ifaces = Vec::new();
-
-    let out = std::process::Command::new("uci")
-        .args(["show", "wireless"])
-        .output()
-        .ok()
-        .and_then(|o| String::from_utf8(o.stdout).ok())
-        .unwrap_or_default();
+    fn option(&self, section: &str, key: &str) -> &str {
+        self.options
+            .get(section)
+            .and_then(|o| o.get(key))
+            .map_or("", String::as_str)
+    }
 
-    for line in out.lines() {
-        if line.starts_with("wireless.") && line.contains(".ssid=") {
-            // Extract section name (e.g., "@wifi-iface[0]" or "default_radio0")
-            if let Some(section) = line.split('.').nth(1) {
-                if !ifaces.contains(&section.to_string()) {
-                    ifaces.push(section.to_string());
-                }
+    /// SSID, Enable and Status of every wifi-iface that has an SSID.
+    fn ssid_params(&self) -> HashMap<String, String> {
+        let mut m = HashMap::new();
+        for (idx, iface) in self.ifaces.iter().enumerate() {
+            let ssid_idx = idx + 1;
+            let ssid = self.option(iface, "ssid");
+            if ssid.is_empty() {
+                continue;
             }
+            let enable = self.option(iface, "disabled") != "1";
+            m.insert(
+                format!("Device.WiFi.SSID.{ssid_idx}.SSID"),
+                ssid.to_string(),
+            );
+            m.insert(
+                format!("Device.WiFi.SSID.{ssid_idx}.Enable"),
+                enable.to_string(),
+            );
+            // SSID Status: Up if enabled
+            m.insert(
+                format!("Device.WiFi.SSID.{ssid_idx}.Status"),
+                if enable { "Up" } else { "Down" }.to_string(),
+            );
         }
+        m
     }
+}
 
-    ifaces
+/// wifi-iface sections in order; SSID/AccessPoint instance `i` is entry `i - 1`.
+pub(crate) fn get_wifi_ifaces() -> Vec<String> {
+    Wireless::load().ifaces
 }
 
-/// Get list of wifi-device (radio) sections
+/// wifi-device (radio) sections in order; Radio instance `i` is entry `i - 1`.
 fn get_wifi_devices() -> Vec<String> {
-    let mut devices = Vec::new();
-
-    let out = std::process::Command::new("uci")
-        .args(["show", "wireless"])
-        .output()
-        .ok()
-        .and_then(|o| String::from_utf8(o.stdout).ok())
-        .unwrap_or_default();
-
-    for line in out.lines() {
-        if line.starts_with("wireless.") && line.contains(".channel=") {
-            if let Some(section) = line.split('.').nth(1) {
-                if !devices.contains(&section.to_string()) {
-                    devices.push(section.to_string());
-                }
-            }
-        }
-    }
-
-    devices
+    Wireless::load().devices
 }
 
 /// Parse SSID index from path like "Device.WiFi.SSID.2.SSID"
@@ -136,29 +152,17 @@ fn parse_ap_index(path: &str) -> Option<usize> {
 
 pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
     let mut m = HashMap::new();
-    let ifaces = get_wifi_ifaces();
-    let devices = get_wifi_devices();
+    let wireless = Wireless::load();
+    let ifaces = wireless.ifaces.clone();
+    let devices = wireless.devices.clone();
     let ubus_map = build_ubus_iface_map();
 
     // Handle SSID requests
     if path.contains("SSID.") || path.ends_with("Device.WiFi.") {
+        m.extend(wireless.ssid_params());
         for (idx, iface) in ifaces.iter().enumerate() {
             let ssid_idx = idx + 1;
-            let ssid = uci_get(&format!("wireless.{iface}.ssid"));
-            let disabled = uci_get(&format!("wireless.{iface}.disabled"));
-            let enable = disabled != "1";
-
-            if !ssid.is_empty() {
-                m.insert(format!("Device.WiFi.SSID.{ssid_idx}.SSID"), ssid);
-                m.insert(
-                    format!("Device.WiFi.SSID.{ssid_idx}.Enable"),
-                    enable.to_string(),
-                );
-                // SSID Status: Up if enabled
-                m.insert(
-                    format!("Device.WiFi.SSID.{ssid_idx}.Status"),
-                    if enable { "Up" } else { "Down" }.to_string(),
-                );
+            if !wireless.option(iface, "ssid").is_empty() {
                 // Try to get BSSID for this SSID's interface
                 let device = uci_get(&format!("wireless.{iface}.device"));
                 let net_iface = {
@@ -219,6 +223,7 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
                 "sae" => "WPA3-Personal",
                 "sae-mixed" => "WPA2-WPA3-Personal",
                 "wpa2" | "wpa2+ccmp" => "WPA2-Enterprise",
+                "wpa3" => "WPA3-Enterprise",
                 "owe" => "OWE",
                 "none" | "" => "None",
                 other => other,
@@ -237,11 +242,36 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
                 m.insert(format!("Device.WiFi.AccessPoint.{ap_idx}.Mode"), mode);
             }
 
+            // RADIUS settings for WPA-Enterprise; the secret is write-only
+            if enc.starts_with("wpa") {
+                let sec = format!("Device.WiFi.AccessPoint.{ap_idx}.Security.");
+                m.insert(
+                    format!("{sec}RadiusServerIPAddr"),
+                    uci_get(&format!("wireless.{iface}.auth_server")),
+                );
+                let port = uci_get(&format!("wireless.{iface}.auth_port"));
+                m.insert(
+                    format!("{sec}RadiusServerPort"),
+                    if port.is_empty() {
+                        DEFAULT_RADIUS_PORT.to_string()
+                    } else {
+                        port
+                    },
+                );
+                m.insert(format!("{sec}RadiusSecret"), String::new());
+            }
+
             // AccessPoint Status
             m.insert(
                 format!("Device.WiFi.AccessPoint.{ap_idx}.Status"),
                 if enabled { "Enabled" } else { "Disabled" }.to_string(),
             );
+            // Same wifi-iface, so the SSID instance shares the index; this is
+            // the table's unique key
+            m.insert(
+                format!("Device.WiFi.AccessPoint.{ap_idx}.SSIDReference"),
+                format!("Device.WiFi.SSID.{ap_idx}"),
+            );
 
             // BSSID for this AccessPoint (from the corresponding wireless interface)
             let ap_device = uci_get(&format!("wireless.{iface}.device"));
@@ -279,10 +309,9 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
 
             // SSIDAdvertisementEnabled (inverse of UCI hidden flag)
             let hidden = uci_get(&format!("wireless.{iface}.hidden"));
-            let advertised = hidden != "1";
             m.insert(
                 format!("Device.WiFi.AccessPoint.{ap_idx}.SSIDAdvertisementEnabled"),
-                advertised.to_string(),
+                ssid_advertised_from_uci(&hidden).to_string(),
             );
 
             // WPA encryption modes (derive from enc)
@@ -292,6 +321,7 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
                 "sae" => "WPA3-Personal",
                 "sae-mixed" => "WPA2-WPA3-Personal",
                 "wpa2" | "wpa2+ccmp" => "WPA2-Enterprise",
+                "wpa3" => "WPA3-Enterprise",
                 "none" | "" => "None",
                 other => other,
             };
@@ -312,6 +342,22 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
                 mfp.to_string(),
             );
 
+            // Group rekey interval and opportunistic PMK caching
+            let rekey = uci_get(&format!("wireless.{iface}.wpa_group_rekey"));
+            m.insert(
+                format!("Device.WiFi.AccessPoint.{ap_idx}.Security.RekeyingInterval"),
+                if rekey.is_empty() {
+                    DEFAULT_GROUP_REKEY.to_string()
+                } else {
+                    rekey
+                },
+            );
+            let okc = uci_get(&format!("wireless.{iface}.okc"));
+            m.insert(
+                format!("Device.WiFi.AccessPoint.{ap_idx}.Security.X_OptimACS_PMKCaching"),
+                (okc == "1").to_string(),
+            );
+
             // AssociatedDeviceNumberOfEntries for this AP
             let device = uci_get(&format!("wireless.{iface}.device"));
             let phy_iface = {
@@ -354,9 +400,6 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
             // Enable is inverse of disabled in UCI
             let enable = disabled != "1";
 
-            if !chan.is_empty() {
-                m.insert(format!("Device.WiFi.Radio.{radio_idx}.Channel"), chan);
-            }
             m.insert(
                 format!("Device.WiFi.Radio.{radio_idx}.Enable"),
                 enable.to_string(),
@@ -389,12 +432,15 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
                     );
                 }
             }
-            if !txpower.is_empty() {
-                m.insert(
-                    format!("Device.WiFi.Radio.{radio_idx}.TransmitPower"),
-                    txpower,
-                );
-            }
+            // -1: no txpower set, the driver/regulatory default applies
+            m.insert(
+                format!("Device.WiFi.Radio.{radio_idx}.TransmitPower"),
+                if txpower.is_empty() {
+                    "-1".to_string()
+                } else {
+                    txpower
+                },
+            );
             if !beacon_int.is_empty() {
                 m.insert(
                     format!("Device.WiFi.Radio.{radio_idx}.BeaconPeriod"),
@@ -488,6 +534,26 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
                 status.to_string(),
             );
 
+            // Channel: "auto" when ACS is configured, otherwise the live
+            // operating channel from iw (falling back to the UCI value)
+            let channel = if chan == "auto" {
+                chan
+            } else {
+                let live = if phy_iface.is_empty() {
+                    String::new()
+                } else {
+                    get_iw_channel(&phy_iface)
+                };
+                if live.is_empty() {
+                    chan
+                } else {
+                    live
+                }
+            };
+            if !channel.is_empty() {
+                m.insert(format!("Device.WiFi.Radio.{radio_idx}.Channel"), channel);
+            }
+
             // BSSID with fallbacks
             let mut radio_bssid = String::new();
             if !phy_iface.is_empty() {
@@ -506,6 +572,33 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
                 );
             }
 
+            // Capabilities from `iw phy <phy> info`
+            let caps = parse_iw_phy(&get_iw_phy_info(&radio_phy(device, &phy_iface)));
+            let possible = caps.channels_for_band(&band);
+            if !possible.is_empty() {
+                m.insert(
+                    format!("Device.WiFi.Radio.{radio_idx}.PossibleChannels"),
+                    possible
+                        .iter()
+                        .map(|c| c.to_string())
+                        .collect::<Vec<_>>()
+                        .join(","),
+                );
+            }
+            let standards = caps.supported_standards(&band);
+            if !standards.is_empty() {
+                m.insert(
+                    format!("Device.WiFi.Radio.{radio_idx}.SupportedStandards"),
+                    standards.join(","),
+                );
+            }
+            if htmode.is_empty() && !caps.ht_max_rate.is_empty() {
+                m.insert(
+                    format!("Device.WiFi.Radio.{radio_idx}.MaxBitRate"),
+                    caps.ht_max_rate.clone(),
+                );
+            }
+
             if !phy_iface.is_empty() {
                 let bitrate = get_iw_bitrate(&phy_iface);
                 if !bitrate.is_empty() {
@@ -525,19 +618,10 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
 
     // Handle AssociatedDevice requests (connected WiFi clients)
     if path.contains("AssociatedDevice.") || path.ends_with("Device.WiFi.") {
+        let now = chrono::Utc::now();
         for (idx, iface) in ifaces.iter().enumerate() {
             let ap_idx = idx + 1;
-            let device = uci_get(&format!("wireless.{iface}.device"));
-            let phy_iface = {
-                let ubus_iface = ubus_map.get(iface.as_str()).cloned().unwrap_or_default();
-                if !ubus_iface.is_empty() {
-                    ubus_iface
-                } else if !device.is_empty() {
-                    get_phy_interface(&device)
-                } else {
-                    String::new()
-                }
-            };
+            let phy_iface = station_iface(iface, &ubus_map);
             if !phy_iface.is_empty() {
                 let stations = get_station_dump(&phy_iface);
                 for (sta_idx, sta) in stations.iter().enumerate() {
@@ -561,6 +645,9 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
                     if let Some(rx_bytes) = sta.get("rx_bytes") {
                         m.insert(format!("{base}.BytesReceived"), rx_bytes.clone());
                     }
+                    if let Some(t) = association_time(sta, now) {
+                        m.insert(format!("{base}.AssociationTime"), t);
+                    }
                     // Try to resolve IP from ARP table
                     if let Some(mac) = sta.get("mac") {
                         let ip = resolve_ip_from_arp(mac);
@@ -573,6 +660,11 @@ pub async fn get(_cfg: &ClientConfig, path: &str) -> HashMap<String, String> {
         }
     }
 
+    // Client history (departed stations, sampled in the background)
+    if path.contains("AccessPoint.") || path.ends_with("Device.WiFi.") {
+        m.extend(client_history::get(path));
+    }
+
     // Handle AccessPoint extra params (IsolationEnable, MaxAssociatedDevices, WMMEnable)
     if path.contains("AccessPoint.") || path.ends_with("Device.WiFi.") {
         for (idx, iface) in ifaces.iter().enumerate() {
@@ -761,30 +853,30 @@ fn get_phy_interface(device: &str) -> String {
     iface_name
 }
 
-/// Estimate max PHY bitrate from htmode and band
+/// Estimate max PHY bitrate in Mbps (TR-181 unsignedInt) from htmode and band
 fn estimate_max_bitrate(htmode: &str, band: &str) -> String {
     // Approximate maximum PHY rates for common configurations
     let rate = match htmode {
         // 802.11be (EHT)
-        "EHT320" => "46080 Mbps",
-        "EHT160" => "23040 Mbps",
-        "EHT80" if band == "6g" => "11520 Mbps",
-        "EHT80" => "11520 Mbps",
-        "EHT40" => "5760 Mbps",
-        "EHT20" => "2880 Mbps",
+        "EHT320" => "46080",
+        "EHT160" => "23040",
+        "EHT80" if band == "6g" => "11520",
+        "EHT80" => "11520",
+        "EHT40" => "5760",
+        "EHT20" => "2880",
         // 802.11ax (HE)
-        "HE160" => "9608 Mbps",
-        "HE80" => "4804 Mbps",
-        "HE40" => "2402 Mbps",
-        "HE20" => "1201 Mbps",
+        "HE160" => "9608",
+        "HE80" => "4804",
+        "HE40" => "2402",
+        "HE20" => "1201",
         // 802.11ac (VHT)
-        "VHT160" => "6933 Mbps",
-        "VHT80" => "3467 Mbps",
-        "VHT40" => "1733 Mbps",
-        "VHT20" => "867 Mbps",
+        "VHT160" => "6933",
+        "VHT80" => "3467",
+        "VHT40" => "1733",
+        "VHT20" => "867",
         // 802.11n (HT)
-        "HT40" => "300 Mbps",
-        "HT20" => "144 Mbps",
+        "HT40" => "300",
+        "HT20" => "144",
         // Legacy
         _ => "",
     };
@@ -809,15 +901,284 @@ fn get_iw_bssid(iface: &str) -> String {
         .and_then(|o| String::from_utf8(o.stdout).ok())
         .unwrap_or_default();
 
+    parse_iw_addr(&output)
+}
+
+/// Extract the interface MAC ("addr 02:11:22:33:44:55") from `iw dev <iface> info`
+fn parse_iw_addr(output: &str) -> String {
+    for line in output.lines() {
+        if let Some(addr) = line.trim().strip_prefix("addr ") {
+            return addr.trim().to_string();
+        }
+    }
+    String::new()
+}
+
+/// Get the operating channel from `iw dev <iface> info`
+fn get_iw_channel(iface: &str) -> String {
+    let output = std::process::Command::new("iw")
+        .args(["dev", iface, "info"])
+        .output()
+        .ok()
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .unwrap_or_default();
+
+    parse_iw_channel(&output)
+}
+
+/// Extract the channel number from `iw dev <iface> info` output
+/// ("channel 36 (5180 MHz), width: 80 MHz, center1: 5210 MHz" → "36")
+fn parse_iw_channel(output: &str) -> String {
     for line in output.lines() {
         let trimmed = line.trim();
-        if trimmed.starts_with("addr ") {
-            return trimmed.trim_start_matches("addr ").trim().to_string();
+        if let Some(rest) = trimmed.strip_prefix("channel ") {
+            if let Some(num) = rest.split_whitespace().next() {
+                return num.to_string();
+            }
         }
     }
     String::new()
 }
 
+/// Radio capabilities parsed from `iw phy <phy> info`
+#[derive(Debug, Default)]
+struct PhyCapabilities {
+    /// Usable (not disabled) channels as (band, channel number)
+    channels: Vec<(&'static str, u32)>,
+    ht: bool,
+    vht: bool,
+    he: bool,
+    eht: bool,
+    /// "HT Max RX data rate" in Mbps, e.g. "300"
+    ht_max_rate: String,
+}
+
+impl PhyCapabilities {
+    /// Channels for a UCI band ("2g", "5g", …); all channels if band is unknown
+    fn channels_for_band(&self, band: &str) -> Vec<u32> {
+        self.channels
+            .iter()
+            .filter(|(b, _)| band.is_empty() || *b == band)
+            .map(|(_, c)| *c)
+            .collect()
+    }
+
+    /// TR-181 SupportedStandards values for the radio's band
+    fn supported_standards(&self, band: &str) -> Vec<&'static str> {
+        let mut out = Vec::new();
+        match band {
+            "2g" => out.extend(["b", "g"]),
+            "5g" => out.push("a"),
+            _ => {}
+        }
+        if self.ht && band != "6g" {
+            out.push("n");
+        }
+        if self.vht && band == "5g" {
+            out.push("ac");
+        }
+        if self.he {
+            out.push("ax");
+        }
+        if self.eht {
+            out.push("be");
+        }
+        out
+    }
+}
+
+/// Map a radio section to its phy: the phy behind its live interface, else
+/// the radioN → phyN naming convention
+fn radio_phy(device: &str, phy_iface: &str) -> String {
+    if !phy_iface.is_empty() {
+        if let Ok(name) =
+            std::fs::read_to_string(format!("/sys/class/net/{phy_iface}/phy80211/name"))
+        {
+            return name.trim().to_string();
+        }
+    }
+    device.replace("radio", "phy")
+}
+
+/// Raw `iw phy <phy> info` output
+fn get_iw_phy_info(phy: &str) -> String {
+    std::process::Command::new("iw")
+        .args(["phy", phy, "info"])
+        .output()
+        .ok()
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .unwrap_or_default()
+}
+
+/// Parse channel list and HT/VHT/HE/EHT capabilities from `iw phy` output
+fn parse_iw_phy(output: &str) -> PhyCapabilities {
+    let mut caps = PhyCapabilities::default();
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("HT Capabilities") || trimmed.starts_with("HT Max RX data rate") {
+            caps.ht = true;
+            if let Some(rate) = trimmed.strip_prefix("HT Max RX data rate:") {
+                // "300 Mbps" -> "300": MaxBitRate is a bare unsignedInt
+                caps.ht_max_rate = rate.trim().trim_end_matches("Mbps").trim().to_string();
+            }
+        } else if trimmed.starts_with("VHT Capabilities") {
+            caps.vht = true;
+        } else if trimmed.starts_with("HE Iftypes") || trimmed.starts_with("HE MAC Capabilities") {
+            caps.he = true;
+        } else if trimmed.starts_with("EHT Iftypes") || trimmed.starts_with("EHT MAC Capabilities")
+        {
+            caps.eht = true;
+        } else if let Some(rest) = trimmed.strip_prefix("* ") {
+            // "* 5180.0 MHz [36] (23.0 dBm)" / "* 2484 MHz [14] (disabled)"
+            if !rest.contains(" MHz [") || rest.contains("(disabled)") {
+                continue;
+            }
+            let freq = rest
+                .split_whitespace()
+                .next()
+                .and_then(|f| f.parse::<f64>().ok())
+                .unwrap_or(0.0) as u32;
+            let chan = rest
+                .split('[')
+                .nth(1)
+                .and_then(|r| r.split(']').next())
+                .and_then(|c| c.parse::<u32>().ok());
+            let band = match freq {
+                2400..=2500 => "2g",
+                5150..=5925 => "5g",
+                5926..=7125 => "6g",
+                57000..=71000 => "60g",
+                _ => continue,
+            };
+            if let Some(chan) = chan {
+                caps.channels.push((band, chan));
+            }
+        }
+    }
+    caps
+}
+
+/// Map a TR-181 Radio.Channel value to the UCI `channel` option.
+///
+/// `0` and `auto` select automatic channel selection (ACS); anything else
+/// must be a channel number that is legal for the radio's band.
+fn channel_to_uci(value: &str, band: &str) -> Result<String, String> {
+    let value = value.trim();
+    if value == "0" || value.eq_ignore_ascii_case("auto") {
+        return Ok("auto".to_string());
+    }
+    let chan: u32 = value
+        .parse()
+        .map_err(|_| format!("Invalid channel '{value}'"))?;
+    if is_valid_channel(chan, band) {
+        Ok(chan.to_string())
+    } else {
+        Err(format!("Channel {chan} is not valid for band '{band}'"))
+    }
+}
+
+/// Check a channel number against the band's channel plan
+fn is_valid_channel(chan: u32, band: &str) -> bool {
+    match band {
+        "2g" => (1..=14).contains(&chan),
+        "5g" => (36..=64)
+            .step_by(4)
+            .chain((100..=144).step_by(4))
+            .chain((149..=177).step_by(4))
+            .any(|c| c == chan),
+        "6g" => chan == 2 || (1..=233).step_by(4).any(|c| c == chan),
+        "60g" => (1..=6).contains(&chan),
+        // Unknown band: accept anything in the overall 802.11 range
+        _ => (1..=233).contains(&chan),
+    }
+}
+
+/// hostapd's `wpa_group_rekey` default, reported when UCI leaves it unset
+const DEFAULT_GROUP_REKEY: u32 = 86400;
+
+/// Map Security.RekeyingInterval (seconds) to UCI `wpa_group_rekey`.
+/// Accepts 60 s .. 7 days; shorter intervals cause needless rekey storms.
+fn rekey_interval_to_uci(value: &str) -> Result<String, String> {
+    let secs: u32 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid rekeying interval '{value}'"))?;
+    if (60..=604_800).contains(&secs) {
+        Ok(secs.to_string())
+    } else {
+        Err(format!(
+            "Rekeying interval {secs}s out of range (60-604800)"
+        ))
+    }
+}
+
+/// Map Security.X_OptimACS_PMKCaching to UCI `okc` (opportunistic key caching)
+fn pmk_caching_to_uci(value: &str) -> Result<&'static str, String> {
+    match value.trim() {
+        "true" | "1" => Ok("1"),
+        "false" | "0" => Ok("0"),
+        other => Err(format!("Invalid boolean '{other}'")),
+    }
+}
+
+/// Default RADIUS authentication port when UCI `auth_port` is unset.
+const DEFAULT_RADIUS_PORT: u16 = 1812;
+
+/// Map a Security.ModeEnabled value to UCI `encryption`.  TR-181 names are
+/// translated; anything else is passed through as a raw UCI value.
+fn mode_enabled_to_uci(value: &str) -> &str {
+    match value.trim() {
+        "None" => "none",
+        "WPA2-Personal" => "psk2",
+        "WPA-WPA2-Personal" => "psk-mixed",
+        "WPA3-Personal" => "sae",
+        "WPA2-WPA3-Personal" => "sae-mixed",
+        "WPA2-Enterprise" => "wpa2",
+        "WPA3-Enterprise" => "wpa3",
+        "OWE" => "owe",
+        other => other,
+    }
+}
+
+/// Map a RADIUS Security parameter to its UCI option and validated value.
+fn radius_param_to_uci(param: &str, value: &str) -> Result<(&'static str, String), String> {
+    let value = value.trim();
+    match param {
+        "RadiusServerIPAddr" => {
+            value
+                .parse::<std::net::IpAddr>()
+                .map_err(|_| format!("Invalid RADIUS server address '{value}'"))?;
+            Ok(("auth_server", value.to_string()))
+        }
+        "RadiusServerPort" => match value.parse::<u16>() {
+            Ok(port) if port > 0 => Ok(("auth_port", port.to_string())),
+            _ => Err(format!("Invalid RADIUS server port '{value}'")),
+        },
+        "RadiusSecret" => {
+            if value.is_empty() {
+                Err("RADIUS secret must not be empty".to_string())
+            } else {
+                Ok(("auth_secret", value.to_string()))
+            }
+        }
+        other => Err(format!("Unknown RADIUS parameter '{other}'")),
+    }
+}
+
+/// SSIDAdvertisementEnabled: true = visible (hidden=0), false = hidden (hidden=1)
+fn ssid_advertisement_to_uci(value: &str) -> Result<&'static str, String> {
+    match value.trim() {
+        "true" | "1" => Ok("0"),
+        "false" | "0" => Ok("1"),
+        other => Err(format!("Invalid boolean '{other}'")),
+    }
+}
+
+/// Inverse of [`ssid_advertisement_to_uci`]; an unset `hidden` means broadcast.
+fn ssid_advertised_from_uci(hidden: &str) -> bool {
+    hidden.trim() != "1"
+}
+
 /// Get TX bitrate from `iw dev <iface> link`
 fn get_iw_bitrate(iface: &str) -> String {
     let output = std::process::Command::new("iw")
@@ -836,7 +1197,42 @@ fn get_iw_bitrate(iface: &str) -> String {
     String::new()
 }
 
-/// Parse `iw dev <iface> station dump` into per-station maps
+/// Netdev to run `iw station dump` on for wireless section `iface`.
+fn station_iface(iface: &str, ubus_map: &HashMap<String, String>) -> String {
+    let ubus_iface = ubus_map.get(iface).cloned().unwrap_or_default();
+    if !ubus_iface.is_empty() {
+        return ubus_iface;
+    }
+    let device = uci_get(&format!("wireless.{iface}.device"));
+    if device.is_empty() {
+        String::new()
+    } else {
+        get_phy_interface(&device)
+    }
+}
+
+/// Take a station snapshot of every access point and fold it into the
+/// client history.
+pub fn sample_stations() {
+    let ubus_map = build_ubus_iface_map();
+    let now = chrono::Utc::now();
+    for (idx, iface) in get_wifi_ifaces().iter().enumerate() {
+        let phy_iface = station_iface(iface, &ubus_map);
+        if phy_iface.is_empty() {
+            continue;
+        }
+        let stations: Vec<(String, String)> = get_station_dump(&phy_iface)
+            .into_iter()
+            .filter_map(|mut sta| {
+                let mac = sta.remove("mac")?;
+                Some((mac, sta.remove("signal").unwrap_or_default()))
+            })
+            .collect();
+        client_history::observe(idx + 1, &stations, now);
+    }
+}
+
+/// Stations from `iw dev <iface> station dump`; none when `iw` is missing
 fn get_station_dump(iface: &str) -> Vec<HashMap<String, String>> {
     let output = std::process::Command::new("iw")
         .args(["dev", iface, "station", "dump"])
@@ -844,7 +1240,11 @@ fn get_station_dump(iface: &str) -> Vec<HashMap<String, String>> {
         .ok()
         .and_then(|o| String::from_utf8(o.stdout).ok())
         .unwrap_or_default();
+    parse_station_dump(&output)
+}
 
+/// Parse `iw dev <iface> station dump` output into per-station maps
+fn parse_station_dump(output: &str) -> Vec<HashMap<String, String>> {
     let mut stations = Vec::new();
     let mut current: Option<HashMap<String, String>> = None;
 
@@ -902,6 +1302,11 @@ fn get_station_dump(iface: &str) -> Vec<HashMap<String, String>> {
                     "tx bytes" => {
                         sta.insert("tx_bytes".to_string(), val.to_string());
                     }
+                    "connected time" => {
+                        // "connected time:  1234 seconds"
+                        let secs = val.split_whitespace().next().unwrap_or(val);
+                        sta.insert("connected_secs".to_string(), secs.to_string());
+                    }
                     _ => {}
                 }
             }
@@ -915,6 +1320,16 @@ fn get_station_dump(iface: &str) -> Vec<HashMap<String, String>> {
     stations
 }
 
+/// When a station associated (RFC 3339), from its `connected time`
+fn association_time(
+    sta: &HashMap<String, String>,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Option<String> {
+    let secs: i64 = sta.get("connected_secs")?.parse().ok()?;
+    let at = now - chrono::Duration::seconds(secs);
+    Some(at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+}
+
 /// Resolve IP address from ARP/neighbor table by MAC
 fn resolve_ip_from_arp(mac: &str) -> String {
     let mac_lower = mac.to_lowercase();
@@ -960,7 +1375,12 @@ fn get_associated_device_count(iface: &str) -> usize {
     output.lines().filter(|l| l.starts_with("Station ")).count()
 }
 
-pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), String> {
+pub async fn set(
+    _cfg: &ClientConfig,
+    txn: &mut UciTxn,
+    path: &str,
+    value: &str,
+) -> Result<(), DmError> {
     let ifaces = get_wifi_ifaces();
     let devices = get_wifi_devices();
 
@@ -970,13 +1390,10 @@ pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), Str
             if idx > 0 && idx <= ifaces.len() {
                 let iface = &ifaces[idx - 1];
                 uci_set(&format!("wireless.{iface}.ssid"), value)?;
-                uci_commit("wireless")?;
+                apply_iface_option(txn, iface, "ssid", value).await?;
                 info!("WiFi SSID {idx} set to '{value}' on {iface}");
             } else {
-                return Err(format!(
-                    "SSID index {idx} out of range (max: {})",
-                    ifaces.len()
-                ));
+                return Err(DmError::InvalidPath(path.to_string()));
             }
         }
     }
@@ -992,11 +1409,10 @@ pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), Str
                     "1"
                 };
                 uci_set(&format!("wireless.{iface}.disabled"), disabled)?;
-                uci_commit("wireless")?;
-                wifi_reload().await?;
+                apply_iface_option(txn, iface, "disabled", disabled).await?;
                 info!("WiFi SSID {idx} enable set to '{value}' (disabled={disabled})");
             } else {
-                return Err(format!("SSID index {idx} out of range"));
+                return Err(DmError::InvalidPath(path.to_string()));
             }
         }
     }
@@ -1006,11 +1422,10 @@ pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), Str
             if idx > 0 && idx <= ifaces.len() {
                 let iface = &ifaces[idx - 1];
                 uci_set(&format!("wireless.{iface}.key"), value)?;
-                uci_commit("wireless")?;
-                wifi_reload().await?;
+                apply_iface_option(txn, iface, "key", value).await?;
                 info!("WiFi AccessPoint {idx} key updated");
             } else {
-                return Err(format!("AccessPoint index {idx} out of range"));
+                return Err(DmError::InvalidPath(path.to_string()));
             }
         }
     }
@@ -1019,32 +1434,75 @@ pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), Str
         if let Some(idx) = parse_ap_index(path) {
             if idx > 0 && idx <= ifaces.len() {
                 let iface = &ifaces[idx - 1];
-                uci_set(&format!("wireless.{iface}.encryption"), value)?;
-                uci_commit("wireless")?;
-                wifi_reload().await?;
-                info!("WiFi AccessPoint {idx} encryption set to '{value}'");
+                let enc = mode_enabled_to_uci(value);
+                uci_set(&format!("wireless.{iface}.encryption"), enc)?;
+                apply_iface_option(txn, iface, "encryption", enc).await?;
+                info!("WiFi AccessPoint {idx} encryption set to '{enc}'");
+            } else {
+                return Err(DmError::InvalidPath(path.to_string()));
+            }
+        }
+    }
+    // Handle AccessPoint RADIUS server settings (WPA-Enterprise)
+    else if path.ends_with(".Security.RadiusServerIPAddr")
+        || path.ends_with(".Security.RadiusServerPort")
+        || path.ends_with(".Security.RadiusSecret")
+    {
+        if let Some(idx) = parse_ap_index(path) {
+            if idx > 0 && idx <= ifaces.len() {
+                let iface = &ifaces[idx - 1];
+                let param = path.rsplit('.').next().unwrap_or_default();
+                let (option, uci_value) = radius_param_to_uci(param, value)
+                    .map_err(|e| DmError::invalid_value(path, e))?;
+                uci_set(&format!("wireless.{iface}.{option}"), &uci_value)?;
+                apply_iface_option(txn, iface, option, &uci_value).await?;
+                info!("WiFi AccessPoint {idx} {param} updated");
+            } else {
+                return Err(DmError::InvalidPath(path.to_string()));
+            }
+        }
+    }
+    // Handle AccessPoint group rekey interval
+    else if path.ends_with(".Security.RekeyingInterval") {
+        if let Some(idx) = parse_ap_index(path) {
+            if idx > 0 && idx <= ifaces.len() {
+                let iface = &ifaces[idx - 1];
+                let secs =
+                    rekey_interval_to_uci(value).map_err(|e| DmError::invalid_value(path, e))?;
+                uci_set(&format!("wireless.{iface}.wpa_group_rekey"), &secs)?;
+                apply_iface_option(txn, iface, "wpa_group_rekey", &secs).await?;
+                info!("WiFi AccessPoint {idx} group rekey interval set to {secs}s");
             } else {
-                return Err(format!("AccessPoint index {idx} out of range"));
+                return Err(DmError::InvalidPath(path.to_string()));
+            }
+        }
+    }
+    // Handle AccessPoint PMK caching (opportunistic key caching)
+    else if path.ends_with(".Security.X_OptimACS_PMKCaching") {
+        if let Some(idx) = parse_ap_index(path) {
+            if idx > 0 && idx <= ifaces.len() {
+                let iface = &ifaces[idx - 1];
+                let okc = pmk_caching_to_uci(value).map_err(|e| DmError::invalid_value(path, e))?;
+                uci_set(&format!("wireless.{iface}.okc"), okc)?;
+                apply_iface_option(txn, iface, "okc", okc).await?;
+                info!("WiFi AccessPoint {idx} PMK caching set to '{okc}'");
+            } else {
+                return Err(DmError::InvalidPath(path.to_string()));
             }
         }
     }
     // Handle SSID Advertisement (hidden SSID)
     else if path.ends_with(".SSIDAdvertisementEnabled") {
-        if let Some(idx) = parse_ssid_index(path) {
+        if let Some(idx) = parse_ap_index(path).or_else(|| parse_ssid_index(path)) {
             if idx > 0 && idx <= ifaces.len() {
                 let iface = &ifaces[idx - 1];
-                // SSIDAdvertisementEnabled: true = visible (hidden=0), false = hidden (hidden=1)
-                let hidden = if value == "true" || value == "1" {
-                    "0"
-                } else {
-                    "1"
-                };
+                let hidden = ssid_advertisement_to_uci(value)
+                    .map_err(|e| DmError::invalid_value(path, e))?;
                 uci_set(&format!("wireless.{iface}.hidden"), hidden)?;
-                uci_commit("wireless")?;
-                wifi_reload().await?;
+                apply_iface_option(txn, iface, "hidden", hidden).await?;
                 info!("WiFi SSID {idx} advertisement set to '{value}' (hidden={hidden})");
             } else {
-                return Err(format!("SSID index {idx} out of range"));
+                return Err(DmError::InvalidPath(path.to_string()));
             }
         }
     }
@@ -1053,12 +1511,12 @@ pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), Str
         if let Some(idx) = parse_ap_index(path) {
             if idx > 0 && idx <= ifaces.len() {
                 let iface = &ifaces[idx - 1];
-                uci_set(&format!("wireless.{iface}.maxassoc"), value)?;
-                uci_commit("wireless")?;
-                wifi_reload().await?;
+                let value = maxassoc_to_uci(value).map_err(|e| DmError::invalid_value(path, e))?;
+                uci_set(&format!("wireless.{iface}.maxassoc"), &value)?;
+                apply_iface_option(txn, iface, "maxassoc", &value).await?;
                 info!("WiFi AccessPoint {idx} max associations set to '{value}'");
             } else {
-                return Err(format!("AccessPoint index {idx} out of range"));
+                return Err(DmError::InvalidPath(path.to_string()));
             }
         }
     }
@@ -1073,11 +1531,10 @@ pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), Str
                     "0"
                 };
                 uci_set(&format!("wireless.{iface}.wmm"), wmm)?;
-                uci_commit("wireless")?;
-                wifi_reload().await?;
+                apply_iface_option(txn, iface, "wmm", wmm).await?;
                 info!("WiFi AccessPoint {idx} WMM set to '{wmm}'");
             } else {
-                return Err(format!("AccessPoint index {idx} out of range"));
+                return Err(DmError::InvalidPath(path.to_string()));
             }
         }
     }
@@ -1086,15 +1543,17 @@ pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), Str
         if let Some(idx) = parse_radio_index(path) {
             if idx > 0 && idx <= devices.len() {
                 let device = &devices[idx - 1];
-                uci_set(&format!("wireless.{device}.channel"), value)?;
-                uci_commit("wireless")?;
-                wifi_reload().await?;
-                info!("WiFi Radio {idx} channel set to '{value}'");
+                let band = uci_get(&format!("wireless.{device}.band"));
+                let channel =
+                    channel_to_uci(value, &band).map_err(|e| DmError::invalid_value(path, e))?;
+                uci_set(&format!("wireless.{device}.channel"), &channel)?;
+                if !txn.defer("wireless") {
+                    uci_commit("wireless")?;
+                    wifi_reload().await?;
+                }
+                info!("WiFi Radio {idx} channel set to '{channel}'");
             } else {
-                return Err(format!(
-                    "Radio index {idx} out of range (max: {})",
-                    devices.len()
-                ));
+                return Err(DmError::InvalidPath(path.to_string()));
             }
         }
     }
@@ -1109,12 +1568,49 @@ pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), Str
                 } else {
                     "1"
                 };
+                if disabled == "1" {
+                    let uplinks =
+                        uplink_ifaces(&Wireless::load(), device, super::ip::carries_default_route);
+                    if !uplinks.is_empty() {
+                        return Err(DmError::invalid_value(
+                            path,
+                            format!(
+                                "Radio {idx} also carries the management uplink ({}); \
+                                 disabling it would cut off the agent",
+                                uplinks.join(", ")
+                            ),
+                        ));
+                    }
+                }
                 uci_set(&format!("wireless.{device}.disabled"), disabled)?;
-                uci_commit("wireless")?;
-                wifi_reload().await?;
+                if !txn.defer("wireless") {
+                    uci_commit("wireless")?;
+                    wifi_reload().await?;
+                }
                 info!("WiFi Radio {idx} enable set to '{value}' (disabled={disabled})");
             } else {
-                return Err(format!("Radio index {idx} out of range"));
+                return Err(DmError::InvalidPath(path.to_string()));
+            }
+        }
+    }
+    // Handle Radio TransmitPower (dBm)
+    else if path.ends_with(".TransmitPower") {
+        if let Some(idx) = parse_radio_index(path) {
+            if idx > 0 && idx <= devices.len() {
+                let device = &devices[idx - 1];
+                let option = format!("wireless.{device}.txpower");
+                match txpower_to_uci(value).map_err(|e| DmError::invalid_value(path, e))? {
+                    Some(dbm) => uci_set(&option, &dbm)?,
+                    None if !uci_get(&option).is_empty() => uci_delete(&option)?,
+                    None => {}
+                }
+                if !txn.defer("wireless") {
+                    uci_commit("wireless")?;
+                    wifi_reload().await?;
+                }
+                info!("WiFi Radio {idx} transmit power set to '{value}'");
+            } else {
+                return Err(DmError::InvalidPath(path.to_string()));
             }
         }
     }
@@ -1123,24 +1619,641 @@ pub async fn set(_cfg: &ClientConfig, path: &str, value: &str) -> Result<(), Str
         if let Some(idx) = parse_radio_index(path) {
             if idx > 0 && idx <= devices.len() {
                 let device = &devices[idx - 1];
+                let value = htmode_to_uci(value).map_err(|e| DmError::invalid_value(path, e))?;
                 uci_set(&format!("wireless.{device}.htmode"), value)?;
-                uci_commit("wireless")?;
-                wifi_reload().await?;
+                if !txn.defer("wireless") {
+                    uci_commit("wireless")?;
+                    wifi_reload().await?;
+                }
                 info!("WiFi Radio {idx} bandwidth set to '{value}'");
             } else {
-                return Err(format!("Radio index {idx} out of range"));
+                return Err(DmError::InvalidPath(path.to_string()));
             }
         }
     } else {
         warn!("DM SET WiFi: unknown path {path}");
-        return Err(format!("Unknown WiFi path: {path}"));
+        return Err(DmError::InvalidPath(path.to_string()));
     }
 
     Ok(())
 }
 
+/// Highest `txpower` accepted, in dBm (1 W; above any regulatory limit).
+const TXPOWER_MAX_DBM: i32 = 30;
+
+/// UCI `txpower` for a TransmitPower SET: dBm in 0..=[`TXPOWER_MAX_DBM`], or
+/// `-1` for the driver/regulatory default (`None`: option removed).
+fn txpower_to_uci(value: &str) -> Result<Option<String>, String> {
+    match value.trim().parse::<i32>() {
+        Ok(-1) => Ok(None),
+        Ok(dbm) if (0..=TXPOWER_MAX_DBM).contains(&dbm) => Ok(Some(dbm.to_string())),
+        _ => Err(format!(
+            "TransmitPower must be 0-{TXPOWER_MAX_DBM} dBm or -1 (auto), got {value:?}"
+        )),
+    }
+}
+
+/// Most stations hostapd allows per BSS (`MAX_STA_COUNT`).
+const MAXASSOC_MAX: u32 = 2007;
+
+/// UCI `maxassoc` for a MaxAssociatedDevices SET: 1..=[`MAXASSOC_MAX`].
+fn maxassoc_to_uci(value: &str) -> Result<String, String> {
+    match value.trim().parse::<u32>() {
+        Ok(n) if (1..=MAXASSOC_MAX).contains(&n) => Ok(n.to_string()),
+        _ => Err(format!(
+            "MaxAssociatedDevices must be 1-{MAXASSOC_MAX}, got {value:?}"
+        )),
+    }
+}
+
+/// UCI `htmode` values accepted for OperatingChannelBandwidth, which is
+/// reported as the raw `htmode`.
+const HTMODES: &[&str] = &[
+    "NOHT", "HT20", "HT40", "HT40+", "HT40-", "VHT20", "VHT40", "VHT80", "VHT160", "HE20", "HE40",
+    "HE80", "HE160", "EHT20", "EHT40", "EHT80", "EHT160", "EHT320",
+];
+
+/// Check an OperatingChannelBandwidth SET against [`HTMODES`].
+fn htmode_to_uci(value: &str) -> Result<&'static str, String> {
+    HTMODES
+        .iter()
+        .find(|m| **m == value.trim())
+        .copied()
+        .ok_or_else(|| format!("Unsupported bandwidth {value:?}, expected one of {HTMODES:?}"))
+}
+
+/// Enabled client-side interfaces (STA/mesh) on radio `device` whose network
+/// carries the default route — the agent's own uplink, which disabling the
+/// radio would take down.
+fn uplink_ifaces(w: &Wireless, device: &str, routed: impl Fn(&str) -> bool) -> Vec<String> {
+    w.ifaces
+        .iter()
+        .filter(|iface| w.option(iface, "device") == device)
+        .filter(|iface| w.option(iface, "disabled") != "1")
+        .filter(|iface| matches!(w.option(iface, "mode"), "sta" | "mesh"))
+        .filter(|iface| w.option(iface, "network").split_whitespace().any(&routed))
+        .cloned()
+        .collect()
+}
+
+/// `Device.WiFi.AccessPoint.{i}.WPS.X_OptimACS_PushButton()` — open a WPS
+/// push-button pairing window on the AP's interface via `hostapd_cli`.
+pub async fn operate_wps_push_button(
+    cfg: &ClientConfig,
+    command: &str,
+    _input_args: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, DmError> {
+    if !cfg.wps_enabled {
+        return Err(DmError::CommandFailure(
+            "WPS push-button is disabled (set wps_enabled to enable)".into(),
+        ));
+    }
+
+    let idx = parse_ap_index(command).ok_or_else(|| DmError::InvalidPath(command.to_string()))?;
+    let ifaces = get_wifi_ifaces();
+    if idx == 0 || idx > ifaces.len() {
+        return Err(DmError::InvalidPath(command.to_string()));
+    }
+    let ifname = bss_ifname(&ifaces[idx - 1]);
+    if ifname.is_empty() {
+        return Err(DmError::CommandFailure(format!(
+            "AccessPoint {idx} has no active interface"
+        )));
+    }
+
+    let output = std::process::Command::new("hostapd_cli")
+        .args(wps_pbc_args(&ifname))
+        .output()
+        .map_err(|e| format!("hostapd_cli: {e}"))?;
+    let reply = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    if output.status.success() && reply.ends_with("OK") {
+        info!("WPS push-button window opened on {ifname} (AccessPoint {idx})");
+        let mut out = HashMap::new();
+        out.insert("Status".to_string(), "Started".to_string());
+        out.insert("Interface".to_string(), ifname);
+        Ok(out)
+    } else {
+        warn!("WPS push-button on {ifname} failed: {reply}");
+        Err(DmError::CommandFailure(format!(
+            "hostapd_cli wps_pbc failed on {ifname}: {reply}"
+        )))
+    }
+}
+
+/// Arguments for `hostapd_cli` to start WPS push-button on `ifname`
+fn wps_pbc_args(ifname: &str) -> [&str; 3] {
+    ["-i", ifname, "wps_pbc"]
+}
+
+/// One channel from `iw dev <if> survey dump`.
+#[derive(Debug, Default, PartialEq)]
+struct SurveyEntry {
+    frequency: u32,
+    in_use: bool,
+    noise: Option<i32>,
+    active_ms: Option<u64>,
+    busy_ms: Option<u64>,
+}
+
+/// Parse `iw dev <if> survey dump`.  Channels the driver reports without any
+/// measurement are dropped, so a driver that only surveys the operating
+/// channel yields just that one.
+fn parse_survey_dump(output: &str) -> Vec<SurveyEntry> {
+    let mut entries = Vec::new();
+    let mut cur: Option<SurveyEntry> = None;
+
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("Survey data from") {
+            entries.extend(cur.take());
+            cur = Some(SurveyEntry::default());
+            continue;
+        }
+        let entry = match cur.as_mut() {
+            Some(e) => e,
+            None => continue,
+        };
+        let (key, value) = match trimmed.split_once(':') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        let number = value.split_whitespace().next().unwrap_or("");
+        match key.trim() {
+            "frequency" => {
+                entry.frequency = number.parse::<f32>().map(|f| f as u32).unwrap_or(0);
+                entry.in_use = value.contains("[in use]");
+            }
+            "noise" => entry.noise = number.parse().ok(),
+            "channel active time" => entry.active_ms = number.parse().ok(),
+            "channel busy time" => entry.busy_ms = number.parse().ok(),
+            _ => {}
+        }
+    }
+    entries.extend(cur);
+
+    entries.retain(|e| e.frequency > 0 && (e.noise.is_some() || e.active_ms.is_some()));
+    entries
+}
+
+/// 802.11 channel number for a centre frequency in MHz
+fn freq_to_channel(freq: u32) -> u32 {
+    match freq {
+        2484 => 14,
+        2412..=2472 => (freq - 2407) / 5,
+        5935 => 2,
+        5955..=7115 => (freq - 5950) / 5,
+        5000..=5925 => (freq - 5000) / 5,
+        58320..=70200 => (freq - 56160) / 2160,
+        _ => 0,
+    }
+}
+
+/// Output args for a survey: `Channel.{n}.*` per surveyed channel.
+fn survey_output_args(entries: &[SurveyEntry]) -> HashMap<String, String> {
+    let mut out = HashMap::new();
+    out.insert(
+        "ChannelNumberOfEntries".to_string(),
+        entries.len().to_string(),
+    );
+    for (i, e) in entries.iter().enumerate() {
+        let base = format!("Channel.{}.", i + 1);
+        out.insert(
+            format!("{base}Channel"),
+            freq_to_channel(e.frequency).to_string(),
+        );
+        out.insert(format!("{base}Frequency"), e.frequency.to_string());
+        out.insert(format!("{base}InUse"), e.in_use.to_string());
+        if let Some(noise) = e.noise {
+            out.insert(format!("{base}Noise"), noise.to_string());
+        }
+        if let Some(active) = e.active_ms {
+            out.insert(format!("{base}ActiveTime"), active.to_string());
+        }
+        if let Some(busy) = e.busy_ms {
+            out.insert(format!("{base}BusyTime"), busy.to_string());
+        }
+        if let (Some(active), Some(busy)) = (e.active_ms, e.busy_ms) {
+            if active > 0 {
+                out.insert(
+                    format!("{base}Utilization"),
+                    (busy * 100 / active).min(100).to_string(),
+                );
+            }
+        }
+    }
+    out
+}
+
+/// `Device.WiFi.Radio.{i}.X_OptimACS_ChannelSurvey()` — per-channel noise and
+/// busy time from `iw dev <if> survey dump`, for channel planning.
+pub async fn operate_channel_survey(
+    _cfg: &ClientConfig,
+    command: &str,
+    _input_args: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, DmError> {
+    let idx =
+        parse_radio_index(command).ok_or_else(|| DmError::InvalidPath(command.to_string()))?;
+    let devices = get_wifi_devices();
+    if idx == 0 || idx > devices.len() {
+        return Err(DmError::InvalidPath(command.to_string()));
+    }
+    let device = &devices[idx - 1];
+
+    let ifname = get_phy_interface(device);
+    if ifname.is_empty() {
+        return Err(DmError::CommandFailure(format!(
+            "Radio {idx} has no active interface"
+        )));
+    }
+
+    let output = tokio::process::Command::new("iw")
+        .args(["dev", &ifname, "survey", "dump"])
+        .output()
+        .await
+        .map_err(|e| format!("iw: {e}"))?;
+    if !output.status.success() {
+        return Err(DmError::CommandFailure(format!(
+            "iw dev {ifname} survey dump failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let entries = parse_survey_dump(&String::from_utf8_lossy(&output.stdout));
+    info!(
+        "Channel survey on {ifname} (Radio {idx}): {} channel(s)",
+        entries.len()
+    );
+    Ok(survey_output_args(&entries))
+}
+
+// ── Channel scan ─────────────────────────────────────────────────────────────
+
+/// Minimum time between two scans on the same radio; a scan takes the AP
+/// off its channel for a moment.
+const SCAN_MIN_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Last successful scan per Radio instance.
+static LAST_SCAN: std::sync::Mutex<std::collections::BTreeMap<usize, std::time::Instant>> =
+    std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+/// Ok if a scan may start at `now`, otherwise the seconds left to wait.
+fn scan_allowed(last: Option<std::time::Instant>, now: std::time::Instant) -> Result<(), u64> {
+    match last {
+        Some(t) if now.saturating_duration_since(t) < SCAN_MIN_INTERVAL => {
+            Err((SCAN_MIN_INTERVAL - now.saturating_duration_since(t))
+                .as_secs_f64()
+                .ceil() as u64)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// One BSS seen by `iw dev <if> scan`.
+#[derive(Debug, Default, PartialEq, serde::Serialize)]
+struct ScanEntry {
+    #[serde(rename = "SSID")]
+    ssid: String,
+    #[serde(rename = "BSSID")]
+    bssid: String,
+    #[serde(rename = "Channel")]
+    channel: u32,
+    /// dBm
+    #[serde(rename = "Signal")]
+    signal: i32,
+}
+
+/// Parse `iw dev <if> scan`.  The channel comes from the DS/HT element when
+/// present, else from the frequency.
+fn parse_scan(output: &str) -> Vec<ScanEntry> {
+    let mut entries = Vec::new();
+    let mut cur: Option<ScanEntry> = None;
+
+    for line in output.lines() {
+        // "BSS 11:22:33:44:55:66(on phy0-ap0) -- associated"
+        if let Some(rest) = line.strip_prefix("BSS ") {
+            entries.extend(cur.take());
+            let bssid: String = rest.chars().take(17).collect();
+            cur = Some(ScanEntry {
+                bssid: bssid.to_uppercase(),
+                ..Default::default()
+            });
+            continue;
+        }
+        let entry = match cur.as_mut() {
+            Some(e) => e,
+            None => continue,
+        };
+        let trimmed = line.trim().trim_start_matches("* ");
+        let (key, value) = match trimmed.split_once(':') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        let value = value.trim();
+        let number = value.split_whitespace().next().unwrap_or("");
+        match key.trim() {
+            "SSID" => entry.ssid = value.to_string(),
+            "freq" if entry.channel == 0 => {
+                entry.channel =
+                    freq_to_channel(number.parse::<f32>().map(|f| f as u32).unwrap_or(0))
+            }
+            "signal" => entry.signal = number.parse::<f32>().map(|f| f as i32).unwrap_or(0),
+            "DS Parameter set" => {
+                // "channel 6"
+                if let Some(ch) = value
+                    .strip_prefix("channel")
+                    .and_then(|c| c.trim().parse().ok())
+                {
+                    entry.channel = ch;
+                }
+            }
+            "primary channel" => {
+                if let Ok(ch) = number.parse() {
+                    entry.channel = ch;
+                }
+            }
+            _ => {}
+        }
+    }
+    entries.extend(cur);
+    entries
+}
+
+/// `Device.WiFi.Radio.{i}.X_OptimACS_ScanChannels()` — neighbouring BSSes
+/// from `iw dev <if> scan ap-force`, returned as a JSON array in `results`
+/// (`SSID`, `BSSID`, `Channel`, `Signal`) next to the radio's
+/// `current_channel`.  At most one scan per radio every
+/// [`SCAN_MIN_INTERVAL`]; earlier requests get a busy error.
+pub async fn operate_scan(
+    _cfg: &ClientConfig,
+    command: &str,
+    _input_args: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, DmError> {
+    let idx =
+        parse_radio_index(command).ok_or_else(|| DmError::InvalidPath(command.to_string()))?;
+    let devices = get_wifi_devices();
+    if idx == 0 || idx > devices.len() {
+        return Err(DmError::InvalidPath(command.to_string()));
+    }
+    let (ifname, entries) = scan_radio(idx, &devices[idx - 1]).await?;
+    let mut out = HashMap::new();
+    out.insert(
+        "results".to_string(),
+        serde_json::to_string(&entries).map_err(|e| e.to_string())?,
+    );
+    out.insert("current_channel".to_string(), get_iw_channel(&ifname));
+    Ok(out)
+}
+
+/// Scan Radio `idx` (wifi-device `device`), subject to the per-radio rate
+/// limit, and remember the BSSIDs seen for the neighbour count.
+async fn scan_radio(idx: usize, device: &str) -> Result<(String, Vec<ScanEntry>), DmError> {
+    let ifname = get_phy_interface(device);
+    if ifname.is_empty() {
+        return Err(DmError::CommandFailure(format!(
+            "Radio {idx} has no active interface"
+        )));
+    }
+
+    let last = LAST_SCAN.lock().unwrap().get(&idx).copied();
+    if let Err(wait) = scan_allowed(last, std::time::Instant::now()) {
+        return Err(DmError::ResourcesExceeded(format!(
+            "Radio {idx} was scanned recently; retry in {wait}s"
+        )));
+    }
+
+    let entries = iw_scan(&ifname, "ap-force").await?;
+    // Only a scan that ran counts against the rate limit
+    LAST_SCAN
+        .lock()
+        .unwrap()
+        .insert(idx, std::time::Instant::now());
+    info!(
+        "Channel scan on {ifname} (Radio {idx}): {} BSS(es)",
+        entries.len()
+    );
+    NEIGHBORS
+        .lock()
+        .unwrap()
+        .insert(idx, (std::time::Instant::now(), distinct_bssids(&entries)));
+    Ok((ifname, entries))
+}
+
+/// `iw dev <ifname> scan <mode>`: `ap-force` scans now, `dump` returns what
+/// the driver already holds from earlier scans.
+async fn iw_scan(ifname: &str, mode: &str) -> Result<Vec<ScanEntry>, DmError> {
+    let output = tokio::process::Command::new("iw")
+        .args(["dev", ifname, "scan", mode])
+        .output()
+        .await
+        .map_err(|e| format!("iw: {e}"))?;
+    if !output.status.success() {
+        return Err(DmError::CommandFailure(format!(
+            "iw dev {ifname} scan {mode} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(parse_scan(&String::from_utf8_lossy(&output.stdout)))
+}
+
+// ── Neighbour count ──────────────────────────────────────────────────────────
+
+/// Scan results older than this no longer count as neighbours.
+const NEIGHBOR_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+/// A background refresh of the neighbour cache is running.
+static NEIGHBOR_REFRESH: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// BSSIDs from the last scan or scan dump per Radio instance, and when it
+/// was taken.
+static NEIGHBORS: std::sync::Mutex<
+    std::collections::BTreeMap<usize, (std::time::Instant, std::collections::BTreeSet<String>)>,
+> = std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+fn distinct_bssids(entries: &[ScanEntry]) -> std::collections::BTreeSet<String> {
+    entries.iter().map(|e| e.bssid.to_uppercase()).collect()
+}
+
+/// Distinct BSSIDs over all radios' scans that are still fresh at `now`, or
+/// `None` when there is none.
+fn count_neighbors<'a>(
+    scans: impl IntoIterator<Item = &'a (std::time::Instant, std::collections::BTreeSet<String>)>,
+    now: std::time::Instant,
+) -> Option<usize> {
+    let mut seen = std::collections::BTreeSet::new();
+    let mut fresh = false;
+    for (at, bssids) in scans {
+        if now.saturating_duration_since(*at) < NEIGHBOR_MAX_AGE {
+            fresh = true;
+            seen.extend(bssids.iter());
+        }
+    }
+    fresh.then_some(seen.len())
+}
+
+/// `Device.DeviceInfo.X_OptimACS_NeighborCount` — nearby BSSes from the
+/// cached scan results, or `None` when no radio was scanned recently.
+///
+/// Never blocks on a scan: radios whose results are stale are refreshed in
+/// the background from the driver's passive scan cache (`iw scan dump`) and
+/// counted from a later call on.  That never takes the AP off its channel
+/// nor uses up the ScanChannels() rate limit.
+pub fn neighbor_count() -> Option<usize> {
+    let now = std::time::Instant::now();
+    let radios = get_wifi_devices().len();
+    let (count, stale) = {
+        let scans = NEIGHBORS.lock().unwrap();
+        let stale: Vec<usize> = (1..=radios)
+            .filter(|idx| match scans.get(idx) {
+                Some((at, _)) => now.saturating_duration_since(*at) >= NEIGHBOR_MAX_AGE,
+                None => true,
+            })
+            .collect();
+        (count_neighbors(scans.values(), now), stale)
+    };
+
+    if stale.is_empty() {
+        return count;
+    }
+    let rt = match tokio::runtime::Handle::try_current() {
+        Ok(rt) => rt,
+        Err(_) => return count,
+    };
+    if NEIGHBOR_REFRESH.swap(true, std::sync::atomic::Ordering::AcqRel) {
+        return count;
+    }
+    rt.spawn(async move {
+        let devices = get_wifi_devices();
+        for idx in stale {
+            let ifname = match devices.get(idx - 1).map(|d| get_phy_interface(d)) {
+                Some(name) if !name.is_empty() => name,
+                _ => continue,
+            };
+            match iw_scan(&ifname, "dump").await {
+                Ok(entries) => {
+                    debug!(
+                        "Neighbour cache of Radio {idx}: {} BSS(es) from scan dump",
+                        entries.len()
+                    );
+                    NEIGHBORS
+                        .lock()
+                        .unwrap()
+                        .insert(idx, (std::time::Instant::now(), distinct_bssids(&entries)));
+                }
+                Err(e) => debug!("Neighbour cache of Radio {idx} not refreshed: {e}"),
+            }
+        }
+        NEIGHBOR_REFRESH.store(false, std::sync::atomic::Ordering::Release);
+    });
+    count
+}
+
+/// Running interface of a `wifi-iface` section (e.g. `phy0-ap0`), or empty
+/// when the BSS is not up.
+fn bss_ifname(section: &str) -> String {
+    match build_ubus_iface_map().get(section) {
+        Some(name) => name.clone(),
+        None => get_phy_interface(&uci_get(&format!("wireless.{section}.device"))),
+    }
+}
+
+/// How a changed `wifi-iface` option reaches the running configuration.
+#[derive(Debug, PartialEq)]
+enum ReloadScope {
+    /// Pushed into the live BSS with `hostapd_cli set <key>`; associated
+    /// clients stay connected.
+    Bss(&'static str),
+    /// Set like [`ReloadScope::Bss`], then `hostapd_cli reload` rebuilds
+    /// every BSS of that radio's hostapd interface from the running config
+    /// so the PSK is derived again from the SSID and passphrase.  Clients of
+    /// all BSSes on the radio reassociate, but the radio is not restarted.
+    BssReload(&'static str),
+    /// Needs `wifi reload`, which restarts the radio and drops its clients.
+    Full,
+}
+
+/// Options hostapd can change on a running BSS; security mode, RADIUS,
+/// WMM and enabling/disabling the BSS need it rebuilt.  Radio options
+/// (channel, bandwidth, country) always take a full reload.
+fn reload_scope(option: &str) -> ReloadScope {
+    match option {
+        "ssid" => ReloadScope::BssReload("ssid"),
+        "key" => ReloadScope::BssReload("wpa_passphrase"),
+        "hidden" => ReloadScope::Bss("ignore_broadcast_ssid"),
+        "maxassoc" => ReloadScope::Bss("max_num_sta"),
+        "wpa_group_rekey" => ReloadScope::Bss("wpa_group_rekey"),
+        _ => ReloadScope::Full,
+    }
+}
+
+/// Commit `wireless` after `option` of interface section `iface` changed and
+/// apply it with the least disruption: straight into the BSS where hostapd
+/// allows it, otherwise (or if that fails) with a full `wifi reload`.
+/// Inside an all-or-nothing SET the change stays staged instead.
+async fn apply_iface_option(
+    txn: &mut UciTxn,
+    iface: &str,
+    option: &str,
+    value: &str,
+) -> Result<(), String> {
+    if txn.defer_iface_option(iface, option, value) {
+        return Ok(());
+    }
+    uci_commit("wireless")?;
+    if apply_in_place(iface, option, value) {
+        return Ok(());
+    }
+    wifi_reload().await
+}
+
+/// Apply the `wifi-iface` options an all-or-nothing SET staged, once
+/// `wireless` is committed: in place where each allows it, otherwise one
+/// `wifi reload` covers them all.
+pub(super) async fn apply_staged_options(
+    options: &[(String, String, String)],
+) -> Result<(), String> {
+    for (iface, option, value) in options {
+        if !apply_in_place(iface, option, value) {
+            return wifi_reload().await;
+        }
+    }
+    Ok(())
+}
+
+/// Push a committed `option` into the running BSS of `iface`; false if it
+/// needs (or hostapd forces) a full reload.
+fn apply_in_place(iface: &str, option: &str, value: &str) -> bool {
+    let (key, refresh) = match reload_scope(option) {
+        ReloadScope::Bss(key) => (key, "update_beacon"),
+        ReloadScope::BssReload(key) => (key, "reload"),
+        ReloadScope::Full => return false,
+    };
+    let ifname = bss_ifname(iface);
+    if !ifname.is_empty() && hostapd_set(&ifname, key, value, refresh) {
+        info!("WiFi {iface}: {option} applied to {ifname} without radio restart");
+        return true;
+    }
+    debug!("WiFi {iface}: cannot apply {option} in place, reloading wifi");
+    false
+}
+
+/// `hostapd_cli set` one BSS parameter, then run `refresh` (`update_beacon`
+/// to advertise it, or `reload` to rebuild the BSS); false if hostapd
+/// refused either step.
+fn hostapd_set(ifname: &str, key: &str, value: &str, refresh: &str) -> bool {
+    let ok = |args: &[&str]| {
+        std::process::Command::new("hostapd_cli")
+            .args(["-i", ifname])
+            .args(args)
+            .output()
+            .map(|o| o.status.success() && String::from_utf8_lossy(&o.stdout).trim() == "OK")
+            .unwrap_or(false)
+    };
+    ok(&["set", key, value]) && ok(&[refresh])
+}
+
 /// Reload WiFi configuration
-async fn wifi_reload() -> Result<(), String> {
+pub async fn wifi_reload() -> Result<(), String> {
     let status = std::process::Command::new("wifi")
         .status()
         .map_err(|e| e.to_string())?;
@@ -1163,3 +2276,425 @@ async fn wifi_reload() -> Result<(), String> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn survey_dump_parsed_per_channel() {
+        let dump = "Survey data from phy0-ap0
+\tfrequency:\t\t\t2412 MHz [in use]
+\tnoise:\t\t\t\t-92 dBm
+\tchannel active time:\t\t2000 ms
+\tchannel busy time:\t\t500 ms
+\tchannel receive time:\t\t300 ms
+\tchannel transmit time:\t\t50 ms
+Survey data from phy0-ap0
+\tfrequency:\t\t\t2417 MHz
+Survey data from phy0-ap0
+\tfrequency:\t\t\t2437 MHz
+\tnoise:\t\t\t\t-88 dBm
+\tchannel active time:\t\t150 ms
+\tchannel busy time:\t\t120 ms
+";
+        let entries = parse_survey_dump(dump);
+        // 2417 MHz carries no measurement and is skipped
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].in_use);
+        assert_eq!(entries[0].noise, Some(-92));
+
+        let out = survey_output_args(&entries);
+        assert_eq!(out["ChannelNumberOfEntries"], "2");
+        assert_eq!(out["Channel.1.Channel"], "1");
+        assert_eq!(out["Channel.1.Utilization"], "25");
+        assert_eq!(out["Channel.2.Channel"], "6");
+        assert_eq!(out["Channel.2.BusyTime"], "120");
+        assert_eq!(out["Channel.2.InUse"], "false");
+
+        // In-use-only driver
+        let only = "Survey data from wlan1\n\tfrequency:\t5180 MHz [in use]\n\tnoise:\t-101 dBm\n";
+        let entries = parse_survey_dump(only);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(freq_to_channel(entries[0].frequency), 36);
+        assert_eq!(freq_to_channel(5955), 1);
+    }
+
+    #[test]
+    fn bss_options_avoid_radio_restart() {
+        // The PSK depends on both: the BSS is rebuilt, not just re-beaconed
+        assert_eq!(reload_scope("ssid"), ReloadScope::BssReload("ssid"));
+        assert_eq!(
+            reload_scope("key"),
+            ReloadScope::BssReload("wpa_passphrase")
+        );
+        assert_eq!(
+            reload_scope("hidden"),
+            ReloadScope::Bss("ignore_broadcast_ssid")
+        );
+        assert_eq!(reload_scope("maxassoc"), ReloadScope::Bss("max_num_sta"));
+        assert_eq!(
+            reload_scope("wpa_group_rekey"),
+            ReloadScope::Bss("wpa_group_rekey")
+        );
+        for option in [
+            "encryption",
+            "disabled",
+            "wmm",
+            "okc",
+            "auth_server",
+            "channel",
+            "htmode",
+        ] {
+            assert_eq!(reload_scope(option), ReloadScope::Full, "{option}");
+        }
+    }
+
+    #[test]
+    fn rekey_and_pmk_caching_uci_mapping() {
+        assert_eq!(rekey_interval_to_uci("3600").unwrap(), "3600");
+        assert_eq!(rekey_interval_to_uci(" 600 ").unwrap(), "600");
+        assert!(rekey_interval_to_uci("0").is_err());
+        assert!(rekey_interval_to_uci("-5").is_err());
+        assert!(rekey_interval_to_uci("1000000").is_err());
+        assert!(rekey_interval_to_uci("hourly").is_err());
+
+        assert_eq!(pmk_caching_to_uci("true").unwrap(), "1");
+        assert_eq!(pmk_caching_to_uci("0").unwrap(), "0");
+        assert!(pmk_caching_to_uci("maybe").is_err());
+    }
+
+    #[test]
+    fn enterprise_mode_uci_mapping() {
+        assert_eq!(mode_enabled_to_uci("WPA2-Enterprise"), "wpa2");
+        assert_eq!(mode_enabled_to_uci("WPA3-Enterprise"), "wpa3");
+        assert_eq!(mode_enabled_to_uci("WPA2-Personal"), "psk2");
+        assert_eq!(mode_enabled_to_uci("sae-mixed"), "sae-mixed");
+
+        assert_eq!(
+            radius_param_to_uci("RadiusServerIPAddr", "10.0.0.5").unwrap(),
+            ("auth_server", "10.0.0.5".to_string())
+        );
+        assert!(radius_param_to_uci("RadiusServerIPAddr", "radius.local").is_err());
+        assert_eq!(
+            radius_param_to_uci("RadiusServerPort", "1812").unwrap(),
+            ("auth_port", "1812".to_string())
+        );
+        assert!(radius_param_to_uci("RadiusServerPort", "0").is_err());
+        assert!(radius_param_to_uci("RadiusServerPort", "70000").is_err());
+        assert_eq!(
+            radius_param_to_uci("RadiusSecret", "s3cret").unwrap(),
+            ("auth_secret", "s3cret".to_string())
+        );
+        assert!(radius_param_to_uci("RadiusSecret", "").is_err());
+    }
+
+    #[test]
+    fn ssid_advertisement_maps_to_inverted_hidden() {
+        assert_eq!(ssid_advertisement_to_uci("true").unwrap(), "0");
+        assert_eq!(ssid_advertisement_to_uci("0").unwrap(), "1");
+        assert!(ssid_advertisement_to_uci("yes please").is_err());
+
+        assert!(ssid_advertised_from_uci(""));
+        assert!(ssid_advertised_from_uci("0"));
+        assert!(!ssid_advertised_from_uci("1"));
+
+        assert_eq!(
+            parse_ap_index("Device.WiFi.AccessPoint.2.SSIDAdvertisementEnabled"),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn bssid_read_from_iw_info() {
+        let info = "Interface phy0-ap0\n\
+                    \tifindex 12\n\
+                    \twdev 0x2\n\
+                    \taddr 02:11:22:33:44:55\n\
+                    \tssid OptimACS\n\
+                    \ttype AP\n\
+                    \tchannel 36 (5180 MHz), width: 80 MHz, center1: 5210 MHz\n";
+        assert_eq!(parse_iw_addr(info), "02:11:22:33:44:55");
+        assert_eq!(parse_iw_addr("command failed: No such device (-19)"), "");
+    }
+
+    #[test]
+    fn channel_auto_maps_to_uci_auto() {
+        assert_eq!(channel_to_uci("0", "5g").unwrap(), "auto");
+        assert_eq!(channel_to_uci("auto", "2g").unwrap(), "auto");
+        assert_eq!(channel_to_uci("AUTO", "6g").unwrap(), "auto");
+        assert_eq!(channel_to_uci("36", "5g").unwrap(), "36");
+        assert_eq!(channel_to_uci("11", "2g").unwrap(), "11");
+    }
+
+    #[test]
+    fn channel_validated_against_band() {
+        assert!(channel_to_uci("15", "2g").is_err());
+        assert!(channel_to_uci("6", "5g").is_err());
+        assert!(channel_to_uci("38", "5g").is_err());
+        assert!(channel_to_uci("165", "5g").is_ok());
+        assert!(channel_to_uci("37", "6g").is_ok());
+        assert!(channel_to_uci("36", "6g").is_err());
+        assert!(channel_to_uci("abc", "2g").is_err());
+    }
+
+    #[test]
+    fn wps_pbc_command_targets_interface() {
+        assert_eq!(wps_pbc_args("phy1-ap0"), ["-i", "phy1-ap0", "wps_pbc"]);
+        assert_eq!(
+            parse_ap_index("Device.WiFi.AccessPoint.2.WPS.X_OptimACS_PushButton()"),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn parse_iw_phy_capabilities() {
+        let out = "Wiphy phy0
+	wiphy index: 0
+	Band 1:
+		Capabilities: 0x19ef
+		HT Max RX data rate: 300 Mbps
+		HE Iftypes: AP
+		Frequencies:
+			* 2412.0 MHz [1] (20.0 dBm)
+			* 2437.0 MHz [6] (20.0 dBm)
+			* 2484.0 MHz [14] (disabled)
+	Band 2:
+		VHT Capabilities (0x338001b2):
+		Frequencies:
+			* 5180 MHz [36] (23.0 dBm)
+			* 5260 MHz [52] (20.0 dBm) (radar detection)
+	Band 4:
+		Frequencies:
+			* 5955 MHz [1] (23.0 dBm)
+	Supported commands:
+		 * new_interface
+";
+        let caps = parse_iw_phy(out);
+        assert_eq!(caps.channels_for_band("2g"), vec![1, 6]);
+        assert_eq!(caps.channels_for_band("5g"), vec![36, 52]);
+        assert_eq!(caps.channels_for_band("6g"), vec![1]);
+        assert_eq!(caps.ht_max_rate, "300");
+        assert_eq!(caps.supported_standards("2g"), vec!["b", "g", "n", "ax"]);
+        assert_eq!(caps.supported_standards("5g"), vec!["a", "n", "ac", "ax"]);
+        assert_eq!(caps.supported_standards("6g"), vec!["ax"]);
+    }
+
+    #[test]
+    fn station_dump_parsed_into_associated_devices() {
+        let dump = "\
+Station 4c:5e:0c:11:22:33 (on phy0-ap0)
+\tinactive time:\t1200 ms
+\trx bytes:\t183754
+\ttx bytes:\t250016
+\tsignal:  \t-52 [-55, -54] dBm
+\ttx bitrate:\t866.7 MBit/s VHT-MCS 9 80MHz short GI VHT-NSS 2
+\trx bitrate:\t6.0 MBit/s
+\tconnected time:\t3600 seconds
+Station 02:aa:bb:cc:dd:ee (on phy0-ap0)
+\tsignal:  \t-71 dBm
+";
+        let stations = parse_station_dump(dump);
+        assert_eq!(stations.len(), 2);
+        let sta = &stations[0];
+        assert_eq!(sta["mac"], "4C:5E:0C:11:22:33");
+        assert_eq!(sta["signal"], "-52");
+        assert_eq!(sta["tx_bitrate"], "866700");
+        assert_eq!(sta["rx_bitrate"], "6000");
+
+        let now = chrono::DateTime::parse_from_rfc3339("2026-03-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        assert_eq!(
+            association_time(sta, now).as_deref(),
+            Some("2026-03-01T11:00:00Z")
+        );
+        assert_eq!(stations[1]["signal"], "-71");
+        assert_eq!(association_time(&stations[1], now), None);
+
+        // No iw on the host: no stations, not an error
+        assert!(parse_station_dump("").is_empty());
+    }
+
+    #[test]
+    fn every_wifi_iface_mapped_to_an_instance() {
+        let show = "\
+wireless.radio0=wifi-device
+wireless.radio0.type='mac80211'
+wireless.radio0.channel='36'
+wireless.radio0.band='5g'
+wireless.radio1=wifi-device
+wireless.radio1.type='mac80211'
+wireless.radio1.band='2g'
+wireless.default_radio0=wifi-iface
+wireless.default_radio0.device='radio0'
+wireless.default_radio0.mode='ap'
+wireless.default_radio0.ssid='Office'
+wireless.default_radio0.key='a=b'
+wireless.@wifi-iface[1]=wifi-iface
+wireless.@wifi-iface[1].device='radio1'
+wireless.@wifi-iface[1].ssid='Office Guest'
+wireless.@wifi-iface[1].disabled='1'
+";
+        let w = Wireless::parse(show);
+        assert_eq!(w.ifaces, vec!["default_radio0", "@wifi-iface[1]"]);
+        // radio1 has no channel option but is still Radio.2
+        assert_eq!(w.devices, vec!["radio0", "radio1"]);
+        assert_eq!(w.option("default_radio0", "key"), "a=b");
+
+        let m = w.ssid_params();
+        assert_eq!(m["Device.WiFi.SSID.1.SSID"], "Office");
+        assert_eq!(m["Device.WiFi.SSID.1.Enable"], "true");
+        assert_eq!(m["Device.WiFi.SSID.2.SSID"], "Office Guest");
+        assert_eq!(m["Device.WiFi.SSID.2.Status"], "Down");
+    }
+
+    #[test]
+    fn scan_results_parsed_and_rate_limited() {
+        let scan = "\
+BSS 11:22:33:44:55:66(on phy1-ap0)
+\tTSF: 1234 usec (0d, 00:00:00)
+\tfreq: 2437
+\tsignal: -48.00 dBm
+\tSSID: Neighbour
+\tDS Parameter set: channel 6
+BSS aa:bb:cc:dd:ee:ff(on phy1-ap0)
+\tfreq: 5180.0
+\tsignal: -71.00 dBm
+\tSSID: Upstairs 5G
+\tHT operation:
+\t\t * primary channel: 36
+BSS 02:00:00:00:00:01(on phy1-ap0)
+\tfreq: 2462
+\tsignal: -90.00 dBm
+\tSSID: 
+";
+        let entries = parse_scan(scan);
+        assert_eq!(
+            entries,
+            vec![
+                ScanEntry {
+                    ssid: "Neighbour".into(),
+                    bssid: "11:22:33:44:55:66".into(),
+                    channel: 6,
+                    signal: -48,
+                },
+                ScanEntry {
+                    ssid: "Upstairs 5G".into(),
+                    bssid: "AA:BB:CC:DD:EE:FF".into(),
+                    channel: 36,
+                    signal: -71,
+                },
+                ScanEntry {
+                    ssid: String::new(),
+                    bssid: "02:00:00:00:00:01".into(),
+                    channel: 11,
+                    signal: -90,
+                },
+            ]
+        );
+        let json = serde_json::to_string(&entries[..1]).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"SSID":"Neighbour","BSSID":"11:22:33:44:55:66","Channel":6,"Signal":-48}]"#
+        );
+
+        let t0 = std::time::Instant::now();
+        assert_eq!(scan_allowed(None, t0), Ok(()));
+        assert_eq!(
+            scan_allowed(Some(t0), t0 + std::time::Duration::from_secs(15)),
+            Err(45)
+        );
+        assert_eq!(scan_allowed(Some(t0), t0 + SCAN_MIN_INTERVAL), Ok(()));
+    }
+
+    #[test]
+    fn neighbors_counted_by_distinct_bssid() {
+        let scan = "\
+BSS 11:22:33:44:55:66(on phy0-ap0)
+\tSSID: Neighbour
+BSS aa:bb:cc:dd:ee:ff(on phy0-ap0)
+\tSSID: Guest
+BSS AA:BB:CC:DD:EE:FF(on phy0-ap0)
+\tSSID: Guest
+BSS 02:00:00:00:00:01(on phy0-ap0)
+\tSSID: 
+";
+        let radio1 = distinct_bssids(&parse_scan(scan));
+        assert_eq!(radio1.len(), 3);
+
+        let t0 = std::time::Instant::now();
+        let radio2: std::collections::BTreeSet<String> = [
+            "11:22:33:44:55:66".to_string(),
+            "66:55:44:33:22:11".to_string(),
+        ]
+        .into();
+        let scans = [(t0, radio1), (t0, radio2)];
+        assert_eq!(count_neighbors(&scans, t0), Some(4));
+
+        // No scans, or only stale ones: unavailable rather than zero
+        assert_eq!(count_neighbors(&[], t0), None);
+        assert_eq!(count_neighbors(&scans, t0 + NEIGHBOR_MAX_AGE), None);
+        assert_eq!(count_neighbors(&[(t0, Default::default())], t0), Some(0));
+    }
+
+    #[test]
+    fn txpower_range_validated() {
+        assert_eq!(txpower_to_uci("20"), Ok(Some("20".to_string())));
+        assert_eq!(txpower_to_uci(" 0 "), Ok(Some("0".to_string())));
+        assert_eq!(txpower_to_uci("-1"), Ok(None));
+        for bad in ["31", "-5", "100", "high", ""] {
+            let err = txpower_to_uci(bad).unwrap_err();
+            assert!(err.contains("0-30 dBm"), "{bad}: {err}");
+        }
+    }
+
+    #[test]
+    fn maxassoc_and_htmode_validated() {
+        assert_eq!(maxassoc_to_uci(" 64 "), Ok("64".to_string()));
+        assert_eq!(maxassoc_to_uci("2007"), Ok("2007".to_string()));
+        for bad in ["0", "2008", "-1", "many", ""] {
+            assert!(maxassoc_to_uci(bad).is_err(), "{bad}");
+        }
+        assert_eq!(htmode_to_uci("HE80"), Ok("HE80"));
+        assert_eq!(htmode_to_uci("HT40+"), Ok("HT40+"));
+        for bad in ["80MHz", "he80", "VHT320", ""] {
+            assert!(htmode_to_uci(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn radio_with_uplink_sta_not_disabled() {
+        let w = Wireless::parse(
+            "\
+wireless.radio0=wifi-device
+wireless.radio1=wifi-device
+wireless.ap0=wifi-iface
+wireless.ap0.device='radio0'
+wireless.ap0.mode='ap'
+wireless.ap0.network='lan'
+wireless.wwan=wifi-iface
+wireless.wwan.device='radio1'
+wireless.wwan.mode='sta'
+wireless.wwan.network='wwan'
+wireless.ap1=wifi-iface
+wireless.ap1.device='radio1'
+wireless.ap1.mode='ap'
+wireless.ap1.network='lan'
+",
+        );
+        let routed = |net: &str| net == "wwan";
+        assert!(uplink_ifaces(&w, "radio0", routed).is_empty());
+        assert_eq!(uplink_ifaces(&w, "radio1", routed), vec!["wwan"]);
+        // An AP bridged into the routed network is not the uplink
+        assert!(uplink_ifaces(&w, "radio1", |net: &str| net == "lan").is_empty());
+    }
+
+    #[test]
+    fn parse_iw_info_channel() {
+        let out = "Interface phy1-ap0\n\tifindex 12\n\taddr 02:11:22:33:44:55\n\
+                   \tchannel 36 (5180 MHz), width: 80 MHz, center1: 5210 MHz\n";
+        assert_eq!(parse_iw_channel(out), "36");
+        assert_eq!(parse_iw_channel("Interface wlan0\n"), "");
+    }
+}
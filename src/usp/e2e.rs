@@ -0,0 +1,370 @@
+//! End-to-end integrity for USP Records (`e2e_security`).
+//!
+//! For deployments where the MQTT broker is not trusted, TLS to the broker
+//! does not stop the broker itself from reading, forging or altering USP
+//! traffic.  With `e2e_security` enabled every Record we build is signed
+//! with the device's own key, and every Record we receive must carry a valid
+//! signature from a certificate issued by our CA *for the sending endpoint*.
+//! The CA issues every AP's certificate too, so chaining alone would let any
+//! one device speak for the controller.
+//!
+//! Records stay `payload_security = PLAINTEXT` — this provides integrity and
+//! origin authentication, not confidentiality (TR-369's TLS-based E2E
+//! session encryption is not implemented):
+//!
+//! - `sender_cert`: DER of the certificate the MTP presents (the provisioned
+//!   identity, or the init certificate before provisioning).
+//! - `mac_signature`: a TLS 1.3 `DigitallySignedStruct` — 2-byte
+//!   SignatureScheme, 2-byte length, signature — made with the matching
+//!   private key over the protobuf encoding of the whole Record with
+//!   `mac_signature` and `sender_cert` left empty.  Addressing, payload and
+//!   the session fields (session_id, sequence_id, expected_id,
+//!   retransmit_id) are all covered, so records cannot be re-sequenced.
+//! - The scheme follows the key type: Ed25519, ECDSA P-256/SHA-256,
+//!   ECDSA P-384/SHA-384 or RSA-PSS/SHA-256, via the rustls crypto provider.
+//!   No KDF is involved: nothing is encrypted.
+//!
+//! A received `sender_cert` must chain to `ca_file` or one of the
+//! `extra_ca_files` (extended key usage is not checked, as for the TLS
+//! peer) and name the record's `from_id`: as a subjectAltName URI
+//! `urn:bbf:usp:id:<from_id>` (TR-369), or as the subject CN.
+
+use std::fs;
+use std::io::Cursor;
+use std::sync::{Arc, RwLock};
+
+use log::{debug, info};
+use prost::Message;
+use rustls::client::danger::ServerCertVerifier;
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::sign::SigningKey;
+use rustls::{CertificateError, Error as TlsError, SignatureScheme};
+use x509_parser::extensions::GeneralName;
+
+use super::usp_record::Record;
+use crate::config::ClientConfig;
+use crate::error::{AcError, Result};
+
+/// Schemes we sign with, in preference order (the key picks the one it can do).
+const SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::ED25519,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::RSA_PSS_SHA256,
+];
+
+struct E2e {
+    key: Arc<dyn SigningKey>,
+    cert: CertificateDer<'static>,
+    roots: Arc<WebPkiServerVerifier>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+/// `None` while `e2e_security` is off.
+static STATE: RwLock<Option<Arc<E2e>>> = RwLock::new(None);
+
+/// (Re)load the signing identity and CA roots.  Called whenever an MTP
+/// connects, so a freshly provisioned identity is picked up.
+pub fn configure(cfg: &ClientConfig) -> Result<()> {
+    if !cfg.e2e_security {
+        *STATE.write().unwrap() = None;
+        return Ok(());
+    }
+    let provider = CryptoProvider::get_default()
+        .cloned()
+        .ok_or_else(|| AcError::Config("no TLS crypto provider installed".into()))?;
+
+    let (cert_file, key_file) = crate::tls::client_identity(cfg);
+    let cert = rustls_pemfile::certs(&mut Cursor::new(fs::read(cert_file)?))
+        .next()
+        .ok_or_else(|| AcError::Config(format!("no certificate in {}", cert_file.display())))??;
+    let key_der = rustls_pemfile::private_key(&mut Cursor::new(fs::read(key_file)?))?
+        .ok_or_else(|| AcError::Config(format!("no private key in {}", key_file.display())))?;
+    let key = provider
+        .key_provider
+        .load_private_key(key_der)
+        .map_err(AcError::Tls)?;
+
+    let root_store = crate::tls::root_store(cfg)?;
+    let roots =
+        WebPkiServerVerifier::builder_with_provider(Arc::new(root_store), Arc::clone(&provider))
+            .build()
+            .map_err(|e| AcError::Verifier(e.to_string()))?;
+
+    debug!("E2E: signing records as {}", cert_file.display());
+    *STATE.write().unwrap() = Some(Arc::new(E2e {
+        key,
+        cert,
+        roots,
+        algorithms: provider.signature_verification_algorithms,
+    }));
+    info!("E2E record signing enabled");
+    Ok(())
+}
+
+/// subjectAltName URI prefix TR-369 uses to name a USP endpoint.
+const USP_ID_URI: &str = "urn:bbf:usp:id:";
+
+/// Bytes covered by `mac_signature`: the Record without its signature.
+fn signed_bytes(record: &Record) -> Vec<u8> {
+    let mut unsigned = record.clone();
+    unsigned.mac_signature.clear();
+    unsigned.sender_cert.clear();
+    unsigned.encode_to_vec()
+}
+
+/// Sign `record` in place when `e2e_security` is on; otherwise a no-op.
+pub fn sign(record: &mut Record) {
+    let state = match STATE.read().unwrap().clone() {
+        Some(s) => s,
+        None => return,
+    };
+    if let Err(e) = sign_with(&state, record) {
+        // Sent unsigned; the controller will reject it and we log why
+        log::error!("E2E: cannot sign record: {e}");
+    }
+}
+
+fn sign_with(state: &E2e, record: &mut Record) -> std::result::Result<(), TlsError> {
+    let signer = state
+        .key
+        .choose_scheme(SCHEMES)
+        .ok_or_else(|| TlsError::General("device key supports none of our schemes".into()))?;
+    let sig = signer.sign(&signed_bytes(record))?;
+    let mut mac = Vec::with_capacity(sig.len() + 4);
+    mac.extend_from_slice(&u16::from(signer.scheme()).to_be_bytes());
+    mac.extend_from_slice(&(sig.len() as u16).to_be_bytes());
+    mac.extend_from_slice(&sig);
+    record.mac_signature = mac;
+    record.sender_cert = state.cert.to_vec();
+    Ok(())
+}
+
+/// Check a received record.  Always `Ok` while `e2e_security` is off.
+pub fn verify(record: &Record) -> std::result::Result<(), String> {
+    match STATE.read().unwrap().clone() {
+        Some(state) => verify_with(&state, record),
+        None => Ok(()),
+    }
+}
+
+fn verify_with(state: &E2e, record: &Record) -> std::result::Result<(), String> {
+    if record.mac_signature.is_empty() || record.sender_cert.is_empty() {
+        return Err("record is not signed".into());
+    }
+    let cert = CertificateDer::from(record.sender_cert.as_slice());
+    check_chain(state, &cert)?;
+    check_signed(state, record, &cert)
+}
+
+/// `cert` names the sender and its key made `mac_signature`.
+fn check_signed(
+    state: &E2e,
+    record: &Record,
+    cert: &CertificateDer<'_>,
+) -> std::result::Result<(), String> {
+    check_identity(cert, &record.from_id)?;
+    check_signature(state, cert, &signed_bytes(record), &record.mac_signature)
+}
+
+/// Split `mac_signature` into its SignatureScheme and signature.
+fn parse_mac(mac: &[u8]) -> Option<(SignatureScheme, &[u8])> {
+    let scheme = u16::from_be_bytes([*mac.first()?, *mac.get(1)?]);
+    let len = u16::from_be_bytes([*mac.get(2)?, *mac.get(3)?]) as usize;
+    let sig = mac.get(4..)?;
+    (sig.len() == len).then_some((SignatureScheme::from(scheme), sig))
+}
+
+fn check_signature(
+    state: &E2e,
+    cert: &CertificateDer<'_>,
+    msg: &[u8],
+    mac: &[u8],
+) -> std::result::Result<(), String> {
+    let (scheme, sig) = parse_mac(mac).ok_or("malformed mac_signature")?;
+    if !SCHEMES.contains(&scheme) {
+        return Err(format!("mac_signature uses unsupported scheme {scheme:?}"));
+    }
+    let algs = state
+        .algorithms
+        .mapping
+        .iter()
+        .find(|(s, _)| *s == scheme)
+        .map(|(_, algs)| *algs)
+        .ok_or_else(|| format!("no verifier for {scheme:?}"))?;
+    let ee = webpki::EndEntityCert::try_from(cert)
+        .map_err(|e| format!("unparsable sender_cert: {e}"))?;
+    if algs
+        .iter()
+        .any(|alg| ee.verify_signature(*alg, msg, sig).is_ok())
+    {
+        Ok(())
+    } else {
+        Err("bad mac_signature".into())
+    }
+}
+
+/// `sender_cert` must be issued to `from_id`.
+fn check_identity(cert: &CertificateDer<'_>, from_id: &str) -> std::result::Result<(), String> {
+    let (_, x509) = x509_parser::parse_x509_certificate(cert)
+        .map_err(|e| format!("unparsable sender_cert: {e}"))?;
+    let uris: Vec<&str> = match x509.subject_alternative_name() {
+        Ok(Some(san)) => san
+            .value
+            .general_names
+            .iter()
+            .filter_map(|n| match n {
+                GeneralName::URI(uri) => Some(*uri),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    let cn = x509
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok());
+    if names_endpoint(&uris, cn, from_id) {
+        Ok(())
+    } else {
+        Err(format!("sender_cert is not issued to {from_id}"))
+    }
+}
+
+/// Whether a certificate's SAN URIs or CN name the endpoint `from_id`.
+fn names_endpoint(uris: &[&str], cn: Option<&str>, from_id: &str) -> bool {
+    !from_id.is_empty()
+        && (uris
+            .iter()
+            .any(|uri| uri.strip_prefix(USP_ID_URI) == Some(from_id))
+            || cn == Some(from_id))
+}
+
+/// `sender_cert` must be issued by our CA (name and EKU are not checked).
+fn check_chain(state: &E2e, cert: &CertificateDer<'_>) -> std::result::Result<(), String> {
+    let name = ServerName::try_from("usp-endpoint").expect("static name");
+    match state
+        .roots
+        .verify_server_cert(cert, &[], &name, &[], UnixTime::now())
+    {
+        Ok(_)
+        | Err(TlsError::InvalidCertificate(
+            CertificateError::NotValidForName
+            | CertificateError::NotValidForNameContext { .. }
+            | CertificateError::InvalidPurpose
+            | CertificateError::InvalidPurposeContext { .. },
+        )) => Ok(()),
+        Err(e) => Err(format!("sender_cert rejected: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usp::record::{no_session_record, session_record};
+    use crate::usp::usp_record::record::RecordType;
+    use std::path::PathBuf;
+
+    fn init_file(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("package/ac-client/files/init")
+            .join(name)
+    }
+
+    #[test]
+    fn signed_record_verifies_and_tampering_is_detected() {
+        let _ = CryptoProvider::install_default(rustls::crypto::aws_lc_rs::default_provider());
+        let provider = CryptoProvider::get_default().unwrap();
+        let cert =
+            rustls_pemfile::certs(&mut Cursor::new(fs::read(init_file("client.crt")).unwrap()))
+                .next()
+                .unwrap()
+                .unwrap();
+        let key_der = rustls_pemfile::private_key(&mut Cursor::new(
+            fs::read(init_file("client.key")).unwrap(),
+        ))
+        .unwrap()
+        .unwrap();
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert.clone()).unwrap();
+        let state = E2e {
+            key: provider.key_provider.load_private_key(key_der).unwrap(),
+            cert,
+            roots: WebPkiServerVerifier::builder_with_provider(
+                Arc::new(roots),
+                Arc::clone(provider),
+            )
+            .build()
+            .unwrap(),
+            algorithms: provider.signature_verification_algorithms,
+        };
+
+        // The init certificate is not issued by the init CA, so the chain
+        // is not checked here.  Its CN is its (all-zero) MAC.
+        let check = |rec: &Record| {
+            check_signed(
+                &state,
+                rec,
+                &CertificateDer::from(rec.sender_cert.as_slice()),
+            )
+        };
+        let me = "00:00:00:00:00:00";
+        let mut rec = session_record(me, "os::ctrl", 7, 3, 2, b"msg".to_vec(), "1.3");
+        sign_with(&state, &mut rec).unwrap();
+        assert_eq!(
+            &rec.mac_signature[..2],
+            &u16::from(SignatureScheme::ED25519).to_be_bytes()
+        );
+        check(&rec).unwrap();
+
+        // A broker rewriting the payload, the addressing or the session
+        // fields breaks the signature
+        let mut forged = rec.clone();
+        forged.record_type =
+            session_record(me, "os::ctrl", 7, 3, 2, b"evil".to_vec(), "1.3").record_type;
+        assert!(check(&forged).unwrap_err().contains("bad"));
+        let mut forged = rec.clone();
+        forged.to_id = "os::other".into();
+        assert!(check(&forged).unwrap_err().contains("bad"));
+        let mut forged = rec.clone();
+        if let Some(RecordType::SessionContext(ctx)) = forged.record_type.as_mut() {
+            ctx.sequence_id = 9;
+        }
+        assert!(check(&forged).unwrap_err().contains("bad"));
+
+        // A valid certificate from our CA cannot speak for another endpoint
+        let mut rec = no_session_record("os::ctrl", "os::agent", b"set".to_vec(), "1.3");
+        sign_with(&state, &mut rec).unwrap();
+        assert!(check(&rec).unwrap_err().contains("not issued to os::ctrl"));
+
+        // Unsigned records are refused outright
+        let unsigned = no_session_record("os::ctrl", "os::agent", b"msg".to_vec(), "1.3");
+        assert!(verify_with(&state, &unsigned)
+            .unwrap_err()
+            .contains("not signed"));
+    }
+
+    #[test]
+    fn sender_cert_must_name_the_endpoint() {
+        let uri = "urn:bbf:usp:id:proto::controller-1";
+        assert!(names_endpoint(&[uri], None, "proto::controller-1"));
+        assert!(names_endpoint(&[], Some("os::agent"), "os::agent"));
+        assert!(!names_endpoint(
+            &[uri],
+            Some("ap-42"),
+            "proto::controller-2"
+        ));
+        assert!(!names_endpoint(
+            &["proto::controller-1"],
+            None,
+            "proto::controller-1"
+        ));
+        assert!(!names_endpoint(&[], Some(""), ""));
+
+        assert!(parse_mac(&[0x08, 0x07, 0, 2, 1, 2]).is_some());
+        assert!(parse_mac(&[0x08, 0x07, 0, 3, 1, 2]).is_none());
+    }
+}
@@ -43,6 +43,25 @@ pub fn build_boot_notify(
     subscription_id: &str,
     send_resp: bool,
     parameter_map: std::collections::HashMap<String, String>,
+) -> Msg {
+    build_event_notify(
+        subscription_id,
+        send_resp,
+        "Device.",
+        "Boot!",
+        parameter_map,
+    )
+}
+
+// ── Builder: NOTIFY Event ────────────────────────────────────────────────────
+
+/// Build an Event Notify (`event_name` on `obj_path`, e.g. `Boot!` on `Device.`).
+pub fn build_event_notify(
+    subscription_id: &str,
+    send_resp: bool,
+    obj_path: &str,
+    event_name: &str,
+    params: std::collections::HashMap<String, String>,
 ) -> Msg {
     Msg {
         header: Some(make_header(MessageType::Notify)),
@@ -53,10 +72,10 @@ pub fn build_boot_notify(
                         subscription_id: subscription_id.into(),
                         send_resp,
                         notification: Some(notify::Notification::Event(notify::Event {
-                            obj_path: "Device.".into(),
-                            event_name: "Boot!".into(),
+                            obj_path: obj_path.into(),
+                            event_name: event_name.into(),
                             command_key: String::new(),
-                            params: parameter_map,
+                            params,
                         })),
                     },
                 )),
@@ -65,11 +84,58 @@ pub fn build_boot_notify(
     }
 }
 
+// ── Builder: NOTIFY OperationComplete ────────────────────────────────────────
+
+/// Build an OperationComplete Notify for an asynchronous OPERATE of
+/// `command` (e.g. `Device.X_OptimACS_Firmware.Download()`): its output
+/// arguments, or the USP error code and message it failed with.
+pub fn build_operation_complete_notify(
+    subscription_id: &str,
+    send_resp: bool,
+    command: &str,
+    command_key: &str,
+    result: std::result::Result<std::collections::HashMap<String, String>, (u32, String)>,
+) -> Msg {
+    use notify::oper_complete::{CommandFailure, OperateRespType, OutputArgs};
+    let (obj_path, command_name) = match command.rfind('.') {
+        Some(i) => command.split_at(i + 1),
+        None => ("", command),
+    };
+    let operate_resp_type = match result {
+        Ok(output_args) => OperateRespType::ReqOutputArgs(OutputArgs { output_args }),
+        Err((err_code, err_msg)) => {
+            OperateRespType::CmdFailure(CommandFailure { err_code, err_msg })
+        }
+    };
+    Msg {
+        header: Some(make_header(MessageType::Notify)),
+        body: Some(Body {
+            msg_body: Some(MsgBody::Request(super::usp_msg::Request {
+                req_type: Some(super::usp_msg::request::ReqType::Notify(
+                    super::usp_msg::Notify {
+                        subscription_id: subscription_id.into(),
+                        send_resp,
+                        notification: Some(notify::Notification::OperComplete(
+                            notify::OperComplete {
+                                obj_path: obj_path.into(),
+                                command_name: command_name.into(),
+                                command_key: command_key.into(),
+                                operate_resp_type: Some(operate_resp_type),
+                            },
+                        )),
+                    },
+                )),
+            })),
+        }),
+    }
+}
+
 // ── Builder: NOTIFY ValueChange ──────────────────────────────────────────────
 
-/// Build a ValueChange Notify message for periodic status heartbeats.
+/// Build a ValueChange Notify for a status heartbeat or subscribed parameter.
 pub fn build_value_change_notify(
     subscription_id: &str,
+    send_resp: bool,
     param_path: &str,
     param_value: &str,
 ) -> Msg {
@@ -80,7 +146,7 @@ pub fn build_value_change_notify(
                 req_type: Some(super::usp_msg::request::ReqType::Notify(
                     super::usp_msg::Notify {
                         subscription_id: subscription_id.into(),
-                        send_resp: false,
+                        send_resp,
                         notification: Some(notify::Notification::ValueChange(
                             notify::ValueChange {
                                 param_path: param_path.into(),
@@ -94,6 +160,27 @@ pub fn build_value_change_notify(
     }
 }
 
+// ── Builder: NOTIFY_RESP ─────────────────────────────────────────────────────
+
+/// Acknowledge a Notify that was sent with `send_resp`.
+pub fn build_notify_resp(msg_id: &str, subscription_id: &str) -> Msg {
+    Msg {
+        header: Some(Header {
+            msg_id: msg_id.into(),
+            msg_type: MessageType::NotifyResp as i32,
+        }),
+        body: Some(Body {
+            msg_body: Some(MsgBody::Response(super::usp_msg::Response {
+                resp_type: Some(super::usp_msg::response::RespType::NotifyResp(
+                    super::usp_msg::NotifyResp {
+                        subscription_id: subscription_id.into(),
+                    },
+                )),
+            })),
+        }),
+    }
+}
+
 // ── Builder: GET_SUPPORTED_PROTO ─────────────────────────────────────────────
 
 pub fn build_get_supported_proto() -> Msg {
@@ -200,3 +287,35 @@ pub fn build_error(msg_id: &str, err_code: u32, err_msg: &str) -> Msg {
         }),
     }
 }
+
+/// Build an ERROR whose `param_errs` names the parameter that failed.
+pub fn build_param_error(msg_id: &str, err_code: u32, err_msg: &str, param_path: &str) -> Msg {
+    build_param_errors(
+        msg_id,
+        err_code,
+        err_msg,
+        &[(param_path.to_string(), err_code, err_msg.to_string())],
+    )
+}
+
+/// Build an ERROR listing each failed parameter as `(param_path, err_code,
+/// err_msg)` in `param_errs`.
+pub fn build_param_errors(
+    msg_id: &str,
+    err_code: u32,
+    err_msg: &str,
+    param_errs: &[(String, u32, String)],
+) -> Msg {
+    let mut msg = build_error(msg_id, err_code, err_msg);
+    if let Some(MsgBody::Error(e)) = msg.body.as_mut().and_then(|b| b.msg_body.as_mut()) {
+        e.param_errs = param_errs
+            .iter()
+            .map(|(path, code, msg)| super::usp_msg::error::ParamError {
+                param_path: path.clone(),
+                err_code: *code,
+                err_msg: msg.clone(),
+            })
+            .collect();
+    }
+    msg
+}
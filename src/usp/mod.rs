@@ -3,10 +3,14 @@
 
 pub mod agent;
 pub mod dm;
+pub mod e2e;
 pub mod endpoint;
 pub mod message;
 pub mod mtp;
+pub mod offline_buffer;
 pub mod record;
+pub mod session;
+pub mod shutdown;
 pub mod tp469;
 
 use thiserror::Error;
@@ -1,2 +1,80 @@
 pub mod mqtt;
+pub mod rehome;
+pub mod state;
 pub mod websocket;
+
+pub use state::MtpState;
+
+use std::time::Duration;
+
+use rand::Rng;
+
+// ── Reconnect backoff ─────────────────────────────────────────────────────────
+
+const BACKOFF_BASE: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(300);
+/// A connection that stayed up this long resets the backoff.
+const BACKOFF_STABLE: Duration = Duration::from_secs(60);
+
+/// Delay between MTP reconnect attempts: doubles from 1s up to 300s, with
+/// random jitter so a fleet that lost its controller together does not
+/// reconnect in lockstep.
+#[derive(Debug)]
+pub struct Backoff {
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Self { next: BACKOFF_BASE }
+    }
+
+    /// Delay before the next attempt.  `up_for` is how long the previous
+    /// connection stayed up (zero if it never connected).
+    pub fn next_delay(&mut self, up_for: Duration) -> Duration {
+        let nominal = self.advance(up_for);
+        // Equal jitter: somewhere in [nominal/2, nominal]
+        nominal.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    /// Un-jittered delay for this attempt; doubles the one after it.
+    fn advance(&mut self, up_for: Duration) -> Duration {
+        if up_for >= BACKOFF_STABLE {
+            self.next = BACKOFF_BASE;
+        }
+        let nominal = self.next;
+        self.next = (self.next * 2).min(BACKOFF_MAX);
+        nominal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_caps_and_resets() {
+        let mut b = Backoff::new();
+        let secs: Vec<u64> = (0..11)
+            .map(|_| b.advance(Duration::ZERO).as_secs())
+            .collect();
+        assert_eq!(secs, [1, 2, 4, 8, 16, 32, 64, 128, 256, 300, 300]);
+
+        // A short-lived connection keeps backing off ...
+        assert_eq!(b.advance(Duration::from_secs(10)).as_secs(), 300);
+        // ... one that stayed up resets it
+        assert_eq!(b.advance(BACKOFF_STABLE).as_secs(), 1);
+        assert_eq!(b.advance(Duration::ZERO).as_secs(), 2);
+
+        let mut b = Backoff::new();
+        for _ in 0..5 {
+            b.advance(Duration::ZERO);
+        }
+        // nominal 32s: jitter keeps it within [16s, 32s]
+        let d = b.next_delay(Duration::ZERO);
+        assert!(
+            (Duration::from_secs(16)..=Duration::from_secs(32)).contains(&d),
+            "{d:?}"
+        );
+    }
+}
@@ -4,19 +4,38 @@ use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use log::{debug, error, info, trace, warn};
-use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use rumqttc::{
+    v5, AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, QoS, TlsConfiguration,
+    Transport,
+};
 
 use super::super::{
+    e2e,
     endpoint::EndpointId,
+    mtp::state::{self_test_interval, self_test_step, MtpState, SelfTestStep},
+    mtp::Backoff,
+    offline_buffer::{NotifyClass, Outbox},
     record::{
-        decode_record, encode_record, extract_msg_payload, mqtt_connect_record, no_session_record,
+        decode_record, disconnect_record, encode_record, extract_msg_payload, mqtt_connect_record,
+        no_session_record,
     },
+    session::{self, Sessions},
+    shutdown,
+    usp_record::{mqtt_connect_record::MqttVersion as RecordMqttVersion, record::RecordType},
 };
-use crate::config::ClientConfig;
-use tokio::sync::mpsc::Receiver;
+use crate::config::{ClientConfig, MqttVersion};
 
-const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+const MQTT_PORT: u16 = 1883;
+const MQTTS_PORT: u16 = 8883;
 const MAX_PACKET_SIZE: usize = 4 * 1024 * 1024;
+/// How long to keep driving the connection after a shutdown so the
+/// DisconnectRecord and MQTT DISCONNECT reach the broker.
+const SHUTDOWN_FLUSH: Duration = Duration::from_secs(2);
+/// DisconnectRecord reason_code for the Last Will: the agent vanished
+/// without closing (7003, Internal error).
+const LWT_REASON_CODE: u32 = 7003;
+/// TR-369 §10.3 Content Type for USP records carried over MQTT 5.
+const USP_CONTENT_TYPE: &str = "application/vnd.bbf.usp.msg";
 
 fn sanitise_topic(s: &str) -> String {
     s.replace(':', "%3A")
@@ -24,16 +43,236 @@ fn sanitise_topic(s: &str) -> String {
         .replace('+', "%2B")
 }
 
+/// Split a broker URL into `(host, port, tls)`.
+///
+/// `mqtts://` selects TLS and defaults to port 8883; `mqtt://` (or no scheme)
+/// is plaintext on 1883.  An unparsable port falls back to the default.
+/// IPv6 literals are bracketed (`mqtts://[fd00::1]:8883`); the host is
+/// returned without the brackets.
+fn parse_broker_url(mqtt_url: &str) -> (String, u16, bool) {
+    let (rest, tls) = match mqtt_url.strip_prefix("mqtts://") {
+        Some(rest) => (rest, true),
+        None => (mqtt_url.trim_start_matches("mqtt://"), false),
+    };
+    let default_port = if tls { MQTTS_PORT } else { MQTT_PORT };
+    let rest = rest.trim_end_matches('/');
+    let (host, port) = match rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+        Some((h, after)) => (h, after.strip_prefix(':')),
+        None => match rest.split_once(':') {
+            Some((h, p)) => (h, Some(p)),
+            None => (rest, None),
+        },
+    };
+    let port = port
+        .and_then(|p| p.parse::<u16>().ok())
+        .unwrap_or(default_port);
+    (host.to_string(), port, tls)
+}
+
+// ── MQTT 3.1.1 / 5 shim ──────────────────────────────────────────────────────
+
+/// Publishing half of the broker connection, for either protocol version.
+#[derive(Clone)]
+enum MqttClient {
+    V3(AsyncClient),
+    V5(v5::AsyncClient),
+}
+
+impl MqttClient {
+    async fn subscribe(&self, topic: &str) -> anyhow::Result<()> {
+        match self {
+            MqttClient::V3(c) => c.subscribe(topic, QoS::AtLeastOnce).await?,
+            MqttClient::V5(c) => c.subscribe(topic, v5::mqttbytes::QoS::AtLeastOnce).await?,
+        }
+        Ok(())
+    }
+
+    /// Publish a USP record.  On MQTT 5 it carries the USP Content Type and
+    /// `reply_to` (our agent topic) as the Response Topic.
+    async fn publish(&self, topic: &str, record: Vec<u8>, reply_to: &str) -> anyhow::Result<()> {
+        match self {
+            MqttClient::V3(c) => c.publish(topic, QoS::AtLeastOnce, false, record).await?,
+            MqttClient::V5(c) => {
+                let props = v5::mqttbytes::v5::PublishProperties {
+                    response_topic: Some(reply_to.to_string()),
+                    content_type: Some(USP_CONTENT_TYPE.to_string()),
+                    ..Default::default()
+                };
+                c.publish_with_properties(
+                    topic,
+                    v5::mqttbytes::QoS::AtLeastOnce,
+                    false,
+                    record,
+                    props,
+                )
+                .await?
+            }
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> anyhow::Result<()> {
+        match self {
+            MqttClient::V3(c) => c.disconnect().await?,
+            MqttClient::V5(c) => c.disconnect().await?,
+        }
+        Ok(())
+    }
+}
+
+/// Event-loop half of the broker connection.
+enum MqttEvents {
+    V3(Box<EventLoop>),
+    V5(Box<v5::EventLoop>),
+}
+
+/// An inbound PUBLISH, reduced to what the USP layer needs.
+struct InboundPublish {
+    topic: String,
+    payload: Vec<u8>,
+    /// MQTT 5 Response Topic set by the controller, if any.
+    response_topic: Option<String>,
+}
+
+impl MqttEvents {
+    /// Drive the connection; yields `Some` for each inbound PUBLISH.
+    async fn poll(&mut self) -> anyhow::Result<Option<InboundPublish>> {
+        match self {
+            MqttEvents::V3(ev) => {
+                let event = ev.poll().await?;
+                trace!("MQTT event received: {:?}", event);
+                Ok(match event {
+                    Event::Incoming(Packet::Publish(p)) => Some(InboundPublish {
+                        topic: p.topic,
+                        payload: p.payload.to_vec(),
+                        response_topic: None,
+                    }),
+                    _ => None,
+                })
+            }
+            MqttEvents::V5(ev) => {
+                let event = ev.poll().await?;
+                trace!("MQTT event received: {:?}", event);
+                Ok(match event {
+                    v5::Event::Incoming(v5::Incoming::Publish(p)) => Some(InboundPublish {
+                        topic: String::from_utf8_lossy(&p.topic).into_owned(),
+                        payload: p.payload.to_vec(),
+                        response_topic: p
+                            .properties
+                            .and_then(|props| props.response_topic)
+                            .filter(|t| !t.is_empty()),
+                    }),
+                    _ => None,
+                })
+            }
+        }
+    }
+}
+
+/// Re-frame a NoSessionContext record built elsewhere (outbox, self-test)
+/// into the session with its recipient: always with `E2ESession.Enable`,
+/// otherwise only once the controller has opened one.  Records without a
+/// Msg payload (connect, disconnect) are passed through unchanged.
+fn into_session(
+    sessions: &mut Sessions,
+    record: &[u8],
+    class: Option<NotifyClass>,
+) -> anyhow::Result<Vec<u8>> {
+    let rec = decode_record(record)?;
+    let session = if session::enabled() {
+        Some(sessions.get(&rec.to_id).0)
+    } else {
+        sessions.active(&rec.to_id)
+    };
+    match (session, extract_msg_payload(&rec)) {
+        (Some(session), Some(payload)) => {
+            let wrapped = session.wrap(
+                &rec.from_id,
+                &rec.to_id,
+                payload.to_vec(),
+                class,
+                &rec.version,
+            );
+            Ok(encode_record(&wrapped)?)
+        }
+        _ => Ok(record.to_vec()),
+    }
+}
+
+/// Create the client for the configured protocol version.  `will` is
+/// registered as the Last Will on `will_topic`, so the broker tells the
+/// controller when we drop off without disconnecting (e.g. power loss).
+fn connect(
+    cfg: &ClientConfig,
+    client_id: &str,
+    host: &str,
+    port: u16,
+    transport: Option<Transport>,
+    will_topic: &str,
+    will: Vec<u8>,
+) -> (MqttClient, MqttEvents) {
+    let keep_alive = Duration::from_secs(60);
+    match cfg.mqtt_version {
+        MqttVersion::V311 => {
+            let mut opts = MqttOptions::new(client_id, host, port);
+            opts.set_keep_alive(keep_alive);
+            opts.set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
+            if let Some(t) = transport {
+                opts.set_transport(t);
+            }
+            if !cfg.mqtt_user.is_empty() {
+                opts.set_credentials(&cfg.mqtt_user, &cfg.mqtt_pass);
+            }
+            opts.set_last_will(LastWill::new(will_topic, will, QoS::AtLeastOnce, false));
+            let (client, event_loop) = AsyncClient::new(opts, 128);
+            (MqttClient::V3(client), MqttEvents::V3(Box::new(event_loop)))
+        }
+        MqttVersion::V5 => {
+            let mut opts = v5::MqttOptions::new(client_id, host, port);
+            opts.set_keep_alive(keep_alive);
+            opts.set_max_packet_size(Some(MAX_PACKET_SIZE as u32));
+            if let Some(t) = transport {
+                opts.set_transport(t);
+            }
+            if !cfg.mqtt_user.is_empty() {
+                opts.set_credentials(&cfg.mqtt_user, &cfg.mqtt_pass);
+            }
+            let props = v5::mqttbytes::v5::LastWillProperties {
+                delay_interval: None,
+                payload_format_indicator: None,
+                message_expiry_interval: None,
+                content_type: Some(USP_CONTENT_TYPE.to_string()),
+                response_topic: None,
+                correlation_data: None,
+                user_properties: Vec::new(),
+            };
+            opts.set_last_will(v5::mqttbytes::v5::LastWill::new(
+                will_topic,
+                will,
+                v5::mqttbytes::QoS::AtLeastOnce,
+                false,
+                Some(props),
+            ));
+            let (client, event_loop) = v5::AsyncClient::new(opts, 128);
+            (MqttClient::V5(client), MqttEvents::V5(Box::new(event_loop)))
+        }
+    }
+}
+
 pub async fn run(
     cfg: Arc<ClientConfig>,
     agent_id: EndpointId,
-    status_rx: Arc<Mutex<Receiver<Vec<u8>>>>,
+    outbox: Arc<Outbox>,
+    negotiated_ver: Arc<Mutex<String>>,
 ) {
     debug!(
         "Starting MQTT MTP run loop for agent: {}",
         agent_id.as_str()
     );
-    let negotiated_ver: Arc<Mutex<String>> = Arc::new(Mutex::new("1.3".into()));
+    let mtp_state = Arc::new(Mutex::new(MtpState::default()));
+    let mut backoff = Backoff::new();
+    // Kept across reconnects so lost records can still be retransmitted
+    let mut sessions = Sessions::default();
 
     loop {
         let mqtt_url = match &cfg.mqtt_url {
@@ -55,7 +294,9 @@ pub async fn run(
             agent_id.clone(),
             &mqtt_url,
             Arc::clone(&negotiated_ver),
-            Arc::clone(&status_rx),
+            Arc::clone(&mtp_state),
+            Arc::clone(&outbox),
+            &mut sessions,
         )
         .await
         {
@@ -67,12 +308,23 @@ pub async fn run(
                 debug!("MQTT error details: {:?}", e);
             }
         }
+        if shutdown::requested() {
+            info!("USP MQTT: shut down");
+            return;
+        }
 
+        let delay = backoff.next_delay(mtp_state.lock().unwrap().take_uptime());
         warn!(
-            "MQTT: reconnecting in {} seconds...",
-            RECONNECT_DELAY.as_secs()
+            "MQTT: reconnecting in {:.1} seconds...",
+            delay.as_secs_f64()
         );
-        tokio::time::sleep(RECONNECT_DELAY).await;
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown::token().cancelled() => {
+                info!("USP MQTT: shut down");
+                return;
+            }
+        }
     }
 }
 
@@ -81,108 +333,200 @@ async fn mqtt_loop(
     agent_id: EndpointId,
     mqtt_url: &str,
     negotiated_ver: Arc<Mutex<String>>,
-    status_rx: Arc<Mutex<Receiver<Vec<u8>>>>,
+    mtp_state: Arc<Mutex<MtpState>>,
+    outbox: Arc<Outbox>,
+    sessions: &mut Sessions,
 ) -> anyhow::Result<()> {
     debug!("Parsing MQTT URL: {}", mqtt_url);
-    let url = mqtt_url
-        .trim_start_matches("mqtt://")
-        .trim_start_matches("mqtts://");
-    let (host, port) = if let Some((h, p)) = url.split_once(':') {
-        let port_num = p.parse::<u16>().unwrap_or(1883);
-        debug!("Parsed MQTT broker: {}: {}", h, port_num);
-        (h.to_string(), port_num)
-    } else {
-        debug!("Parsed MQTT broker: {}:1883 (default port)", url);
-        (url.to_string(), 1883)
-    };
+    let (host, port, tls) = parse_broker_url(mqtt_url);
+    debug!("Parsed MQTT broker: {}:{} (tls={})", host, port, tls);
+    // Before the identity is read (TLS, E2E signing) so no change is missed
+    let mut identity = crate::tls::watch_identity();
+    e2e::configure(&cfg)?;
 
     let client_id = sanitise_topic(agent_id.as_str());
     debug!("MQTT client ID: {}", client_id);
 
-    let mut opts = MqttOptions::new(&client_id, &host, port);
-    opts.set_keep_alive(Duration::from_secs(60));
-    opts.set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
+    let transport = if tls {
+        // Same mTLS setup (CA pinning, client identity, TLS 1.3) as the
+        // WebSocket MTP; the broker cert is checked against ca_file only.
+        let tls_config = crate::tls::build_tls_config(&cfg)?;
+        debug!("MQTT transport: TLS");
+        Some(Transport::tls_with_config(TlsConfiguration::Rustls(
+            tls_config,
+        )))
+    } else {
+        None
+    };
+    if cfg.mqtt_user.is_empty() {
+        info!("USP MQTT: no mqtt_user configured, connecting anonymously");
+    } else {
+        info!("USP MQTT: authenticating as '{}'", cfg.mqtt_user);
+    }
+
+    let controller_id = &cfg.controller_id;
+    let controller_topic = format!("usp/v1/controller/{}", sanitise_topic(controller_id));
+    debug!("Controller topic: {}", controller_topic);
+
+    let will = encode_record(&disconnect_record(
+        agent_id.as_str(),
+        controller_id,
+        "lost connection",
+        LWT_REASON_CODE,
+    ))?;
+    let (client, mut event_loop) = connect(
+        &cfg,
+        &client_id,
+        &host,
+        port,
+        transport,
+        &controller_topic,
+        will,
+    );
     debug!(
-        "MQTT options configured: keep_alive=60s, max_packet_size={}",
-        MAX_PACKET_SIZE
+        "MQTT client created: version={:?}, keep_alive=60s, max_packet_size={}",
+        cfg.mqtt_version, MAX_PACKET_SIZE
     );
 
-    let (client, mut event_loop) = AsyncClient::new(opts, 128);
-    debug!("MQTT client created");
-
     // Subscribe to our own agent topic
     let agent_topic = format!("usp/v1/agent/{}", sanitise_topic(agent_id.as_str()));
     debug!("Subscribing to agent topic: {}", agent_topic);
-    client.subscribe(&agent_topic, QoS::AtLeastOnce).await?;
+    client.subscribe(&agent_topic).await?;
     debug!("Successfully subscribed to {}", agent_topic);
 
     // Send MQTTConnectRecord to identify ourselves to the controller
-    let controller_id = &cfg.controller_id;
-    let controller_topic = format!("usp/v1/controller/{}", sanitise_topic(controller_id));
-    debug!("Controller topic: {}", controller_topic);
-
     debug!("Sending MQTTConnectRecord...");
-    let connect_rec = mqtt_connect_record(agent_id.as_str(), controller_id, &agent_topic);
+    let record_version = match cfg.mqtt_version {
+        MqttVersion::V311 => RecordMqttVersion::V311,
+        MqttVersion::V5 => RecordMqttVersion::V5,
+    };
+    let connect_rec = mqtt_connect_record(
+        agent_id.as_str(),
+        controller_id,
+        &agent_topic,
+        record_version,
+    );
     let connect_bytes = encode_record(&connect_rec)?;
     debug!("MQTTConnectRecord encoded ({} bytes)", connect_bytes.len());
     client
-        .publish(&controller_topic, QoS::AtLeastOnce, false, connect_bytes)
+        .publish(&controller_topic, connect_bytes, &agent_topic)
         .await?;
     debug!("MQTTConnectRecord published successfully");
 
+    // Further controllers learn the agent topic the same way
+    for extra in &cfg.extra_controllers {
+        let rec = mqtt_connect_record(agent_id.as_str(), extra, &agent_topic, record_version);
+        let topic = format!("usp/v1/controller/{}", sanitise_topic(extra));
+        debug!("Sending MQTTConnectRecord to {extra}");
+        client
+            .publish(&topic, encode_record(&rec)?, &agent_topic)
+            .await?;
+    }
+
+    // MQTT does not order records for us: with E2ESession.Enable, talk to
+    // the controller inside a SessionContext, opened with an empty handshake
+    // record on first contact.
+    if session::enabled() {
+        let (session, created) = sessions.get(controller_id);
+        if created {
+            let ver = negotiated_ver.lock().unwrap().clone();
+            let hello = session.wrap(agent_id.as_str(), controller_id, Vec::new(), None, &ver);
+            debug!("USP session {}: sending handshake", session.session_id());
+            client
+                .publish(&controller_topic, encode_record(&hello)?, &agent_topic)
+                .await?;
+        }
+    }
+
     info!("USP MQTT: connected; subscribed to {agent_topic}");
 
-    // Spawn status heartbeat sender task
-    let client2 = client.clone();
-    let status_controller_topic = controller_topic.clone();
-    tokio::spawn(async move {
-        debug!("Starting MQTT status heartbeat sender");
-        loop {
-            // Receive status message without holding lock across await
-            let status_msg = {
-                let mut rx = status_rx.lock().unwrap();
-                // Try_recv is not async, so we can use it without Send issues
-                rx.try_recv().ok()
-            };
+    let pending = outbox.len();
+    if pending > 0 {
+        info!("USP MQTT: flushing {pending} buffered notification(s)");
+    }
 
-            if let Some(record_bytes) = status_msg {
-                debug!(
-                    "Sending status heartbeat via MQTT ({} bytes)",
-                    record_bytes.len()
-                );
-                match client2
-                    .publish(
-                        &status_controller_topic,
-                        QoS::AtLeastOnce,
-                        false,
-                        record_bytes,
-                    )
+    mtp_state.lock().unwrap().on_connect();
+    let mut self_test = self_test_interval(cfg.self_test_interval);
+
+    debug!("Entering MQTT event loop...");
+    loop {
+        let event = tokio::select! {
+            event = event_loop.poll() => event?,
+            // SIGTERM/SIGINT: say goodbye instead of leaving the Last Will
+            _ = shutdown::token().cancelled() => {
+                info!("USP MQTT: shutting down, sending DisconnectRecord");
+                let bye = encode_record(&disconnect_record(
+                    agent_id.as_str(),
+                    controller_id,
+                    "shutdown",
+                    0,
+                ))?;
+                client.publish(&controller_topic, bye, &agent_topic).await?;
+                client.disconnect().await?;
+                // A clean MQTT DISCONNECT also tells the broker to discard the
+                // Last Will; drive the loop until the connection closes.
+                let _ = tokio::time::timeout(SHUTDOWN_FLUSH, async {
+                    while event_loop.poll().await.is_ok() {}
+                })
+                .await;
+                return Ok(());
+            }
+            // Provisioned cert installed (IssueCert): reconnect with it once
+            // the IssueCert response has gone out
+            _ = identity.changed() => {
+                info!("USP MQTT: device identity changed, reconnecting with provisioned certificate");
+                client.disconnect().await?;
+                let _ = tokio::time::timeout(SHUTDOWN_FLUSH, async {
+                    while event_loop.poll().await.is_ok() {}
+                })
+                .await;
+                return Ok(());
+            }
+            // Flush queued notifications (Boot!, ValueChange, status heartbeat)
+            // even when no inbound traffic is flowing.
+            item = outbox.next() => {
+                debug!("MQTT: publishing queued notification ({} bytes)", item.record.len());
+                let framed = into_session(sessions, &item.record, Some(item.class.clone()))?;
+                if let Err(e) = client
+                    .publish(&controller_topic, framed, &agent_topic)
                     .await
                 {
-                    Ok(()) => debug!("Status heartbeat sent via MQTT successfully"),
-                    Err(e) => warn!("Failed to send status heartbeat via MQTT: {e}"),
+                    warn!("MQTT: failed to publish queued notification: {e}");
+                    // Keep it for the next connection
+                    outbox.requeue(item);
+                    return Err(e);
                 }
-            } else {
-                // No message available, sleep briefly
-                tokio::time::sleep(Duration::from_millis(100)).await;
+                item.mark_sent();
+                continue;
             }
-        }
-    });
-
-    debug!("Entering MQTT event loop...");
-    loop {
-        let event = event_loop.poll().await?;
-        trace!("MQTT event received: {:?}", event);
+            // Application-level self-test: is the controller still answering?
+            _ = async { self_test.as_mut().unwrap().tick().await }, if self_test.is_some() => {
+                let ver = negotiated_ver.lock().unwrap().clone();
+                let step =
+                    self_test_step(&mtp_state, cfg.self_test_failures, &agent_id, controller_id, &ver)?;
+                match step {
+                    SelfTestStep::Probe(bytes) => {
+                        debug!("MQTT: sending self-test probe");
+                        let framed = into_session(sessions, &bytes, None)?;
+                        client.publish(&controller_topic, framed, &agent_topic).await?;
+                    }
+                    SelfTestStep::Reconnect => {
+                        warn!("MQTT: controller stopped answering self-tests, reconnecting");
+                        return Ok(());
+                    }
+                }
+                continue;
+            }
+        };
 
-        if let Event::Incoming(Packet::Publish(pub_msg)) = event {
+        if let Some(pub_msg) = event {
             let topic = &pub_msg.topic;
-            let payload = pub_msg.payload.to_vec();
+            let payload = pub_msg.payload;
 
             debug!(
-                "MQTT message received on topic '{}' ({} bytes, QoS={:?})",
+                "MQTT message received on topic '{}' ({} bytes)",
                 topic,
-                payload.len(),
-                pub_msg.qos
+                payload.len()
             );
             trace!(
                 "MQTT payload (first 64 bytes): {:?}",
@@ -219,54 +563,171 @@ async fn mqtt_loop(
                 );
                 continue;
             }
+            if let Err(e) = e2e::verify(&record) {
+                warn!("MQTT: {e}, discarding record from {}", record.from_id);
+                continue;
+            }
 
-            let msg_bytes = match extract_msg_payload(&record) {
-                Some(b) => {
-                    debug!("Extracted {} bytes USP message payload", b.len());
-                    b.to_vec()
-                }
-                None => {
-                    warn!("No USP message payload found in MQTT record");
-                    continue;
+            // MQTT 5: reply where the controller asked us to (§10.3).
+            let reply_topic = pub_msg
+                .response_topic
+                .as_deref()
+                .unwrap_or(&controller_topic);
+            let ver = negotiated_ver.lock().unwrap().clone();
+
+            // Msgs to hand to the agent, in order, and whether the replies
+            // belong in the controller's session
+            let (msgs, in_session) = match &record.record_type {
+                Some(RecordType::SessionContext(ctx)) => {
+                    let (session, _) = sessions.get(&record.from_id);
+                    let got = session.receive(ctx);
+                    for (class, payload) in got.requeue {
+                        match class {
+                            // Notifications go back through the outbox with
+                            // their own priority
+                            Some(class) => {
+                                let rec = no_session_record(
+                                    agent_id.as_str(),
+                                    &record.from_id,
+                                    payload,
+                                    &ver,
+                                );
+                                outbox.push(class, encode_record(&rec)?);
+                            }
+                            // Responses are resent in the new session at once
+                            None => {
+                                let rec = session.wrap(
+                                    agent_id.as_str(),
+                                    &record.from_id,
+                                    payload,
+                                    None,
+                                    &ver,
+                                );
+                                client
+                                    .publish(reply_topic, encode_record(&rec)?, &agent_topic)
+                                    .await?;
+                            }
+                        }
+                    }
+                    for (seq, payload) in got.resend {
+                        debug!("USP session: resending sequence_id {seq}");
+                        let rec =
+                            session.resend(agent_id.as_str(), &record.from_id, seq, payload, &ver);
+                        client
+                            .publish(reply_topic, encode_record(&rec)?, &agent_topic)
+                            .await?;
+                    }
+                    if let Some(from) = got.request_retransmit {
+                        let rec = session.retransmit_request(
+                            agent_id.as_str(),
+                            &record.from_id,
+                            from,
+                            &ver,
+                        );
+                        client
+                            .publish(reply_topic, encode_record(&rec)?, &agent_topic)
+                            .await?;
+                    }
+                    (got.deliver, true)
                 }
+                _ => match extract_msg_payload(&record) {
+                    Some(b) => {
+                        debug!("Extracted {} bytes USP message payload", b.len());
+                        (vec![b.to_vec()], false)
+                    }
+                    None => {
+                        warn!("No USP message payload found in MQTT record");
+                        continue;
+                    }
+                },
             };
 
-            debug!(
-                "Calling handle_incoming for message from {}",
-                record.from_id
-            );
-            if let Some(resp) = super::super::agent::handle_incoming(
-                cfg.clone(),
-                agent_id.clone(),
-                &msg_bytes,
-                Arc::clone(&negotiated_ver),
-            )
-            .await
-            {
+            for msg_bytes in msgs {
+                debug!(
+                    "Calling handle_incoming for message from {}",
+                    record.from_id
+                );
+                let resp = match super::super::agent::handle_incoming(
+                    cfg.clone(),
+                    agent_id.clone(),
+                    &record.from_id,
+                    &msg_bytes,
+                    Arc::clone(&negotiated_ver),
+                    Arc::clone(&mtp_state),
+                )
+                .await
+                {
+                    Some(resp) => resp,
+                    None => {
+                        debug!("No response needed for this message");
+                        continue;
+                    }
+                };
                 let ver = negotiated_ver.lock().unwrap().clone();
                 debug!("Sending response via MQTT (version={})", ver);
-                let resp_rec = no_session_record(agent_id.as_str(), &record.from_id, resp, &ver);
+                let resp_rec = if in_session {
+                    let (session, _) = sessions.get(&record.from_id);
+                    session.wrap(agent_id.as_str(), &record.from_id, resp, None, &ver)
+                } else {
+                    no_session_record(agent_id.as_str(), &record.from_id, resp, &ver)
+                };
                 if let Ok(encoded) = encode_record(&resp_rec) {
                     debug!(
                         "Response encoded ({} bytes), publishing to {}",
                         encoded.len(),
-                        controller_topic
+                        reply_topic
                     );
-                    match client
-                        .publish(&controller_topic, QoS::AtLeastOnce, false, encoded)
-                        .await
-                    {
+                    match client.publish(reply_topic, encoded, &agent_topic).await {
                         Ok(()) => debug!("Response published successfully"),
                         Err(e) => error!("Failed to publish response: {}", e),
                     }
                 } else {
                     error!("Failed to encode response record");
                 }
-            } else {
-                debug!("No response needed for this message");
             }
         } else {
             trace!("Non-publish MQTT event received");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broker_url_scheme_selects_tls_and_default_port() {
+        assert_eq!(
+            parse_broker_url("mqtts://broker.example.com"),
+            ("broker.example.com".to_string(), 8883, true)
+        );
+        assert_eq!(
+            parse_broker_url("mqtt://broker.example.com"),
+            ("broker.example.com".to_string(), 1883, false)
+        );
+        assert_eq!(
+            parse_broker_url("mqtts://10.0.0.1:9883/"),
+            ("10.0.0.1".to_string(), 9883, true)
+        );
+        assert_eq!(
+            parse_broker_url("broker:1884"),
+            ("broker".to_string(), 1884, false)
+        );
+    }
+
+    #[test]
+    fn broker_url_accepts_bracketed_ipv6_host() {
+        assert_eq!(
+            parse_broker_url("mqtts://[fd00::1]:8883"),
+            ("fd00::1".to_string(), 8883, true)
+        );
+        assert_eq!(
+            parse_broker_url("mqtt://[2001:db8::10]:1884/"),
+            ("2001:db8::10".to_string(), 1884, false)
+        );
+        assert_eq!(
+            parse_broker_url("mqtts://[fd00::1]"),
+            ("fd00::1".to_string(), 8883, true)
+        );
+    }
+}
@@ -0,0 +1,280 @@
+//! Controller re-homing: moving the WebSocket MTP to another controller at
+//! runtime with a SET of `Device.LocalAgent.Controller.1.MTP.1.WebSocket.*`.
+//!
+//! The new URL is used from the next connection attempt, but only written to
+//! `ws_url` (in UCI or the config file, wherever the config came from) once
+//! the controller there answers over USP; a WebSocket handshake alone does
+//! not count.  If it has not within `rehome_timeout` seconds the MTP falls
+//! back to the previous controller, so a mistyped fleet-wide SET cannot
+//! strand devices.  A restart before the move is confirmed also lands on the
+//! old, still-persisted URL.
+
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use tokio::sync::watch;
+
+/// Bumped whenever the controller URL changes.
+static URL_GENERATION: OnceLock<watch::Sender<u64>> = OnceLock::new();
+
+fn url_tx() -> &'static watch::Sender<u64> {
+    URL_GENERATION.get_or_init(|| watch::channel(0).0)
+}
+
+fn url_changed() {
+    url_tx().send_modify(|generation| *generation += 1);
+}
+
+/// Receiver whose `changed()` fires on the next controller URL change; the
+/// WebSocket MTP then drops its connection and reconnects.  Take it before
+/// reading [`ws_url`] so no change is missed.
+pub fn watch_url() -> watch::Receiver<u64> {
+    url_tx().subscribe()
+}
+
+static STATE: Mutex<Rehome> = Mutex::new(Rehome::new());
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rehome {
+    /// URL the WebSocket MTP connects to; `None` until the MTP has started.
+    active: Option<String>,
+    /// Previous URL and the deadline for reaching `active`, while a move is
+    /// unconfirmed.
+    fallback: Option<(String, Instant)>,
+}
+
+impl Rehome {
+    pub const fn new() -> Self {
+        Self {
+            active: None,
+            fallback: None,
+        }
+    }
+
+    /// Move to `url`.  Several SETs before the move is confirmed (Host, then
+    /// Path) keep the original URL as the fallback.
+    pub fn start(&mut self, url: String, now: Instant, timeout: Duration) -> Result<(), String> {
+        let current = match &self.active {
+            Some(u) => u.clone(),
+            None => return Err("WebSocket MTP is not running".into()),
+        };
+        if url == current {
+            return Ok(());
+        }
+        let previous = match self.fallback.take() {
+            Some((prev, _)) => prev,
+            None => current,
+        };
+        self.fallback = Some((previous, now + timeout));
+        self.active = Some(url);
+        Ok(())
+    }
+
+    /// The controller at `url` answered; returns the URL to persist when
+    /// this confirms a move.
+    pub fn connected(&mut self, url: &str) -> Option<String> {
+        if self.fallback.is_some() && self.active.as_deref() == Some(url) {
+            self.fallback = None;
+            return Some(url.to_string());
+        }
+        None
+    }
+
+    /// Deadline for the controller at `url` to answer, while a move to it
+    /// is unconfirmed.
+    pub fn deadline(&self, url: &str) -> Option<Instant> {
+        match &self.fallback {
+            Some((_, deadline)) if self.active.as_deref() == Some(url) => Some(*deadline),
+            _ => None,
+        }
+    }
+
+    /// A connection ended or failed before the controller answered; once
+    /// the deadline has passed, go back to the previous URL and return it.
+    pub fn connect_failed(&mut self, now: Instant) -> Option<String> {
+        match &self.fallback {
+            Some((_, deadline)) if now >= *deadline => {
+                let (previous, _) = self.fallback.take()?;
+                self.active = Some(previous.clone());
+                Some(previous)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// URL for the next connection attempt: the re-homed one if a SET changed
+/// it, otherwise `configured` (`ws_url`).
+pub fn ws_url(configured: Option<&String>) -> Option<String> {
+    let mut st = STATE.lock().unwrap();
+    if st.active.is_none() {
+        st.active = configured.cloned();
+    }
+    st.active.clone()
+}
+
+/// URL currently in use by the WebSocket MTP, for GET.
+pub fn current_url() -> Option<String> {
+    STATE.lock().unwrap().active.clone()
+}
+
+/// Re-home to `url` and make the WebSocket MTP reconnect.
+pub fn set_url(url: String, timeout: Duration) -> Result<(), String> {
+    STATE
+        .lock()
+        .unwrap()
+        .start(url.clone(), Instant::now(), timeout)?;
+    info!(
+        "Controller URL changed to {url}; reverting unless connected within {}s",
+        timeout.as_secs()
+    );
+    url_changed();
+    Ok(())
+}
+
+/// Current re-homing state, to [`restore`] if the SET that moves the
+/// controller is rolled back.
+pub fn checkpoint() -> Rehome {
+    STATE.lock().unwrap().clone()
+}
+
+/// Undo [`set_url`]s made since `checkpoint` and reconnect accordingly.
+pub fn restore(checkpoint: Rehome) {
+    let mut st = STATE.lock().unwrap();
+    if *st != checkpoint {
+        *st = checkpoint;
+        drop(st);
+        info!("Controller URL change rolled back");
+        url_changed();
+    }
+}
+
+/// Deadline for the controller at `url` to answer, if a move to it is
+/// still unconfirmed.
+pub fn pending_deadline(url: &str) -> Option<Instant> {
+    STATE.lock().unwrap().deadline(url)
+}
+
+/// Called on the first USP record received from the controller at `url`.
+pub fn on_answered(url: &str) {
+    let confirmed = STATE.lock().unwrap().connected(url);
+    if let Some(url) = confirmed {
+        info!("Controller re-home to {url} confirmed");
+        if let Err(e) = crate::config::persist("ws_url", &url) {
+            warn!("Re-homed to {url} but ws_url not persisted: {e}");
+        }
+    }
+}
+
+/// Called whenever a connection attempt fails or a connection ends.
+pub fn on_disconnected() {
+    let reverted = STATE.lock().unwrap().connect_failed(Instant::now());
+    if let Some(url) = reverted {
+        error!("New controller not reachable in time; reverting to {url}");
+    }
+}
+
+/// Replace one component (`Host`, `Port` or `Path`) of a WebSocket URL.
+pub fn with_component(url: &str, param: &str, value: &str) -> Result<String, String> {
+    let mut u = url::Url::parse(url).map_err(|e| format!("current URL {url:?}: {e}"))?;
+    let value = value.trim();
+    match param {
+        "Host" => {
+            if value.is_empty() {
+                return Err("Host must not be empty".into());
+            }
+            u.set_host(Some(value))
+                .map_err(|e| format!("invalid host {value:?}: {e}"))?;
+        }
+        "Port" => {
+            let port: u16 = value
+                .parse()
+                .ok()
+                .filter(|p| *p != 0)
+                .ok_or_else(|| format!("invalid port {value:?}"))?;
+            u.set_port(Some(port))
+                .map_err(|_| format!("cannot set port on {url:?}"))?;
+        }
+        "Path" => {
+            let path = if value.starts_with('/') {
+                value.to_string()
+            } else {
+                format!("/{value}")
+            };
+            u.set_path(&path);
+        }
+        _ => return Err(format!("unknown WebSocket parameter {param}")),
+    }
+    Ok(u.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_new_controller_connect_reverts() {
+        let old = "wss://acs.example.com:3491/usp";
+        let new = "wss://acs2.example.com:3491/usp";
+        let timeout = Duration::from_secs(120);
+        let t0 = Instant::now();
+
+        let mut st = Rehome::new();
+        assert!(st.start(new.into(), t0, timeout).is_err());
+        st.active = Some(old.into());
+
+        st.start(new.into(), t0, timeout).unwrap();
+        assert_eq!(st.active.as_deref(), Some(new));
+
+        // Failures within the timeout keep trying the new controller
+        assert_eq!(st.connect_failed(t0 + Duration::from_secs(30)), None);
+        assert_eq!(st.active.as_deref(), Some(new));
+
+        // Deadline passed: back to the old one, and nothing to persist
+        assert_eq!(st.connect_failed(t0 + timeout), Some(old.to_string()));
+        assert_eq!(st.active.as_deref(), Some(old));
+        assert_eq!(st.connected(old), None);
+    }
+
+    #[test]
+    fn successful_rehome_confirmed_once() {
+        let old = "wss://acs.example.com:3491/usp";
+        let t0 = Instant::now();
+        let timeout = Duration::from_secs(120);
+        let mut st = Rehome::new();
+        st.active = Some(old.into());
+
+        // Host then Path in one SET: the original URL stays the fallback
+        let host = with_component(old, "Host", "acs2.example.com").unwrap();
+        st.start(host.clone(), t0, timeout).unwrap();
+        let path = with_component(&host, "Path", "v2/usp").unwrap();
+        assert_eq!(path, "wss://acs2.example.com:3491/v2/usp");
+        st.start(path.clone(), t0, timeout).unwrap();
+        assert_eq!(st.fallback.as_ref().map(|(u, _)| u.as_str()), Some(old));
+
+        assert_eq!(st.deadline(&path), Some(t0 + timeout));
+        assert_eq!(st.connected(&path), Some(path.clone()));
+        assert_eq!(st.deadline(&path), None);
+        assert_eq!(st.connected(&path), None);
+        assert_eq!(st.connect_failed(t0 + timeout), None);
+        assert_eq!(st.active.as_deref(), Some(path.as_str()));
+    }
+
+    #[test]
+    fn url_components_validated() {
+        let url = "wss://acs.example.com:3491/usp";
+        assert_eq!(
+            with_component(url, "Port", "443").unwrap(),
+            "wss://acs.example.com/usp"
+        );
+        assert_eq!(
+            with_component(url, "Port", "8443").unwrap(),
+            "wss://acs.example.com:8443/usp"
+        );
+        assert!(with_component(url, "Port", "0").is_err());
+        assert!(with_component(url, "Port", "http").is_err());
+        assert!(with_component(url, "Host", "").is_err());
+        assert!(with_component(url, "Host", "bad host").is_err());
+    }
+}
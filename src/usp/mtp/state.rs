@@ -0,0 +1,186 @@
+//! Per-MTP connection state shared between the MTP loop and message handling.
+//!
+//! Holds the application-level self-test: every `self_test_interval` the MTP
+//! sends a GetSupportedProto and expects the controller's
+//! GetSupportedProtoResp before the next probe is due.  A socket can stay up
+//! while the controller behind it has stopped answering (half-open TCP, a
+//! wedged controller process); after `self_test_failures` unanswered probes in
+//! a row the MTP drops the connection and reconnects.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use super::super::{
+    endpoint::EndpointId,
+    message::{build_get_supported_proto, encode_msg},
+    record::{encode_record, no_session_record},
+};
+
+/// Outcome of one self-test probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestResult {
+    /// The controller answered after `rtt`.
+    Passed { rtt: Duration },
+    /// No answer before the next probe was due.
+    Failed,
+}
+
+#[derive(Debug, Default)]
+pub struct MtpState {
+    /// Result of the most recent self-test (kept across reconnects).
+    pub last_self_test: Option<SelfTestResult>,
+    /// Consecutive unanswered probes on the current connection.
+    pub self_test_failures: u32,
+    /// msg_id and send time of the probe awaiting its response.
+    probe: Option<(String, Instant)>,
+    /// When the current connection came up; feeds the reconnect backoff.
+    pub connected_at: Option<Instant>,
+}
+
+impl MtpState {
+    /// Start counting afresh on a new connection.
+    pub fn on_connect(&mut self) {
+        self.self_test_failures = 0;
+        self.probe = None;
+        self.connected_at = Some(Instant::now());
+    }
+
+    /// How long the last connection stayed up (zero if it never came up);
+    /// clears the timestamp for the next attempt.
+    pub fn take_uptime(&mut self) -> Duration {
+        self.connected_at
+            .take()
+            .map(|t| t.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// Called when the next probe is due.  A probe still unanswered counts as
+    /// a failure; returns true once `max_failures` are reached in a row and
+    /// the connection should be dropped.
+    pub fn self_test_due(&mut self, max_failures: u32) -> bool {
+        if let Some((msg_id, _)) = self.probe.take() {
+            self.self_test_failures += 1;
+            self.last_self_test = Some(SelfTestResult::Failed);
+            warn!(
+                "Self-test {msg_id} unanswered ({} in a row)",
+                self.self_test_failures
+            );
+        }
+        max_failures > 0 && self.self_test_failures >= max_failures
+    }
+
+    pub fn probe_sent(&mut self, msg_id: String, now: Instant) {
+        self.probe = Some((msg_id, now));
+    }
+
+    /// Record a GetSupportedProtoResp; true if it answered the outstanding
+    /// probe (rather than the version negotiation at connect).
+    pub fn probe_answered(&mut self, msg_id: &str, now: Instant) -> bool {
+        match &self.probe {
+            Some((id, sent)) if id == msg_id => {
+                self.last_self_test = Some(SelfTestResult::Passed {
+                    rtt: now.duration_since(*sent),
+                });
+                self.self_test_failures = 0;
+                self.probe = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// What the MTP should do when a self-test is due.
+pub enum SelfTestStep {
+    /// Send this encoded Record to the controller.
+    Probe(Vec<u8>),
+    /// Too many probes went unanswered: reconnect.
+    Reconnect,
+}
+
+/// Account for the previous probe and build the next one.
+pub fn self_test_step(
+    state: &Mutex<MtpState>,
+    max_failures: u32,
+    agent_id: &EndpointId,
+    controller_id: &str,
+    ver: &str,
+) -> anyhow::Result<SelfTestStep> {
+    let mut state = state.lock().unwrap();
+    if state.self_test_due(max_failures) {
+        return Ok(SelfTestStep::Reconnect);
+    }
+    let msg = build_get_supported_proto();
+    let msg_id = msg
+        .header
+        .as_ref()
+        .map(|h| h.msg_id.clone())
+        .unwrap_or_default();
+    let rec = no_session_record(agent_id.as_str(), controller_id, encode_msg(&msg)?, ver);
+    let bytes = encode_record(&rec)?;
+    state.probe_sent(msg_id, Instant::now());
+    Ok(SelfTestStep::Probe(bytes))
+}
+
+/// Self-test ticker; the first probe goes out one period after connecting.
+/// `None` when the self-test is disabled (`secs == 0`).
+pub fn self_test_interval(secs: u64) -> Option<tokio::time::Interval> {
+    if secs == 0 {
+        return None;
+    }
+    let period = Duration::from_secs(secs);
+    Some(tokio::time::interval_at(
+        tokio::time::Instant::now() + period,
+        period,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_self_test_failures_trigger_reconnect() {
+        let mut st = MtpState::default();
+        let t0 = Instant::now();
+
+        // Answered probe: passes and resets the failure count
+        st.probe_sent("p1".into(), t0);
+        assert!(!st.probe_answered("negotiation", t0));
+        assert!(st.probe_answered("p1", t0 + Duration::from_millis(40)));
+        assert_eq!(
+            st.last_self_test,
+            Some(SelfTestResult::Passed {
+                rtt: Duration::from_millis(40)
+            })
+        );
+
+        // Three unanswered probes in a row: the third due check reconnects
+        assert!(!st.self_test_due(3));
+        st.probe_sent("p2".into(), t0);
+        assert!(!st.self_test_due(3));
+        st.probe_sent("p3".into(), t0);
+        assert!(!st.self_test_due(3));
+        st.probe_sent("p4".into(), t0);
+        assert!(st.self_test_due(3));
+        assert_eq!(st.self_test_failures, 3);
+        assert_eq!(st.last_self_test, Some(SelfTestResult::Failed));
+
+        // A late answer to an abandoned probe is not a pass
+        assert!(!st.probe_answered("p4", t0));
+
+        // New connection starts over; the last result stays visible
+        st.on_connect();
+        assert_eq!(st.self_test_failures, 0);
+        assert!(!st.self_test_due(3));
+        assert_eq!(st.last_self_test, Some(SelfTestResult::Failed));
+
+        // max_failures = 0 never forces a reconnect
+        for _ in 0..5 {
+            st.probe_sent("p".into(), t0);
+            assert!(!st.self_test_due(0));
+        }
+    }
+}
@@ -3,7 +3,7 @@
 #![allow(clippy::all)]
 
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures_util::{SinkExt, StreamExt};
 use log::{debug, error, info, trace, warn};
@@ -14,17 +14,20 @@ use tokio_tungstenite::{
 };
 
 use super::super::{
+    e2e,
     endpoint::EndpointId,
     message::{build_get_supported_proto, encode_msg},
+    mtp::rehome,
+    mtp::state::{self_test_interval, self_test_step, MtpState, SelfTestStep},
+    mtp::Backoff,
+    offline_buffer::Outbox,
     record::{
-        decode_record, encode_record, extract_msg_payload, no_session_record,
+        decode_record, disconnect_record, encode_record, extract_msg_payload, no_session_record,
         websocket_connect_record,
     },
+    shutdown,
 };
 use crate::config::ClientConfig;
-use tokio::sync::mpsc::Receiver;
-
-const RECONNECT_DELAY: Duration = Duration::from_secs(10);
 
 /// Generate a Sec-WebSocket-Key header value (base64-encoded 16-byte nonce)
 fn generate_websocket_key() -> String {
@@ -35,23 +38,32 @@ fn generate_websocket_key() -> String {
     base64::engine::general_purpose::STANDARD.encode(nonce)
 }
 
+/// True once a Ping sent at `since` has gone unanswered for two ping
+/// periods: the connection is treated as dead.
+fn pong_overdue(since: Option<Instant>, now: Instant, period: Duration) -> bool {
+    since.is_some_and(|t| now.duration_since(t) >= period * 2)
+}
+
 /// Run the WebSocket MTP agent loop.  Reconnects automatically.
 pub async fn run(
     cfg: Arc<ClientConfig>,
     agent_id: EndpointId,
-    status_rx: Arc<Mutex<Receiver<Vec<u8>>>>,
+    outbox: Arc<Outbox>,
+    negotiated_ver: Arc<Mutex<String>>,
 ) {
     debug!(
         "Starting WebSocket MTP run loop for agent: {}",
         agent_id.as_str()
     );
-    let negotiated_ver: Arc<Mutex<String>> = Arc::new(Mutex::new("1.3".into()));
+    let mtp_state = Arc::new(Mutex::new(MtpState::default()));
+    let mut backoff = Backoff::new();
 
     loop {
-        let ws_url = match &cfg.ws_url {
+        let mut url_change = rehome::watch_url();
+        let ws_url = match rehome::ws_url(cfg.ws_url.as_ref()) {
             Some(u) => {
                 debug!("WebSocket URL configured: {}", u);
-                u.clone()
+                u
             }
             None => {
                 warn!("WebSocket MTP disabled (no ws_url configured)");
@@ -70,7 +82,9 @@ pub async fn run(
             agent_id.clone(),
             &ws_url,
             Arc::clone(&negotiated_ver),
-            Arc::clone(&status_rx),
+            Arc::clone(&mtp_state),
+            Arc::clone(&outbox),
+            &mut url_change,
         )
         .await
         {
@@ -83,12 +97,24 @@ pub async fn run(
                 debug!("WebSocket error details: {:?}", e);
             }
         }
+        rehome::on_disconnected();
+        if shutdown::requested() {
+            info!("USP WS: shut down");
+            return;
+        }
 
+        let delay = backoff.next_delay(mtp_state.lock().unwrap().take_uptime());
         warn!(
-            "USP WS: reconnecting in {} seconds...",
-            RECONNECT_DELAY.as_secs()
+            "USP WS: reconnecting in {:.1} seconds...",
+            delay.as_secs_f64()
         );
-        tokio::time::sleep(RECONNECT_DELAY).await;
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown::token().cancelled() => {
+                info!("USP WS: shut down");
+                return;
+            }
+        }
     }
 }
 
@@ -97,11 +123,15 @@ async fn connect_and_serve(
     agent_id: EndpointId,
     ws_url: &str,
     negotiated_ver: Arc<Mutex<String>>,
-    status_rx: Arc<Mutex<Receiver<Vec<u8>>>>,
+    mtp_state: Arc<Mutex<MtpState>>,
+    outbox: Arc<Outbox>,
+    url_change: &mut tokio::sync::watch::Receiver<u64>,
 ) -> anyhow::Result<()> {
     debug!("Building TLS configuration for WebSocket connection");
     // Build mTLS config using the agent's cert
+    let mut identity = crate::tls::watch_identity();
     let tls_cfg = crate::tls::build_tls_config(&cfg)?;
+    e2e::configure(&cfg)?;
     let connector = Connector::Rustls(tls_cfg);
     debug!("TLS connector created with mTLS enabled");
 
@@ -146,6 +176,8 @@ async fn connect_and_serve(
     }
 
     info!("USP WS: connected to {ws_url}");
+    // A re-homed controller counts once it answers, not on the handshake
+    let mut rehome_deadline = rehome::pending_deadline(ws_url).map(tokio::time::Instant::from_std);
     trace!(
         "WebSocket connection response headers: {:?}",
         response.headers()
@@ -173,13 +205,43 @@ async fn connect_and_serve(
     ws.send(Message::Binary(encode_record(&gsp_rec)?)).await?;
     info!("USP WS: version negotiation initiated (GetSupportedProto sent)");
 
+    let pending = outbox.len();
+    if pending > 0 {
+        info!("USP WS: flushing {pending} buffered notification(s)");
+    }
+
+    mtp_state.lock().unwrap().on_connect();
+    let mut self_test = self_test_interval(cfg.self_test_interval);
+
+    // Keepalive: our own Pings catch a silently dropped TCP connection long
+    // before the kernel's timeout would.
+    let ping_period = Duration::from_secs(cfg.ws_ping_interval);
+    // A request handled inline can hold the loop past a tick; Delay keeps
+    // the missed ticks from firing back to back afterwards.
+    let mut ping = (cfg.ws_ping_interval > 0).then(|| {
+        let mut ping =
+            tokio::time::interval_at(tokio::time::Instant::now() + ping_period, ping_period);
+        ping.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ping
+    });
+    // Send time of the oldest Ping still waiting for a Pong
+    let mut unanswered_ping: Option<Instant> = None;
+
     debug!("Entering message receive loop...");
     loop {
         tokio::select! {
+            // Frames already received are read before the keepalive tick,
+            // so a Pong that arrived during a slow request still counts
+            biased;
+
             // Handle incoming WebSocket messages
             frame = ws.next() => {
                 let frame = match frame {
-                    Some(Ok(f)) => f,
+                    Some(Ok(f)) => {
+                        // Any frame proves the connection is alive
+                        unanswered_ping = None;
+                        f
+                    }
                     Some(Err(e)) => {
                         error!("WebSocket error: {e}");
                         break;
@@ -240,6 +302,13 @@ async fn connect_and_serve(
                           record.to_id, agent_id.as_str());
                     continue;
                 }
+                if let Err(e) = e2e::verify(&record) {
+                    warn!("USP WS: {e}, discarding record from {}", record.from_id);
+                    continue;
+                }
+                if rehome_deadline.take().is_some() {
+                    rehome::on_answered(ws_url);
+                }
 
                 let msg_bytes = match extract_msg_payload(&record) {
                     Some(b) => {
@@ -254,7 +323,9 @@ async fn connect_and_serve(
 
                 debug!("Calling handle_incoming for message from {}", record.from_id);
                 if let Some(resp) = super::super::agent::handle_incoming(
-                    cfg.clone(), agent_id.clone(), &msg_bytes, Arc::clone(&negotiated_ver)
+                    cfg.clone(), agent_id.clone(), &record.from_id, &msg_bytes,
+                    Arc::clone(&negotiated_ver),
+                    Arc::clone(&mtp_state)
                 ).await {
                     let ver = negotiated_ver.lock().unwrap().clone();
                     debug!("Sending response (version={})", ver);
@@ -268,23 +339,80 @@ async fn connect_and_serve(
                 }
             }
 
-            // Handle outgoing status messages from heartbeat loop
-            status_msg = async {
-                let mut rx = status_rx.lock().unwrap();
-                rx.recv().await
-            } => {
-                if let Some(record_bytes) = status_msg {
-                    info!("WebSocket: Sending status heartbeat ({} bytes)", record_bytes.len());
-                    trace!("Status record bytes (first 64): {:?}", &record_bytes[..record_bytes.len().min(64)]);
-                    match ws.send(Message::Binary(record_bytes)).await {
-                        Ok(()) => info!("WebSocket: Status heartbeat sent successfully"),
-                        Err(e) => {
-                            warn!("WebSocket: Failed to send status heartbeat: {e}");
-                            // Don't break here - let the connection error handling deal with it
-                        }
+            // Application-level self-test: is the controller still answering?
+            _ = async { self_test.as_mut().unwrap().tick().await }, if self_test.is_some() => {
+                let ver = negotiated_ver.lock().unwrap().clone();
+                let step =
+                    self_test_step(&mtp_state, cfg.self_test_failures, &agent_id, &controller_id, &ver)?;
+                match step {
+                    SelfTestStep::Probe(bytes) => {
+                        debug!("USP WS: sending self-test probe");
+                        ws.send(Message::Binary(bytes)).await?;
+                    }
+                    SelfTestStep::Reconnect => {
+                        warn!("USP WS: controller stopped answering self-tests, reconnecting");
+                        break;
+                    }
+                }
+            }
+
+            // Keepalive ping; give up if the last one went unanswered
+            _ = async { ping.as_mut().unwrap().tick().await }, if ping.is_some() => {
+                let now = Instant::now();
+                if pong_overdue(unanswered_ping, now, ping_period) {
+                    return Err(anyhow::anyhow!(
+                        "no Pong within {}s, connection presumed dead",
+                        (ping_period * 2).as_secs()
+                    ));
+                }
+                trace!("USP WS: sending keepalive ping");
+                ws.send(Message::Ping(Vec::new())).await?;
+                unanswered_ping.get_or_insert(now);
+            }
+
+            // SIGTERM/SIGINT: tell the controller we are going away
+            _ = shutdown::token().cancelled() => {
+                info!("USP WS: shutting down, sending DisconnectRecord");
+                let bye = disconnect_record(agent_id.as_str(), &controller_id, "shutdown", 0);
+                ws.send(Message::Binary(encode_record(&bye)?)).await?;
+                ws.close(None).await?;
+                break;
+            }
+
+            // Provisioned cert installed (IssueCert): reconnect with it
+            _ = identity.changed() => {
+                info!("USP WS: device identity changed, reconnecting with provisioned certificate");
+                break;
+            }
+
+            // Controller URL changed by a SET: re-home
+            _ = url_change.changed() => {
+                info!("USP WS: controller URL changed, reconnecting");
+                break;
+            }
+
+            // Re-homed controller never answered: drop it so the next
+            // attempt falls back to the previous one
+            _ = async { tokio::time::sleep_until(rehome_deadline.unwrap()).await }, if rehome_deadline.is_some() => {
+                warn!("USP WS: new controller at {ws_url} did not answer in time");
+                break;
+            }
+
+            // Flush queued notifications (status heartbeat, buffered while offline)
+            item = outbox.next() => {
+                info!("WebSocket: Sending status heartbeat ({} bytes)", item.record.len());
+                trace!("Status record bytes (first 64): {:?}", &item.record[..item.record.len().min(64)]);
+                match ws.send(Message::Binary(item.record.clone())).await {
+                    Ok(()) => {
+                        info!("WebSocket: Status heartbeat sent successfully");
+                        item.mark_sent();
+                    }
+                    Err(e) => {
+                        warn!("WebSocket: Failed to send status heartbeat: {e}");
+                        // Keep it for the next connection
+                        outbox.requeue(item);
+                        return Err(e.into());
                     }
-                } else {
-                    debug!("Status channel closed");
                 }
             }
         }
@@ -293,3 +421,19 @@ async fn connect_and_serve(
     info!("USP WS: message loop ended");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_pong_for_two_intervals_is_fatal() {
+        let period = Duration::from_secs(30);
+        let t0 = Instant::now();
+        assert!(!pong_overdue(None, t0 + period * 10, period));
+        // First ping unanswered at the next tick: keep going
+        assert!(!pong_overdue(Some(t0), t0 + period, period));
+        // Still unanswered two ticks later: dead
+        assert!(pong_overdue(Some(t0), t0 + period * 2, period));
+    }
+}
@@ -0,0 +1,254 @@
+//! Offline notification buffer.
+//!
+//! Notify records produced by the agent (status ValueChange, Boot!,
+//! OperationComplete) are queued here rather than handed straight to an MTP.
+//! While no MTP is connected nothing drains the queue, so records accumulate
+//! and are flushed in order once a transport reconnects.
+//!
+//! The buffer is bounded by `offline_buffer_len` records and lives in RAM only,
+//! so an outage can never fill flash.  When full, the oldest ValueChange is
+//! evicted first; Boot! and OperationComplete are only dropped when the buffer
+//! holds nothing else.  A newer ValueChange for the same parameter replaces the
+//! queued one, so a long outage flushes one value per path instead of a backlog
+//! of stale samples.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use log::{debug, warn};
+use tokio::sync::Notify;
+
+/// Kind of notification held in the buffer; decides flush priority.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotifyClass {
+    /// Boot! event — flushed ahead of everything else.
+    Boot,
+    /// OperationComplete event — flushed ahead of ValueChange.
+    OperationComplete,
+    /// Other Event notification (alarms) — flushed ahead of ValueChange.
+    Event,
+    /// ValueChange for the given parameter path (coalesced per path).
+    ValueChange(String),
+}
+
+impl NotifyClass {
+    fn is_priority(&self) -> bool {
+        matches!(
+            self,
+            NotifyClass::Boot | NotifyClass::OperationComplete | NotifyClass::Event
+        )
+    }
+}
+
+/// An encoded USP Record waiting to be sent.
+#[derive(Debug, Clone)]
+pub struct QueuedNotify {
+    pub class: NotifyClass,
+    pub record: Vec<u8>,
+    /// Signalled once an MTP has sent the record (see [`Outbox::push_tracked`]).
+    pub sent: Option<Arc<Notify>>,
+}
+
+impl QueuedNotify {
+    /// Called by the MTP once the record is on the wire.
+    pub fn mark_sent(&self) {
+        if let Some(sent) = &self.sent {
+            sent.notify_one();
+        }
+    }
+}
+
+/// Bounded two-level FIFO: priority events first, then ValueChanges.
+#[derive(Debug)]
+pub struct OfflineBuffer {
+    capacity: usize,
+    priority: VecDeque<QueuedNotify>,
+    normal: VecDeque<QueuedNotify>,
+}
+
+impl OfflineBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            priority: VecDeque::new(),
+            normal: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.priority.len() + self.normal.len()
+    }
+
+    /// Queue a record, coalescing and evicting as needed.
+    pub fn push(&mut self, item: QueuedNotify) {
+        if let NotifyClass::ValueChange(path) = &item.class {
+            self.normal
+                .retain(|q| !matches!(&q.class, NotifyClass::ValueChange(p) if p == path));
+        }
+
+        if self.len() >= self.capacity {
+            if let Some(dropped) = self.normal.pop_front() {
+                warn!("Offline buffer full: dropping oldest {:?}", dropped.class);
+            } else if item.class.is_priority() {
+                if let Some(dropped) = self.priority.pop_front() {
+                    warn!("Offline buffer full: dropping oldest {:?}", dropped.class);
+                }
+            } else {
+                warn!("Offline buffer full: dropping new {:?}", item.class);
+                return;
+            }
+        }
+
+        if item.class.is_priority() {
+            self.priority.push_back(item);
+        } else {
+            self.normal.push_back(item);
+        }
+    }
+
+    /// Put back a record whose send failed so it is the next one flushed.
+    pub fn requeue(&mut self, item: QueuedNotify) {
+        if self.len() >= self.capacity {
+            // Anything queued meanwhile is newer; keep it over the retry
+            // unless the retry outranks it.
+            if !item.class.is_priority() || self.normal.pop_back().is_none() {
+                warn!("Offline buffer full: dropping retried {:?}", item.class);
+                return;
+            }
+        }
+        if item.class.is_priority() {
+            self.priority.push_front(item);
+        } else {
+            self.normal.push_front(item);
+        }
+    }
+
+    /// Next record to send: oldest priority event, else oldest ValueChange.
+    pub fn pop(&mut self) -> Option<QueuedNotify> {
+        self.priority
+            .pop_front()
+            .or_else(|| self.normal.pop_front())
+    }
+}
+
+/// Shared outbox between the notification producers and the MTP(s).
+pub struct Outbox {
+    buf: Mutex<OfflineBuffer>,
+    ready: Notify,
+}
+
+impl Outbox {
+    pub fn new(capacity: usize) -> Self {
+        debug!("Offline notification buffer: capacity {}", capacity.max(1));
+        Self {
+            buf: Mutex::new(OfflineBuffer::new(capacity)),
+            ready: Notify::new(),
+        }
+    }
+
+    pub fn push(&self, class: NotifyClass, record: Vec<u8>) {
+        self.buf.lock().unwrap().push(QueuedNotify {
+            class,
+            record,
+            sent: None,
+        });
+        self.ready.notify_one();
+    }
+
+    /// Queue a record and return a handle notified once it has been sent,
+    /// for work that must wait for the controller to be told (a reboot).
+    pub fn push_tracked(&self, class: NotifyClass, record: Vec<u8>) -> Arc<Notify> {
+        let sent = Arc::new(Notify::new());
+        self.buf.lock().unwrap().push(QueuedNotify {
+            class,
+            record,
+            sent: Some(Arc::clone(&sent)),
+        });
+        self.ready.notify_one();
+        sent
+    }
+
+    pub fn requeue(&self, item: QueuedNotify) {
+        self.buf.lock().unwrap().requeue(item);
+        self.ready.notify_one();
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.lock().unwrap().len()
+    }
+
+    /// Wait for the next record.  Cancel-safe: a record is only removed from
+    /// the buffer once this future completes.
+    pub async fn next(&self) -> QueuedNotify {
+        loop {
+            if let Some(item) = self.buf.lock().unwrap().pop() {
+                return item;
+            }
+            self.ready.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vc(path: &str, val: &str) -> QueuedNotify {
+        QueuedNotify {
+            class: NotifyClass::ValueChange(path.into()),
+            record: val.as_bytes().to_vec(),
+            sent: None,
+        }
+    }
+
+    fn ev(class: NotifyClass, tag: &str) -> QueuedNotify {
+        QueuedNotify {
+            class,
+            record: tag.as_bytes().to_vec(),
+            sent: None,
+        }
+    }
+
+    fn drain(buf: &mut OfflineBuffer) -> Vec<String> {
+        std::iter::from_fn(|| buf.pop())
+            .map(|q| String::from_utf8(q.record).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn flush_on_reconnect_order() {
+        let mut buf = OfflineBuffer::new(16);
+        buf.push(vc("Device.DeviceInfo.UpTime", "up1"));
+        buf.push(vc("Device.DeviceInfo.X_OptimACS_LoadAvg", "load1"));
+        buf.push(ev(NotifyClass::Boot, "boot"));
+        buf.push(vc("Device.DeviceInfo.UpTime", "up2"));
+        buf.push(ev(NotifyClass::OperationComplete, "opc"));
+
+        assert_eq!(drain(&mut buf), vec!["boot", "opc", "load1", "up2"]);
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn bounded_evicts_value_change_first() {
+        let mut buf = OfflineBuffer::new(3);
+        buf.push(ev(NotifyClass::Boot, "boot"));
+        buf.push(vc("A", "a"));
+        buf.push(vc("B", "b"));
+        buf.push(ev(NotifyClass::OperationComplete, "opc"));
+        buf.push(vc("C", "c"));
+
+        assert_eq!(buf.len(), 3);
+        assert_eq!(drain(&mut buf), vec!["boot", "opc", "c"]);
+    }
+
+    #[test]
+    fn requeue_is_flushed_first() {
+        let mut buf = OfflineBuffer::new(4);
+        buf.push(vc("A", "a"));
+        buf.push(vc("B", "b"));
+        let first = buf.pop().unwrap();
+        buf.requeue(first);
+
+        assert_eq!(drain(&mut buf), vec!["a", "b"]);
+    }
+}
@@ -8,7 +8,8 @@
 use prost::Message;
 
 use super::usp_record::{
-    record::RecordType, MqttConnectRecord, NoSessionContextRecord, Record, WebSocketConnectRecord,
+    mqtt_connect_record::MqttVersion, record::RecordType, DisconnectRecord, MqttConnectRecord,
+    NoSessionContextRecord, Record, SessionContextRecord, WebSocketConnectRecord,
 };
 use super::{Result, UspError};
 
@@ -42,7 +43,7 @@ pub fn no_session_record(
     msg_bytes: Vec<u8>,
     usp_version: &str,
 ) -> Record {
-    Record {
+    let mut record = Record {
         version: usp_version.into(),
         to_id: to_id.into(),
         from_id: from_id.into(),
@@ -52,13 +53,80 @@ pub fn no_session_record(
         record_type: Some(RecordType::NoSessionContext(NoSessionContextRecord {
             payload: msg_bytes,
         })),
+    };
+    super::e2e::sign(&mut record);
+    record
+}
+
+/// Build a `SessionContextRecord` carrying one unsegmented USP Msg payload
+/// (none for the session handshake).  Used for the MQTT MTP; see
+/// [`super::session`].
+pub fn session_record(
+    from_id: &str,
+    to_id: &str,
+    session_id: u64,
+    sequence_id: u64,
+    expected_id: u64,
+    msg_bytes: Vec<u8>,
+    usp_version: &str,
+) -> Record {
+    let mut record = Record {
+        version: usp_version.into(),
+        to_id: to_id.into(),
+        from_id: from_id.into(),
+        payload_security: 0,
+        mac_signature: vec![],
+        sender_cert: vec![],
+        record_type: Some(RecordType::SessionContext(SessionContextRecord {
+            session_id,
+            sequence_id,
+            expected_id,
+            retransmit_id: 0,
+            payload_sar_state: 0,    // NONE
+            payloadrec_sar_state: 0, // NONE
+            payload: if msg_bytes.is_empty() {
+                vec![]
+            } else {
+                vec![msg_bytes]
+            },
+        })),
+    };
+    super::e2e::sign(&mut record);
+    record
+}
+
+/// Build an empty `SessionContextRecord` asking the peer to resend every
+/// record from `retransmit_id` up to our `expected_id - 1` (a gap).
+pub fn retransmit_request_record(
+    from_id: &str,
+    to_id: &str,
+    session_id: u64,
+    sequence_id: u64,
+    expected_id: u64,
+    retransmit_id: u64,
+    usp_version: &str,
+) -> Record {
+    let mut record = session_record(
+        from_id,
+        to_id,
+        session_id,
+        sequence_id,
+        expected_id,
+        Vec::new(),
+        usp_version,
+    );
+    if let Some(RecordType::SessionContext(ctx)) = record.record_type.as_mut() {
+        ctx.retransmit_id = retransmit_id;
     }
+    // retransmit_id is covered by the signature
+    super::e2e::sign(&mut record);
+    record
 }
 
 /// Build a `WebSocketConnectRecord` — sent once when a controller first
 /// accepts a WebSocket connection from an agent.
 pub fn websocket_connect_record(from_id: &str, to_id: &str) -> Record {
-    Record {
+    let mut record = Record {
         version: "1.3".into(),
         to_id: to_id.into(),
         from_id: from_id.into(),
@@ -66,12 +134,19 @@ pub fn websocket_connect_record(from_id: &str, to_id: &str) -> Record {
         mac_signature: vec![],
         sender_cert: vec![],
         record_type: Some(RecordType::WebsocketConnect(WebSocketConnectRecord {})),
-    }
+    };
+    super::e2e::sign(&mut record);
+    record
 }
 
-/// Build an `MqttConnectRecord`.
-pub fn mqtt_connect_record(from_id: &str, to_id: &str, subscribed_topic: &str) -> Record {
-    Record {
+/// Build an `MqttConnectRecord` for a broker session of MQTT `version`.
+pub fn mqtt_connect_record(
+    from_id: &str,
+    to_id: &str,
+    subscribed_topic: &str,
+    version: MqttVersion,
+) -> Record {
+    let mut record = Record {
         version: "1.3".into(),
         to_id: to_id.into(),
         from_id: from_id.into(),
@@ -79,10 +154,31 @@ pub fn mqtt_connect_record(from_id: &str, to_id: &str, subscribed_topic: &str) -
         mac_signature: vec![],
         sender_cert: vec![],
         record_type: Some(RecordType::MqttConnect(MqttConnectRecord {
-            version: 0, // V3_1_1
+            version: version as i32,
             subscribed_topic: subscribed_topic.into(),
         })),
-    }
+    };
+    super::e2e::sign(&mut record);
+    record
+}
+
+/// Build a `DisconnectRecord` telling the peer this endpoint is going away.
+/// `reason_code` 0 means a normal closure.
+pub fn disconnect_record(from_id: &str, to_id: &str, reason: &str, reason_code: u32) -> Record {
+    let mut record = Record {
+        version: "1.3".into(),
+        to_id: to_id.into(),
+        from_id: from_id.into(),
+        payload_security: 0,
+        mac_signature: vec![],
+        sender_cert: vec![],
+        record_type: Some(RecordType::Disconnect(DisconnectRecord {
+            reason: reason.into(),
+            reason_code,
+        })),
+    };
+    super::e2e::sign(&mut record);
+    record
 }
 
 /// Extract the serialised `Msg` payload bytes from a Record, regardless of
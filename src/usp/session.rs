@@ -0,0 +1,423 @@
+//! USP session context (TR-369 §7.4): per-controller sequence numbering and
+//! retransmission for MTPs that do not guarantee ordered delivery (MQTT).
+//!
+//! Every outgoing Msg is stamped with the next `sequence_id` and kept until
+//! the controller's `expected_id` shows it arrived, so it can be resent when
+//! the controller asks with `retransmit_id`.  Incoming records are delivered
+//! strictly in `sequence_id` order: a record that arrives early is held back
+//! while we ask the controller to resend from the first missing one, and is
+//! delivered as soon as the gap is filled.
+//!
+//! Whether the agent opens a session itself is the per-controller
+//! `E2ESession.Enable` switch ([`enabled`]); when off, records go out as
+//! NoSessionContext and a session is only used once the controller opens one.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use rand::Rng;
+
+use super::offline_buffer::NotifyClass;
+use super::record::{retransmit_request_record, session_record};
+use super::usp_record::{Record, SessionContextRecord};
+
+/// Sent Msgs kept for retransmission, per session.
+const RETRANSMIT_BUFFER: usize = 32;
+/// Early (out-of-order) records held back waiting for a gap, per session.
+const REORDER_BUFFER: usize = 32;
+/// How long to wait for a requested retransmit before asking again; the
+/// request itself may be lost.
+const RETRANSMIT_REQUEST_RETRY: Duration = Duration::from_secs(10);
+
+/// `Device.LocalAgent.Controller.1.E2ESession.Enable`.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the agent opens a SessionContext with the controller.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+#[derive(Debug)]
+pub struct SessionContext {
+    session_id: u64,
+    /// sequence_id of the next record we send.
+    next_sequence_id: u64,
+    /// sequence_id of the next record we expect from the controller.
+    expected_id: u64,
+    /// `(sequence_id, payload, class)` of sent Msgs not yet acknowledged;
+    /// `class` is set for notifications queued through the outbox.
+    /// Retransmit requests are numbered but not kept here.
+    sent: VecDeque<(u64, Vec<u8>, Option<NotifyClass>)>,
+    /// Lowest sequence_id that can still be resent: everything below was
+    /// acknowledged or dropped from `sent` for lack of room.
+    retained_from: u64,
+    /// Payloads received ahead of `expected_id`, by sequence_id.
+    early: BTreeMap<u64, Vec<u8>>,
+    /// Gap (its first missing sequence_id) we asked to be resent, and when.
+    requested: Option<(u64, Instant)>,
+}
+
+/// What to do with one received SessionContextRecord.
+#[derive(Debug, Default, PartialEq)]
+pub struct Received {
+    /// Msg payloads to hand to the agent, in order.
+    pub deliver: Vec<Vec<u8>>,
+    /// Ask the controller to resend everything from this sequence_id.
+    pub request_retransmit: Option<u64>,
+    /// Our earlier Msgs the controller asked for again, as
+    /// `(sequence_id, payload)`.
+    pub resend: Vec<(u64, Vec<u8>)>,
+    /// Msgs of the session the controller just replaced that it never
+    /// acknowledged, with their outbox class (`None`: a response); they go
+    /// out again in the new session.
+    pub requeue: Vec<(Option<NotifyClass>, Vec<u8>)>,
+}
+
+impl SessionContext {
+    /// Start a new session with a random session_id.
+    pub fn new() -> Self {
+        Self::with_id(rand::thread_rng().gen_range(1..=u64::MAX))
+    }
+
+    fn with_id(session_id: u64) -> Self {
+        Self {
+            session_id,
+            next_sequence_id: 1,
+            expected_id: 1,
+            sent: VecDeque::new(),
+            retained_from: 1,
+            early: BTreeMap::new(),
+            requested: None,
+        }
+    }
+
+    pub fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    /// Claim the next outgoing sequence_id.
+    fn next_sequence_id(&mut self) -> u64 {
+        let id = self.next_sequence_id;
+        self.next_sequence_id += 1;
+        id
+    }
+
+    /// Claim the next sequence_id for `payload` and keep it for
+    /// retransmission.
+    fn keep_sent(&mut self, payload: Vec<u8>, class: Option<NotifyClass>) -> u64 {
+        let seq = self.next_sequence_id();
+        if self.sent.len() == RETRANSMIT_BUFFER {
+            if let Some((dropped, _, _)) = self.sent.pop_front() {
+                self.retained_from = dropped + 1;
+            }
+        }
+        self.sent.push_back((seq, payload, class));
+        seq
+    }
+
+    /// Wrap a Msg payload in a SessionContextRecord and keep it for
+    /// retransmission, with the outbox `class` of a notification.  An empty
+    /// payload is the session handshake.
+    pub fn wrap(
+        &mut self,
+        from_id: &str,
+        to_id: &str,
+        payload: Vec<u8>,
+        class: Option<NotifyClass>,
+        ver: &str,
+    ) -> Record {
+        let seq = self.keep_sent(payload.clone(), class);
+        session_record(
+            from_id,
+            to_id,
+            self.session_id,
+            seq,
+            self.expected_id,
+            payload,
+            ver,
+        )
+    }
+
+    /// Rebuild a record for a Msg the controller asked us to resend.
+    pub fn resend(
+        &self,
+        from_id: &str,
+        to_id: &str,
+        seq: u64,
+        payload: Vec<u8>,
+        ver: &str,
+    ) -> Record {
+        session_record(
+            from_id,
+            to_id,
+            self.session_id,
+            seq,
+            self.expected_id,
+            payload,
+            ver,
+        )
+    }
+
+    /// Ask the controller to resend everything from `retransmit_id`.  The
+    /// request takes a sequence_id but carries no Msg, so it is not kept for
+    /// retransmission.
+    pub fn retransmit_request(
+        &mut self,
+        from_id: &str,
+        to_id: &str,
+        retransmit_id: u64,
+        ver: &str,
+    ) -> Record {
+        let seq = self.next_sequence_id();
+        retransmit_request_record(
+            from_id,
+            to_id,
+            self.session_id,
+            seq,
+            self.expected_id,
+            retransmit_id,
+            ver,
+        )
+    }
+
+    /// Process a SessionContextRecord from the controller.
+    pub fn receive(&mut self, rec: &SessionContextRecord) -> Received {
+        let mut out = Received::default();
+
+        if rec.session_id != self.session_id {
+            // The controller started a new session (or restarted): adopt it
+            info!(
+                "USP session: controller opened session {} (was {})",
+                rec.session_id, self.session_id
+            );
+            let old = std::mem::replace(self, Self::with_id(rec.session_id));
+            out.requeue = old
+                .sent
+                .into_iter()
+                .filter(|(_, payload, _)| !payload.is_empty())
+                .map(|(_, payload, class)| (class, payload))
+                .collect();
+            self.expected_id = rec.sequence_id;
+        }
+
+        // Everything below the controller's expected_id has arrived
+        self.sent.retain(|(seq, _, _)| *seq >= rec.expected_id);
+        self.retained_from = self.retained_from.max(rec.expected_id);
+
+        if rec.retransmit_id != 0 {
+            debug!(
+                "USP session: controller asks to resend from {}",
+                rec.retransmit_id
+            );
+            // sequence_ids missing from `sent` were retransmit requests;
+            // they go out again empty so the controller sees no gap
+            let from = rec.retransmit_id.max(self.retained_from);
+            out.resend = (from..self.next_sequence_id)
+                .map(|seq| {
+                    let payload = self
+                        .sent
+                        .iter()
+                        .find(|(s, _, _)| *s == seq)
+                        .map(|(_, payload, _)| payload.clone())
+                        .unwrap_or_default();
+                    (seq, payload)
+                })
+                .collect();
+        }
+
+        if rec.sequence_id == self.expected_id {
+            self.accept(rec.payload.concat(), &mut out.deliver);
+            // The gap is filled: release the records held back behind it
+            while let Some(payload) = self.early.remove(&self.expected_id) {
+                debug!(
+                    "USP session: delivering held sequence_id {}",
+                    self.expected_id
+                );
+                self.accept(payload, &mut out.deliver);
+            }
+        } else if rec.sequence_id > self.expected_id {
+            warn!(
+                "USP session: got sequence_id {}, expected {}; holding it and requesting retransmit",
+                rec.sequence_id, self.expected_id
+            );
+            if self.early.len() < REORDER_BUFFER {
+                self.early.insert(rec.sequence_id, rec.payload.concat());
+            } else {
+                warn!(
+                    "USP session: reorder buffer full, dropping sequence_id {}",
+                    rec.sequence_id
+                );
+            }
+            // One request per gap, not one per record held behind it,
+            // unless the last one went unanswered
+            let pending = self.requested.is_some_and(|(gap, at)| {
+                gap == self.expected_id && at.elapsed() < RETRANSMIT_REQUEST_RETRY
+            });
+            if !pending {
+                self.requested = Some((self.expected_id, Instant::now()));
+                out.request_retransmit = Some(self.expected_id);
+            }
+        } else {
+            debug!(
+                "USP session: duplicate sequence_id {}, dropped",
+                rec.sequence_id
+            );
+        }
+        out
+    }
+
+    /// Deliver the record at `expected_id` (empty ones are only counted).
+    fn accept(&mut self, payload: Vec<u8>, deliver: &mut Vec<Vec<u8>>) {
+        self.expected_id += 1;
+        if !payload.is_empty() {
+            deliver.push(payload);
+        }
+    }
+}
+
+/// One SessionContext per controller endpoint.
+#[derive(Debug, Default)]
+pub struct Sessions {
+    by_peer: HashMap<String, SessionContext>,
+}
+
+impl Sessions {
+    /// The session with `peer`, and whether it was just created (and so
+    /// still needs its handshake record).
+    pub fn get(&mut self, peer: &str) -> (&mut SessionContext, bool) {
+        let created = !self.by_peer.contains_key(peer);
+        let ctx = self
+            .by_peer
+            .entry(peer.to_string())
+            .or_insert_with(SessionContext::new);
+        (ctx, created)
+    }
+
+    /// The session with `peer`, if one was opened.
+    pub fn active(&mut self, peer: &str) -> Option<&mut SessionContext> {
+        self.by_peer.get_mut(peer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usp::usp_record::record::RecordType;
+
+    fn rec(session_id: u64, seq: u64, expected: u64, payload: &str) -> SessionContextRecord {
+        SessionContextRecord {
+            session_id,
+            sequence_id: seq,
+            expected_id: expected,
+            payload: vec![payload.as_bytes().to_vec()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn in_order_delivery_gap_and_retransmit() {
+        let mut s = SessionContext::with_id(7);
+
+        // Outgoing records are numbered 1, 2, ...
+        s.wrap("agent", "ctrl", Vec::new(), None, "1.3");
+        s.wrap("agent", "ctrl", b"notify".to_vec(), None, "1.3");
+        assert_eq!(s.next_sequence_id, 3);
+
+        let r = s.receive(&rec(7, 1, 1, "get"));
+        assert_eq!(r.deliver, vec![b"get".to_vec()]);
+        assert_eq!(s.expected_id, 2);
+
+        // The controller saw our 1 but wants 2 again
+        let mut ask = rec(7, 2, 2, "add");
+        ask.retransmit_id = 2;
+        let r = s.receive(&ask);
+        assert_eq!(r.deliver, vec![b"add".to_vec()]);
+        assert_eq!(r.resend, vec![(2, b"notify".to_vec())]);
+
+        // Duplicate is dropped
+        assert!(s.receive(&rec(7, 2, 3, "add")).deliver.is_empty());
+    }
+
+    #[test]
+    fn out_of_order_records_delivered_in_sequence() {
+        let mut s = SessionContext::with_id(7);
+        let mut delivered = Vec::new();
+
+        let r = s.receive(&rec(7, 1, 1, "1"));
+        delivered.extend(r.deliver);
+
+        // 3 arrives before 2: held back, retransmit of 2 requested
+        let r = s.receive(&rec(7, 3, 1, "3"));
+        assert!(r.deliver.is_empty());
+        assert_eq!(r.request_retransmit, Some(2));
+        let req = s.retransmit_request("agent", "ctrl", 2, "1.3");
+        match req.record_type {
+            Some(RecordType::SessionContext(ctx)) => {
+                assert_eq!((ctx.retransmit_id, ctx.expected_id), (2, 2));
+                assert_eq!(ctx.sequence_id, 1);
+                assert!(ctx.payload.is_empty());
+            }
+            other => panic!("unexpected record type {other:?}"),
+        }
+        // The request is numbered but not kept for retransmission
+        assert!(s.sent.is_empty());
+
+        // 4 waits behind the same gap: no second request
+        let r = s.receive(&rec(7, 4, 1, "4"));
+        assert!(r.deliver.is_empty());
+        assert_eq!(r.request_retransmit, None);
+
+        // The request went unanswered: the next early record asks again
+        s.requested = Some((2, Instant::now() - RETRANSMIT_REQUEST_RETRY));
+        let r = s.receive(&rec(7, 5, 1, "5"));
+        assert!(r.deliver.is_empty());
+        assert_eq!(r.request_retransmit, Some(2));
+
+        // 2 fills the gap and releases 3 to 5; the controller missed our
+        // request too, which goes out again without a payload
+        let mut fill = rec(7, 2, 1, "2");
+        fill.retransmit_id = 1;
+        let r = s.receive(&fill);
+        delivered.extend(r.deliver);
+        assert_eq!(r.resend, vec![(1, Vec::new())]);
+
+        assert_eq!(
+            delivered,
+            vec![
+                b"1".to_vec(),
+                b"2".to_vec(),
+                b"3".to_vec(),
+                b"4".to_vec(),
+                b"5".to_vec()
+            ]
+        );
+        assert_eq!(s.expected_id, 6);
+    }
+
+    #[test]
+    fn new_controller_session_is_adopted() {
+        let mut s = SessionContext::with_id(7);
+        s.wrap("agent", "ctrl", Vec::new(), None, "1.3");
+        s.wrap(
+            "agent",
+            "ctrl",
+            b"boot".to_vec(),
+            Some(NotifyClass::Boot),
+            "1.3",
+        );
+        let r = s.receive(&rec(99, 5, 1, "get"));
+        assert_eq!(s.session_id(), 99);
+        assert_eq!(r.deliver, vec![b"get".to_vec()]);
+        assert_eq!(s.expected_id, 6);
+        // Unacknowledged Msgs of the old session are sent again; the
+        // handshake is not
+        assert_eq!(r.requeue, vec![(Some(NotifyClass::Boot), b"boot".to_vec())]);
+        assert!(s.sent.is_empty());
+    }
+}
@@ -0,0 +1,26 @@
+//! Process-wide shutdown request (SIGTERM/SIGINT).
+//!
+//! The signal handler in `main` cancels the token; the MTPs watch it, send a
+//! `DisconnectRecord` to the controller and close their connection instead of
+//! being killed mid-flight.
+
+use std::sync::OnceLock;
+
+use tokio_util::sync::CancellationToken;
+
+static TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+/// The shared shutdown token.
+pub fn token() -> &'static CancellationToken {
+    TOKEN.get_or_init(CancellationToken::new)
+}
+
+/// Ask every MTP to disconnect and the agent to stop.
+pub fn request() {
+    token().cancel();
+}
+
+/// Whether a shutdown has been requested.
+pub fn requested() -> bool {
+    token().is_cancelled()
+}
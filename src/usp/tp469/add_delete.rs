@@ -6,6 +6,7 @@
 use super::error_codes::ErrorCode;
 use super::uci_backend::{self, UciResult};
 use crate::config::ClientConfig;
+use crate::usp::dm::{subscriptions, DmError};
 use crate::usp::usp_msg;
 use log::info;
 
@@ -50,7 +51,9 @@ async fn create_object_instance(create_obj: &usp_msg::add::CreateObject) -> AddR
     let obj_path = &create_obj.obj_path;
 
     // Determine the object type and dispatch to appropriate handler
-    if obj_path.contains("DHCP") || obj_path.contains("dhcp") {
+    if obj_path.starts_with("Device.LocalAgent.Subscription") {
+        add_subscription(create_obj)
+    } else if obj_path.contains("DHCP") || obj_path.contains("dhcp") {
         add_dhcp_static_lease(create_obj).await
     } else if obj_path.contains("Hosts") || obj_path.contains("hosts") {
         add_static_host(create_obj).await
@@ -165,6 +168,34 @@ async fn add_static_host(create_obj: &usp_msg::add::CreateObject) -> AddResult {
     convert_uci_result(&create_obj.obj_path, result)
 }
 
+fn add_subscription(create_obj: &usp_msg::add::CreateObject) -> AddResult {
+    let settings: Vec<(String, String)> = create_obj
+        .param_settings
+        .iter()
+        .map(|p| (p.param.clone(), p.value.clone()))
+        .collect();
+
+    match subscriptions::add(&settings) {
+        Ok(instance) => {
+            info!("Subscription instance {} created", instance);
+            convert_uci_result(&create_obj.obj_path, UciResult::success(instance))
+        }
+        Err(e) => convert_uci_result(
+            &create_obj.obj_path,
+            UciResult::error(dm_error_code(&e), &e.to_string()),
+        ),
+    }
+}
+
+/// ADD/DELETE error code for a data-model failure.
+fn dm_error_code(e: &DmError) -> ErrorCode {
+    match e {
+        DmError::InvalidValue { .. } => ErrorCode::InvalidValue,
+        DmError::InvalidPath(_) => ErrorCode::ObjectNotFound,
+        _ => ErrorCode::InternalError,
+    }
+}
+
 /// Convert UciResult to AddResult
 fn convert_uci_result(obj_path: &str, result: UciResult) -> AddResult {
     AddResult {
@@ -231,7 +262,9 @@ async fn delete_object_instance(obj_path: &str) -> DeleteResult {
     }
 
     // Determine object type and dispatch
-    if obj_path.contains("DHCP") || obj_path.contains("dhcp") {
+    if obj_path.starts_with("Device.LocalAgent.Subscription.") {
+        delete_subscription(obj_path, instance)
+    } else if obj_path.contains("DHCP") || obj_path.contains("dhcp") {
         delete_dhcp_static_lease(obj_path, instance).await
     } else if obj_path.contains("Hosts") || obj_path.contains("hosts") {
         delete_static_host(obj_path, instance).await
@@ -273,6 +306,22 @@ async fn delete_wifi_interface(obj_path: &str, instance: u32) -> DeleteResult {
     }
 }
 
+fn delete_subscription(obj_path: &str, instance: u32) -> DeleteResult {
+    info!("Deleting subscription instance {}", instance);
+
+    let (success, err_code, err_msg) = match subscriptions::delete(instance) {
+        Ok(()) => (true, None, None),
+        Err(e) => (false, Some(dm_error_code(&e)), Some(e.to_string())),
+    };
+
+    DeleteResult {
+        obj_path: obj_path.to_string(),
+        success,
+        err_code,
+        err_msg,
+    }
+}
+
 async fn delete_static_host(obj_path: &str, instance: u32) -> DeleteResult {
     info!("Deleting static host instance {}", instance);
 
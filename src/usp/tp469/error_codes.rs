@@ -10,6 +10,9 @@ pub enum ErrorCode {
     ResourcesExceeded = 7004,
     InvalidInstanceIdentifier = 7007,
 
+    // Parameter errors
+    InvalidValue = 7012,
+
     // GET/SET/ADD/DELETE errors (7200-7299)
     RequiredParameterMissing = 7204,
     ObjectNotFound = 7206,
@@ -2,10 +2,11 @@
 //!
 //! Implements GetInstances request/response per TR-369 §6.1.6
 
-use super::search::extract_instance_number;
+use super::search::instance_paths;
 use crate::config::ClientConfig;
 use crate::usp::dm;
 use crate::usp::usp_msg;
+use std::collections::HashMap;
 
 /// Handle GetInstances request and return response message
 pub async fn handle_get_instances(
@@ -17,16 +18,16 @@ pub async fn handle_get_instances(
     let mut path_results = Vec::new();
 
     for path in obj_paths {
-        let instances = get_instances_for_path(cfg, path, first_level_only).await;
+        // Full (non-delta) read: every instance must be visible, not only the
+        // ones whose values changed since the last GET.
+        let params = dm::get_all(cfg, path).await;
+        let instances = instance_paths(path, params.keys().map(String::as_str), first_level_only);
 
-        // Convert to CurrInstance type
         let curr_insts: Vec<usp_msg::get_instances_resp::CurrInstance> = instances
             .into_iter()
-            .map(|(path, _instance)| {
-                usp_msg::get_instances_resp::CurrInstance {
-                    obj_path: path,
-                    unique_keys: std::collections::HashMap::new(), // Would populate from schema
-                }
+            .map(|obj_path| usp_msg::get_instances_resp::CurrInstance {
+                unique_keys: unique_keys(&obj_path, &params),
+                obj_path,
             })
             .collect();
 
@@ -55,31 +56,55 @@ pub async fn handle_get_instances(
     })
 }
 
-async fn get_instances_for_path(
-    cfg: &ClientConfig,
-    path: &str,
-    first_level_only: bool,
-) -> Vec<(String, u32)> {
-    let mut instances = Vec::new();
-
-    // Get all parameters under this path
-    let max_depth = if first_level_only { 1 } else { 0 };
-    let params = dm::get_params(cfg, &[path.into()], max_depth).await;
-
-    // Extract unique instance numbers from parameter paths
-    let mut seen_instances = std::collections::HashSet::new();
+/// Unique-key parameters per table, keyed by the table path with instance
+/// numbers written as `{i}`.  The first key present on an instance is
+/// reported: static Hosts entries have no PhysAddress, so their IPAddress is
+/// used instead.
+const UNIQUE_KEYS: &[(&str, &[&str])] = &[
+    ("Device.Hosts.Host.{i}.", &["PhysAddress", "IPAddress"]),
+    (
+        "Device.DHCPv4.Server.Pool.{i}.StaticAddress.{i}.",
+        &["Chaddr"],
+    ),
+    ("Device.DHCPv4.Server.Pool.{i}.Client.{i}.", &["Chaddr"]),
+    ("Device.WiFi.Radio.{i}.", &["Name"]),
+    ("Device.WiFi.SSID.{i}.", &["BSSID"]),
+    ("Device.WiFi.AccessPoint.{i}.", &["SSIDReference"]),
+    (
+        "Device.WiFi.AccessPoint.{i}.AssociatedDevice.{i}.",
+        &["MACAddress"],
+    ),
+    ("Device.LocalAgent.Subscription.{i}.", &["ID"]),
+];
 
-    for (param_path, _) in params {
-        if let Some(instance) = extract_instance_number(&param_path) {
-            if seen_instances.insert(instance) {
-                // Get the object path (without parameter)
-                if let Some(last_dot) = param_path.rfind('.') {
-                    let obj_path = &param_path[..last_dot];
-                    instances.push((obj_path.to_string(), instance));
-                }
+/// `Device.WiFi.SSID.2.` → `Device.WiFi.SSID.{i}.`
+fn table_pattern(obj_path: &str) -> String {
+    obj_path
+        .split('.')
+        .map(|seg| {
+            if !seg.is_empty() && seg.bytes().all(|b| b.is_ascii_digit()) {
+                "{i}"
+            } else {
+                seg
             }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Unique-key values of the instance at `obj_path`, read from `params`.
+pub fn unique_keys(obj_path: &str, params: &HashMap<String, String>) -> HashMap<String, String> {
+    let pattern = table_pattern(obj_path);
+    let mut keys = HashMap::new();
+    let names = match UNIQUE_KEYS.iter().find(|(table, _)| *table == pattern) {
+        Some((_, names)) => *names,
+        None => return keys,
+    };
+    for name in names {
+        if let Some(value) = params.get(&format!("{obj_path}{name}")) {
+            keys.insert(name.to_string(), value.clone());
+            break;
         }
     }
-
-    instances
+    keys
 }
@@ -1,21 +1,22 @@
 //! TP-469 GetSupportedDM Message Handler
 //!
-//! Implements GetSupportedDM request/response per TR-369 §6.1.5
-//! NOTE: Simplified version - full implementation requires matching exact protobuf schema
+//! Implements GetSupportedDM request/response per TR-369 §6.1.5, answering
+//! from the static table in [`super::supported_dm_schema`].
 
+use super::supported_dm_schema::{ObjectDef, ParamAccess, SUPPORTED_DM};
 use crate::usp::usp_msg;
+use crate::usp::usp_msg::get_supported_dm_resp as resp;
 
 /// Handle GetSupportedDM request and return response message
-/// NOTE: This is a simplified stub that returns basic info
 pub fn handle_get_supported_dm(
     msg_id: &str,
-    _obj_paths: &[String],
-    _first_level_only: bool,
-    _include_commands: bool,
-    _include_events: bool,
+    req: &usp_msg::GetSupportedDm,
 ) -> Option<usp_msg::Msg> {
-    // Simplified implementation - return empty supported objects
-    let path_results: Vec<usp_msg::get_supported_dm_resp::RequestedObjectResult> = vec![];
+    let path_results = req
+        .obj_paths
+        .iter()
+        .map(|path| requested_object_result(path, req))
+        .collect();
 
     Some(usp_msg::Msg {
         header: Some(usp_msg::Header {
@@ -33,3 +34,133 @@ pub fn handle_get_supported_dm(
         }),
     })
 }
+
+fn requested_object_result(
+    path: &str,
+    req: &usp_msg::GetSupportedDm,
+) -> resp::RequestedObjectResult {
+    let requested = supported_path(path);
+    let objs = matching_objects(&requested, req.first_level_only);
+    if objs.is_empty() {
+        return resp::RequestedObjectResult {
+            req_obj_path: path.to_string(),
+            err_code: 7026,
+            err_msg: format!("unsupported object path: {path}"),
+            ..Default::default()
+        };
+    }
+
+    resp::RequestedObjectResult {
+        req_obj_path: path.to_string(),
+        supported_objs: objs.into_iter().map(|o| supported_object(o, req)).collect(),
+        ..Default::default()
+    }
+}
+
+/// Normalise a requested path to schema form: trailing dot, instance
+/// numbers written as `{i}` (`Device.WiFi.SSID.1` → `Device.WiFi.SSID.{i}.`).
+pub fn supported_path(path: &str) -> String {
+    let mut out: String = path
+        .trim_end_matches('.')
+        .split('.')
+        .map(|seg| {
+            if !seg.is_empty() && seg.bytes().all(|b| b.is_ascii_digit()) {
+                "{i}"
+            } else {
+                seg
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".");
+    out.push('.');
+    out
+}
+
+/// The requested object and its sub-objects; only direct children when
+/// `first_level_only` is set.
+pub fn matching_objects(requested: &str, first_level_only: bool) -> Vec<&'static ObjectDef> {
+    SUPPORTED_DM
+        .iter()
+        .filter(|o| match o.path.strip_prefix(requested) {
+            Some("") => true,
+            Some(rest) => !first_level_only || object_depth(rest) == 1,
+            None => false,
+        })
+        .collect()
+}
+
+/// Number of object levels in a relative path, `{i}` not counting as one.
+fn object_depth(rest: &str) -> usize {
+    rest.split('.')
+        .filter(|seg| !seg.is_empty() && *seg != "{i}")
+        .count()
+}
+
+fn supported_object(obj: &ObjectDef, req: &usp_msg::GetSupportedDm) -> resp::SupportedObject {
+    use resp::supported_object::Access;
+    use resp::supported_param::Access as ParamAccessPb;
+
+    let supported_params = if req.return_params {
+        obj.params
+            .iter()
+            .map(|p| resp::SupportedParam {
+                param_name: p.name.to_string(),
+                access: match p.access {
+                    ParamAccess::ReadOnly => ParamAccessPb::ParamReadOnly,
+                    ParamAccess::ReadWrite => ParamAccessPb::ParamReadWrite,
+                    ParamAccess::WriteOnly => ParamAccessPb::ParamWriteOnly,
+                } as i32,
+                value_change: p.access != ParamAccess::WriteOnly,
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+
+    let supported_commands = if req.return_commands {
+        obj.commands
+            .iter()
+            .map(|c| resp::SupportedCommand {
+                command_name: c.name.to_string(),
+                input_arg_names: c.input_args.iter().map(|a| a.to_string()).collect(),
+                output_arg_names: c.output_args.iter().map(|a| a.to_string()).collect(),
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+
+    let supported_events = if req.return_events {
+        obj.events
+            .iter()
+            .map(|e| resp::SupportedEvent {
+                event_name: e.name.to_string(),
+                arg_names: e.args.iter().map(|a| a.to_string()).collect(),
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+
+    let unique_key_sets = if req.return_unique_key_sets && !obj.unique_keys.is_empty() {
+        vec![resp::SupportedUniqueKeySet {
+            unique_keys: obj.unique_keys.iter().map(|k| k.to_string()).collect(),
+        }]
+    } else {
+        vec![]
+    };
+
+    resp::SupportedObject {
+        obj_path: obj.path.to_string(),
+        access: if obj.add_delete {
+            Access::ObjAddDelete
+        } else {
+            Access::ObjReadOnly
+        } as i32,
+        is_multi_instance: obj.multi_instance,
+        supported_params,
+        supported_commands,
+        supported_events,
+        unique_key_sets,
+    }
+}
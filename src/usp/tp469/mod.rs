@@ -11,6 +11,7 @@ pub mod error_codes;
 pub mod get_instances;
 pub mod get_supported_dm;
 pub mod search;
+pub mod supported_dm_schema;
 pub mod uci_backend;
 
 #[cfg(test)]
@@ -2,9 +2,79 @@
 //!
 //! Implements path utilities per TR-369 §6.1.1
 
-/// Extract instance number from path
-/// Device.WiFi.SSID.1 -> 1
-pub fn extract_instance_number(path: &str) -> Option<u32> {
-    // Find the last numeric segment
-    path.split('.').next_back()?.parse().ok()
+/// Enumerate the object instances under `requested` from a set of parameter
+/// paths (TR-369 §6.1.6 GetInstances).
+///
+/// Every numeric segment below `requested` closes an instance path, so nested
+/// tables (`Device.WiFi.AccessPoint.1.AssociatedDevice.2.`) are found along
+/// with their parents.  With `first_level_only` only the first instance level
+/// below `requested` is returned.  Paths carry a trailing dot and are ordered
+/// by instance number.
+pub fn instance_paths<'a>(
+    requested: &str,
+    param_paths: impl IntoIterator<Item = &'a str>,
+    first_level_only: bool,
+) -> Vec<String> {
+    let prefix = if requested.ends_with('.') {
+        requested.to_string()
+    } else {
+        format!("{requested}.")
+    };
+    let mut found = std::collections::BTreeSet::new();
+
+    for param in param_paths {
+        let rest = match param.strip_prefix(&prefix) {
+            Some(r) => r,
+            None => continue,
+        };
+        // The last segment is the parameter name itself.
+        let segments: Vec<&str> = rest.split('.').collect();
+        let mut obj = prefix.clone();
+        for seg in &segments[..segments.len().saturating_sub(1)] {
+            obj.push_str(seg);
+            obj.push('.');
+            if seg.parse::<u32>().is_ok() {
+                found.insert(InstancePath(obj.clone()));
+                if first_level_only {
+                    break;
+                }
+            }
+        }
+    }
+
+    found.into_iter().map(|p| p.0).collect()
+}
+
+/// Object path ordered segment-wise, numerically for instance numbers
+/// (`SSID.2.` before `SSID.10.`).
+#[derive(PartialEq, Eq)]
+struct InstancePath(String);
+
+impl Ord for InstancePath {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let mut a = self.0.split('.');
+        let mut b = other.0.split('.');
+        loop {
+            match (a.next(), b.next()) {
+                (None, None) => return std::cmp::Ordering::Equal,
+                (None, Some(_)) => return std::cmp::Ordering::Less,
+                (Some(_), None) => return std::cmp::Ordering::Greater,
+                (Some(x), Some(y)) => {
+                    let ord = match (x.parse::<u32>(), y.parse::<u32>()) {
+                        (Ok(n), Ok(m)) => n.cmp(&m),
+                        _ => x.cmp(y),
+                    };
+                    if ord != std::cmp::Ordering::Equal {
+                        return ord;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl PartialOrd for InstancePath {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
 }
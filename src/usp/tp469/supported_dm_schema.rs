@@ -0,0 +1,580 @@
+//! Supported data model served by this agent, for GetSupportedDM.
+//!
+//! Lists the objects, parameters, commands and events the `dm` handlers
+//! actually implement.  Multi-instance objects use `{i}` for the instance
+//! number, as in TR-181.  Keep this table in step with the handlers when a
+//! parameter is added or becomes writable.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamAccess {
+    ReadOnly,
+    ReadWrite,
+    WriteOnly,
+}
+
+#[derive(Debug)]
+pub struct ParamDef {
+    pub name: &'static str,
+    pub access: ParamAccess,
+}
+
+#[derive(Debug)]
+pub struct CommandDef {
+    pub name: &'static str,
+    pub input_args: &'static [&'static str],
+    pub output_args: &'static [&'static str],
+}
+
+#[derive(Debug)]
+pub struct EventDef {
+    pub name: &'static str,
+    pub args: &'static [&'static str],
+}
+
+#[derive(Debug)]
+pub struct ObjectDef {
+    pub path: &'static str,
+    pub multi_instance: bool,
+    /// Instances can be created and deleted with ADD/DELETE.
+    pub add_delete: bool,
+    pub params: &'static [ParamDef],
+    pub commands: &'static [CommandDef],
+    pub events: &'static [EventDef],
+    pub unique_keys: &'static [&'static str],
+}
+
+const fn ro(name: &'static str) -> ParamDef {
+    ParamDef {
+        name,
+        access: ParamAccess::ReadOnly,
+    }
+}
+
+const fn rw(name: &'static str) -> ParamDef {
+    ParamDef {
+        name,
+        access: ParamAccess::ReadWrite,
+    }
+}
+
+const fn wo(name: &'static str) -> ParamDef {
+    ParamDef {
+        name,
+        access: ParamAccess::WriteOnly,
+    }
+}
+
+/// Single-instance object with parameters only.
+const fn object(path: &'static str, params: &'static [ParamDef]) -> ObjectDef {
+    ObjectDef {
+        path,
+        multi_instance: false,
+        add_delete: false,
+        params,
+        commands: &[],
+        events: &[],
+        unique_keys: &[],
+    }
+}
+
+/// Read-only table (instances follow the device, not the controller).
+const fn table(
+    path: &'static str,
+    params: &'static [ParamDef],
+    unique_keys: &'static [&'static str],
+) -> ObjectDef {
+    ObjectDef {
+        path,
+        multi_instance: true,
+        add_delete: false,
+        params,
+        commands: &[],
+        events: &[],
+        unique_keys,
+    }
+}
+
+pub static SUPPORTED_DM: &[ObjectDef] = &[
+    object("Device.", &[]),
+    // ── DeviceInfo ──────────────────────────────────────────────────────────
+    ObjectDef {
+        commands: &[
+            CommandDef {
+                name: "Reboot()",
+                input_args: &["delay_seconds"],
+                output_args: &["status", "delay_seconds"],
+            },
+            CommandDef {
+                name: "FactoryReset()",
+                input_args: &["keep_certs"],
+                output_args: &["status"],
+            },
+        ],
+        events: &[
+            EventDef {
+                name: "Boot!",
+                args: &["CommandKey", "Cause", "FirmwareUpdated", "ParameterMap"],
+            },
+            EventDef {
+                name: "X_OptimACS_TempAlarm!",
+                args: &["State", "Temperature", "Threshold"],
+            },
+        ],
+        ..object(
+            "Device.DeviceInfo.",
+            &[
+                rw("HostName"),
+                ro("Manufacturer"),
+                ro("ManufacturerOUI"),
+                ro("ModelName"),
+                ro("Description"),
+                ro("ProductClass"),
+                ro("SerialNumber"),
+                ro("HardwareVersion"),
+                ro("SoftwareVersion"),
+                ro("AdditionalSoftwareVersion"),
+                ro("ProcessorArchitecture"),
+                ro("BaseMacAddress"),
+                ro("DeviceStatus"),
+                ro("UpTime"),
+                ro("X_OptimACS_LoadAvg"),
+                ro("X_OptimACS_FreeMem"),
+                ro("X_OptimACS_MemTotal"),
+                ro("X_OptimACS_KernelVersion"),
+                ro("X_OptimACS_Distribution"),
+                ro("X_OptimACS_Target"),
+                ro("X_OptimACS_FlashTotal"),
+                ro("X_OptimACS_FlashUsed"),
+                ro("X_OptimACS_FlashFree"),
+                ro("X_OptimACS_TmpTotal"),
+                ro("X_OptimACS_TmpFree"),
+                ro("X_OptimACS_ConntrackCount"),
+                ro("X_OptimACS_ConntrackMax"),
+                ro("X_OptimACS_PowerSource"),
+                ro("X_OptimACS_BatteryLevel"),
+                ro("X_OptimACS_PowerVoltage"),
+                ro("X_OptimACS_PublicIP"),
+                ro("X_OptimACS_WatchdogEnabled"),
+                ro("X_OptimACS_WatchdogInterval"),
+                rw("X_OptimACS_LogLevel"),
+                ro("X_OptimACS_ConfigAppliedTime"),
+                ro("X_OptimACS_Latitude"),
+                ro("X_OptimACS_Longitude"),
+                ro("X_OptimACS_Position"),
+                ro("X_OptimACS_GnssSource"),
+                ro("X_OptimACS_GnssDevice"),
+                ro("X_OptimACS_GnssLastFixAge"),
+                ro("X_OptimACS_NeighborCount"),
+            ],
+        )
+    },
+    object("Device.DeviceInfo.ProcessStatus.", &[ro("CPUUsage")]),
+    object(
+        "Device.DeviceInfo.TemperatureStatus.",
+        &[ro("TemperatureSensorNumberOfEntries")],
+    ),
+    table(
+        "Device.DeviceInfo.TemperatureStatus.TemperatureSensor.{i}.",
+        &[ro("Name"), ro("Value")],
+        &["Name"],
+    ),
+    // ── Ethernet ────────────────────────────────────────────────────────────
+    object("Device.Ethernet.", &[ro("InterfaceNumberOfEntries")]),
+    table(
+        "Device.Ethernet.Interface.{i}.",
+        &[
+            ro("Name"),
+            ro("Status"),
+            ro("MACAddress"),
+            ro("CurrentBitRate"),
+            ro("DuplexMode"),
+            ro("X_OptimACS_LinkSpeed"),
+        ],
+        &["Name"],
+    ),
+    // ── WiFi ────────────────────────────────────────────────────────────────
+    object("Device.WiFi.", &[]),
+    ObjectDef {
+        commands: &[
+            CommandDef {
+                name: "X_OptimACS_ChannelSurvey()",
+                input_args: &[],
+                output_args: &[
+                    "ChannelNumberOfEntries",
+                    "Channel.{i}.Channel",
+                    "Channel.{i}.Frequency",
+                    "Channel.{i}.InUse",
+                    "Channel.{i}.Noise",
+                    "Channel.{i}.ActiveTime",
+                    "Channel.{i}.BusyTime",
+                    "Channel.{i}.Utilization",
+                ],
+            },
+            CommandDef {
+                name: "X_OptimACS_ScanChannels()",
+                input_args: &[],
+                output_args: &["results", "current_channel"],
+            },
+        ],
+        ..table(
+            "Device.WiFi.Radio.{i}.",
+            &[
+                rw("Enable"),
+                ro("Status"),
+                ro("Name"),
+                rw("Channel"),
+                ro("OperatingFrequencyBand"),
+                rw("OperatingChannelBandwidth"),
+                ro("PossibleChannels"),
+                ro("SupportedStandards"),
+                ro("MaxBitRate"),
+                rw("TransmitPower"),
+                ro("IEEE"),
+                ro("BeaconPeriod"),
+                ro("DTIMPeriod"),
+                ro("RTSThreshold"),
+                ro("GuardInterval"),
+                ro("MaxAssociatedDevices"),
+                ro("AssociatedDeviceNumberOfEntries"),
+                ro("X_OptimACS_BSSID"),
+                ro("X_OptimACS_Bitrate"),
+            ],
+            &["Name"],
+        )
+    },
+    table(
+        "Device.WiFi.SSID.{i}.",
+        &[rw("Enable"), ro("Status"), rw("SSID"), ro("BSSID")],
+        &["BSSID"],
+    ),
+    table(
+        "Device.WiFi.AccessPoint.{i}.",
+        &[
+            ro("Status"),
+            ro("SSIDReference"),
+            ro("Mode"),
+            ro("BSSID"),
+            rw("SSIDAdvertisementEnabled"),
+            rw("MaxAssociatedDevices"),
+            rw("WMMEnable"),
+            ro("IsolationEnable"),
+            ro("AssociatedDeviceNumberOfEntries"),
+            ro("X_OptimACS_ClientHistoryNumberOfEntries"),
+        ],
+        &["SSIDReference"],
+    ),
+    object(
+        "Device.WiFi.AccessPoint.{i}.Security.",
+        &[
+            rw("ModeEnabled"),
+            wo("KeyPassphrase"),
+            ro("WPAEncryptionModes"),
+            ro("MFPConfig"),
+            rw("RekeyingInterval"),
+            rw("X_OptimACS_PMKCaching"),
+            rw("RadiusServerIPAddr"),
+            rw("RadiusServerPort"),
+            wo("RadiusSecret"),
+        ],
+    ),
+    ObjectDef {
+        commands: &[CommandDef {
+            name: "X_OptimACS_PushButton()",
+            input_args: &[],
+            output_args: &["Status", "Interface"],
+        }],
+        ..object("Device.WiFi.AccessPoint.{i}.WPS.", &[])
+    },
+    table(
+        "Device.WiFi.AccessPoint.{i}.AssociatedDevice.{i}.",
+        &[
+            ro("MACAddress"),
+            ro("IPAddress"),
+            ro("SignalStrength"),
+            ro("LastDataDownlinkRate"),
+            ro("LastDataUplinkRate"),
+            ro("BytesSent"),
+            ro("BytesReceived"),
+            ro("AssociationTime"),
+        ],
+        &["MACAddress"],
+    ),
+    table(
+        "Device.WiFi.AccessPoint.{i}.X_OptimACS_ClientHistory.{i}.",
+        &[
+            ro("MACAddress"),
+            ro("ConnectTime"),
+            ro("DisconnectTime"),
+            ro("LastSignalStrength"),
+        ],
+        &[],
+    ),
+    // ── IP ──────────────────────────────────────────────────────────────────
+    object("Device.IP.", &[]),
+    ObjectDef {
+        commands: &[
+            CommandDef {
+                name: "IPPing()",
+                input_args: &["Host", "NumberOfRepetitions", "Timeout", "DataBlockSize"],
+                output_args: &[
+                    "Status",
+                    "SuccessCount",
+                    "FailureCount",
+                    "AverageResponseTime",
+                    "MinimumResponseTime",
+                    "MaximumResponseTime",
+                ],
+            },
+            CommandDef {
+                name: "TraceRoute()",
+                input_args: &["Host", "NumberOfTries", "Timeout", "MaxHopCount"],
+                output_args: &[
+                    "Status",
+                    "RouteHopsNumberOfEntries",
+                    "RouteHops.{i}.Host",
+                    "RouteHops.{i}.HopRTTimes",
+                ],
+            },
+        ],
+        ..object("Device.IP.Diagnostics.", &[])
+    },
+    ObjectDef {
+        commands: &[CommandDef {
+            name: "Reset()",
+            input_args: &[],
+            output_args: &["Interface"],
+        }],
+        ..table(
+            "Device.IP.Interface.{i}.",
+            &[
+                ro("Status"),
+                ro("Name"),
+                ro("MACAddress"),
+                rw("MaxMTUSize"),
+                ro("X_OptimACS_Name"),
+                ro("X_OptimACS_Protocol"),
+                ro("X_OptimACS_Upstream"),
+                ro("X_OptimACS_Uptime"),
+                rw("X_OptimACS_Gateway"),
+                rw("X_OptimACS_DNS"),
+                ro("X_OptimACS_RXBytes"),
+                ro("X_OptimACS_TXBytes"),
+                ro("X_OptimACS_RXPackets"),
+                ro("X_OptimACS_TXPackets"),
+            ],
+            &["X_OptimACS_Name"],
+        )
+    },
+    table(
+        "Device.IP.Interface.{i}.IPv4Address.{i}.",
+        &[rw("IPAddress"), rw("SubnetMask"), rw("AddressingType")],
+        &["IPAddress"],
+    ),
+    object(
+        "Device.IP.Interface.{i}.Stats.",
+        &[
+            ro("BytesSent"),
+            ro("BytesReceived"),
+            ro("PacketsSent"),
+            ro("PacketsReceived"),
+        ],
+    ),
+    table(
+        "Device.IP.Interface.{i}.IPv6Address.{i}.",
+        &[
+            ro("IPAddress"),
+            ro("PrefixLength"),
+            ro("Prefix"),
+            ro("Origin"),
+        ],
+        &["IPAddress"],
+    ),
+    // ── DHCPv4 ──────────────────────────────────────────────────────────────
+    object("Device.DHCPv4.", &[]),
+    object("Device.DHCPv4.Server.", &[ro("PoolNumberOfEntries")]),
+    table(
+        "Device.DHCPv4.Server.Pool.{i}.",
+        &[
+            ro("Enable"),
+            ro("Status"),
+            ro("Interface"),
+            ro("MinAddress"),
+            ro("MaxAddress"),
+            ro("SubnetMask"),
+            ro("DomainName"),
+            ro("DNSServers"),
+            ro("LeaseTime"),
+            ro("LeaseNumberOfEntries"),
+            ro("StaticAddressNumberOfEntries"),
+        ],
+        &["Interface"],
+    ),
+    ObjectDef {
+        add_delete: true,
+        ..table(
+            "Device.DHCPv4.Server.Pool.{i}.StaticAddress.{i}.",
+            &[rw("Chaddr"), rw("Yiaddr"), rw("X_OptimACS_Hostname")],
+            &["Chaddr"],
+        )
+    },
+    table(
+        "Device.DHCPv4.Server.Pool.{i}.Client.{i}.",
+        &[
+            ro("Chaddr"),
+            ro("X_OptimACS_Hostname"),
+            ro("LeaseTimeRemaining"),
+        ],
+        &["Chaddr"],
+    ),
+    table(
+        "Device.DHCPv4.Server.Pool.{i}.Client.{i}.IPv4Address.{i}.",
+        &[ro("IPAddress")],
+        &["IPAddress"],
+    ),
+    // ── Hosts ───────────────────────────────────────────────────────────────
+    object("Device.Hosts.", &[]),
+    ObjectDef {
+        add_delete: true,
+        ..table(
+            "Device.Hosts.Host.{i}.",
+            &[
+                rw("HostName"),
+                rw("IPAddress"),
+                ro("PhysAddress"),
+                ro("AddressSource"),
+                ro("Active"),
+                ro("LeaseTimeRemaining"),
+                ro("X_OptimACS_Interface"),
+            ],
+            &["PhysAddress"],
+        )
+    },
+    // ── LocalAgent ──────────────────────────────────────────────────────────
+    ObjectDef {
+        commands: &[CommandDef {
+            name: "X_OptimACS_ReRegister()",
+            input_args: &[],
+            output_args: &["EndpointID", "Cause", "ParameterMap"],
+        }],
+        ..object(
+            "Device.LocalAgent.",
+            &[
+                rw("X_OptimACS_StatusInterval"),
+                ro("SubscriptionNumberOfEntries"),
+            ],
+        )
+    },
+    ObjectDef {
+        add_delete: true,
+        ..table(
+            "Device.LocalAgent.Subscription.{i}.",
+            &[
+                rw("Enable"),
+                ro("ID"),
+                rw("Recipient"),
+                rw("NotifType"),
+                rw("ReferenceList"),
+                rw("NotifRetry"),
+            ],
+            &["ID"],
+        )
+    },
+    table("Device.LocalAgent.Controller.{i}.", &[], &[]),
+    object(
+        "Device.LocalAgent.Controller.{i}.E2ESession.",
+        &[rw("Enable")],
+    ),
+    table("Device.LocalAgent.Controller.{i}.MTP.{i}.", &[], &[]),
+    object(
+        "Device.LocalAgent.Controller.{i}.MTP.{i}.WebSocket.",
+        &[rw("Host"), rw("Port"), rw("Path")],
+    ),
+    // ── Vendor objects ──────────────────────────────────────────────────────
+    ObjectDef {
+        commands: &[
+            CommandDef {
+                name: "Download()",
+                input_args: &["url", "md5", "sha256"],
+                output_args: &["status", "size"],
+            },
+            CommandDef {
+                name: "Rollback()",
+                input_args: &[],
+                output_args: &["status", "method", "previous_version"],
+            },
+        ],
+        ..object(
+            "Device.X_OptimACS_Firmware.",
+            &[ro("AvailableVersion"), ro("RollbackAvailable")],
+        )
+    },
+    ObjectDef {
+        commands: &[CommandDef {
+            name: "IssueCert()",
+            input_args: &["ca_cert", "cert", "key", "key_type"],
+            output_args: &["status", "message", "csr"],
+        }],
+        ..object(
+            "Device.X_OptimACS_Security.",
+            &[
+                wo("DevicePassword"),
+                ro("X_OptimACS_EnrollStatus"),
+                wo("X_OptimACS_SignedCert"),
+            ],
+        )
+    },
+    object("Device.X_OptimACS_Network.", &[ro("BridgeNumberOfEntries")]),
+    ObjectDef {
+        commands: &[CommandDef {
+            name: "Restart()",
+            input_args: &[],
+            output_args: &["status", "message"],
+        }],
+        ..table(
+            "Device.X_OptimACS_Network.Bridge.{i}.",
+            &[
+                ro("Enable"),
+                ro("Status"),
+                rw("Name"),
+                rw("Type"),
+                rw("Ports"),
+                rw("Proto"),
+                rw("IPAddress"),
+                rw("Netmask"),
+                rw("Gateway"),
+                ro("DNS"),
+            ],
+            &["Name"],
+        )
+    },
+    ObjectDef {
+        commands: &[CommandDef {
+            name: "Restart()",
+            input_args: &["Service"],
+            output_args: &["Service", "ExitCode"],
+        }],
+        ..object("Device.X_OptimACS_Services.", &[])
+    },
+    object(
+        "Device.X_OptimACS_GNSS.",
+        &[
+            ro("Latitude"),
+            ro("Longitude"),
+            ro("Altitude"),
+            ro("FixQuality"),
+            ro("Satellites"),
+            ro("LastFixAge"),
+        ],
+    ),
+    ObjectDef {
+        commands: &[CommandDef {
+            name: "Confirm()",
+            input_args: &[],
+            output_args: &["Status"],
+        }],
+        ..object(
+            "Device.X_OptimACS_Config.",
+            &[ro("ConfirmPending"), ro("ConfirmTimeRemaining")],
+        )
+    },
+];
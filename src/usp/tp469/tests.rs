@@ -103,6 +103,149 @@ mod tests {
         // Integration test for WiFi ADD
     }
 
+    // ─────────────────────────────────────────────────────────────────────────
+    // GetInstances Tests
+    // ─────────────────────────────────────────────────────────────────────────
+
+    const WIFI_TREE: &[&str] = &[
+        "Device.WiFi.RadioNumberOfEntries",
+        "Device.WiFi.SSID.1.SSID",
+        "Device.WiFi.SSID.2.SSID",
+        "Device.WiFi.SSID.10.SSID",
+        "Device.WiFi.AccessPoint.1.Status",
+        "Device.WiFi.AccessPoint.1.AssociatedDevice.1.MACAddress",
+        "Device.WiFi.AccessPoint.1.AssociatedDevice.2.MACAddress",
+        "Device.WiFi.AccessPoint.2.Status",
+    ];
+
+    #[test]
+    fn test_get_instances_nested() {
+        let paths = search::instance_paths("Device.WiFi.", WIFI_TREE.iter().copied(), false);
+        assert_eq!(
+            paths,
+            vec![
+                "Device.WiFi.AccessPoint.1.",
+                "Device.WiFi.AccessPoint.1.AssociatedDevice.1.",
+                "Device.WiFi.AccessPoint.1.AssociatedDevice.2.",
+                "Device.WiFi.AccessPoint.2.",
+                "Device.WiFi.SSID.1.",
+                "Device.WiFi.SSID.2.",
+                "Device.WiFi.SSID.10.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_supported_dm_flags_and_depth() {
+        use crate::usp::usp_msg::{body::MsgBody, response::RespType, GetSupportedDm};
+
+        let req = GetSupportedDm {
+            obj_paths: vec!["Device.WiFi.".into(), "Device.Camera.".into()],
+            first_level_only: true,
+            return_commands: true,
+            ..Default::default()
+        };
+        let msg = handle_get_supported_dm("gsdm-1", &req).unwrap();
+        let results = match msg.body.and_then(|b| b.msg_body) {
+            Some(MsgBody::Response(r)) => match r.resp_type {
+                Some(RespType::GetSupportedDmResp(r)) => r.req_obj_results,
+                other => panic!("unexpected response {other:?}"),
+            },
+            other => panic!("unexpected body {other:?}"),
+        };
+
+        let wifi: Vec<&str> = results[0]
+            .supported_objs
+            .iter()
+            .map(|o| o.obj_path.as_str())
+            .collect();
+        assert_eq!(
+            wifi,
+            vec![
+                "Device.WiFi.",
+                "Device.WiFi.Radio.{i}.",
+                "Device.WiFi.SSID.{i}.",
+                "Device.WiFi.AccessPoint.{i}."
+            ]
+        );
+        let radio = &results[0].supported_objs[1];
+        assert!(radio.is_multi_instance);
+        assert!(radio.supported_params.is_empty());
+        assert_eq!(
+            radio.supported_commands[0].command_name,
+            "X_OptimACS_ChannelSurvey()"
+        );
+        assert_eq!(results[1].err_code, 7026);
+
+        // Instance numbers map onto the table; nested objects without first_level_only
+        let objs = get_supported_dm::matching_objects(
+            &get_supported_dm::supported_path("Device.DHCPv4.Server.Pool.1"),
+            false,
+        );
+        assert_eq!(objs.len(), 4);
+        assert_eq!(
+            objs[1].path,
+            "Device.DHCPv4.Server.Pool.{i}.StaticAddress.{i}."
+        );
+        assert!(objs[1].add_delete);
+    }
+
+    #[test]
+    fn test_get_instances_unique_keys() {
+        let params: std::collections::HashMap<String, String> = [
+            ("Device.Hosts.Host.1.IPAddress", "192.168.1.20"),
+            ("Device.Hosts.Host.2.IPAddress", "192.168.1.31"),
+            ("Device.Hosts.Host.2.PhysAddress", "AA:BB:CC:00:00:02"),
+            (
+                "Device.DHCPv4.Server.Pool.1.StaticAddress.3.Chaddr",
+                "AA:BB:CC:00:00:03",
+            ),
+            ("Device.WiFi.SSID.1.SSID", "office"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let keys = get_instances::unique_keys("Device.Hosts.Host.1.", &params);
+        assert_eq!(
+            keys.get("IPAddress").map(String::as_str),
+            Some("192.168.1.20")
+        );
+        let keys = get_instances::unique_keys("Device.Hosts.Host.2.", &params);
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys["PhysAddress"], "AA:BB:CC:00:00:02");
+        let keys =
+            get_instances::unique_keys("Device.DHCPv4.Server.Pool.1.StaticAddress.3.", &params);
+        assert_eq!(keys["Chaddr"], "AA:BB:CC:00:00:03");
+        // SSID table key (BSSID) not reported by this read
+        assert!(get_instances::unique_keys("Device.WiFi.SSID.1.", &params).is_empty());
+    }
+
+    #[test]
+    fn test_get_instances_first_level_only() {
+        let paths = search::instance_paths("Device.WiFi.", WIFI_TREE.iter().copied(), true);
+        assert_eq!(
+            paths,
+            vec![
+                "Device.WiFi.AccessPoint.1.",
+                "Device.WiFi.AccessPoint.2.",
+                "Device.WiFi.SSID.1.",
+                "Device.WiFi.SSID.2.",
+                "Device.WiFi.SSID.10.",
+            ]
+        );
+
+        let nested =
+            search::instance_paths("Device.WiFi.AccessPoint.1", WIFI_TREE.iter().copied(), true);
+        assert_eq!(
+            nested,
+            vec![
+                "Device.WiFi.AccessPoint.1.AssociatedDevice.1.",
+                "Device.WiFi.AccessPoint.1.AssociatedDevice.2.",
+            ]
+        );
+    }
+
     // ─────────────────────────────────────────────────────────────────────────
     // Compliance Summary Test
     // ─────────────────────────────────────────────────────────────────────────
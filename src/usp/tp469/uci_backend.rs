@@ -4,6 +4,10 @@
 //! Handles instance number management, rollback on failure, and service restarts.
 
 use super::error_codes::ErrorCode;
+use crate::usp::dm::dhcp::read_static_leases;
+use crate::usp::dm::hosts::{read_static_hosts, HostSource};
+use crate::usp::dm::wifi::get_wifi_ifaces;
+use crate::util::mac_no_colons;
 use log::{info, warn};
 use std::process::Command;
 
@@ -47,19 +51,23 @@ pub fn add_dhcp_lease(mac: &str, ip: &str, hostname: Option<&str>) -> UciResult
         mac, ip, hostname
     );
 
-    // Find next available host section index
-    let next_idx = find_next_dhcp_host_index();
-    if next_idx == 0 {
-        return UciResult::error(
-            ErrorCode::ResourcesExceeded,
-            "Could not find available host section",
+    // One reservation per MAC: hand back the existing instance
+    let leases = read_static_leases();
+    if let Some(pos) = leases.iter().position(|l| l.mac.eq_ignore_ascii_case(mac)) {
+        info!(
+            "DHCP static lease for {} already exists as instance {}",
+            mac,
+            pos + 1
         );
+        return UciResult::success(pos as u32 + 1);
     }
 
-    let section = format!("@host[{}]", next_idx);
+    // Named sections are appended, so the new lease is the last instance
+    let next_idx = leases.len() + 1;
+    let section = format!("host_{}", mac_no_colons(mac).to_lowercase());
 
     // Add the host section
-    if let Err(e) = uci_add("dhcp", "host") {
+    if let Err(e) = uci_set(&format!("dhcp.{}", section), "host") {
         return UciResult::error(
             ErrorCode::InternalError,
             &format!("Failed to add host section: {}", e),
@@ -118,17 +126,9 @@ pub fn add_dhcp_lease(mac: &str, ip: &str, hostname: Option<&str>) -> UciResult
 pub fn delete_dhcp_lease(instance: u32) -> UciResult {
     info!("Deleting DHCP static lease instance {}", instance);
 
-    let section = format!("@host[{}]", instance);
-    let full_path = format!("dhcp.{}", section);
-
-    // Check if section exists
-    let out = Command::new("uci")
-        .args(["get", &format!("{}.mac", full_path)])
-        .output();
-
-    match out {
-        Ok(result) if result.status.success() => {
-            // Section exists, delete it
+    match read_static_leases().get((instance as usize).wrapping_sub(1)) {
+        Some(lease) => {
+            let full_path = format!("dhcp.{}", lease.section);
             if let Err(e) = uci_delete(&full_path) {
                 return UciResult::error(
                     ErrorCode::InternalError,
@@ -151,45 +151,20 @@ pub fn delete_dhcp_lease(instance: u32) -> UciResult {
             info!("Successfully deleted DHCP lease instance {}", instance);
             UciResult::success(instance)
         }
-        _ => UciResult::error(
+        None => UciResult::error(
             ErrorCode::ObjectNotFound,
             &format!("DHCP lease instance {} not found", instance),
         ),
     }
 }
 
-/// Find the next available DHCP host section index
-fn find_next_dhcp_host_index() -> usize {
-    let mut idx = 1;
-
-    loop {
-        let section = format!("@host[{}]", idx);
-        let out = Command::new("uci")
-            .args(["get", &format!("dhcp.{}.mac", section)])
-            .output();
-
-        match out {
-            Ok(result) if result.status.success() => {
-                // Section exists, try next
-                idx += 1;
-            }
-            _ => {
-                // Section doesn't exist, this is our slot
-                return idx;
-            }
-        }
-
-        // Safety limit
-        if idx > 100 {
-            return 0;
-        }
-    }
-}
-
 // ─────────────────────────────────────────────────────────────────────────────
 // WiFi Interface Operations
 // ─────────────────────────────────────────────────────────────────────────────
 
+/// Upper bound on wifi-iface sections before ADD is refused.
+const MAX_WIFI_IFACES: usize = 50;
+
 /// Add a new WiFi interface (SSID)
 pub fn add_wifi_interface(
     ssid: &str,
@@ -199,24 +174,23 @@ pub fn add_wifi_interface(
 ) -> UciResult {
     info!("Adding WiFi interface: SSID={}", ssid);
 
-    // Find next available wifi-iface index
-    let next_idx = find_next_wifi_iface_index();
-    if next_idx == 0 {
+    if get_wifi_ifaces().len() >= MAX_WIFI_IFACES {
         return UciResult::error(
             ErrorCode::ResourcesExceeded,
             "Could not find available wifi-iface section",
         );
     }
 
-    let section = format!("@wifi-iface[{}]", next_idx);
-
-    // Add the wifi-iface section
+    // Add the wifi-iface section; `uci add` appends it, so it is the last
+    // one and its instance number is the new wifi-iface count
     if let Err(e) = uci_add("wireless", "wifi-iface") {
         return UciResult::error(
             ErrorCode::InternalError,
             &format!("Failed to add wifi-iface: {}", e),
         );
     }
+    let section = "@wifi-iface[-1]";
+    let next_idx = get_wifi_ifaces().len();
 
     // Set SSID
     if let Err(e) = uci_set(&format!("wireless.{}.ssid", section), ssid) {
@@ -295,13 +269,24 @@ pub fn add_wifi_interface(
 pub fn delete_wifi_interface(instance: u32) -> UciResult {
     info!("Deleting WiFi interface instance {}", instance);
 
-    let section = format!("@wifi-iface[{}]", instance);
+    // Same instance numbering as Device.WiFi.SSID.{i} (1-based, config order)
+    let ifaces = get_wifi_ifaces();
+    let section = match (instance as usize)
+        .checked_sub(1)
+        .and_then(|i| ifaces.get(i))
+    {
+        Some(s) => s.clone(),
+        None => {
+            return UciResult::error(
+                ErrorCode::ObjectNotFound,
+                &format!("WiFi interface instance {} not found", instance),
+            )
+        }
+    };
     let full_path = format!("wireless.{}", section);
 
     // Check if section exists
-    let out = Command::new("uci")
-        .args(["get", &format!("{}.ssid", full_path)])
-        .output();
+    let out = Command::new("uci").args(["get", &full_path]).output();
 
     match out {
         Ok(result) if result.status.success() => {
@@ -335,34 +320,6 @@ pub fn delete_wifi_interface(instance: u32) -> UciResult {
     }
 }
 
-/// Find the next available wifi-iface index
-fn find_next_wifi_iface_index() -> usize {
-    let mut idx = 0;
-
-    loop {
-        let section = format!("@wifi-iface[{}]", idx);
-        let out = Command::new("uci")
-            .args(["get", &format!("wireless.{}.ssid", section)])
-            .output();
-
-        match out {
-            Ok(result) if result.status.success() => {
-                // Section exists, try next
-                idx += 1;
-            }
-            _ => {
-                // Section doesn't exist, this is our slot
-                return if idx == 0 { 1 } else { idx };
-            }
-        }
-
-        // Safety limit
-        if idx > 50 {
-            return 0;
-        }
-    }
-}
-
 // ─────────────────────────────────────────────────────────────────────────────
 // Static Host Operations
 // ─────────────────────────────────────────────────────────────────────────────
@@ -406,43 +363,52 @@ pub fn add_static_host(ip: &str, hostname: &str) -> UciResult {
     UciResult::success(instance)
 }
 
-/// Delete a static host entry
+/// Delete a static host entry, numbered as Device.Hosts.Host GET lists them
 pub fn delete_static_host(instance: u32) -> UciResult {
     info!("Deleting static host instance {}", instance);
 
-    // Find and remove from dnsmasq address list
-    let out = Command::new("uci")
-        .args(["get", "dhcp.@dnsmasq[0].address"])
-        .output();
-
-    if let Ok(result) = out {
-        if result.status.success() {
-            let stdout = String::from_utf8_lossy(&result.stdout);
-            let addresses: Vec<&str> = stdout.lines().collect();
-
-            if (instance as usize) < addresses.len() {
-                let to_remove = addresses[instance as usize].trim();
-
-                // Remove this specific address entry
-                if let Err(e) = uci_del_list("dhcp.@dnsmasq[0].address", to_remove) {
-                    warn!("Failed to remove from dnsmasq: {}", e);
-                } else {
-                    let _ = uci_commit("dhcp");
-                    let _ = restart_dnsmasq();
-                    return UciResult::success(instance);
-                }
+    let hosts = read_static_hosts();
+    let host = match hosts.get((instance as usize).wrapping_sub(1)) {
+        Some(h) => h,
+        None => {
+            // Instances past the static entries are learned neighbours
+            return UciResult::error(
+                ErrorCode::ObjectNotFound,
+                &format!("Host instance {} is not a static host entry", instance),
+            );
+        }
+    };
+
+    match &host.source {
+        HostSource::Dnsmasq(entry) => {
+            if let Err(e) = uci_del_list("dhcp.@dnsmasq[0].address", entry) {
+                return UciResult::error(
+                    ErrorCode::InternalError,
+                    &format!("Failed to remove from dnsmasq: {}", e),
+                );
+            }
+            if let Err(e) = uci_commit("dhcp") {
+                return UciResult::error(
+                    ErrorCode::InternalError,
+                    &format!("Failed to commit: {}", e),
+                );
+            }
+        }
+        HostSource::HostsFile(line) => {
+            if let Err(e) = remove_from_hosts_file(*line) {
+                return UciResult::error(ErrorCode::InternalError, &e);
             }
         }
     }
 
-    // Try removing from /etc/hosts
-    if let Err(e) = remove_from_hosts_file(instance) {
-        return UciResult::error(
-            ErrorCode::ObjectNotFound,
-            &format!("Host instance {} not found: {}", instance, e),
-        );
+    if let Err(e) = restart_dnsmasq() {
+        warn!("Failed to restart dnsmasq: {}", e);
     }
 
+    info!(
+        "Deleted static host instance {} ({} -> {})",
+        instance, host.hostname, host.ip
+    );
     UciResult::success(instance)
 }
 
@@ -464,35 +430,23 @@ fn add_to_hosts_file(ip: &str, hostname: &str) -> Result<(), String> {
     Ok(())
 }
 
-/// Remove entry from /etc/hosts file by instance number (line index)
-fn remove_from_hosts_file(instance: u32) -> Result<(), String> {
+/// Comment out line `line_idx` of /etc/hosts
+fn remove_from_hosts_file(line_idx: usize) -> Result<(), String> {
     use std::fs;
 
     let content = fs::read_to_string("/etc/hosts")
         .map_err(|e| format!("Failed to read /etc/hosts: {}", e))?;
 
-    let lines: Vec<&str> = content.lines().collect();
-    let mut host_entries = Vec::new();
-
-    // Find host entries (non-empty, non-comment lines)
-    for (i, line) in lines.iter().enumerate() {
-        let trimmed = line.trim();
-        if !trimmed.is_empty() && !trimmed.starts_with('#') {
-            host_entries.push(i);
-        }
-    }
-
-    if (instance as usize) >= host_entries.len() {
-        return Err(format!("Host instance {} not found", instance));
-    }
-
-    let line_idx = host_entries[instance as usize];
+    let mut lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+    let line = match lines.get_mut(line_idx) {
+        Some(l) => l,
+        None => return Err(format!("/etc/hosts has no line {}", line_idx + 1)),
+    };
 
     // Comment out the line instead of removing (safer)
-    let mut new_lines: Vec<String> = lines.iter().map(|&s| s.to_string()).collect();
-    new_lines[line_idx] = format!("# Removed by USP DELETE: {}", new_lines[line_idx]);
+    *line = format!("# Removed by USP DELETE: {}", line);
 
-    fs::write("/etc/hosts", new_lines.join("\n"))
+    fs::write("/etc/hosts", lines.join("\n") + "\n")
         .map_err(|e| format!("Failed to write /etc/hosts: {}", e))?;
 
     Ok(())
@@ -500,37 +454,11 @@ fn remove_from_hosts_file(instance: u32) -> Result<(), String> {
 
 /// Find instance number for a hostname
 fn find_host_instance_number(hostname: &str) -> u32 {
-    // Try dnsmasq addresses first
-    let out = Command::new("uci")
-        .args(["get", "dhcp.@dnsmasq[0].address"])
-        .output();
-
-    if let Ok(result) = out {
-        if result.status.success() {
-            let stdout = String::from_utf8_lossy(&result.stdout);
-            for (i, line) in stdout.lines().enumerate() {
-                if line.contains(hostname) {
-                    return i as u32;
-                }
-            }
-        }
-    }
-
-    // Try /etc/hosts
-    if let Ok(content) = std::fs::read_to_string("/etc/hosts") {
-        let mut host_count = 0;
-        for line in content.lines() {
-            let trimmed = line.trim();
-            if !trimmed.is_empty() && !trimmed.starts_with('#') {
-                if trimmed.contains(hostname) {
-                    return host_count;
-                }
-                host_count += 1;
-            }
-        }
-    }
-
-    0 // Default
+    read_static_hosts()
+        .iter()
+        .rposition(|h| h.hostname == hostname)
+        .map(|pos| pos as u32 + 1)
+        .unwrap_or(0)
 }
 
 // ─────────────────────────────────────────────────────────────────────────────
@@ -579,7 +507,7 @@ pub fn uci_set(path: &str, value: &str) -> Result<(), String> {
     }
 }
 
-fn uci_delete(path: &str) -> Result<(), String> {
+pub fn uci_delete(path: &str) -> Result<(), String> {
     let status = Command::new("uci")
         .args(["delete", path])
         .status()
@@ -653,6 +581,20 @@ pub fn uci_commit(config: &str) -> Result<(), String> {
     }
 }
 
+/// Discard uncommitted UCI changes for a config package
+pub fn uci_revert(config: &str) -> Result<(), String> {
+    let status = Command::new("uci")
+        .args(["revert", config])
+        .status()
+        .map_err(|e| format!("Failed to execute uci revert: {}", e))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("uci revert {} failed", config))
+    }
+}
+
 fn restart_dnsmasq() -> Result<(), String> {
     let methods: Vec<Vec<&str>> = vec![
         vec!["/etc/init.d/dnsmasq", "restart"],
@@ -729,6 +671,16 @@ pub fn set_system_hostname(hostname: &str) -> UciResult {
     UciResult::success(1)
 }
 
+/// Full `uci export` of a config package, or `None` if it does not exist
+pub fn uci_export(config: &str) -> Option<String> {
+    let out = Command::new("uci").args(["export", config]).output().ok()?;
+    if out.status.success() {
+        String::from_utf8(out.stdout).ok()
+    } else {
+        None
+    }
+}
+
 /// Get system hostname from UCI
 pub fn get_system_hostname() -> String {
     uci_get("system.@system[0].hostname")
@@ -83,17 +83,117 @@ pub fn get_default_gateway() -> String {
     String::new()
 }
 
+// ── ARP table ────────────────────────────────────────────────────────────────
+
+/// One row of `/proc/net/arp`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArpEntry {
+    pub ip: String,
+    pub mac: String,
+    /// Interface the neighbour was learned on (e.g. `br-lan`).
+    pub device: String,
+    /// ATF_COM set: the MAC has been resolved.
+    pub complete: bool,
+}
+
+/// Parse `/proc/net/arp` (IP, HW type, Flags, HW address, Mask, Device).
+pub fn parse_arp_table(content: &str) -> Vec<ArpEntry> {
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 6 {
+                return None;
+            }
+            let flags = u32::from_str_radix(fields[2].trim_start_matches("0x"), 16).unwrap_or(0);
+            Some(ArpEntry {
+                ip: fields[0].to_string(),
+                mac: fields[3].to_lowercase(),
+                device: fields[5].to_string(),
+                complete: flags & 0x2 != 0,
+            })
+        })
+        .collect()
+}
+
+pub fn read_arp_table() -> Vec<ArpEntry> {
+    fs::read_to_string("/proc/net/arp")
+        .map(|c| parse_arp_table(&c))
+        .unwrap_or_default()
+}
+
+/// Keep only neighbours learned on one of `lan_interfaces`, so the upstream
+/// router and other WAN-side peers are not reported as local clients.
+pub fn lan_arp_entries(entries: Vec<ArpEntry>, lan_interfaces: &[String]) -> Vec<ArpEntry> {
+    entries
+        .into_iter()
+        .filter(|e| lan_interfaces.contains(&e.device))
+        .collect()
+}
+
 // ── Firmware version ──────────────────────────────────────────────────────────
 
+/// Fields of `/etc/openwrt_release` (`DISTRIB_*` shell assignments).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpenWrtRelease {
+    /// `DISTRIB_ID`, e.g. "OpenWrt".
+    pub id: String,
+    /// `DISTRIB_RELEASE`, e.g. "23.05.3".
+    pub release: String,
+    /// `DISTRIB_REVISION`, e.g. "r23809-234f1a2efa".
+    pub revision: String,
+    /// `DISTRIB_TARGET`, e.g. "mediatek/filogic".
+    pub target: String,
+    /// `DISTRIB_ARCH`, e.g. "aarch64_cortex-a53".
+    pub arch: String,
+    /// `DISTRIB_DESCRIPTION`, e.g. "OpenWrt 23.05.3 r23809-234f1a2efa".
+    pub description: String,
+}
+
+impl OpenWrtRelease {
+    /// Distribution name and release, e.g. "OpenWrt 23.05.3".
+    pub fn distribution(&self) -> String {
+        format!("{} {}", self.id, self.release).trim().to_string()
+    }
+}
+
+/// Parse the contents of `/etc/openwrt_release`.
+pub fn parse_openwrt_release(content: &str) -> OpenWrtRelease {
+    let mut rel = OpenWrtRelease::default();
+    for line in content.lines() {
+        let (key, val) = match line.trim().split_once('=') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        let val = val.trim().trim_matches('\'').trim_matches('"').to_string();
+        match key.trim() {
+            "DISTRIB_ID" => rel.id = val,
+            "DISTRIB_RELEASE" => rel.release = val,
+            "DISTRIB_REVISION" => rel.revision = val,
+            "DISTRIB_TARGET" => rel.target = val,
+            "DISTRIB_ARCH" => rel.arch = val,
+            "DISTRIB_DESCRIPTION" => rel.description = val,
+            _ => {}
+        }
+    }
+    rel
+}
+
+/// Read and parse `/etc/openwrt_release`; `None` if the file is unreadable.
+pub fn read_openwrt_release() -> Option<OpenWrtRelease> {
+    fs::read_to_string("/etc/openwrt_release")
+        .ok()
+        .map(|c| parse_openwrt_release(&c))
+}
+
 /// Read the firmware version string from `/etc/openwrt_release` or
 /// `/etc/openwrt_version`.  Returns an empty string on failure.
 pub fn read_fw_version() -> String {
     // Try the release file first (DISTRIB_REVISION field)
-    if let Ok(content) = fs::read_to_string("/etc/openwrt_release") {
-        for line in content.lines() {
-            if let Some(rest) = line.strip_prefix("DISTRIB_REVISION=") {
-                return rest.trim_matches('\'').trim_matches('"').to_string();
-            }
+    if let Some(rel) = read_openwrt_release() {
+        if !rel.revision.is_empty() {
+            return rel.revision;
         }
     }
     // Fall back to the plain version file
@@ -155,6 +255,250 @@ pub fn read_mem_total() -> String {
     String::new()
 }
 
+// ── Temperature ──────────────────────────────────────────────────────────────
+
+/// Hottest thermal zone in °C (`/sys/class/thermal/thermal_zone*/temp` is in
+/// millidegrees), or `None` if the device exposes no sensors.
+pub fn read_max_temperature_c() -> Option<f64> {
+    fs::read_dir("/sys/class/thermal")
+        .ok()?
+        .flatten()
+        .filter(|e| e.file_name().to_string_lossy().starts_with("thermal_zone"))
+        .filter_map(|e| fs::read_to_string(e.path().join("temp")).ok())
+        .filter_map(|t| t.trim().parse::<i64>().ok())
+        .map(|milli| milli as f64 / 1000.0)
+        .reduce(f64::max)
+}
+
+// ── Connection tracking ──────────────────────────────────────────────────────
+
+/// Netfilter connection-tracking table occupancy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conntrack {
+    pub count: u64,
+    pub max: u64,
+}
+
+/// Read `nf_conntrack_count` / `nf_conntrack_max` from
+/// `/proc/sys/net/netfilter`, or `None` when the conntrack module is not
+/// loaded and the files are absent.
+pub fn read_conntrack() -> Option<Conntrack> {
+    read_conntrack_in(Path::new("/proc/sys/net/netfilter"))
+}
+
+fn read_conntrack_in(netfilter: &Path) -> Option<Conntrack> {
+    let read = |name: &str| -> Option<u64> {
+        fs::read_to_string(netfilter.join(name))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    Some(Conntrack {
+        count: read("nf_conntrack_count")?,
+        max: read("nf_conntrack_max")?,
+    })
+}
+
+// ── Power supply ─────────────────────────────────────────────────────────────
+
+/// Power source and, where the hardware exposes them, battery readings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PowerSupply {
+    /// `battery`, `mains`, `usb`, ... (the sysfs `type`, lower-cased), or
+    /// `unknown` when no power-supply path is configured or readable.
+    pub source: String,
+    /// Battery charge in percent (`capacity`).
+    pub capacity: Option<u8>,
+    /// Supply voltage in mV (`voltage_now` is reported in µV).
+    pub voltage_mv: Option<u64>,
+}
+
+/// Read a `/sys/class/power_supply/<name>` directory (`power_sysfs_path`).
+/// An empty path means the device has no power telemetry.
+pub fn read_power_supply(dir: &str) -> PowerSupply {
+    let unknown = PowerSupply {
+        source: "unknown".into(),
+        capacity: None,
+        voltage_mv: None,
+    };
+    if dir.is_empty() {
+        return unknown;
+    }
+    let dir = Path::new(dir);
+    let read = |name: &str| -> Option<String> {
+        let v = fs::read_to_string(dir.join(name)).ok()?;
+        let v = v.trim();
+        (!v.is_empty()).then(|| v.to_string())
+    };
+    match read("type") {
+        Some(kind) => PowerSupply {
+            source: kind.to_ascii_lowercase(),
+            capacity: read("capacity").and_then(|v| v.parse().ok()),
+            voltage_mv: read("voltage_now")
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(|uv| uv / 1000),
+        },
+        None => unknown,
+    }
+}
+
+// ── Filesystem usage ─────────────────────────────────────────────────────────
+
+/// Capacity of a mounted filesystem, in kB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsUsage {
+    pub total_kb: u64,
+    pub used_kb: u64,
+    /// Space available to non-root writers (`f_bavail`).
+    pub free_kb: u64,
+}
+
+impl FsUsage {
+    /// Compute usage from raw `statvfs` fields.
+    pub fn from_statvfs(frag_size: u64, blocks: u64, blocks_free: u64, blocks_avail: u64) -> Self {
+        let to_kb = |n: u64| n.saturating_mul(frag_size) / 1024;
+        Self {
+            total_kb: to_kb(blocks),
+            used_kb: to_kb(blocks.saturating_sub(blocks_free)),
+            free_kb: to_kb(blocks_avail),
+        }
+    }
+}
+
+/// Return usage of the filesystem mounted at `path` via `statvfs(2)`,
+/// or `None` if the call fails (path missing, not mounted, ...).
+pub fn read_filesystem_usage(path: &str) -> Option<FsUsage> {
+    let st = nix::sys::statvfs::statvfs(path).ok()?;
+    Some(FsUsage::from_statvfs(
+        st.fragment_size() as u64,
+        st.blocks() as u64,
+        st.blocks_free() as u64,
+        st.blocks_available() as u64,
+    ))
+}
+
+/// Usage of the writable flash: the `/overlay` mount on squashfs images,
+/// falling back to `/` on images without an overlay (e.g. ext4 rootfs).
+pub fn read_flash_usage() -> Option<FsUsage> {
+    let overlay_mounted = fs::read_to_string("/proc/mounts")
+        .map(|m| {
+            m.lines()
+                .any(|l| l.split_whitespace().nth(1) == Some("/overlay"))
+        })
+        .unwrap_or(false);
+    if overlay_mounted {
+        read_filesystem_usage("/overlay")
+    } else {
+        read_filesystem_usage("/")
+    }
+}
+
+// ── Ethernet link ────────────────────────────────────────────────────────────
+
+/// Negotiated speed/duplex of a network interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkSpeed {
+    /// Speed in Mbit/s; `None` when the link is down or not reported.
+    pub speed_mbps: Option<u32>,
+    /// "full" or "half"; `None` when unknown.
+    pub duplex: Option<String>,
+}
+
+impl LinkSpeed {
+    /// Short form for logs and the heartbeat, e.g. "1000/full" or "unknown".
+    pub fn describe(&self) -> String {
+        match (&self.speed_mbps, &self.duplex) {
+            (Some(s), Some(d)) => format!("{s}/{d}"),
+            (Some(s), None) => s.to_string(),
+            _ => "unknown".to_string(),
+        }
+    }
+}
+
+/// Read link speed and duplex from `/sys/class/net/<iface>/{speed,duplex}`.
+///
+/// Wireless interfaces and down links either lack these files or report
+/// `-1`/`unknown` (reading `speed` on a down link fails with EINVAL); all of
+/// those come back as `None`.
+pub fn read_link_speed(iface: &str) -> LinkSpeed {
+    read_link_speed_in(Path::new("/sys/class/net"), iface)
+}
+
+fn read_link_speed_in(sysfs_net: &Path, iface: &str) -> LinkSpeed {
+    let dir = sysfs_net.join(iface);
+    let speed_mbps = fs::read_to_string(dir.join("speed"))
+        .ok()
+        .and_then(|s| s.trim().parse::<i64>().ok())
+        .filter(|&s| s > 0)
+        .map(|s| s as u32);
+    let duplex = fs::read_to_string(dir.join("duplex"))
+        .ok()
+        .map(|s| s.trim().to_ascii_lowercase())
+        .filter(|d| d == "full" || d == "half");
+    LinkSpeed { speed_mbps, duplex }
+}
+
+/// Physical wired interfaces (including DSA switch ports), sorted by name.
+///
+/// Skips virtual devices (bridges, VLANs, tunnels, loopback), which live under
+/// `/sys/devices/virtual`, and anything with a wireless PHY.
+pub fn list_ethernet_interfaces() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir("/sys/class/net")
+        .map(|rd| {
+            rd.filter_map(|e| e.ok())
+                .filter(|e| {
+                    let target = fs::read_link(e.path()).unwrap_or_default();
+                    !target.to_string_lossy().contains("/virtual/")
+                        && !e.path().join("wireless").exists()
+                        && !e.path().join("phy80211").exists()
+                })
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+// ── Interface counters ───────────────────────────────────────────────────────
+
+/// Traffic counters of a network interface since it was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IfaceStats {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+}
+
+/// Read byte/packet counters from `/sys/class/net/<iface>/statistics/`.
+///
+/// The kernel exposes them as `unsigned long`: 64-bit on 64-bit kernels, but
+/// only 32-bit on most MIPS/ARM32 OpenWrt targets, where the byte counters
+/// wrap every 4 GiB.  Values are parsed as u64 so neither width truncates;
+/// anything computing rates from two samples must treat a smaller second
+/// sample as a wrap (or an interface that was recreated), not as negative
+/// traffic.  Returns `None` when the interface does not exist.
+pub fn read_iface_stats(iface: &str) -> Option<IfaceStats> {
+    read_iface_stats_in(Path::new("/sys/class/net"), iface)
+}
+
+fn read_iface_stats_in(sysfs_net: &Path, iface: &str) -> Option<IfaceStats> {
+    let dir = sysfs_net.join(iface).join("statistics");
+    let counter = |name: &str| -> Option<u64> {
+        fs::read_to_string(dir.join(name))
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+    };
+    Some(IfaceStats {
+        rx_bytes: counter("rx_bytes")?,
+        tx_bytes: counter("tx_bytes")?,
+        rx_packets: counter("rx_packets").unwrap_or(0),
+        tx_packets: counter("tx_packets").unwrap_or(0),
+    })
+}
+
 // ── SSID ─────────────────────────────────────────────────────────────────────
 
 /// Read the SSID of the first wireless interface via `iw`.
@@ -174,6 +518,47 @@ pub fn read_ssid() -> String {
     String::new()
 }
 
+// ── Config hash ──────────────────────────────────────────────────────────────
+
+/// Stable content hash (64-bit FNV-1a, hex) used as a config version string.
+/// Unlike `DefaultHasher` the value never changes between builds.
+pub fn config_hash(data: &[u8]) -> String {
+    let hash = data.iter().fold(0xcbf2_9ce4_8422_2325u64, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("{hash:016x}")
+}
+
+// ── File digests ─────────────────────────────────────────────────────────────
+
+/// Lower-case hex MD5 of a file, via BusyBox `md5sum`.
+pub fn md5_file(path: &Path) -> io::Result<String> {
+    digest_file("md5sum", path)
+}
+
+/// Lower-case hex SHA-256 of a file, via BusyBox `sha256sum`.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    digest_file("sha256sum", path)
+}
+
+/// Run a coreutils-style `<tool> <file>` and take the digest from the first
+/// column of its output.
+fn digest_file(tool: &str, path: &Path) -> io::Result<String> {
+    let out = std::process::Command::new(tool).arg(path).output()?;
+    if !out.status.success() {
+        return Err(io::Error::other(format!(
+            "{tool} {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    String::from_utf8_lossy(&out.stdout)
+        .split_whitespace()
+        .next()
+        .map(|d| d.to_ascii_lowercase())
+        .ok_or_else(|| io::Error::other(format!("{tool} printed no digest")))
+}
+
 // ── PID file ──────────────────────────────────────────────────────────────────
 
 /// Write the current process PID to `path`.
@@ -186,6 +571,14 @@ pub fn write_pid_file(path: &Path) -> io::Result<()> {
     Ok(())
 }
 
+/// Remove the PID file written by [`write_pid_file`]; a missing file is fine.
+pub fn remove_pid_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
 /// Get the primary local IP address
 pub fn get_local_ip() -> String {
     // Try to get IP from network interface using ip command
@@ -356,12 +749,199 @@ pub fn read_device_description() -> String {
 /// Get kernel version (AdditionalSoftwareVersion)
 pub fn read_kernel_version() -> String {
     if let Ok(output) = std::process::Command::new("uname").arg("-r").output() {
-        return String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let v = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !v.is_empty() {
+            return v;
+        }
     }
-    String::new()
+    // Fallback: "Linux version 5.15.150 (builder@...) ..."
+    fs::read_to_string("/proc/version")
+        .ok()
+        .and_then(|v| v.split_whitespace().nth(2).map(str::to_string))
+        .unwrap_or_default()
 }
 
 /// Get device status - always returns "Up" if agent is running
 pub fn read_device_status() -> String {
     "Up".to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_speed_from_sysfs() {
+        let root = std::env::temp_dir().join(format!("ac-client-sysfs-{}", std::process::id()));
+        let up = root.join("eth0");
+        let down = root.join("eth1");
+        fs::create_dir_all(&up).unwrap();
+        fs::create_dir_all(&down).unwrap();
+        fs::write(up.join("speed"), "1000\n").unwrap();
+        fs::write(up.join("duplex"), "full\n").unwrap();
+        fs::write(down.join("speed"), "-1\n").unwrap();
+        fs::write(down.join("duplex"), "unknown\n").unwrap();
+
+        let eth0 = read_link_speed_in(&root, "eth0");
+        assert_eq!(eth0.speed_mbps, Some(1000));
+        assert_eq!(eth0.duplex.as_deref(), Some("full"));
+        assert_eq!(eth0.describe(), "1000/full");
+
+        let eth1 = read_link_speed_in(&root, "eth1");
+        assert_eq!(eth1.speed_mbps, None);
+        assert_eq!(eth1.duplex, None);
+        assert_eq!(eth1.describe(), "unknown");
+
+        // Wireless / missing files
+        assert_eq!(read_link_speed_in(&root, "wlan0").describe(), "unknown");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn iface_stats_beyond_32_bits() {
+        let root = std::env::temp_dir().join(format!("ac-client-stats-{}", std::process::id()));
+        let stats = root.join("eth0").join("statistics");
+        fs::create_dir_all(&stats).unwrap();
+        // 64-bit kernel: past the 4 GiB a 32-bit counter would wrap at
+        fs::write(stats.join("rx_bytes"), "6442450944\n").unwrap();
+        fs::write(stats.join("tx_bytes"), "1024\n").unwrap();
+        fs::write(stats.join("rx_packets"), "4300000\n").unwrap();
+        fs::write(stats.join("tx_packets"), "12\n").unwrap();
+
+        assert_eq!(
+            read_iface_stats_in(&root, "eth0"),
+            Some(IfaceStats {
+                rx_bytes: 6_442_450_944,
+                tx_bytes: 1024,
+                rx_packets: 4_300_000,
+                tx_packets: 12,
+            })
+        );
+        assert_eq!(read_iface_stats_in(&root, "eth9"), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn conntrack_from_proc_files() {
+        let root = std::env::temp_dir().join(format!("ac-client-conntrack-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("nf_conntrack_count"), "1834\n").unwrap();
+        fs::write(root.join("nf_conntrack_max"), "16384\n").unwrap();
+        assert_eq!(
+            read_conntrack_in(&root),
+            Some(Conntrack {
+                count: 1834,
+                max: 16384
+            })
+        );
+
+        // Module not loaded: no files
+        fs::remove_file(root.join("nf_conntrack_count")).unwrap();
+        assert_eq!(read_conntrack_in(&root), None);
+        assert_eq!(read_conntrack_in(&root.join("missing")), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn power_supply_from_sysfs_fixture() {
+        let root = std::env::temp_dir().join(format!("ac-client-power-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("type"), "Battery\n").unwrap();
+        fs::write(root.join("capacity"), "87\n").unwrap();
+        fs::write(root.join("voltage_now"), "12150000\n").unwrap();
+        let dir = root.to_str().unwrap();
+        assert_eq!(
+            read_power_supply(dir),
+            PowerSupply {
+                source: "battery".into(),
+                capacity: Some(87),
+                voltage_mv: Some(12150),
+            }
+        );
+
+        // Supply without battery gauges (e.g. PoE input)
+        fs::write(root.join("type"), "Mains\n").unwrap();
+        fs::remove_file(root.join("capacity")).unwrap();
+        fs::remove_file(root.join("voltage_now")).unwrap();
+        let ps = read_power_supply(dir);
+        assert_eq!(ps.source, "mains");
+        assert_eq!((ps.capacity, ps.voltage_mv), (None, None));
+
+        // Not configured, or path gone
+        assert_eq!(read_power_supply("").source, "unknown");
+        assert_eq!(
+            read_power_supply(root.join("missing").to_str().unwrap()).source,
+            "unknown"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn pid_file_written_and_removed() {
+        let path = std::env::temp_dir()
+            .join(format!("ac-client-pid-{}", std::process::id()))
+            .join("apclient.pid");
+        write_pid_file(&path).unwrap();
+        let pid: u32 = fs::read_to_string(&path).unwrap().trim().parse().unwrap();
+        assert_eq!(pid, std::process::id());
+        remove_pid_file(&path).unwrap();
+        assert!(!path.exists());
+        // Already gone: not an error
+        remove_pid_file(&path).unwrap();
+        fs::remove_dir(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn parse_openwrt_release_sample() {
+        let sample = "DISTRIB_ID='OpenWrt'\n\
+                      DISTRIB_RELEASE='23.05.3'\n\
+                      DISTRIB_REVISION='r23809-234f1a2efa'\n\
+                      DISTRIB_TARGET='mediatek/filogic'\n\
+                      DISTRIB_ARCH='aarch64_cortex-a53'\n\
+                      DISTRIB_DESCRIPTION='OpenWrt 23.05.3 r23809-234f1a2efa'\n\
+                      DISTRIB_TAINTS=''\n";
+        let rel = parse_openwrt_release(sample);
+        assert_eq!(rel.id, "OpenWrt");
+        assert_eq!(rel.release, "23.05.3");
+        assert_eq!(rel.revision, "r23809-234f1a2efa");
+        assert_eq!(rel.target, "mediatek/filogic");
+        assert_eq!(rel.arch, "aarch64_cortex-a53");
+        assert_eq!(rel.description, "OpenWrt 23.05.3 r23809-234f1a2efa");
+        assert_eq!(rel.distribution(), "OpenWrt 23.05.3");
+    }
+
+    #[test]
+    fn wan_side_arp_entries_excluded() {
+        let sample = "IP address       HW type     Flags       HW address            Mask     Device\n\
+                      192.168.1.20     0x1         0x2         AA:BB:CC:00:00:20     *        br-lan\n\
+                      10.0.0.1         0x1         0x2         de:ad:be:ef:00:01     *        eth1\n\
+                      192.168.2.30     0x1         0x0         00:00:00:00:00:00     *        br-guest\n";
+        let entries = parse_arp_table(sample);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].mac, "aa:bb:cc:00:00:20");
+        assert!(entries[0].complete);
+        assert!(!entries[2].complete);
+
+        let lan = lan_arp_entries(entries, &["br-lan".to_string(), "br-guest".to_string()]);
+        let ips: Vec<_> = lan.iter().map(|e| e.ip.as_str()).collect();
+        assert_eq!(ips, vec!["192.168.1.20", "192.168.2.30"]);
+    }
+
+    #[test]
+    fn fs_usage_from_statvfs() {
+        // 4 KiB fragments: 2048 blocks total, 512 free, 448 available to users
+        let u = FsUsage::from_statvfs(4096, 2048, 512, 448);
+        assert_eq!(u.total_kb, 8192);
+        assert_eq!(u.used_kb, 6144);
+        assert_eq!(u.free_kb, 1792);
+
+        // Full filesystem
+        let u = FsUsage::from_statvfs(1024, 100, 0, 0);
+        assert_eq!(u.used_kb, 100);
+        assert_eq!(u.free_kb, 0);
+    }
+}